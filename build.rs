@@ -32,9 +32,18 @@ fn main() {
         .output()
         .unwrap();
 
+    // the kernel command line can't be passed with qemu's -append since we boot an iso, limine
+    // gives the kernel the `cmdline` of its entry instead
+    let cmdline = std::env::var("NAVI_CMDLINE").unwrap_or_default();
+    let config = fs::read_to_string("limine.conf").unwrap();
+    fs::write(
+        "iso_root/boot/limine/limine.conf",
+        format!("{}    cmdline: {}\n", config, cmdline.trim()),
+    )
+    .unwrap();
+
     Command::new("cp")
         .arg("-v")
-        .arg("limine.conf")
         .arg("limine/limine-bios.sys")
         .arg("limine/limine-bios-cd.bin")
         .arg("limine/limine-uefi-cd.bin")
@@ -42,6 +51,13 @@ fn main() {
         .output()
         .unwrap();
 
+    Command::new("cp")
+        .arg("-v")
+        .arg("kernel/initramfs.cpio")
+        .arg("iso_root/boot/initramfs.cpio")
+        .output()
+        .unwrap();
+
     fs::create_dir_all("iso_root/EFI/BOOT").unwrap();
     Command::new("cp")
         .arg("-v")
@@ -76,6 +92,9 @@ fn main() {
     let iso_path = current_dir().unwrap().join("navios.iso");
     println!("cargo:rerun-if-changed={}", iso_path.display());
     println!("cargo:rerun-if-changed={}", "limine");
+    println!("cargo:rerun-if-changed={}", "kernel/initramfs.cpio");
+    println!("cargo:rerun-if-changed={}", "limine.conf");
+    println!("cargo:rerun-if-env-changed=NAVI_CMDLINE");
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=ISO_PATH={}", iso_path.display());
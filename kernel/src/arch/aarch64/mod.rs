@@ -0,0 +1,216 @@
+// a stub of the `Arch` interface for aarch64, nothing is implemented yet, everything panics
+// it only exists so `Arch` is written against more than one architecture (see `arch`), the
+// modules below have the items the kernel uses from their x86_64 counterparts
+
+use super::Arch;
+use crate::{
+    memory::{
+        frame_allocator::Frame,
+        paging::{EntryFlags, MapToError, Page, PageTable},
+    },
+    time::Duration,
+};
+use threading::CPUStatus;
+
+pub mod threading {
+    /// the registers saved when a thread is switched out, not decided yet
+    #[derive(Debug, Clone, Copy, Default)]
+    #[repr(C)]
+    pub struct CPUStatus;
+}
+
+pub mod fpu {
+    /// the fpu and simd registers of a thread, not decided yet
+    #[derive(Debug, Clone, Copy)]
+    pub struct FpuState;
+
+    impl FpuState {
+        pub const fn new() -> Self {
+            Self
+        }
+
+        pub fn save(&mut self) {
+            unimplemented!("aarch64: FpuState::save")
+        }
+
+        pub fn restore(&self) {
+            unimplemented!("aarch64: FpuState::restore")
+        }
+    }
+}
+
+pub mod serial {
+    use core::fmt::{self, Write};
+
+    /// the uart, not decided yet
+    pub struct SerialPort;
+
+    impl SerialPort {
+        pub fn write(&self, _byte: u8) {
+            unimplemented!("aarch64: SerialPort::write")
+        }
+
+        pub fn write_string(&self, string: &str) {
+            string.bytes().for_each(|byte| self.write(byte))
+        }
+
+        pub fn is_present(&self) -> bool {
+            false
+        }
+
+        /// writes are always synchronous
+        pub fn transmit_pending(&self) -> bool {
+            false
+        }
+    }
+
+    impl Write for &SerialPort {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.write_string(s);
+            Ok(())
+        }
+    }
+
+    static UART: SerialPort = SerialPort;
+
+    /// the port the serial console and `serial!` use
+    pub fn console() -> &'static SerialPort {
+        &UART
+    }
+
+    /// the port `log!` writes to
+    pub fn log_port() -> &'static SerialPort {
+        &UART
+    }
+
+    /// writes are always synchronous
+    pub fn synchronous() {}
+
+    pub fn _serial(args: fmt::Arguments) {
+        _ = console().write_fmt(args);
+    }
+}
+
+pub mod power {
+    pub fn shutdown() {
+        unimplemented!("aarch64: shutdown")
+    }
+
+    pub fn reboot() {
+        unimplemented!("aarch64: reboot")
+    }
+}
+
+#[cfg(any(feature = "test", feature = "bench"))]
+pub mod qemu {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u8)]
+    pub enum ExitCode {
+        Success = 0x10,
+        Failed = 0x11,
+    }
+
+    pub fn exit(_code: ExitCode) {
+        unimplemented!("aarch64: qemu::exit")
+    }
+}
+
+pub mod backtrace {
+    pub fn print() {
+        crate::serial!("no backtrace on aarch64\n");
+    }
+
+    pub fn log() {
+        crate::log!("no backtrace on aarch64");
+    }
+}
+
+pub mod cpu {
+    pub fn dump_registers() {
+        crate::serial!("no registers dump on aarch64\n");
+    }
+}
+
+pub fn init_cpu() {
+    unimplemented!("aarch64: init_cpu")
+}
+
+pub fn init_acpi() {
+    unimplemented!("aarch64: init_acpi")
+}
+
+pub fn init_interrupts() {
+    unimplemented!("aarch64: init_interrupts")
+}
+
+/// there is only one cpu running
+pub fn halt_others() {}
+
+/// how many times each vector was raised, nothing is counted yet
+pub fn interrupt_stats() -> impl Iterator<Item = (u8, u64)> {
+    core::iter::empty()
+}
+
+pub struct AArch64;
+
+impl Arch for AArch64 {
+    type Context = CPUStatus;
+
+    fn map(_page: Page, _frame: Frame, _flags: EntryFlags) -> Result<(), MapToError> {
+        unimplemented!("aarch64: map")
+    }
+
+    unsafe fn flush(_page: Page) {
+        unimplemented!("aarch64: flush")
+    }
+
+    unsafe fn current_root_table() -> &'static mut PageTable {
+        unimplemented!("aarch64: current_root_table")
+    }
+
+    fn write_combining() -> EntryFlags {
+        unimplemented!("aarch64: write_combining")
+    }
+
+    fn enable_interrupts() {
+        unimplemented!("aarch64: enable_interrupts")
+    }
+
+    fn disable_interrupts() {
+        unimplemented!("aarch64: disable_interrupts")
+    }
+
+    fn interrupts_enabled() -> bool {
+        unimplemented!("aarch64: interrupts_enabled")
+    }
+
+    unsafe fn switch_to(_context: &CPUStatus) -> ! {
+        unimplemented!("aarch64: switch_to")
+    }
+
+    fn counter() -> u64 {
+        unimplemented!("aarch64: counter")
+    }
+
+    fn counter_ticks_to_ns(_ticks: u64) -> u64 {
+        unimplemented!("aarch64: counter_ticks_to_ns")
+    }
+
+    fn ns_to_counter_ticks(_ns: u64) -> u64 {
+        unimplemented!("aarch64: ns_to_counter_ticks")
+    }
+
+    const TIMER_TICK: Duration = Duration::from_millis(10);
+
+    fn timer_deadline_mode() -> bool {
+        false
+    }
+
+    fn arm_timer_deadline(_deadline: u64) {
+        unimplemented!("aarch64: arm_timer_deadline")
+    }
+
+    fn rearm_timer() {
+        unimplemented!("aarch64: rearm_timer")
+    }
+}
@@ -1,11 +1,96 @@
+// the architecture specific code, `Arch` is what the rest of the kernel needs from an
+// architecture and `Current` is the implementation of the target we build for
+// only x86_64 works, aarch64 is a stub that panics so the interface has a second implementation
+// to keep it honest, the kernel reaches the architecture through `Arch` and the modules
+// re-exported below (each architecture has its own with the same items), only the pc drivers
+// (see `drivers`), the syscall abi and the x86_64 code itself use `arch::x86_64` directly
+// `cargo check --target aarch64-unknown-none` checks that nothing else does
+
+use crate::{
+    memory::{
+        frame_allocator::Frame,
+        paging::{EntryFlags, MapToError, Page, PageTable},
+    },
+    time::Duration,
+};
+
+pub trait Arch {
+    /// the registers saved when a thread is switched out
+    type Context;
+
+    /// maps `page` to `frame` in the current address space
+    fn map(page: Page, frame: Frame, flags: EntryFlags) -> Result<(), MapToError>;
+    /// invalidates the tlb entry of `page`
+    unsafe fn flush(page: Page);
+    /// the root page table of the current address space
+    unsafe fn current_root_table() -> &'static mut PageTable;
+    /// the flags mapping a page write combining, for the framebuffer
+    fn write_combining() -> EntryFlags;
+
+    fn enable_interrupts();
+    fn disable_interrupts();
+    fn interrupts_enabled() -> bool;
+
+    /// switches to the thread `context` was saved from
+    unsafe fn switch_to(context: &Self::Context) -> !;
+
+    /// the monotonic counter `time::Instant` reads
+    fn counter() -> u64;
+    fn counter_ticks_to_ns(ticks: u64) -> u64;
+    fn ns_to_counter_ticks(ns: u64) -> u64;
+
+    /// how long a tick of the scheduler timer is
+    const TIMER_TICK: Duration;
+    /// wether or not the timer can be armed for a counter value, see `threading::timer`
+    fn timer_deadline_mode() -> bool;
+    /// arms the timer for the counter value `deadline`, 0 disarms it
+    fn arm_timer_deadline(deadline: u64);
+    /// arms the timer for the next tick
+    fn rearm_timer();
+}
+
 #[cfg(target_arch = "x86_64")]
 pub mod x86_64;
+#[cfg(target_arch = "x86_64")]
+pub type Current = x86_64::X86_64;
+
+#[cfg(target_arch = "aarch64")]
+pub mod aarch64;
+#[cfg(target_arch = "aarch64")]
+pub type Current = aarch64::AArch64;
+
+/// runs `f` with interrupts disabled, they are re-enabled after only if they were enabled before
+#[inline]
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = Current::interrupts_enabled();
+    Current::disable_interrupts();
+
+    let result = f();
+
+    if enabled {
+        Current::enable_interrupts();
+    }
+    result
+}
 
 #[cfg(target_arch = "x86_64")]
-pub use x86_64::threading;
+pub use x86_64::{backtrace, cpu, fpu, power, serial, threading};
+
+// only the tests and the benchmarks exit qemu
+#[cfg(all(target_arch = "x86_64", any(feature = "test", feature = "bench")))]
+pub use x86_64::qemu;
 
 #[cfg(target_arch = "x86_64")]
-pub use x86_64::init;
+pub use x86_64::{init_acpi, init_cpu, init_interrupts};
 
 #[cfg(target_arch = "x86_64")]
-pub use x86_64::power;
+pub use x86_64::interrupts::{apic::halt_others, interrupt_stats};
+
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::{backtrace, cpu, fpu, power, serial, threading};
+
+#[cfg(all(target_arch = "aarch64", any(feature = "test", feature = "bench")))]
+pub use aarch64::qemu;
+
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::{halt_others, init_acpi, init_cpu, init_interrupts, interrupt_stats};
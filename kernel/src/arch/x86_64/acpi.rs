@@ -1,4 +1,4 @@
-use crate::{arch::x86_64::inw, kernel, memory::identity_map_present, serial};
+use crate::{arch::x86_64::inw, kernel, memory::identity_map_present, serial, PhysAddr};
 
 use super::outb;
 
@@ -209,7 +209,7 @@ impl SDT for RSDT {
 
         let total_offset = (table_start as usize - (self as *const Self) as usize) + offset;
         let addr = *(table_start.byte_add(offset) as *const u32) as usize;
-        identity_map_present(addr);
+        identity_map_present(PhysAddr::new(addr));
 
         (addr, total_offset as u32)
     }
@@ -283,10 +283,82 @@ impl MADT {
     pub fn get(ptsd: &dyn PTSD) -> &MADT {
         unsafe { &*(ptsd.get_entry_of_signatrue(*b"APIC").unwrap() as *const MADT) }
     }
+
+    /// every record of the table, stops at a broken one
+    pub fn records(&self) -> impl Iterator<Item = *const MADTRecord> {
+        let start = self as *const Self as usize;
+        let end = start + self.header.len as usize;
+        let mut record = start + size_of::<MADT>();
+
+        core::iter::from_fn(move || {
+            if record + size_of::<MADTRecord>() > end {
+                return None;
+            }
+
+            let ptr = record as *const MADTRecord;
+            let header = unsafe { *ptr };
+            if header.length == 0 {
+                return None;
+            }
+
+            record += header.length as usize;
+            Some(ptr)
+        })
+    }
+
+    /// the global system interrupt isa irq `irq` arrives at on the ioapic, that is `irq` unless a
+    /// record remaps it (the pit irq 0 usually is on 2)
+    pub fn isa_irq_gsi(&self, irq: u8) -> u32 {
+        self.records()
+            .filter(|&record| unsafe { (*record).entry_type } == MADT_INTERRUPT_OVERRIDE)
+            .map(|record| unsafe { *(record as *const MADTInterruptOverride) })
+            .find(|remap| remap.bus == 0 && remap.source == irq)
+            .map_or(irq as u32, |remap| remap.gsi)
+    }
+
+    /// the local apic ids of the cpus the firmware enabled, the boot cpu is one of them
+    pub fn local_apic_ids(&self) -> impl Iterator<Item = u8> {
+        self.records()
+            .filter(|&record| unsafe { (*record).entry_type } == MADT_LOCAL_APIC)
+            .map(|record| unsafe { *(record as *const MADTLocalApic) })
+            .filter(|local_apic| local_apic.flags & LOCAL_APIC_ENABLED != 0)
+            .map(|local_apic| local_apic.apic_id)
+    }
+}
+
+const MADT_LOCAL_APIC: u8 = 0;
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+/// a cpu and its local apic
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct MADTLocalApic {
+    _header: MADTRecord,
+    _processor_id: u8,
+    apic_id: u8,
+    flags: u32,
+}
+
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+
+/// remaps an isa irq to another global system interrupt
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct MADTInterruptOverride {
+    _header: MADTRecord,
+    bus: u8,
+    source: u8,
+    gsi: u32,
+    _flags: u16,
+}
+
+/// wether or not the acpi tables describe an hpet
+pub fn has_hpet() -> bool {
+    unsafe { get_sdt().get_entry_of_signatrue(*b"HPET") }.is_some()
 }
 
 fn get_rsdp() -> RSDPDesc {
-    identity_map_present(kernel().rsdp_addr.unwrap() as usize);
+    identity_map_present(PhysAddr::new(kernel().rsdp_addr.unwrap() as usize));
     let ptr = kernel().rsdp_addr.unwrap() as *mut RSDPDesc;
 
     let desc = unsafe { *ptr };
@@ -301,7 +373,7 @@ pub fn get_sdt() -> &'static dyn PTSD {
     //     return SDT::XSDT(rsdp.xsdt_addr as *const XSDT);
     // }
 
-    identity_map_present(rsdp.rsdt_addr as usize);
+    identity_map_present(PhysAddr::new(rsdp.rsdt_addr as usize));
 
     unsafe { &*(rsdp.rsdt_addr as *const RSDT) }
 }
@@ -0,0 +1,140 @@
+// walks the rbp chain, the kernel is built with `force-frame-pointers` (see .cargo/config.toml)
+// so every function pushes rbp and the chain looks like
+// [rbp] -> previous rbp, [rbp + 8] -> return address
+
+use core::{arch::asm, ops::Range};
+
+use crate::{
+    cross_println, kernel, kernel_inited, log, memory::hexdump::first_unmapped, println, scheduler,
+    scheduler_inited, serial, terminal, terminal_inited, threading::STACK_SIZE, VirtAddr,
+};
+
+pub const MAX_FRAMES: usize = 32;
+/// used as the stack size when we don't know the stack we are running on (the bootloader stack
+/// or an interrupt stack), 64KiB is the default limine stack size
+const FALLBACK_STACK_SIZE: usize = 64 * 1024;
+
+/// returns the bounds of the stack `rsp` is in
+fn stack_bounds(rsp: VirtAddr) -> Range<VirtAddr> {
+    if scheduler_inited() {
+        let stack_end = VirtAddr::from_ptr(unsafe { (*scheduler().current_thread).stack_end });
+        let stack = stack_end - STACK_SIZE..stack_end;
+
+        if stack.contains(&rsp) {
+            return stack;
+        }
+    }
+
+    rsp..rsp.saturating_add(FALLBACK_STACK_SIZE)
+}
+
+/// captures at most `max` (and at most `MAX_FRAMES`) return addresses of the current call stack
+/// the rest of the array is zeroed, each frame pointer is checked to be inside the current stack
+/// before being dereferenced so a corrupted stack won't fault the backtracer
+#[inline(never)]
+pub fn capture(max: usize) -> [usize; MAX_FRAMES] {
+    let (fp, rsp): (usize, usize);
+
+    unsafe {
+        asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+
+    walk(
+        VirtAddr::new(fp),
+        stack_bounds(VirtAddr::new(rsp)),
+        max,
+        false,
+    )
+}
+
+/// like `capture` but for the code an exception interrupted, from its frame pointer `fp` and its
+/// stack pointer `rsp`, every frame is also checked to be mapped since a bad stack may be what
+/// faulted in the first place
+pub fn capture_from(fp: VirtAddr, rsp: VirtAddr) -> [usize; MAX_FRAMES] {
+    walk(fp, stack_bounds(rsp), MAX_FRAMES, true)
+}
+
+fn walk(
+    mut fp: VirtAddr,
+    bounds: Range<VirtAddr>,
+    max: usize,
+    mapped: bool,
+) -> [usize; MAX_FRAMES] {
+    let mut frames = [0usize; MAX_FRAMES];
+    let max = max.min(MAX_FRAMES);
+
+    for frame in frames.iter_mut().take(max) {
+        // the frame has to fit entirely inside the stack
+        if fp.as_usize() == 0 || !fp.is_aligned(8) || !bounds.contains(&fp) || fp + 16 > bounds.end
+        {
+            break;
+        }
+
+        if mapped && first_unmapped(fp, 16).is_some() {
+            break;
+        }
+
+        let return_address = unsafe { *fp.as_ptr::<usize>().offset(1) };
+        if return_address == 0 {
+            break;
+        }
+        *frame = return_address;
+
+        let next_fp = VirtAddr::new(unsafe { *fp.as_ptr::<usize>() });
+        // frames only go up the stack, anything else is a loop or garbage
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+
+    frames
+}
+
+/// returns the name of the symbol containing `address` or "??"
+pub fn symbol_name(address: VirtAddr) -> &'static str {
+    if !kernel_inited() {
+        return "??";
+    }
+
+    let elf = &kernel().elf;
+    match elf.sym_from_value_range(address) {
+        Some(sym) => elf.string_table_index(sym.name_index),
+        None => "??",
+    }
+}
+
+/// prints the current call stack to the serial and the terminal
+pub fn print() {
+    cross_println!("stack trace: ");
+    print_frames(capture(MAX_FRAMES));
+}
+
+/// prints the frames returned by `capture` or `capture_from`
+pub fn print_frames(frames: [usize; MAX_FRAMES]) {
+    for address in frames {
+        if address == 0 {
+            break;
+        }
+
+        cross_println!("  {:#x} <{}>", address, symbol_name(VirtAddr::new(address)));
+    }
+}
+
+/// like `print` but through `log!`, for when the terminal can't be used, doesn't allocate or lock
+pub fn log() {
+    log!("stack trace:\n");
+
+    for address in capture(MAX_FRAMES) {
+        if address == 0 {
+            break;
+        }
+
+        log!(
+            "  {:#x} <{}>\n",
+            address,
+            symbol_name(VirtAddr::new(address))
+        );
+    }
+}
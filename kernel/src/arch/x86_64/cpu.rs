@@ -0,0 +1,283 @@
+// captures the cpu state from anywhere, unlike the interrupt frames this works outside of
+// exception handlers
+// the registers are stored into `CAPTURED` through rip relative addressing so the capture
+// itself doesn't need a register to hold the destination
+// the control register and EFER bits the kernel relies on are all set by `enable_features`,
+// once and in one place, from what `features` finds in cpuid, the code using a feature checks
+// `enabled` instead of cpuid or the registers
+
+use core::{
+    arch::{
+        asm,
+        x86_64::{__cpuid, __cpuid_count},
+    },
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use bitflags::bitflags;
+
+use crate::{cross_println, println, serial, terminal, terminal_inited};
+
+use super::msr::{self, IA32_EFER};
+
+const RFLAGS_INTERRUPT_ENABLE: u64 = 1 << 9;
+
+/// CR0.MP, wait/fwait fault with CR0.TS like the fpu instructions do
+const CR0_MONITOR_COPROCESSOR: u64 = 1 << 1;
+/// CR0.EM, the fpu instructions fault so they can be emulated, cleared
+const CR0_EMULATION: u64 = 1 << 2;
+/// CR0.TS, the fpu instructions fault after a task switch, cleared since the state is saved on
+/// every context switch
+const CR0_TASK_SWITCHED: u64 = 1 << 3;
+
+/// CR4.PGE, the global pages stay in the tlb when cr3 is written
+const CR4_PGE: u64 = 1 << 7;
+/// CR4.OSFXSR, fxsave/fxrstor save the sse state and the sse instructions don't fault
+const CR4_OSFXSR: u64 = 1 << 9;
+/// CR4.OSXMMEXCPT, the unmasked sse exceptions are #XM instead of #UD
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+/// CR4.SMEP, ring 0 can't execute user pages
+const CR4_SMEP: u64 = 1 << 20;
+/// CR4.SMAP, ring 0 can't access user pages unless rflags.AC is set
+const CR4_SMAP: u64 = 1 << 21;
+/// CR4.PKE, protection keys for user pages (see `pku`)
+const CR4_PKE: u64 = 1 << 22;
+
+/// EFER.NXE, the no execute bit of the page table entries is honored instead of reserved
+const EFER_NXE: u64 = 1 << 11;
+
+bitflags! {
+    /// the features `enable_features` knows about, the bit of each is its cpuid bit
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CpuFeatures: u32 {
+        /// cpuid 1 edx bit 13
+        const PGE = 1 << 0;
+        /// cpuid 1 edx bit 24 (fxsave/fxrstor) and bit 25 (sse)
+        const SSE = 1 << 1;
+        /// cpuid 0x8000_0001 edx bit 20
+        const NX = 1 << 2;
+        /// cpuid 7.0 ebx bit 7
+        const SMEP = 1 << 3;
+        /// cpuid 7.0 ebx bit 20
+        const SMAP = 1 << 4;
+        /// cpuid 7.0 ecx bit 3
+        const PKU = 1 << 5;
+    }
+}
+
+/// what `enable_features` enabled
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+
+/// the features the cpu has according to cpuid
+pub fn features() -> CpuFeatures {
+    let bit = |register: u32, bit: u32| register & (1 << bit) != 0;
+    let standard = unsafe { __cpuid(1) };
+    let max_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+    let extended = unsafe { __cpuid(0x8000_0001) };
+    let max_standard = unsafe { __cpuid(0) }.eax;
+    let structured = unsafe { __cpuid_count(7, 0) };
+
+    let mut features = CpuFeatures::empty();
+    features.set(CpuFeatures::PGE, bit(standard.edx, 13));
+    features.set(
+        CpuFeatures::SSE,
+        bit(standard.edx, 24) && bit(standard.edx, 25),
+    );
+    features.set(
+        CpuFeatures::NX,
+        max_extended >= 0x8000_0001 && bit(extended.edx, 20),
+    );
+    if max_standard >= 7 {
+        features.set(CpuFeatures::SMEP, bit(structured.ebx, 7));
+        features.set(CpuFeatures::SMAP, bit(structured.ebx, 20));
+        features.set(CpuFeatures::PKU, bit(structured.ecx, 3));
+    }
+    features
+}
+
+/// what `enable_features` enabled, empty before it ran
+#[inline]
+pub fn enabled() -> CpuFeatures {
+    CpuFeatures::from_bits_truncate(ENABLED.load(Ordering::Relaxed))
+}
+
+#[inline]
+pub fn read_cr0() -> u64 {
+    let cr0;
+    unsafe { asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags)) };
+    cr0
+}
+
+#[inline]
+pub fn read_cr4() -> u64 {
+    let cr4;
+    unsafe { asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags)) };
+    cr4
+}
+
+/// sets the CR0, CR4 and EFER bits of every feature `features` finds and returns them, has to run
+/// before anything uses them (the fpu, protection keys) and only once
+/// with SMAP the kernel has to go through `memory::user` to touch the user pages
+pub fn enable_features() -> CpuFeatures {
+    let supported = features();
+    let mut cr0 = read_cr0();
+    let mut cr4 = read_cr4();
+
+    if supported.contains(CpuFeatures::SSE) {
+        cr0 &= !(CR0_EMULATION | CR0_TASK_SWITCHED);
+        cr0 |= CR0_MONITOR_COPROCESSOR;
+        cr4 |= CR4_OSFXSR | CR4_OSXMMEXCPT;
+    }
+    for (feature, bit) in [
+        (CpuFeatures::PGE, CR4_PGE),
+        (CpuFeatures::SMEP, CR4_SMEP),
+        (CpuFeatures::SMAP, CR4_SMAP),
+        (CpuFeatures::PKU, CR4_PKE),
+    ] {
+        if supported.contains(feature) {
+            cr4 |= bit;
+        }
+    }
+
+    unsafe {
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack));
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack));
+    }
+    if supported.contains(CpuFeatures::NX) {
+        msr::write(IA32_EFER, msr::read(IA32_EFER) | EFER_NXE);
+    }
+
+    ENABLED.store(supported.bits(), Ordering::Relaxed);
+    serial!(
+        "cpu: enabled {:?}, not supported {:?}\n",
+        supported,
+        CpuFeatures::all().difference(supported)
+    );
+    supported
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+
+    pub rip: u64,
+    pub rflags: u64,
+
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            [("rax", self.rax), ("rbx", self.rbx), ("rcx", self.rcx)],
+            [("rdx", self.rdx), ("rsi", self.rsi), ("rdi", self.rdi)],
+            [("rbp", self.rbp), ("rsp", self.rsp), ("r8", self.r8)],
+            [("r9", self.r9), ("r10", self.r10), ("r11", self.r11)],
+            [("r12", self.r12), ("r13", self.r13), ("r14", self.r14)],
+            [
+                ("r15", self.r15),
+                ("rip", self.rip),
+                ("rflags", self.rflags),
+            ],
+            [("cr0", self.cr0), ("cr2", self.cr2), ("cr3", self.cr3)],
+        ];
+
+        for row in rows {
+            for (name, value) in row {
+                write!(f, "{:>6}: {:#018x} ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        write!(f, "{:>6}: {:#018x}", "cr4", self.cr4)
+    }
+}
+
+/// only accessed with interrupts disabled
+static mut CAPTURED: Registers = unsafe { core::mem::zeroed() };
+
+/// returns the registers as they are at the call site, rip and rsp are the caller's since this
+/// is always inlined
+/// interrupts are disabled during the capture and re-enabled only if they were enabled before
+#[inline(always)]
+pub fn capture_registers() -> Registers {
+    unsafe {
+        asm!(
+            // rflags first, before cli changes it
+            "pushfq",
+            "cli",
+            "mov [rip + {regs}], rax",
+            "pop rax",
+            "mov [rip + {regs} + 136], rax",
+
+            "mov [rip + {regs} + 8], rbx",
+            "mov [rip + {regs} + 16], rcx",
+            "mov [rip + {regs} + 24], rdx",
+            "mov [rip + {regs} + 32], rsi",
+            "mov [rip + {regs} + 40], rdi",
+            "mov [rip + {regs} + 48], rbp",
+            "mov [rip + {regs} + 56], rsp",
+            "mov [rip + {regs} + 64], r8",
+            "mov [rip + {regs} + 72], r9",
+            "mov [rip + {regs} + 80], r10",
+            "mov [rip + {regs} + 88], r11",
+            "mov [rip + {regs} + 96], r12",
+            "mov [rip + {regs} + 104], r13",
+            "mov [rip + {regs} + 112], r14",
+            "mov [rip + {regs} + 120], r15",
+
+            "lea rax, [rip]",
+            "mov [rip + {regs} + 128], rax",
+
+            "mov rax, cr0",
+            "mov [rip + {regs} + 144], rax",
+            "mov rax, cr2",
+            "mov [rip + {regs} + 152], rax",
+            "mov rax, cr3",
+            "mov [rip + {regs} + 160], rax",
+            "mov rax, cr4",
+            "mov [rip + {regs} + 168], rax",
+
+            "mov rax, [rip + {regs}]",
+            regs = sym CAPTURED,
+        );
+
+        let registers = CAPTURED;
+        if registers.rflags & RFLAGS_INTERRUPT_ENABLE != 0 {
+            asm!("sti");
+        }
+
+        registers
+    }
+}
+
+/// prints `registers` to the serial and the terminal
+pub fn print_registers(registers: &Registers) {
+    cross_println!("registers:\n{}", registers);
+}
+
+/// captures and prints the registers at the call site, see `capture_registers`
+#[inline(always)]
+pub fn dump_registers() {
+    print_registers(&capture_registers());
+}
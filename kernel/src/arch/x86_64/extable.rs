@@ -0,0 +1,56 @@
+// the exception table, `(fault, fixup)` pairs of the instructions that are allowed to page fault
+// and where to resume when they do, the page fault handler looks the faulting rip up here before
+// panicking (see `memory::user` for the user copies which are what it is for)
+// the entries are put in the `__ex_table` section by `extable_entry!` next to the instruction
+// and the linker script gathers them between `__ex_table_start` and `__ex_table_end`, there
+// are only a few so the lookup is linear
+
+use core::{mem::size_of, ptr::addr_of};
+
+use crate::VirtAddr;
+
+/// the assembly putting a `(fault, fixup)` entry in the table, the labels are assembly labels
+/// of the same `global_asm!`
+#[macro_export]
+macro_rules! extable_entry {
+    ($fault:literal, $fixup:literal) => {
+        concat!(
+            ".pushsection __ex_table, \"a\"\n",
+            ".balign 8\n",
+            ".quad ",
+            $fault,
+            ", ",
+            $fixup,
+            "\n.popsection\n"
+        )
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ExtableEntry {
+    pub fault: u64,
+    pub fixup: u64,
+}
+
+extern "C" {
+    static __ex_table_start: ExtableEntry;
+    static __ex_table_end: ExtableEntry;
+}
+
+/// every entry of the table
+pub fn entries() -> &'static [ExtableEntry] {
+    let start = unsafe { addr_of!(__ex_table_start) };
+    let end = unsafe { addr_of!(__ex_table_end) };
+    let len = (end as usize - start as usize) / size_of::<ExtableEntry>();
+
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
+
+/// where to resume after a fault at `rip`, None if the instruction at `rip` isn't allowed to fault
+pub fn search(rip: VirtAddr) -> Option<VirtAddr> {
+    entries()
+        .iter()
+        .find(|entry| entry.fault == rip.as_u64())
+        .map(|entry| VirtAddr::new(entry.fixup as usize))
+}
@@ -0,0 +1,79 @@
+// the x87 fpu and sse state, each thread has its own copy which is saved and restored with
+// fxsave/fxrstor on every context switch (see `context_switch`)
+// the pkru register (see `pku`) goes with it, fxsave doesn't have it but the access rights a
+// thread gave itself are just as much its own as its sse registers
+// the kernel itself is built without sse or x87 (x86_64-unknown-none is soft-float) so the
+// registers still hold the interrupted thread's state when we save them
+
+use core::{arch::asm, fmt};
+
+use super::{
+    cpu::{self, CpuFeatures},
+    pku,
+};
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct FpuState {
+    /// the area fxsave writes to, it must be 16 bytes aligned
+    area: [u8; 512],
+    /// only saved and restored if protection keys are enabled
+    pkru: u32,
+}
+
+impl FpuState {
+    /// the state after `fninit` with all sse exceptions masked and every protection key
+    /// accessible
+    pub const fn new() -> Self {
+        let mut area = [0; 512];
+
+        // fcw = 0x37F
+        area[0] = 0x7F;
+        area[1] = 0x03;
+        // mxcsr = 0x1F80
+        area[24] = 0x80;
+        area[25] = 0x1F;
+
+        Self { area, pkru: 0 }
+    }
+
+    /// saves the current fpu, sse and pkru registers into self
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe { asm!("fxsave [{}]", in(reg) self.area.as_mut_ptr(), options(nostack)) }
+        if let Some(pkru) = pku::read_pkru() {
+            self.pkru = pkru;
+        }
+    }
+
+    /// loads the fpu, sse and pkru registers from self
+    #[inline]
+    pub fn restore(&self) {
+        unsafe { asm!("fxrstor [{}]", in(reg) self.area.as_ptr(), options(nostack)) }
+        _ = pku::write_pkru(self.pkru);
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FpuState").finish_non_exhaustive()
+    }
+}
+
+/// resets the fpu and sse registers to `FpuState::new`, `cpu::enable_features` must have enabled
+/// sse first so fxsave, fxrstor and the sse instructions don't fault
+pub fn init() {
+    assert!(
+        cpu::enabled().contains(CpuFeatures::SSE),
+        "fpu: sse isn't enabled"
+    );
+    unsafe { asm!("fninit") };
+
+    FpuState::new().restore();
+}
@@ -1,4 +1,9 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    ptr::{addr_of, addr_of_mut},
+};
+
+use crate::VirtAddr;
 
 use lazy_static::lazy_static;
 
@@ -53,6 +58,7 @@ impl GDTEntry {
 const ACCESS_WRITE_READ: u8 = 1 << 1;
 const ACCESS_EXECUTABLE: u8 = 1 << 3;
 const NON_SYSTEM: u8 = 1 << 4;
+const ACCESS_DPL_USER: u8 = 3 << 5;
 const ACCESS_VAILD: u8 = 1 << 7;
 
 const ACCESS_TYPE_TSS: u8 = 0x9;
@@ -87,21 +93,38 @@ impl TaskStateSegment {
     }
 }
 
-lazy_static! {
-    pub static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[0] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = unsafe { &STACK.as_ptr() };
-            let stack_end = unsafe { stack_start.add(STACK_SIZE) };
-            stack_end as u64
-        };
-        tss
-    };
+const IST_STACK_SIZE: usize = 4096 * 5;
+/// the stack of `TSS.interrupt_stack_table[0]`
+static mut IST_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+/// only written with interrupts disabled, by `init_gdt` and then by `set_kernel_stack`
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// sets the stack the cpu switches to when an interrupt comes from ring 3 (rsp0), the context
+/// switch sets it to the kernel stack of every thread it switches to
+pub fn set_kernel_stack(stack_end: VirtAddr) {
+    unsafe {
+        let tss = &mut *addr_of_mut!(TSS);
+        let mut privilege_stack_table = tss.privilege_stack_table;
+        privilege_stack_table[0] = stack_end.as_u64();
+        tss.privilege_stack_table = privilege_stack_table;
+    }
+}
+
+/// see `set_kernel_stack`
+pub fn kernel_stack() -> VirtAddr {
+    let privilege_stack_table = unsafe { (*addr_of!(TSS)).privilege_stack_table };
+    VirtAddr::new(privilege_stack_table[0] as usize)
 }
-pub type GDTType = [GDTEntry; 5];
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+/// index 5 with rpl 3
+pub const USER_DATA_SELECTOR: u16 = 0x28 | 3;
+/// index 6 with rpl 3
+pub const USER_CODE_SELECTOR: u16 = 0x30 | 3;
+
+pub type GDTType = [GDTEntry; 7];
 
 lazy_static! {
     pub static ref GDT: GDTType = [
@@ -120,14 +143,25 @@ lazy_static! {
         ), // kernel data segment
 
         GDTEntry::new(
-            (((&*TSS) as *const TaskStateSegment as u64) & 0xFFFFFFFF) as u32,
+            ((addr_of!(TSS) as u64) & 0xFFFFFFFF) as u32,
             (size_of::<TaskStateSegment>() - 1) as u32,
             ACCESS_VAILD | ACCESS_TYPE_TSS,
             FLAG_PAGELIMIT | FLAG_LONG
         ), // TSS segment
-        GDTEntry::new_upper_64seg(
-            &*TSS as *const TaskStateSegment as u64,
-        )
+        GDTEntry::new_upper_64seg(addr_of!(TSS) as u64),
+
+        GDTEntry::new(
+            0,
+            0xFFFFF,
+            ACCESS_VAILD | ACCESS_WRITE_READ | NON_SYSTEM | ACCESS_DPL_USER,
+            FLAG_PAGELIMIT | FLAG_LONG
+        ), // user data segment
+        GDTEntry::new(
+            0,
+            0xFFFFF,
+            ACCESS_VAILD | NON_SYSTEM | ACCESS_WRITE_READ | ACCESS_EXECUTABLE | ACCESS_DPL_USER,
+            FLAG_PAGELIMIT | FLAG_LONG
+        ) // user code segment
     ];
 }
 #[repr(C, packed)]
@@ -144,6 +178,9 @@ lazy_static! {
 
 pub fn init_gdt() {
     unsafe {
+        let ist_stack_end = addr_of!(IST_STACK) as u64 + IST_STACK_SIZE as u64;
+        (*addr_of_mut!(TSS)).interrupt_stack_table = [ist_stack_end, 0, 0, 0, 0, 0, 0];
+
         asm!("lgdt [{}]", in(reg) &*GDT_DESCRIPTOR, options(nostack));
 
         asm!(
@@ -1,10 +1,23 @@
-use super::read_msr;
+use core::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU8, Ordering};
+
 use bitflags::bitflags;
+use lazy_static::lazy_static;
 
 use crate::{
-    arch::x86_64::acpi::{self, MADT},
-    memory::identity_map_writeable,
-    VirtAddr,
+    arch::x86_64::{
+        acpi::{self, MADT},
+        msr::{self, IA32_APIC_BASE, IA32_TSC_DEADLINE},
+        rdtsc, serial, tsc,
+    },
+    drivers::pit,
+    log,
+    memory::{
+        mmio::{RegBlock, RegOffset},
+        paging::PAGE_SIZE,
+        vmm::map_mmio,
+    },
+    time::Duration,
+    PhysAddr, VirtAddr,
 };
 
 #[repr(C, packed)]
@@ -34,17 +47,13 @@ bitflags! {
         const LEVEL_TRIGGERED = 1 << 7;
         const DISABLED = 1 << 8;
         const TIMER_PERIODIC = 1 << 9;
+        const TIMER_TSC_DEADLINE = 1 << 10;
     }
 }
 
 #[inline]
 pub fn send_eoi() {
-    unsafe {
-        let address = get_local_apic_addr();
-        let eoi_reg = get_local_apic_reg(address, 0xB0);
-        let eoi_reg = eoi_reg as *mut u32;
-        *eoi_reg = 0;
-    }
+    local_apic().write(LOCAL_APIC_EOI, 0);
 }
 
 #[repr(C, packed)]
@@ -57,35 +66,55 @@ pub struct MADTIOApic {
     global_system_interrupt_base: u32,
 }
 
+/// maps the ioapic registers returning their address
 #[inline]
 pub fn get_io_apic_addr(madt: &MADT) -> VirtAddr {
     unsafe {
         let record = madt.get_record_of_type(1).unwrap() as *const MADTIOApic;
         let addr = (*record).ioapic_address;
-        identity_map_writeable(addr as usize);
-        addr as VirtAddr
+        map_mmio(PhysAddr::new(addr as usize), PAGE_SIZE).expect("failed to map the ioapic")
     }
 }
 
+lazy_static! {
+    /// the local apic registers are mapped once on first use
+    static ref LOCAL_APIC_ADDR: VirtAddr = {
+        let address = PhysAddr::new(msr::read(IA32_APIC_BASE) as usize & 0xFFFFF000);
+        map_mmio(address, PAGE_SIZE).expect("failed to map the local apic")
+    };
+}
+
 #[inline]
 pub fn get_local_apic_addr() -> VirtAddr {
-    let address = read_msr(0x1B) & 0xFFFFF000;
-
-    identity_map_writeable(address);
-    address
+    *LOCAL_APIC_ADDR
 }
 
+/// the registers of the local apic
 #[inline]
-pub fn get_local_apic_reg(local_apic_addr: VirtAddr, local_apic_reg: u16) -> VirtAddr {
-    local_apic_addr + local_apic_reg as usize
+pub fn local_apic() -> RegBlock {
+    unsafe { RegBlock::new(get_local_apic_addr()) }
 }
 
+const LOCAL_APIC_ID: RegOffset<u32> = RegOffset::new(0x20);
+const LOCAL_APIC_EOI: RegOffset<u32> = RegOffset::new(0xB0);
+const LOCAL_APIC_SIVR: RegOffset<u32> = RegOffset::new(0xF0);
+const LOCAL_APIC_ICR_LOW: RegOffset<u32> = RegOffset::new(0x300);
+const LOCAL_APIC_ICR_HIGH: RegOffset<u32> = RegOffset::new(0x310);
+const LOCAL_APIC_LVT_TIMER: RegOffset<u32> = RegOffset::new(0x320);
+const LOCAL_APIC_TIMER_INIT: RegOffset<u32> = RegOffset::new(0x380);
+const LOCAL_APIC_TIMER_CURRENT: RegOffset<u32> = RegOffset::new(0x390);
+const LOCAL_APIC_TIMER_DIVIDE: RegOffset<u32> = RegOffset::new(0x3E0);
+
+const IOAPIC_REGSEL: RegOffset<u32> = RegOffset::new(0);
+const IOAPIC_WIN: RegOffset<u32> = RegOffset::new(0x10);
+
 // NOTES:
 // when we write the offset of the reg we want to access to ioregsel, iowin should have that reg
 // no it is not the addr of that reg it is the reg itself each reg is 32bits long
 pub unsafe fn write_ioapic_val_to_reg(ioapic_addr: VirtAddr, reg: u8, val: u32) {
-    *(ioapic_addr as *mut u32) = reg as u32;
-    *((ioapic_addr + 0x10) as *mut u32) = val;
+    let ioapic = RegBlock::new(ioapic_addr);
+    ioapic.write(IOAPIC_REGSEL, reg as u32);
+    ioapic.write(IOAPIC_WIN, val);
 }
 
 // pub unsafe fn read_ioapic_reg(ioapic_addr: VirtAddr, reg: u8) -> u32 {
@@ -155,31 +184,227 @@ fn enable_apic_keyboard(ioapic_addr: VirtAddr, apic_id: u8) {
     }
 }
 
-fn enable_apic_timer(local_apic_addr: VirtAddr) {
-    let timer = LVTEntry::new(0x20, LVTEntryFlags::TIMER_PERIODIC);
+/// IRQ4, COM1 transmits through it from then on
+fn enable_apic_serial(ioapic_addr: VirtAddr, apic_id: u8) {
+    unsafe {
+        let serial = IOREDTBL::new(LVTEntry::new(0x24, LVTEntryFlags::empty()), apic_id);
 
-    let addr = get_local_apic_reg(local_apic_addr, 0x320) as *mut u32;
-    let init = get_local_apic_reg(local_apic_addr, 0x380) as *mut u32;
-    let divide = get_local_apic_reg(local_apic_addr, 0x3E0) as *mut u8;
+        write_ioapic_irq(ioapic_addr, 4, serial);
+    }
+    serial::COM1.enable_transmit_interrupt();
+}
 
-    unsafe {
-        core::ptr::write_volatile(addr, timer.encode_u32());
-        core::ptr::write_volatile(divide, 0xB);
-        core::ptr::write_volatile(init, 0xFFFFFF);
+// the timer fires the scheduler's tick (vector 0x20), see `TickSource` for where it comes from,
+// the periodic apic timer counts down from an initial count at the bus frequency which is
+// measured against the pit so a tick is `TIMER_TICK` long, cpus with tsc deadline mode (cpuid
+// tsc-deadline) fire once the tsc reaches the value written to `IA32_TSC_DEADLINE` instead, that
+// is used when there and rearmed `TIMER_TICK` later by every tick (see `rearm_timer`)
+// the deadline is compared to the same tsc `time::Instant` reads so an `Instant` is a deadline
+// as is (`Instant::ticks`), only turning a `Duration` into ticks goes through the calibrated
+// `tsc::frequency` (if the calibration failed the timer still fires but not on time), this needs
+// an invariant tsc like the monotonic clock does
+// a tickless scheduler can arm exactly its next deadline with `arm_deadline` instead
+
+/// what fires the tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+    /// the local apic timer in tsc deadline mode
+    TscDeadline,
+    /// the local apic timer in periodic mode
+    Apic,
+    /// the pit on isa irq 0, the fallback for machines with neither tsc deadline mode nor an
+    /// hpet (the periodic apic timer is only trusted next to one)
+    Pit,
+}
+
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Apic as u8);
+
+/// what fires the tick, set once by `enable_apic_interrupts`
+#[inline]
+pub fn tick_source() -> TickSource {
+    match TICK_SOURCE.load(Ordering::Relaxed) {
+        0 => TickSource::TscDeadline,
+        2 => TickSource::Pit,
+        _ => TickSource::Apic,
     }
 }
 
+/// the time between two ticks in tsc deadline mode
+pub const TIMER_TICK: Duration = Duration::from_millis(10);
+
+/// wether or not the local apic timer has a tsc deadline mode (cpuid tsc-deadline)
+pub fn has_tsc_deadline() -> bool {
+    let features = unsafe { core::arch::x86_64::__cpuid(1) };
+    features.ecx & (1 << 24) != 0
+}
+
+/// wether or not the timer runs in tsc deadline mode
+#[inline]
+pub fn tsc_deadline_mode() -> bool {
+    tick_source() == TickSource::TscDeadline
+}
+
+/// fires the timer once the tsc reaches `tsc` (now if it already did), replaces the last
+/// deadline and 0 disarms the timer, only for the tsc deadline mode
+#[inline]
+pub fn arm_deadline(tsc: u64) {
+    debug_assert!(tsc_deadline_mode(), "arm_deadline: the timer is periodic");
+    msr::write(IA32_TSC_DEADLINE, tsc);
+}
+
+/// arms the next tick in tsc deadline mode, called by every tick, the periodic timer rearms
+/// itself
+#[inline]
+pub fn rearm_timer() {
+    if tsc_deadline_mode() {
+        arm_deadline(rdtsc() + tsc::ns_to_ticks(TIMER_TICK.as_nanos() as u64));
+    }
+}
+
+/// the initial count that makes the periodic timer fire every `TIMER_TICK`, measured against
+/// the pit with the timer counting down once, None if the pit doesn't answer
+fn calibrate_apic_timer(local_apic: RegBlock) -> Option<u32> {
+    const CALIBRATION_MS: u64 = 10;
+
+    let masked = LVTEntry::new(0x20, LVTEntryFlags::DISABLED);
+    local_apic.write(LOCAL_APIC_LVT_TIMER, masked.encode_u32());
+    local_apic.write(LOCAL_APIC_TIMER_DIVIDE, 0xB);
+    local_apic.write(LOCAL_APIC_TIMER_INIT, u32::MAX);
+
+    let counted = pit::reference(CALIBRATION_MS, || local_apic.read(LOCAL_APIC_TIMER_CURRENT));
+    local_apic.write(LOCAL_APIC_TIMER_INIT, 0);
+    let (start, end) = counted?;
+
+    let per_ms = start.checked_sub(end)? as u64 / CALIBRATION_MS;
+    let count = per_ms * TIMER_TICK.as_nanos() as u64 / 1_000_000;
+    (count != 0).then(|| count.min(u32::MAX as u64) as u32)
+}
+
+/// isa irq 0 as the tick, the apic timer stays masked
+unsafe fn enable_pit_timer(
+    local_apic: RegBlock,
+    madt: &MADT,
+    ioapic_addr: VirtAddr,
+    apic_id: u8,
+) -> Result<(), ()> {
+    let hz = 1_000_000_000 / TIMER_TICK.as_nanos() as u64;
+    pit::set_periodic(hz)?;
+
+    local_apic.write(
+        LOCAL_APIC_LVT_TIMER,
+        LVTEntry::new(0x20, LVTEntryFlags::DISABLED).encode_u32(),
+    );
+
+    let pit = IOREDTBL::new(LVTEntry::new(0x20, LVTEntryFlags::empty()), apic_id);
+    write_ioapic_irq(ioapic_addr, madt.isa_irq_gsi(0) as u8, pit);
+    Ok(())
+}
+
+unsafe fn enable_apic_timer(local_apic: RegBlock, madt: &MADT, ioapic_addr: VirtAddr, apic_id: u8) {
+    if has_tsc_deadline() {
+        let timer = LVTEntry::new(0x20, LVTEntryFlags::TIMER_TSC_DEADLINE);
+        local_apic.write(LOCAL_APIC_LVT_TIMER, timer.encode_u32());
+        // the mode has to be set before the msr is written or the write is ignored
+        atomic::fence(Ordering::SeqCst);
+
+        TICK_SOURCE.store(TickSource::TscDeadline as u8, Ordering::Relaxed);
+        rearm_timer();
+        log!("apic: the timer is in tsc deadline mode\n");
+        return;
+    }
+
+    if !acpi::has_hpet() && enable_pit_timer(local_apic, madt, ioapic_addr, apic_id).is_ok() {
+        TICK_SOURCE.store(TickSource::Pit as u8, Ordering::Relaxed);
+        log!("apic: no hpet, the pit drives the tick\n");
+        return;
+    }
+
+    let count = calibrate_apic_timer(local_apic).unwrap_or_else(|| {
+        log!("apic: the pit didn't answer, the timer isn't calibrated\n");
+        0xFFFFFF
+    });
+
+    let timer = LVTEntry::new(0x20, LVTEntryFlags::TIMER_PERIODIC);
+    local_apic.write(LOCAL_APIC_LVT_TIMER, timer.encode_u32());
+    local_apic.write(LOCAL_APIC_TIMER_DIVIDE, 0xB);
+    local_apic.write(LOCAL_APIC_TIMER_INIT, count);
+}
+
 pub fn enable_apic_interrupts() {
-    let local_apic_addr = get_local_apic_addr();
-    let sivr = get_local_apic_reg(local_apic_addr, 0xF0) as *mut u32;
+    let local_apic = local_apic();
+    local_apic.write(LOCAL_APIC_SIVR, 0x1ff);
 
     unsafe {
-        core::ptr::write_volatile(sivr, 0x1ff);
-
         let madt = MADT::get(acpi::get_sdt());
         let ioapic_addr = get_io_apic_addr(madt);
-        let apic_id = *(get_local_apic_reg(local_apic_addr, 0x20) as *const u8);
-        enable_apic_timer(local_apic_addr);
+        let apic_id = local_apic_id() as u8;
+        enable_apic_timer(local_apic, madt, ioapic_addr, apic_id);
         enable_apic_keyboard(ioapic_addr, apic_id);
+        enable_apic_serial(ioapic_addr, apic_id);
+    }
+
+    APIC_ENABLED.store(true, Ordering::Release);
+}
+
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// set once `enable_apic_interrupts` is done, ipis can't be sent before that
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+/// set by `halt_others`, every cpu but `HALTING_CPU` halts on its next nmi
+static HALTING: AtomicBool = AtomicBool::new(false);
+/// the local apic id of the cpu that called `halt_others`
+static HALTING_CPU: AtomicU32 = AtomicU32::new(0);
+
+/// the local apic id of the current cpu
+#[inline]
+pub fn local_apic_id() -> u32 {
+    local_apic().read(LOCAL_APIC_ID) >> 24
+}
+
+/// the local apic ids of the cpus the madt says the firmware enabled
+pub fn madt_local_apic_ids() -> impl Iterator<Item = u32> {
+    MADT::get(acpi::get_sdt())
+        .local_apic_ids()
+        .map(|id| id as u32)
+}
+
+/// stops every other cpu by sending them an nmi (see `should_halt`) so only the calling cpu keeps
+/// running, used by the panic handler
+/// does nothing if the apic isn't enabled yet or if it was already called, a panic while sending
+/// the ipi calls it again
+pub fn halt_others() {
+    // the swap lets only the first of two cpus panicking at once send the ipi, the other one is
+    // halted by it
+    if !APIC_ENABLED.load(Ordering::Acquire) || HALTING.swap(true, Ordering::AcqRel) {
+        return;
     }
+
+    // stored before the ipi is sent, the nmi handlers read it after it
+    HALTING_CPU.store(local_apic_id(), Ordering::Release);
+
+    let local_apic = local_apic();
+    // the destination is ignored with a shorthand
+    local_apic.write(LOCAL_APIC_ICR_HIGH, 0);
+    local_apic.write(
+        LOCAL_APIC_ICR_LOW,
+        ICR_DELIVERY_NMI | ICR_LEVEL_ASSERT | ICR_ALL_EXCLUDING_SELF,
+    );
+
+    // don't hang the panic if the ipi is never accepted
+    for _ in 0..100_000 {
+        if local_apic.read(LOCAL_APIC_ICR_LOW) & ICR_SEND_PENDING == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// wether or not the current cpu received an nmi because another cpu called `halt_others`
+#[inline]
+pub fn should_halt() -> bool {
+    HALTING.load(Ordering::Acquire) && local_apic_id() != HALTING_CPU.load(Ordering::Acquire)
 }
@@ -1,13 +1,15 @@
 use lazy_static::lazy_static;
 
-use super::idt::{GateDescriptor, IDTT};
-use super::{InterruptFrame, TrapFrame};
-
-use crate::arch::x86_64::interrupts::apic::send_eoi;
-use crate::arch::x86_64::{inb, threading};
-use crate::{drivers, println};
-const ATTR_TRAP: u8 = 0xF;
-const ATTR_INT: u8 = 0xE;
+use super::idt::{GateDescriptor, ATTR_INT, ATTR_TRAP, ATTR_USER, IDTT};
+use super::{count, InterruptFrame, TrapFrame};
+
+use crate::arch::x86_64::interrupts::apic::{self, send_eoi};
+use crate::arch::x86_64::{backtrace, extable, inb, ps2, threading};
+use crate::memory::paging::{current_root_table, Page};
+use crate::memory::{hexdump, user};
+use crate::threading::softirq;
+use crate::utils::Locked;
+use crate::{cross_println, drivers, println, serial, terminal, terminal_inited, VirtAddr};
 const EMPTY_TABLE: IDTT = [GateDescriptor::default(); 256]; // making sure it is made at compile-time
 
 macro_rules! create_idt {
@@ -33,44 +35,183 @@ macro_rules! create_idt {
 }
 
 lazy_static! {
-    pub static ref IDT: IDTT = create_idt!(
+    /// the vectors known at compile time, drivers add theirs with `idt::set_handler`
+    pub static ref IDT: Locked<IDTT> = Locked::new(create_idt!(
         (0, divide_by_zero_handler, ATTR_INT),
+        (2, nmi_handler, ATTR_INT),
         (3, breakpoint_handler, ATTR_INT),
         (8, dobule_fault_handler, ATTR_TRAP, 0),
         (13, general_protection_fault_handler, ATTR_TRAP),
         (14, page_fault_handler, ATTR_TRAP),
         (0x20, threading::context_switch_stub, ATTR_INT),
-        (0x21, keyboard_interrupt_handler, ATTR_INT)
-    );
+        (0x21, keyboard_interrupt_handler, ATTR_INT),
+        (0x24, serial_interrupt_handler, ATTR_INT),
+        (0x80, threading::syscall_stub, ATTR_INT | ATTR_USER)
+    ));
 }
 
 extern "x86-interrupt" fn divide_by_zero_handler(frame: InterruptFrame) {
-    panic!("divide by zero exception\nframe: {:#?}", frame);
+    count(0);
+    let rip = VirtAddr::new(frame.insturaction as usize);
+    panic!(
+        "divide by zero exception at {:#x} <{}>\nframe: {:#?}",
+        rip,
+        backtrace::symbol_name(rip),
+        frame
+    );
+}
+
+extern "x86-interrupt" fn nmi_handler(frame: InterruptFrame) {
+    count(2);
+    // another cpu panicked, this one stops here so it doesn't mess with the panic output
+    if apic::should_halt() {
+        loop {
+            unsafe { core::arch::asm!("cli; hlt") }
+        }
+    }
+
+    serial!("nmi at {:#x}\n", { frame.insturaction });
 }
 
 extern "x86-interrupt" fn breakpoint_handler(frame: InterruptFrame) {
+    count(3);
     println!("hi from interrupt, breakpoint!, {:#?}", frame);
 }
 
+/// the address the last page fault accessed
+#[inline]
+fn read_cr2() -> VirtAddr {
+    let addr: usize;
+    unsafe {
+        core::arch::asm!("mov {}, cr2", out(reg) addr, options(nomem, nostack, preserves_flags))
+    };
+    VirtAddr::new(addr)
+}
+
+/// runs on its own stack (ist 1) so a stack overflow still gets here, the first fault is often a
+/// page fault on the guard page or a fault in another exception handler, so cr2 and the stack of
+/// the faulting code are reported along with the frame
 extern "x86-interrupt" fn dobule_fault_handler(frame: TrapFrame) {
-    panic!("double fault exception\nframe: {:#?}", frame);
+    count(8);
+    let rip = VirtAddr::new(frame.insturaction as usize);
+    let rsp = VirtAddr::new(frame.stack_pointer as usize);
+    let cr2 = read_cr2();
+
+    // the handler's frame starts with the rbp of the code it interrupted (frame pointers are
+    // forced), `capture_from` doesn't trust it
+    let fp: usize;
+    unsafe {
+        core::arch::asm!("mov {}, [rbp]", out(reg) fp, options(readonly, nostack, preserves_flags))
+    };
+
+    cross_println!(
+        "double fault at {:#x} <{}> with rsp {:#x}, cr2 (the last page fault) is {:#x}",
+        rip,
+        backtrace::symbol_name(rip),
+        rsp,
+        cr2
+    );
+    cross_println!("stack trace of the faulting code:");
+    backtrace::print_frames(backtrace::capture_from(VirtAddr::new(fp), rsp));
+
+    panic!(
+        "double fault exception at {:#x} <{}> (error code {:#x}, rsp {:#x}, cr2 {:#x})\nframe: {:#?}",
+        rip,
+        backtrace::symbol_name(rip),
+        { frame.error_code },
+        rsp,
+        cr2,
+        frame
+    );
 }
 
 extern "x86-interrupt" fn general_protection_fault_handler(frame: TrapFrame) {
-    panic!("general protection fault\nframe: {:#?}", frame);
+    count(13);
+    let rip = VirtAddr::new(frame.insturaction as usize);
+    panic!(
+        "general protection fault at {:#x} <{}>\nframe: {:#?}",
+        rip,
+        backtrace::symbol_name(rip),
+        frame
+    );
 }
 
-extern "x86-interrupt" fn page_fault_handler(frame: TrapFrame) {
-    panic!("page fault exception\nframe: {:#?}", frame)
+/// the one handler taking an error code that returns, the error code is a separate argument so
+/// the compiler pops it before iretq, a fault on a user page at an instruction of the exception
+/// table (see `extable`) resumes at its fixup and anything else panics
+extern "x86-interrupt" fn page_fault_handler(mut frame: InterruptFrame, error_code: u64) {
+    count(14);
+    let rip = VirtAddr::new(frame.insturaction as usize);
+    let addr = read_cr2();
+
+    // the kernel side of a copy faulting is still a bug
+    if addr < user::USER_END {
+        if let Some(fixup) = extable::search(rip) {
+            frame.set_instruction(fixup);
+            return;
+        }
+    }
+
+    let page = Page::containing_address(addr);
+    let entry = unsafe { current_root_table() }.get_entry(page);
+
+    // the bytes around the fault if they can be read, for example when writing to a read only
+    // page
+    let (dump_start, dump_len) = hexdump::around(addr);
+    if hexdump::first_unmapped(dump_start, dump_len).is_none() {
+        cross_println!("bytes around {:#x}:", addr);
+        _ = hexdump::hexdump(dump_start, dump_len);
+    }
+
+    match entry {
+        Some(entry) => panic!(
+            "page fault exception at {:#x} <{}> accessing {:#x} mapped as {}\nerror code: {:#x}, frame: {:#?}",
+            rip,
+            backtrace::symbol_name(rip),
+            addr,
+            entry,
+            error_code,
+            frame
+        ),
+        None => panic!(
+            "page fault exception at {:#x} <{}> accessing unmapped {:#x}\nerror code: {:#x}, frame: {:#?}",
+            rip,
+            backtrace::symbol_name(rip),
+            addr,
+            error_code,
+            frame
+        ),
+    }
 }
 
 #[inline]
 pub fn handle_ps2_keyboard() {
-    let key = inb(0x60);
-    drivers::keyboard::encode_ps2_set_1(key);
+    let key = inb(ps2::DATA_PORT);
+    drivers::keyboard::handle_byte(key);
 }
 
 pub extern "x86-interrupt" fn keyboard_interrupt_handler() {
+    count(0x21);
     handle_ps2_keyboard();
+    // wakes the softirq thread, the scancodes that don't get encoded now are the next time
+    _ = softirq::raise(drivers::keyboard::encode_pending);
+    send_eoi();
+}
+
+pub extern "x86-interrupt" fn serial_interrupt_handler() {
+    count(0x24);
+    // the ioapic line is edge triggered, a cause left pending keeps it asserted and no other
+    // interrupt would come so this runs until there is none
+    loop {
+        while serial::serial_received() {
+            drivers::serial::push_byte(serial::read_serial());
+        }
+        serial::COM1.handle_transmit_interrupt();
+
+        if !serial::COM1.interrupt_pending() {
+            break;
+        }
+    }
+    crate::threading::timer::wake();
     send_eoi();
 }
@@ -1,13 +1,55 @@
-use super::handlers::IDT;
+// the idt is built at compile time by `create_idt!` (see `handlers`) for the exceptions and the
+// vectors everything uses, drivers that come later claim a vector from `DYNAMIC_VECTORS` with
+// `alloc_vector` and install their handler with `set_handler`, `free_vector` gives it back
+// the table is written in place, the cpu reads it on every interrupt so it is only loaded once
+// by `load` at boot (see `init_idt`), before that the cpu has whatever the bootloader left
+
+use core::{
+    arch::asm,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{handlers::IDT, InterruptFrame};
 use lazy_static::lazy_static;
 pub type IDTT = [GateDescriptor; 256];
 
+pub const ATTR_TRAP: u8 = 0xF;
+pub const ATTR_INT: u8 = 0xE;
+/// allows ring 3 to call the handler using `int`
+pub const ATTR_USER: u8 = 3 << 5;
+
+/// below are the exceptions which only `create_idt!` sets
+pub const FIRST_IRQ_VECTOR: u8 = 0x20;
+/// the vectors `alloc_vector` hands out, 0x20-0x2F are the ones hardcoded for the timer and the
+/// ioapic irqs and 0xF0 and up are left for the apic (0xFF is the spurious interrupt)
+pub const DYNAMIC_VECTORS: RangeInclusive<u8> = 0x30..=0xEF;
+
+pub type Handler = extern "x86-interrupt" fn(InterruptFrame);
+
+/// the vectors `alloc_vector` returned, so a vector isn't given twice before its handler is set
+static CLAIMED: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+
+/// the idtr, what lidt loads and sidt stores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C, packed)]
 pub struct IDTDescriptor {
     limit: u16,
     base: usize,
 }
 
+impl IDTDescriptor {
+    #[inline]
+    pub const fn limit(&self) -> u16 {
+        self.limit
+    }
+
+    #[inline]
+    pub const fn base(&self) -> usize {
+        self.base
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
 pub struct GateDescriptor {
@@ -34,6 +76,12 @@ impl GateDescriptor {
         }
     }
 
+    /// wether or not the cpu calls a handler for this gate
+    #[inline]
+    pub const fn is_present(&self) -> bool {
+        self.attributes & 1 << 7 != 0
+    }
+
     pub const fn default() -> Self {
         Self {
             offset0: 0,
@@ -50,6 +98,80 @@ impl GateDescriptor {
 lazy_static! {
     pub static ref IDTDesc: IDTDescriptor = IDTDescriptor {
         limit: (size_of::<IDTT>() - 1) as u16,
-        base: (&*IDT).as_ptr() as usize
+        base: IDT.lock().as_ptr() as usize
     };
 }
+
+/// points the cpu at `IDT`, reading the idtr back to check it took in debug builds
+pub fn load() {
+    unsafe {
+        asm!("lidt [{}]", in(reg) &*IDTDesc, options(readonly, nostack, preserves_flags));
+    }
+    debug_assert_eq!(loaded(), *IDTDesc, "lidt didn't load the idt");
+}
+
+/// the idtr the cpu uses
+pub fn loaded() -> IDTDescriptor {
+    let mut idtr = IDTDescriptor { limit: 0, base: 0 };
+    unsafe {
+        asm!("sidt [{}]", in(reg) &mut idtr, options(nostack, preserves_flags));
+    }
+    idtr
+}
+
+/// wether or not `vector` has a handler
+pub fn is_set(vector: u8) -> bool {
+    IDT.lock()[vector as usize].is_present()
+}
+
+/// points `vector` at `handler` replacing the previous one, `attributes` are `ATTR_INT` or
+/// `ATTR_TRAP` maybe with `ATTR_USER`
+/// returns Err(()) if `vector` is an exception
+pub fn set_handler(vector: u8, handler: Handler, attributes: u8) -> Result<(), ()> {
+    if vector < FIRST_IRQ_VECTOR {
+        return Err(());
+    }
+
+    // interrupts are disabled meanwhile so the gate is never used half written
+    let mut idt = IDT.lock_exclusive();
+    idt[vector as usize] = GateDescriptor::new(handler as usize as u64, attributes);
+    Ok(())
+}
+
+/// claims a vector from `DYNAMIC_VECTORS` that has no handler, None if all are taken
+/// the vector isn't given again until `free_vector`, its handler should be set with `set_handler`
+pub fn alloc_vector() -> Option<u8> {
+    DYNAMIC_VECTORS.into_iter().find(|&vector| {
+        !is_set(vector)
+            && CLAIMED[vector as usize]
+                .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+    })
+}
+
+/// claims `vector` from `DYNAMIC_VECTORS` like `alloc_vector`, for the handlers raised with
+/// `int` which needs the vector as an immediate
+/// returns Err(()) if it isn't a dynamic vector or is already claimed or set
+pub fn claim_vector(vector: u8) -> Result<(), ()> {
+    if !DYNAMIC_VECTORS.contains(&vector) || is_set(vector) {
+        return Err(());
+    }
+
+    CLAIMED[vector as usize]
+        .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+        .map(|_| ())
+        .map_err(|_| ())
+}
+
+/// removes the handler of `vector` and gives it back to `alloc_vector`, the device behind it
+/// must not raise it anymore
+/// returns Err(()) if `vector` wasn't claimed with `alloc_vector`
+pub fn free_vector(vector: u8) -> Result<(), ()> {
+    if !DYNAMIC_VECTORS.contains(&vector) || !CLAIMED[vector as usize].load(Ordering::Relaxed) {
+        return Err(());
+    }
+
+    IDT.lock_exclusive()[vector as usize] = GateDescriptor::default();
+    CLAIMED[vector as usize].store(false, Ordering::Relaxed);
+    Ok(())
+}
@@ -1,13 +1,20 @@
 pub mod apic;
 pub mod handlers;
-mod idt;
+pub mod idt;
 
-use core::arch::asm;
-use idt::IDTDesc;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::PhysAddr;
+use crate::VirtAddr;
 
-#[derive(Debug)]
+use super::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+
+/// the frame the cpu pushes before calling an interrupt handler, it is the same frame iretq pops
+/// so writing to it before the handler returns changes where and how execution resumes (the
+/// writes must be volatile, the compiler doesn't know iretq reads the frame)
+#[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct InterruptFrame {
     pub insturaction: u64,
@@ -17,36 +24,113 @@ pub struct InterruptFrame {
     pub stack_segment: u64,
 }
 
+impl InterruptFrame {
+    pub const fn new() -> Self {
+        Self {
+            insturaction: 0,
+            code_segment: 0,
+            flags: 0,
+            stack_pointer: 0,
+            stack_segment: 0,
+        }
+    }
+
+    /// points the frame at `rip` with the stack `rsp` in ring 3 with interrupts enabled
+    pub fn set_entry(&mut self, rip: VirtAddr, rsp: VirtAddr) {
+        let frame = Self {
+            insturaction: rip.as_u64(),
+            code_segment: USER_CODE_SELECTOR as u64,
+            flags: 0x202,
+            stack_pointer: rsp.as_u64(),
+            stack_segment: USER_DATA_SELECTOR as u64,
+        };
+
+        unsafe { core::ptr::write_volatile(self, frame) }
+    }
+
+    /// makes iretq resume at `rip`
+    pub fn set_instruction(&mut self, rip: VirtAddr) {
+        let frame = Self {
+            insturaction: rip.as_u64(),
+            ..*self
+        };
+
+        unsafe { core::ptr::write_volatile(self, frame) }
+    }
+
+    /// wether or not iretq-ing this frame lands in ring 3 with valid user selectors
+    pub fn is_user(&self) -> bool {
+        self.code_segment == USER_CODE_SELECTOR as u64
+            && self.stack_segment == USER_DATA_SELECTOR as u64
+    }
+}
+
+/// launches ring 3 code by iretq-ing a fake interrupt frame (see `InterruptFrame::set_entry`),
+/// `arg` is passed in rdi
+/// the code and the stack must be mapped with `EntryFlags::USER_ACCESSIBLE` and
+/// rsp0 must point to a kernel stack for interrupts coming from ring 3, the context switch points
+/// it at the stack of the current thread (see `gdt::set_kernel_stack`)
+/// panics if the frame doesn't use the user selectors so we never iretq into ring 0 by mistake
+pub unsafe fn jump_to_usermode(frame: &InterruptFrame, arg: u64) -> ! {
+    assert!(
+        frame.is_user(),
+        "refusing to iretq into non user selectors {:#?}",
+        frame
+    );
+
+    asm!(
+        "
+        push [{frame} + 32] // ss
+        push [{frame} + 24] // rsp
+        push [{frame} + 16] // rflags
+        push [{frame} + 8]  // cs
+        push [{frame}]      // rip
+        iretq
+        ",
+        frame = in(reg) frame as *const InterruptFrame,
+        in("rdi") arg,
+        options(noreturn)
+    );
+}
+
+/// the same as `InterruptFrame` but for exceptions that push an error code, the cpu pushes it
+/// last so it comes first
+/// the handlers taking one never return, iretq would pop the error code as the rip (the page fault
+/// handler which returns takes the error code as its own argument instead)
 #[derive(Debug)]
 #[repr(C, packed)]
 pub struct TrapFrame {
+    pub error_code: u64,
     pub insturaction: u64,
     pub code_segment: u64,
     pub flags: u64,
     pub stack_pointer: u64,
     pub stack_segment: u64,
-    pub error_code: u64,
 }
 
-pub fn read_msr(msr: u32) -> PhysAddr {
-    let (low, high): (u32, u32);
-    unsafe {
-        asm!(
-            "
-            mov ecx, {0:e}
-            rdmsr
-            mov {1:e}, eax
-            mov {2:e}, edx
-            ", 
-            in(reg) msr, out(reg) low, out(reg) high
-        );
-    }
+/// how many times each vector fired since boot, the handlers bump their vector first thing so
+/// an interrupt storm shows up here (see `interrupt_stats`)
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// counts an interrupt on `vector`, cheap enough for every handler
+#[inline(always)]
+pub fn count(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
 
-    (high as usize) << 32 | (low as usize)
+/// how many times `vector` fired since boot
+#[inline]
+pub fn interrupt_count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// the vectors that fired at least once with how many times they did
+pub fn interrupt_stats() -> impl Iterator<Item = (u8, u64)> {
+    (0..=u8::MAX)
+        .map(|vector| (vector, interrupt_count(vector)))
+        .filter(|(_, count)| *count != 0)
 }
 
 pub fn init_idt() {
-    unsafe {
-        asm!("lidt [{}]", in(reg) &*IDTDesc, options(nostack));
-    }
+    idt::load();
 }
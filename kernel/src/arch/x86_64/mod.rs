@@ -1,9 +1,20 @@
 mod acpi;
-mod gdt;
+pub mod backtrace;
+pub mod cpu;
+pub mod extable;
+pub mod fpu;
+pub mod gdt;
 pub mod interrupts;
+pub mod msr;
+pub mod pat;
+pub mod pku;
 pub mod power;
+pub mod ps2;
+#[cfg(any(feature = "test", feature = "bench"))]
+pub mod qemu;
 pub mod serial;
 pub mod threading;
+pub mod tsc;
 
 use core::arch::asm;
 
@@ -12,6 +23,103 @@ use interrupts::{apic, init_idt};
 use serial::init_serial;
 
 use self::gdt::init_gdt;
+use super::Arch;
+use crate::{
+    memory::{
+        frame_allocator::Frame,
+        paging::{self, EntryFlags, MapToError, Page, PageTable},
+        phys_to_virt, PhysAddr,
+    },
+    time::Duration,
+};
+use threading::{restore_cpu_status, CPUStatus};
+
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    type Context = CPUStatus;
+
+    #[inline]
+    fn map(page: Page, frame: Frame, flags: EntryFlags) -> Result<(), MapToError> {
+        unsafe { Self::current_root_table() }.map_to(page, frame, flags)
+    }
+
+    #[inline]
+    unsafe fn flush(page: Page) {
+        paging::flush_page(page)
+    }
+
+    /// the pml4 in cr3
+    #[inline]
+    unsafe fn current_root_table() -> &'static mut PageTable {
+        let phys_addr: usize;
+        asm!("mov {}, cr3", out(reg) phys_addr);
+        let frame = Frame::containing_address(PhysAddr::new(phys_addr));
+
+        &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>()
+    }
+
+    #[inline]
+    fn write_combining() -> EntryFlags {
+        pat::write_combining()
+    }
+
+    #[inline]
+    fn enable_interrupts() {
+        unsafe { asm!("sti", options(nostack)) }
+    }
+
+    #[inline]
+    fn disable_interrupts() {
+        unsafe { asm!("cli", options(nostack)) }
+    }
+
+    #[inline]
+    fn interrupts_enabled() -> bool {
+        let rflags: u64;
+        unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+        // the interrupt flag
+        rflags & (1 << 9) != 0
+    }
+
+    unsafe fn switch_to(context: &CPUStatus) -> ! {
+        restore_cpu_status(context);
+        unreachable!("restore_cpu_status returned")
+    }
+
+    /// the tsc
+    #[inline]
+    fn counter() -> u64 {
+        rdtsc()
+    }
+
+    #[inline]
+    fn counter_ticks_to_ns(ticks: u64) -> u64 {
+        tsc::ticks_to_ns(ticks)
+    }
+
+    #[inline]
+    fn ns_to_counter_ticks(ns: u64) -> u64 {
+        tsc::ns_to_ticks(ns)
+    }
+
+    const TIMER_TICK: Duration = apic::TIMER_TICK;
+
+    #[inline]
+    fn timer_deadline_mode() -> bool {
+        apic::tsc_deadline_mode()
+    }
+
+    #[inline]
+    fn arm_timer_deadline(deadline: u64) {
+        apic::arm_deadline(deadline)
+    }
+
+    #[inline]
+    fn rearm_timer() {
+        apic::rearm_timer()
+    }
+}
 
 pub fn inb(port: u16) -> u8 {
     let value: u8;
@@ -41,12 +149,58 @@ pub fn inw(port: u16) -> u16 {
     value
 }
 
+pub fn outl(port: u16, value: u32) {
+    unsafe {
+        asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
+pub fn inl(port: u16) -> u32 {
+    let value;
+    unsafe {
+        asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// reads the time stamp counter
 #[inline]
-pub fn init() {
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// everything the cpu needs to run the kernel, doesn't depend on anything
+#[inline]
+pub fn init_cpu() {
     init_serial();
+    cpu::enable_features();
+    fpu::init();
+    pat::init();
+    pku::init();
     init_gdt();
     init_idt();
 
+    if tsc::calibrate().is_err() {
+        crate::serial!("tsc: the pit didn't answer, time will be off\n");
+    }
+}
+
+/// finds the acpi tables and enables the acpi, the tables are identity mapped with the frame
+/// allocator
+#[inline]
+pub fn init_acpi() {
     acpi::enable_acpi(FADT::get(get_sdt()));
+}
+
+/// the ps/2 controller and the apic which is found through the madt and mapped with the vmm
+#[inline]
+pub fn init_interrupts() {
+    if ps2::init().is_err() {
+        crate::serial!("ps/2: no usable controller, the keyboard won't work\n");
+    }
     apic::enable_apic_interrupts();
 }
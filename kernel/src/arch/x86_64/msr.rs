@@ -0,0 +1,50 @@
+// model specific registers, rdmsr and wrmsr take the msr in ecx and the value split in edx:eax
+// (high:low), everything accessing msrs should go through `read` and `write`
+
+use core::arch::asm;
+
+pub const IA32_APIC_BASE: u32 = 0x1B;
+/// the tsc value the local apic timer fires at in tsc deadline mode
+pub const IA32_TSC_DEADLINE: u32 = 0x6E0;
+pub const IA32_PAT: u32 = 0x277;
+/// extended features, bit 0 enables syscall/sysret and bit 11 the no execute bit
+pub const IA32_EFER: u32 = 0xC000_0080;
+/// the segments syscall and sysret load
+pub const IA32_STAR: u32 = 0xC000_0081;
+/// the address syscall jumps to
+pub const IA32_LSTAR: u32 = 0xC000_0082;
+/// the rflags bits syscall clears
+pub const IA32_FMASK: u32 = 0xC000_0084;
+pub const IA32_FS_BASE: u32 = 0xC000_0100;
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+/// the value swapgs swaps `IA32_GS_BASE` with
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// reads the msr `msr`
+/// faults if `msr` doesn't exist
+#[inline]
+pub fn read(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr, out("eax") low, out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    (high as u64) << 32 | low as u64
+}
+
+/// writes `value` to the msr `msr`
+/// faults if `msr` doesn't exist or `value` sets reserved bits
+#[inline]
+pub fn write(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// the page attribute table, the WRITE_THROUGH, NO_CACHE and PAT bits of a level 1 entry select
+// one of its 8 memory types
+// we keep the power-on layout except for entry 1 (WRITE_THROUGH alone) which becomes write
+// combining, write through memory is still available with the PAT bit (entry 5)
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::msr::{self, IA32_PAT};
+use crate::{memory::paging::EntryFlags, serial};
+
+const UNCACHEABLE: u64 = 0x00;
+const WRITE_COMBINING: u64 = 0x01;
+const WRITE_THROUGH: u64 = 0x04;
+const WRITE_BACK: u64 = 0x06;
+/// UC-, uncacheable but can be overridden by the mtrrs
+const UNCACHED: u64 = 0x07;
+
+/// the memory type of each entry, entry n is selected by PAT << 2 | NO_CACHE << 1 | WRITE_THROUGH
+const LAYOUT: [u64; 8] = [
+    WRITE_BACK,
+    WRITE_COMBINING,
+    UNCACHED,
+    UNCACHEABLE,
+    WRITE_BACK,
+    WRITE_THROUGH,
+    UNCACHED,
+    UNCACHEABLE,
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// wether or not the cpu has a pat (cpuid pat)
+fn supported() -> bool {
+    let features = unsafe { core::arch::x86_64::__cpuid(1) };
+    features.edx & (1 << 16) != 0
+}
+
+/// programs the pat with `LAYOUT`, does nothing if the cpu doesn't have one
+pub fn init() {
+    if !supported() {
+        serial!("pat: not supported, write combining falls back to uncached\n");
+        return;
+    }
+
+    let value = LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |value, (index, kind)| value | kind << (index * 8));
+
+    serial!("pat: 0x{:x} -> 0x{:x}\n", msr::read(IA32_PAT), value);
+    msr::write(IA32_PAT, value);
+
+    // nothing should be cached with the old types of entry 1
+    unsafe {
+        core::arch::asm!("wbinvd", options(nostack, preserves_flags));
+        crate::memory::paging::flush_all();
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// returns the flags that map a level 1 entry as write combining memory, uncached if there is
+/// no pat
+pub fn write_combining() -> EntryFlags {
+    if ENABLED.load(Ordering::Relaxed) {
+        EntryFlags::WRITE_THROUGH
+    } else {
+        EntryFlags::NO_CACHE
+    }
+}
@@ -0,0 +1,96 @@
+// protection keys for user pages, bits 59-62 of a level 1 entry of a user page (see
+// `EntryFlags::with_protection_key`) select one of 16 keys and the pkru register says for each
+// key if its pages can be read and written, so the access to every page of a key changes with a
+// single `write_pkru` instead of walking the page tables
+// pkru is checked for data accesses to user pages from any ring, not for instruction fetches or
+// supervisor pages, key 0 is what every page has by default so it is left accessible
+// all of this only exists if the cpu has pku and `cpu::enable_features` enabled CR4.PKE, the
+// pkru instructions fault otherwise so every helper checks `enabled` first
+// each thread has its own pkru, it is switched along the sse registers (see `fpu::FpuState`)
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::serial;
+
+use super::cpu::{self, CpuFeatures};
+
+pub const PROTECTION_KEYS: u8 = 16;
+
+/// the pkru bit that disables every access to the pages of `key`
+#[inline]
+pub const fn access_disable(key: u8) -> u32 {
+    1 << (key * 2)
+}
+
+/// the pkru bit that disables writes to the pages of `key`
+#[inline]
+pub const fn write_disable(key: u8) -> u32 {
+    1 << (key * 2 + 1)
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// makes every key accessible, does nothing if the cpu doesn't have protection keys for user
+/// pages (`cpu::enable_features` sets CR4.PKE when it does)
+pub fn init() {
+    if !cpu::enabled().contains(CpuFeatures::PKU) {
+        serial!("pku: not supported\n");
+        return;
+    }
+
+    // every key is accessible until told otherwise
+    ENABLED.store(true, Ordering::Relaxed);
+    _ = write_pkru(0);
+}
+
+/// wether or not `init` enabled protection keys
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// the pkru register, None if protection keys aren't enabled
+pub fn read_pkru() -> Option<u32> {
+    if !enabled() {
+        return None;
+    }
+
+    let pkru: u32;
+    unsafe {
+        asm!("rdpkru", in("ecx") 0, out("eax") pkru, out("edx") _, options(nomem, nostack));
+    }
+    Some(pkru)
+}
+
+/// replaces the pkru register with `pkru`, returns Err(()) if protection keys aren't enabled
+/// takes effect right away, there is no tlb to flush
+pub fn write_pkru(pkru: u32) -> Result<(), ()> {
+    if !enabled() {
+        return Err(());
+    }
+
+    unsafe {
+        asm!("wrpkru", in("eax") pkru, in("ecx") 0, in("edx") 0, options(nostack));
+    }
+    Ok(())
+}
+
+/// sets the access rights of the pages of `key` keeping the other keys as they are
+/// returns Err(()) if protection keys aren't enabled
+pub fn set_key_access(key: u8, readable: bool, writable: bool) -> Result<(), ()> {
+    debug_assert!(key < PROTECTION_KEYS, "there is no protection key {}", key);
+    let mut pkru = read_pkru().ok_or(())?;
+
+    pkru &= !(access_disable(key) | write_disable(key));
+    if !readable {
+        pkru |= access_disable(key);
+    }
+    if !writable {
+        pkru |= write_disable(key);
+    }
+
+    write_pkru(pkru)
+}
@@ -0,0 +1,177 @@
+// the 8042 ps/2 controller, we don't trust the firmware to leave it in a usable state so we
+// configure it ourselves before enabling interrupts
+// the keyboard driver expects scancode set 1, the keyboard talks in set 2 after a reset
+// so we keep the controller translation on
+
+use super::{inb, outb};
+use crate::{
+    serial,
+    time::{Duration, Instant},
+};
+
+pub const DATA_PORT: u16 = 0x60;
+/// reading gives the status register, writing sends a command to the controller
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const CONFIG_PORT_1_IRQ: u8 = 1;
+const CONFIG_PORT_2_IRQ: u8 = 1 << 1;
+const CONFIG_PORT_2_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_PORT_2: u8 = 0xA7;
+const COMMAND_ENABLE_PORT_2: u8 = 0xA8;
+const COMMAND_TEST_PORT_2: u8 = 0xA9;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_PORT_1: u8 = 0xAB;
+const COMMAND_DISABLE_PORT_1: u8 = 0xAD;
+const COMMAND_ENABLE_PORT_1: u8 = 0xAE;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const DEVICE_RESET: u8 = 0xFF;
+/// what the device answers every byte sent to it with
+pub const DEVICE_ACK: u8 = 0xFA;
+/// what the device answers with when it wants the last byte again
+pub const DEVICE_RESEND: u8 = 0xFE;
+const DEVICE_SELF_TEST_PASSED: u8 = 0xAA;
+
+/// how long we wait on the controller before giving up, there might be no controller at all
+const TIMEOUT: Duration = Duration::from_millis(50);
+/// the keyboard takes a lot longer to answer a reset since it runs its self test first
+const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[inline]
+fn status() -> u8 {
+    inb(COMMAND_PORT)
+}
+
+/// waits at most `timeout` until the status bits in `mask` are `expected`
+fn wait_status(mask: u8, expected: u8, timeout: Duration) -> Result<(), ()> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if status() & mask == expected {
+            return Ok(());
+        }
+    }
+
+    Err(())
+}
+
+/// waits until the controller can take a byte
+fn wait_input() -> Result<(), ()> {
+    wait_status(STATUS_INPUT_FULL, 0, TIMEOUT)
+}
+
+fn command(command: u8) -> Result<(), ()> {
+    wait_input()?;
+    outb(COMMAND_PORT, command);
+    Ok(())
+}
+
+/// waits at most `timeout` for a byte from the controller
+fn read_within(timeout: Duration) -> Result<u8, ()> {
+    wait_status(STATUS_OUTPUT_FULL, STATUS_OUTPUT_FULL, timeout)?;
+    Ok(inb(DATA_PORT))
+}
+
+#[inline]
+fn read() -> Result<u8, ()> {
+    read_within(TIMEOUT)
+}
+
+fn write(byte: u8) -> Result<(), ()> {
+    wait_input()?;
+    outb(DATA_PORT, byte);
+    Ok(())
+}
+
+/// sends `byte` to the device on port 1 (the keyboard), it answers with `DEVICE_ACK` or
+/// `DEVICE_RESEND` through its irq once it is initialized
+#[inline]
+pub fn send(byte: u8) -> Result<(), ()> {
+    write(byte)
+}
+
+fn read_config() -> Result<u8, ()> {
+    command(COMMAND_READ_CONFIG)?;
+    read()
+}
+
+fn write_config(config: u8) -> Result<(), ()> {
+    command(COMMAND_WRITE_CONFIG)?;
+    write(config)
+}
+
+/// drops whatever the controller has in its output buffer
+fn flush() {
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        inb(DATA_PORT);
+    }
+}
+
+/// resets the device on port 1 (the keyboard), returns Err(()) if it didn't pass its self test
+fn reset_keyboard() -> Result<(), ()> {
+    write(DEVICE_RESET)?;
+
+    if read()? != DEVICE_ACK || read_within(RESET_TIMEOUT)? != DEVICE_SELF_TEST_PASSED {
+        return Err(());
+    }
+
+    Ok(())
+}
+
+/// initializes the ps/2 controller and resets the keyboard, only the first port is enabled
+/// since we have no mouse driver
+/// returns Err(()) if there is no controller or it failed one of its tests
+pub fn init() -> Result<(), ()> {
+    command(COMMAND_DISABLE_PORT_1)?;
+    command(COMMAND_DISABLE_PORT_2)?;
+    flush();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_PORT_1_IRQ | CONFIG_PORT_2_IRQ);
+    config |= CONFIG_TRANSLATION;
+    write_config(config)?;
+
+    command(COMMAND_SELF_TEST)?;
+    if read()? != SELF_TEST_PASSED {
+        serial!("ps/2: controller self test failed\n");
+        return Err(());
+    }
+    // the self test may reset the controller
+    write_config(config)?;
+
+    // if the second port clock gets enabled it is a dual channel controller
+    command(COMMAND_ENABLE_PORT_2)?;
+    let dual_channel = read_config()? & CONFIG_PORT_2_CLOCK_DISABLED == 0;
+    command(COMMAND_DISABLE_PORT_2)?;
+
+    command(COMMAND_TEST_PORT_1)?;
+    if read()? != PORT_TEST_PASSED {
+        serial!("ps/2: port 1 test failed\n");
+        return Err(());
+    }
+
+    if dual_channel {
+        command(COMMAND_TEST_PORT_2)?;
+        if read()? != PORT_TEST_PASSED {
+            serial!("ps/2: port 2 test failed, ignoring it\n");
+        }
+    }
+
+    command(COMMAND_ENABLE_PORT_1)?;
+    if reset_keyboard().is_err() {
+        serial!("ps/2: keyboard reset failed\n");
+    }
+    flush();
+
+    write_config(config | CONFIG_PORT_1_IRQ)?;
+    serial!("ps/2: initialized, dual channel: {}\n", dual_channel);
+    Ok(())
+}
@@ -0,0 +1,22 @@
+// exiting qemu using the isa-debug-exit device, qemu has to be started with
+// `-device isa-debug-exit,iobase=0xf4,iosize=0x04` (see src/main.rs)
+// qemu exits with `(code << 1) | 1`, writing to the port does nothing on real hardware
+
+use super::{outb, serial};
+
+const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitCode {
+    /// qemu exits with 33
+    Success = 0x10,
+    /// qemu exits with 35
+    Failed = 0x11,
+}
+
+/// exits once what is queued for the serial ports is written
+pub fn exit(code: ExitCode) {
+    serial::synchronous();
+    outb(ISA_DEBUG_EXIT_PORT, code as u8);
+}
@@ -1,50 +1,333 @@
-use core::fmt::{self, Write};
+// the 16550 uarts at the standard com ports, each one is probed through its scratch register
+// when initialized since there is no reliable way to tell how many a machine has
+// COM1 is the console, `serial!` and the serial shell, and it is the only one that interrupts
+// (IRQ4), `log!` goes to COM2 if it is there (qemu's second `-serial`) and to COM1 otherwise
+// (see `logger`)
+// COM1 transmits through its interrupt once the ioapic routes it: `SerialPort::write` pushes the
+// bytes to a ring and enables the transmit holding register empty interrupt, the handler refills
+// the fifo from the ring and disables the interrupt once the ring is empty, so a writer only
+// waits for the uart if the ring is full
+// - with interrupts disabled nothing drains the ring so a writer finding it full writes a
+//   fifo's worth itself
+// - `synchronous` flushes the ring and goes back to polling, for the panic handler and qemu exit
 
-use super::{inb, outb};
+use core::{
+    arch::asm,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::{without_interrupts, Arch},
+    utils::ring_buffer::RingBuffer,
+};
+
+use super::{inb, outb, X86_64};
 
 pub const SERIAL_COM1_BASE: u16 = 0x3F8;
+pub const SERIAL_COM2_BASE: u16 = 0x2F8;
+pub const SERIAL_COM3_BASE: u16 = 0x3E8;
+pub const SERIAL_COM4_BASE: u16 = 0x2E8;
 
-const SERIAL_DATA_PORT: u16 = SERIAL_COM1_BASE;
-const SERIAL_FIFO_COMMAND_PORT: u16 = SERIAL_COM1_BASE + 2;
-const SERIAL_LINE_COMMAND_PORT: u16 = SERIAL_COM1_BASE + 3;
-const SERIAL_MODEM_COMMAND_PORT: u16 = SERIAL_COM1_BASE + 4;
-const SERIAL_LINE_STATUS_PORT: u16 = SERIAL_COM1_BASE + 5;
+// offsets from the base port
+const SERIAL_DATA_PORT: u16 = 0;
+const SERIAL_INTERRUPT_ENABLE_PORT: u16 = 1;
+const SERIAL_FIFO_COMMAND_PORT: u16 = 2;
+/// the fifo command port when read
+const SERIAL_INTERRUPT_ID_PORT: u16 = 2;
+const SERIAL_LINE_COMMAND_PORT: u16 = 3;
+const SERIAL_MODEM_COMMAND_PORT: u16 = 4;
+const SERIAL_LINE_STATUS_PORT: u16 = 5;
+const SERIAL_SCRATCH_PORT: u16 = 7;
 
 const SERIAL_LINE_ENABLE_DLAB: u8 = 0x80;
+const SERIAL_INTERRUPT_DATA_AVAILABLE: u8 = 0x01;
+const SERIAL_INTERRUPT_TRANSMIT_EMPTY: u8 = 0x02;
+/// bit 0 of the interrupt id is clear while an interrupt is pending
+const SERIAL_INTERRUPT_NONE_PENDING: u8 = 0x01;
+const SCRATCH_TEST_VALUE: u8 = 0xAE;
 
-pub fn init_serial() {
-    outb(SERIAL_LINE_COMMAND_PORT, SERIAL_LINE_ENABLE_DLAB);
-    outb(SERIAL_DATA_PORT, 0x01);
-    outb(SERIAL_DATA_PORT + 1, 0x00);
-    outb(SERIAL_LINE_COMMAND_PORT, 0x03);
-    outb(SERIAL_FIFO_COMMAND_PORT, 0xC7);
-    outb(SERIAL_MODEM_COMMAND_PORT, 0x0B);
+/// the bytes the 16550 takes at once when its transmit fifo is empty
+const TRANSMIT_FIFO_SIZE: usize = 16;
+pub const TRANSMIT_RING_SIZE: usize = 4096;
+
+static COM1_TRANSMIT: RingBuffer<u8, TRANSMIT_RING_SIZE> = RingBuffer::new();
+
+pub struct SerialPort {
+    base: u16,
+    present: AtomicBool,
+    /// the bytes waiting for the transmit interrupt, only the ports with an irq have one
+    transmit: Option<&'static RingBuffer<u8, TRANSMIT_RING_SIZE>>,
+    /// set once the irq is routed, see `Self::enable_transmit_interrupt`
+    interrupt_driven: AtomicBool,
+}
+
+/// assumed to be there until it is probed so what is printed before that isn't lost
+pub static COM1: SerialPort = SerialPort::new(SERIAL_COM1_BASE, true, Some(&COM1_TRANSMIT));
+pub static COM2: SerialPort = SerialPort::new(SERIAL_COM2_BASE, false, None);
+pub static COM3: SerialPort = SerialPort::new(SERIAL_COM3_BASE, false, None);
+pub static COM4: SerialPort = SerialPort::new(SERIAL_COM4_BASE, false, None);
+pub static PORTS: [&SerialPort; 4] = [&COM1, &COM2, &COM3, &COM4];
+
+impl SerialPort {
+    pub const fn new(
+        base: u16,
+        present: bool,
+        transmit: Option<&'static RingBuffer<u8, TRANSMIT_RING_SIZE>>,
+    ) -> Self {
+        Self {
+            base,
+            present: AtomicBool::new(present),
+            transmit,
+            interrupt_driven: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub const fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// wether or not the port passed the probe in `Self::init`
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// probes the port and sets it up as 115200 8N1 if it is there, returns wether or not it is
+    /// there, `interrupts` enables IRQs when a byte is received
+    pub fn init(&self, interrupts: bool) -> bool {
+        // nothing answers reads of a port without a uart
+        outb(self.base + SERIAL_SCRATCH_PORT, SCRATCH_TEST_VALUE);
+        if inb(self.base + SERIAL_SCRATCH_PORT) != SCRATCH_TEST_VALUE {
+            self.present.store(false, Ordering::Relaxed);
+            return false;
+        }
+
+        outb(
+            self.base + SERIAL_LINE_COMMAND_PORT,
+            SERIAL_LINE_ENABLE_DLAB,
+        );
+        outb(self.base + SERIAL_DATA_PORT, 0x01);
+        outb(self.base + SERIAL_DATA_PORT + 1, 0x00);
+        outb(self.base + SERIAL_LINE_COMMAND_PORT, 0x03);
+        outb(self.base + SERIAL_FIFO_COMMAND_PORT, 0xC7);
+        outb(self.base + SERIAL_MODEM_COMMAND_PORT, 0x0B);
+        outb(
+            self.base + SERIAL_INTERRUPT_ENABLE_PORT,
+            if interrupts {
+                SERIAL_INTERRUPT_DATA_AVAILABLE
+            } else {
+                0
+            },
+        );
+
+        self.present.store(true, Ordering::Relaxed);
+        true
+    }
+
+    pub fn received(&self) -> bool {
+        (inb(self.base + SERIAL_LINE_STATUS_PORT) & 0x01) != 0
+    }
+
+    /// reads a received byte, check `Self::received` first
+    pub fn read(&self) -> u8 {
+        inb(self.base + SERIAL_DATA_PORT)
+    }
+
+    pub fn is_transmit_fifo_empty(&self) -> bool {
+        (inb(self.base + SERIAL_LINE_STATUS_PORT) & 0x20) != 0
+    }
+
+    /// writes `byte` does nothing if the port isn't there so we don't wait forever for its
+    /// fifo, queues it if the port is interrupt driven
+    pub fn write(&self, byte: u8) {
+        if !self.is_present() {
+            return;
+        }
+
+        match self.ring() {
+            Some(ring) => {
+                self.queue(ring, byte);
+                self.set_transmit_interrupt(true);
+            }
+            None => self.write_polled(byte),
+        }
+    }
+
+    pub fn write_string(&self, s: &str) {
+        if !self.is_present() {
+            return;
+        }
+
+        match self.ring() {
+            Some(ring) => {
+                for byte in s.bytes() {
+                    self.queue(ring, byte);
+                }
+                self.set_transmit_interrupt(true);
+            }
+            None => {
+                for byte in s.bytes() {
+                    self.write_polled(byte);
+                }
+            }
+        }
+    }
+
+    /// writes `byte` once the fifo is empty, bypassing the ring
+    fn write_polled(&self, byte: u8) {
+        while !self.is_transmit_fifo_empty() {}
+        outb(self.base + SERIAL_DATA_PORT, byte);
+    }
+
+    /// the ring the writes go to, None while the port is polled
+    #[inline]
+    fn ring(&self) -> Option<&'static RingBuffer<u8, TRANSMIT_RING_SIZE>> {
+        self.transmit
+            .filter(|_| self.interrupt_driven.load(Ordering::Acquire))
+    }
+
+    /// pushes `byte` to `ring` waiting for room if it is full
+    fn queue(&self, ring: &RingBuffer<u8, TRANSMIT_RING_SIZE>, byte: u8) {
+        while ring.push(byte).is_err() {
+            if !X86_64::interrupts_enabled() {
+                // nothing drains the ring until interrupts are enabled again
+                for byte in (0..TRANSMIT_FIFO_SIZE).map_while(|_| ring.pop()) {
+                    self.write_polled(byte);
+                }
+                continue;
+            }
+
+            unsafe { asm!("cli") };
+            self.set_transmit_interrupt(true);
+            if ring.push(byte).is_ok() {
+                unsafe { asm!("sti") };
+                return;
+            }
+            // sti only takes effect after hlt so the transmit interrupt can't come in between
+            unsafe { asm!("sti", "hlt") };
+        }
+    }
+
+    fn set_transmit_interrupt(&self, enabled: bool) {
+        without_interrupts(|| {
+            let port = self.base + SERIAL_INTERRUPT_ENABLE_PORT;
+            let interrupts = inb(port);
+            if enabled {
+                outb(port, interrupts | SERIAL_INTERRUPT_TRANSMIT_EMPTY)
+            } else {
+                outb(port, interrupts & !SERIAL_INTERRUPT_TRANSMIT_EMPTY)
+            }
+        })
+    }
+
+    /// makes the writes go through the transmit interrupt, its irq must be routed, does nothing
+    /// if the port doesn't have a ring
+    pub fn enable_transmit_interrupt(&self) {
+        if self.transmit.is_some() && self.is_present() {
+            self.interrupt_driven.store(true, Ordering::Release);
+        }
+    }
+
+    /// wether or not the writes go through the transmit interrupt
+    #[inline]
+    pub fn is_interrupt_driven(&self) -> bool {
+        self.ring().is_some()
+    }
+
+    /// wether or not some bytes are waiting for the transmit interrupt
+    pub fn transmit_pending(&self) -> bool {
+        self.transmit.is_some_and(|ring| !ring.is_empty())
+    }
+
+    /// writes what is queued and makes the writes polled again, for when interrupts won't come
+    pub fn synchronous(&self) {
+        without_interrupts(|| {
+            self.interrupt_driven.store(false, Ordering::Release);
+            if let Some(ring) = self.transmit {
+                while let Some(byte) = ring.pop() {
+                    self.write_polled(byte);
+                }
+            }
+            if self.is_present() {
+                self.set_transmit_interrupt(false);
+            }
+        })
+    }
+
+    /// the transmit half of the port irq, refills the fifo from the ring if it is empty and
+    /// disables the interrupt once there is nothing left
+    pub fn handle_transmit_interrupt(&self) {
+        let Some(ring) = self.transmit else {
+            return;
+        };
+        if !self.is_transmit_fifo_empty() {
+            return;
+        }
+
+        for _ in 0..TRANSMIT_FIFO_SIZE {
+            let Some(byte) = ring.pop() else {
+                self.set_transmit_interrupt(false);
+                return;
+            };
+            outb(self.base + SERIAL_DATA_PORT, byte);
+        }
+    }
+
+    /// wether or not the port still asserts its irq, reading it acknowledges the transmit
+    /// interrupt
+    pub fn interrupt_pending(&self) -> bool {
+        inb(self.base + SERIAL_INTERRUPT_ID_PORT) & SERIAL_INTERRUPT_NONE_PENDING == 0
+    }
 }
 
-pub fn serial_is_transmit_fifo_empty() -> bool {
-    (inb(SERIAL_LINE_STATUS_PORT) & 0x20) != 0
+impl Write for &SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
 }
 
-pub fn write_serial(byte: u8) {
-    // Wait for the FIFO buffer to be empty
-    while !serial_is_transmit_fifo_empty() {}
-    outb(SERIAL_DATA_PORT, byte);
+/// probes every port, COM1 interrupts when it receives a byte
+pub fn init_serial() {
+    for port in PORTS {
+        port.init(port.base == SERIAL_COM1_BASE);
+    }
 }
 
-pub fn write_serial_string(s: &str) {
-    for byte in s.bytes() {
-        write_serial(byte);
+/// the port the serial console (see `drivers::serial`) and `serial!` use
+#[inline]
+pub fn console() -> &'static SerialPort {
+    &COM1
+}
+
+/// the port `log!` writes to
+#[inline]
+pub fn log_port() -> &'static SerialPort {
+    if COM2.is_present() {
+        &COM2
+    } else {
+        &COM1
     }
 }
 
-pub struct Serial;
-impl Write for Serial {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        write_serial_string(s);
-        Ok(())
+/// flushes every port and makes them polled again, see `SerialPort::synchronous`
+pub fn synchronous() {
+    for port in PORTS {
+        port.synchronous();
     }
 }
 
+pub fn serial_received() -> bool {
+    COM1.received()
+}
+
+/// reads a received byte, check `serial_received` first
+pub fn read_serial() -> u8 {
+    COM1.read()
+}
+
 pub fn _serial(args: fmt::Arguments) {
-    Serial {}.write_fmt(args).unwrap();
+    (&COM1).write_fmt(args).unwrap();
 }
@@ -1,6 +1,24 @@
-use core::arch::global_asm;
+// the context switch, `context_switch_stub` is the timer interrupt handler (vector 0x20), the
+// scheduler only accounts the tick there and the switch happens on the way out if
+// `threading::need_resched` is set, the syscall stub does the same so a syscall can switch too
+// the cpu pushes ss, rsp, rflags, cs and rip (the `InterruptFrame`), the stub then pushes every
+// general purpose register and cr3, and zeros in place of the frame fields so the stack is a
+// `CPUStatus` followed by the `InterruptFrame`, both passed to `context_switch` by value
+// `restore_cpu_status` does the opposite from the `CPUStatus` of the next thread: builds an iretq
+// frame, loads every register, loads cr3 last then iretq
+// - rax and rdi are popped off the stack after cr3 is loaded which is fine because kernel stacks
+//   are in the higher half shared by every address space
+// - the fs and gs bases aren't saved, nothing sets them yet (no swapgs and no tls) so they are
+//   the same for every thread, once something does they have to be added to `CPUStatus`
+// - an interrupt from ring 3 lands on the kernel stack of the thread (rsp0 in the tss), `reschedule`
+//   points rsp0 at the stack of every thread it switches to
+// - the sse state and pkru aren't in `CPUStatus`, they are saved in the thread's `fpu_state` by
+//   `context_switch`
+// the offsets `restore_cpu_status` reads are checked against `CPUStatus` at compile time below
 
-use crate::{scheduler, scheduler_inited};
+use core::{arch::global_asm, mem::offset_of};
+
+use crate::{scheduler, scheduler_inited, threading, VirtAddr};
 
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(C)]
@@ -21,21 +39,42 @@ pub struct CPUStatus {
     r9: u64,
     r8: u64,
 
-    rbp: u64,
-    rdi: u64,
-    rsi: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
 
-    rdx: u64,
+    pub rdx: u64,
     rcx: u64,
     rbx: u64,
     pub cr3: u64,
-    rax: u64,
+    pub rax: u64,
 }
 
+// each field is pushed by the stubs, the first field last
+const _: () = assert!(core::mem::size_of::<CPUStatus>() == 21 * 8);
+const _: () = {
+    assert!(offset_of!(CPUStatus, rsp) == 0);
+    assert!(offset_of!(CPUStatus, rflags) == 8);
+    assert!(offset_of!(CPUStatus, ss) == 16);
+    assert!(offset_of!(CPUStatus, cs) == 24);
+    assert!(offset_of!(CPUStatus, rip) == 32);
+    assert!(offset_of!(CPUStatus, r15) == 40);
+    assert!(offset_of!(CPUStatus, r8) == 96);
+    assert!(offset_of!(CPUStatus, rbp) == 104);
+    assert!(offset_of!(CPUStatus, rdi) == 0x70);
+    assert!(offset_of!(CPUStatus, rsi) == 120);
+    assert!(offset_of!(CPUStatus, rdx) == 128);
+    assert!(offset_of!(CPUStatus, rcx) == 136);
+    assert!(offset_of!(CPUStatus, rbx) == 144);
+    assert!(offset_of!(CPUStatus, cr3) == 0x98);
+    assert!(offset_of!(CPUStatus, rax) == 0xA0);
+};
+
 global_asm!(
     "
 .global restore_cpu_status
 .global context_switch_stub
+.global syscall_stub
 
 restore_cpu_status:
     // push the iretq frame
@@ -103,6 +142,37 @@ context_switch_stub:
     call context_switch
     // UNREACHABLE!!!
     ud2
+
+syscall_stub:
+    push rax
+    mov rax, cr3
+    push rax
+
+    push rbx
+    push rcx
+    push rdx
+    
+    push rsi
+    push rdi
+    push rbp
+    
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    push 0    // rip
+    push 0x8  // cs
+    push 0x10 // ss
+    pushfq 
+    push 0 // rsp
+    call syscall_entry
+    // UNREACHABLE!!!
+    ud2
 "
 );
 
@@ -112,25 +182,78 @@ extern "C" {
 
 extern "x86-interrupt" {
     pub fn context_switch_stub();
+    /// the `int 0x80` handler, the syscall number is passed in rax and the result is returned in
+    /// rax
+    pub fn syscall_stub();
+}
+
+impl CPUStatus {
+    /// makes the context start in ring 3 at `rip` on the user stack `rsp` with `arg` in rdi, see
+    /// `InterruptFrame::set_entry`
+    pub fn set_user_entry(&mut self, rip: VirtAddr, rsp: VirtAddr, arg: u64) {
+        let mut frame = super::interrupts::InterruptFrame::new();
+        frame.set_entry(rip, rsp);
+        self.capture_frame(&frame);
+        self.rdi = arg;
+    }
+
+    /// fills the registers the cpu pushed before calling an interrupt handler from `frame`
+    #[inline]
+    fn capture_frame(&mut self, frame: &super::interrupts::InterruptFrame) {
+        self.rsp = frame.stack_pointer;
+        self.rip = frame.insturaction;
+
+        self.cs = frame.code_segment;
+        self.ss = frame.stack_segment;
+        self.rflags = frame.flags;
+    }
+}
+
+/// switches to the next thread if `threading::need_resched` is set, replacing `capture` with
+/// its context, called right before an interrupt returns
+#[inline]
+fn reschedule(capture: &mut CPUStatus) {
+    if !scheduler_inited() || !threading::take_need_resched() {
+        return;
+    }
+
+    unsafe {
+        (*scheduler().current_thread).fpu_state.save();
+        *capture = scheduler().switch(*capture);
+        (*scheduler().current_thread).fpu_state.restore();
+        // where the next interrupt lands if the thread is in ring 3
+        super::gdt::set_kernel_stack(VirtAddr::from_ptr((*scheduler().current_thread).stack_end));
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn context_switch(mut capture: CPUStatus, frame: super::interrupts::InterruptFrame) {
-    capture.rsp = frame.stack_pointer;
-    capture.rip = frame.insturaction;
-
-    capture.cs = frame.code_segment;
-    capture.ss = frame.stack_segment;
-    capture.rflags = frame.flags;
-
-    if scheduler_inited() {
-        // actual context switching:
-        unsafe {
-            capture = scheduler().switch(capture);
-        }
+    super::interrupts::count(0x20);
+    capture.capture_frame(&frame);
+
+    if scheduler_inited() && unsafe { scheduler().tick() } {
+        threading::set_need_resched();
     }
+    reschedule(&mut capture);
 
+    crate::threading::timer::arm();
     super::interrupts::apic::send_eoi();
+    // restore_cpu_status loads cr3
+    crate::memory::paging::tlb_flushed();
+    unsafe {
+        restore_cpu_status(&capture);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn syscall_entry(mut capture: CPUStatus, frame: super::interrupts::InterruptFrame) {
+    super::interrupts::count(0x80);
+    capture.capture_frame(&frame);
+
+    // rip already points at the instruction after `int 0x80`
+    crate::syscalls::handle(&mut capture);
+    reschedule(&mut capture);
+
     unsafe {
         restore_cpu_status(&capture);
     }
@@ -0,0 +1,49 @@
+// the time stamp counter as a monotonic clock, it counts at a constant rate on anything recent
+// (invariant tsc) but that rate isn't given anywhere reliable so it is measured once at boot
+// against the pit (see `pit::reference`)
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::drivers::pit;
+
+use super::rdtsc;
+
+const CALIBRATION_MS: u64 = 10;
+/// used until `calibrate` runs or if there is no pit
+const FALLBACK_FREQUENCY: u64 = 1_000_000_000;
+
+/// the ticks per second, 0 until `calibrate` runs
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// measures the tsc frequency, returns Err(()) and keeps using `FALLBACK_FREQUENCY` if the pit
+/// doesn't answer
+pub fn calibrate() -> Result<(), ()> {
+    let (start, end) = pit::reference(CALIBRATION_MS, rdtsc).ok_or(())?;
+    if end <= start {
+        return Err(());
+    }
+
+    FREQUENCY.store((end - start) * 1000 / CALIBRATION_MS, Ordering::Relaxed);
+    Ok(())
+}
+
+/// the ticks per second
+#[inline]
+pub fn frequency() -> u64 {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => FALLBACK_FREQUENCY,
+        frequency => frequency,
+    }
+}
+
+/// converts tsc ticks to nanoseconds
+#[inline]
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    (ticks as u128 * 1_000_000_000 / frequency() as u128) as u64
+}
+
+/// converts nanoseconds to tsc ticks
+#[inline]
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    (ns as u128 * frequency() as u128 / 1_000_000_000) as u64
+}
@@ -0,0 +1,235 @@
+// microbenchmarks ran instead of the tests with the `bench` feature, `NAVI_BENCH=1 cargo run
+// --features bench` boots the kernel headless and prints what this writes (see src/main.rs)
+// every benchmark is one serial line a host script can diff between runs:
+// `bench: <name> iterations=<n> cycles=<total> per_iteration=<total / n>`
+// then `bench: done` and qemu exits, the cycles are `Arch::counter` ticks (the tsc on x86_64) so
+// only compare runs on the same machine, the timer keeps interrupting so expect some noise
+
+use core::{alloc::Layout, arch::asm, hint::black_box};
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use crate::{
+    arch::{
+        qemu::{self, ExitCode},
+        serial as serial_port, Arch, Current,
+    },
+    globals::kernel,
+    khalt, limine,
+    memory::{
+        allocator::{HeapGrowth, LinkedListAllocator},
+        paging::{current_root_table, EntryFlags, Page, PageTable, TlbBatch, PAGE_SIZE},
+        VirtAddr,
+    },
+    serial,
+    syscalls::SYS_YIELD,
+    threading,
+};
+
+const ALLOCATIONS: usize = 10_000;
+const MEMCPY_SIZE: usize = 1024 * 1024;
+const MEMCPY_ROUNDS: usize = 16;
+const CONTEXT_SWITCHES: usize = 1_000;
+const MAPPED_PAGES: usize = 1_000;
+const SERIAL_BURST_SIZE: usize = 64 * 1024;
+const SERIAL_LINE_SIZE: usize = 64;
+const FRAMEBUFFER_FILLS: usize = 8;
+/// how much `heap_growth` grows a heap by
+const HEAP_GROWTH_SIZE: usize = 4 * 1024 * 1024;
+const HEAP_GROWTH_BLOCK: usize = 256;
+
+/// runs `f` `iterations` times and prints how long it took
+fn bench(name: &str, iterations: usize, mut f: impl FnMut()) {
+    let start = Current::counter();
+    for _ in 0..iterations {
+        f();
+    }
+    report(name, iterations, Current::counter() - start);
+}
+
+fn report(name: &str, iterations: usize, cycles: u64) {
+    serial!(
+        "bench: {} iterations={} cycles={} per_iteration={}\n",
+        name,
+        iterations,
+        cycles,
+        cycles / iterations as u64
+    );
+}
+
+/// allocates `ALLOCATIONS` small boxes then frees them, the frees are timed as well
+fn allocations() {
+    let mut boxes = Vec::with_capacity(ALLOCATIONS);
+
+    bench("alloc", ALLOCATIONS, || {
+        boxes.push(black_box(Box::new([0u64; 8])))
+    });
+    bench("dealloc", ALLOCATIONS, || drop(boxes.pop()));
+}
+
+fn memcpy() {
+    let src = vec![0xAAu8; MEMCPY_SIZE];
+    let mut dst = vec![0u8; MEMCPY_SIZE];
+
+    bench("memcpy_1mib", MEMCPY_ROUNDS, || {
+        dst.copy_from_slice(black_box(&src));
+        black_box(&mut dst);
+    })
+}
+
+/// yields `CONTEXT_SWITCHES` times, each one goes through the syscall's context switch path
+/// (saving the thread, `Scheduler::switch` and restoring whichever thread it picked) without
+/// the eoi, timer rearm and tick accounting the timer interrupt does
+fn context_switches() {
+    bench("context_switch", CONTEXT_SWITCHES, || unsafe {
+        asm!("int 0x80", inout("rax") SYS_YIELD => _)
+    });
+}
+
+/// maps `MAPPED_PAGES` pages flushing each one then maps them again flushing them all at once
+/// with a `TlbBatch`, the batch flush is timed as well
+fn mapping() {
+    let start = kernel()
+        .virt_allocator()
+        .reserve(MAPPED_PAGES * PAGE_SIZE, PAGE_SIZE)
+        .unwrap();
+    let mut frames = Vec::with_capacity(MAPPED_PAGES);
+    for _ in 0..MAPPED_PAGES {
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        frames.push(frame);
+    }
+
+    let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+    let page = |index: usize| Page::containing_address(start) + index;
+    let table = unsafe { current_root_table() };
+    let unmap_all = |table: &mut PageTable| {
+        let mut batch = TlbBatch::new();
+        for index in 0..MAPPED_PAGES {
+            table.unmap_batched(page(index), &mut batch);
+        }
+    };
+
+    let cycles = Current::counter();
+    for (index, &frame) in frames.iter().enumerate() {
+        table.map_to(page(index), frame, flags).unwrap();
+    }
+    report(
+        "map_flush_per_page",
+        MAPPED_PAGES,
+        Current::counter() - cycles,
+    );
+    unmap_all(table);
+
+    let cycles = Current::counter();
+    let mut batch = TlbBatch::new();
+    for (index, &frame) in frames.iter().enumerate() {
+        table
+            .map_to_batched(page(index), frame, flags, &mut batch)
+            .unwrap();
+    }
+    batch.flush();
+    report(
+        "map_flush_batched",
+        MAPPED_PAGES,
+        Current::counter() - cycles,
+    );
+    unmap_all(table);
+
+    for frame in frames.drain(..) {
+        kernel().frame_allocator().deallocate_frame(frame);
+    }
+    kernel()
+        .virt_allocator()
+        .release(start, MAPPED_PAGES * PAGE_SIZE);
+}
+
+/// writes `SERIAL_BURST_SIZE` bytes of log lines to the serial console through its transmit interrupt, the
+/// time is what the writer spent, then how long the port took to send all of it
+fn serial_burst() {
+    let mut line = [b'.'; SERIAL_LINE_SIZE];
+    line[SERIAL_LINE_SIZE - 1] = b'\n';
+    let line = core::str::from_utf8(&line).unwrap();
+
+    let port = serial_port::console();
+    let start = Current::counter();
+    bench(
+        "serial_burst_64kib",
+        SERIAL_BURST_SIZE / SERIAL_LINE_SIZE,
+        || port.write_string(black_box(line)),
+    );
+    while port.transmit_pending() {
+        threading::wait_for_interrupt();
+    }
+    report("serial_burst_64kib_drained", 1, Current::counter() - start);
+}
+
+/// fills the framebuffer with black `FRAMEBUFFER_FILLS` times through its physmap alias,
+/// `init_terminal` runs it as `framebuffer_fill_wb` before remapping the alias write combining
+/// and `run` as `framebuffer_fill_wc` after, so the two lines only differ by the memory type
+pub fn framebuffer_fill(name: &str) {
+    let (buffer, _) = limine::get_framebuffer();
+    let pixels = buffer.len() / 4;
+    let ptr = buffer.as_mut_ptr().cast::<u32>();
+
+    bench(name, FRAMEBUFFER_FILLS, || {
+        for i in 0..pixels {
+            unsafe { ptr.add(i).write_volatile(0) };
+        }
+    });
+
+    // the write back fill is still in the caches and the write combining one in the buffers,
+    // both have to reach the framebuffer before its memory type changes
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+}
+
+/// allocates `HEAP_GROWTH_SIZE` bytes in blocks of `HEAP_GROWTH_BLOCK` bytes from a heap of one
+/// page growing as `growth` says, the time is mostly the extends the allocations went through
+fn heap_growth(name: &str, growth: HeapGrowth) {
+    // room for the last extend to overshoot
+    let region_size = 2 * HEAP_GROWTH_SIZE;
+    let start = kernel()
+        .virt_allocator()
+        .reserve(region_size, PAGE_SIZE)
+        .unwrap();
+    let frame = kernel().frame_allocator().allocate_frame().unwrap();
+    unsafe { current_root_table() }
+        .map_to_writeable(Page::containing_address(start), frame)
+        .unwrap();
+
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(start.as_usize(), PAGE_SIZE, growth) }.unwrap();
+
+    let layout = Layout::from_size_align(HEAP_GROWTH_BLOCK, 8).unwrap();
+    bench(name, HEAP_GROWTH_SIZE / HEAP_GROWTH_BLOCK, || {
+        assert!(!black_box(unsafe { allocator.alloc_mut(layout) }).is_null());
+    });
+
+    let end = Page::containing_address(VirtAddr::new(allocator.heap_end - 1));
+    for page in Page::iter_pages(Page::containing_address(start), end) {
+        let frame = unsafe { current_root_table() }.unmap(page).unwrap();
+        kernel().frame_allocator().deallocate_frame(frame);
+    }
+    kernel().virt_allocator().release(start, region_size);
+}
+
+/// runs every benchmark and exits qemu, called by `kmain` once the scheduler runs
+pub fn run() -> ! {
+    allocations();
+    memcpy();
+    context_switches();
+    mapping();
+    serial_burst();
+    framebuffer_fill("framebuffer_fill_wc");
+    heap_growth("heap_growth_fixed", HeapGrowth::Fixed(128));
+    heap_growth(
+        "heap_growth_geometric",
+        HeapGrowth::Geometric {
+            initial: 16,
+            max: 512,
+        },
+    );
+
+    serial!("bench: done\n");
+    qemu::exit(ExitCode::Success);
+    khalt()
+}
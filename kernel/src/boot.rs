@@ -0,0 +1,298 @@
+// the boot sequence, `kinit` runs each `Phase` in order through `run` which checks the phases
+// it depends on are done before running it and reports over serial if it failed
+// the dependency graph (see `Phase::requires`):
+//   globals -> vmm, physmap, acpi
+//   cpu (serial, features, fpu, pat, gdt, idt) -> acpi -> interrupts (ps/2, apic) <- vmm
+//   physmap -> heap -> vfs
+//   heap, vmm, cpu -> terminal
+//   heap, vmm, interrupts -> net (pci, the network card)
+//   heap, vfs, interrupts, terminal -> scheduler
+//   heap, vmm -> selftest (with the `selftest` feature or `selftest=1`, before the scheduler)
+//   heap, vmm, terminal, net -> wxaudit (with `wxaudit=1`, once the kernel mapped everything)
+// a phase running before one it depends on is a triple fault at best so it is a panic here
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+#[cfg(target_arch = "x86_64")]
+use crate::drivers::net;
+use crate::{
+    arch,
+    cmdline::CmdLine,
+    drivers::vfs,
+    globals::*,
+    kmain, limine, log, logger,
+    memory::{
+        self,
+        frame_allocator::{RegionKind, LOW_MEMORY_END},
+        wxaudit, PhysAddr, VirtRange,
+    },
+    serial, spawn_init, terminal,
+    threading::{
+        priority::LOWEST_PRIORITY,
+        softirq,
+        timer::{self, TimerMode},
+        Scheduler,
+    },
+    utils::{self, Locked},
+    RegionAllocator, Terminal, VirtAddr, VirtRegionAllocator,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phase {
+    /// `KERNEL` with the frame and virtual region allocators
+    Globals,
+    Vmm,
+    /// the physical memory window at `phy_offset`
+    Physmap,
+    /// everything the cpu needs to run in the kernel safely, serial, the cr0/cr4/efer features,
+    /// fpu, pat, gdt, idt and the tsc frequency
+    Cpu,
+    Acpi,
+    /// the ps/2 controller and the apic
+    Interrupts,
+    Heap,
+    Vfs,
+    Terminal,
+    /// the network card if there is one, found on the pci bus
+    Net,
+    SelfTest,
+    /// the writable and executable pages of the kernel, see `memory::wxaudit`
+    WxAudit,
+    Scheduler,
+}
+
+impl Phase {
+    /// the phases that must be done before self
+    pub const fn requires(self) -> &'static [Phase] {
+        match self {
+            Self::Globals | Self::Cpu => &[],
+            Self::Vmm | Self::Physmap => &[Self::Globals],
+            // the acpi tables are identity mapped using the frame allocator
+            Self::Acpi => &[Self::Globals, Self::Cpu],
+            // the apic is found through the madt and mapped as mmio
+            Self::Interrupts => &[Self::Cpu, Self::Vmm, Self::Acpi],
+            // the heap lives in the same level 4 entry as the physmap
+            Self::Heap => &[Self::Physmap],
+            Self::Vfs => &[Self::Heap],
+            // the framebuffer is remapped as write combining with the pat
+            Self::Terminal => &[Self::Heap, Self::Vmm, Self::Cpu],
+            // the card registers are mmio and it interrupts through a vector of the idt
+            Self::Net => &[Self::Heap, Self::Vmm, Self::Interrupts],
+            Self::SelfTest => &[Self::Heap, Self::Vmm],
+            // the framebuffer and the card registers are mapped by then
+            Self::WxAudit => &[Self::Heap, Self::Vmm, Self::Terminal, Self::Net],
+            Self::Scheduler => &[Self::Heap, Self::Vfs, Self::Interrupts, Self::Terminal],
+        }
+    }
+
+    /// wether or not self ran successfully
+    #[inline]
+    pub fn done(self) -> bool {
+        DONE.load(Ordering::Relaxed) & self.bit() != 0
+    }
+
+    #[inline]
+    const fn bit(self) -> u16 {
+        1 << self as u8
+    }
+}
+
+/// a bit for each `Phase` that is done
+static DONE: AtomicU16 = AtomicU16::new(0);
+
+/// runs `phase` with `init` asserting the phases it requires are done, panics if `init` fails
+/// so nothing runs on top of a broken phase
+pub fn run(phase: Phase, init: fn() -> Result<(), ()>) {
+    for required in phase.requires() {
+        assert!(
+            required.done(),
+            "boot: {:?} requires {:?} which isn't done",
+            phase,
+            required
+        );
+    }
+    assert!(!phase.done(), "boot: {:?} ran twice", phase);
+
+    if init().is_err() {
+        panic!("boot: {:?} failed", phase);
+    }
+
+    DONE.fetch_or(phase.bit(), Ordering::Relaxed);
+    log!("boot: {:?} done\n", phase);
+}
+
+pub fn init_globals() -> Result<(), ()> {
+    let phy_offset = limine::get_phy_offset();
+    let kernel_img = limine::kernel_image_info();
+
+    serial!(
+        "image at: 0x{:x}\nlen: 0x{:x}\nphy_offset: 0x{:x}..0x{:x}\nmemory size: 0x{:x}\n",
+        kernel_img.0 as usize,
+        kernel_img.1,
+        phy_offset,
+        limine::get_phy_offset_end(),
+        *limine::MEMORY_SIZE
+    );
+
+    let kernel_img_addr = unsafe { &*kernel_img.0 };
+    let cmdline = CmdLine::from_bytes(limine::kernel_cmdline());
+    serial!("cmdline: `{}`\n", cmdline.raw());
+
+    let elf = utils::elf::Elf::parse(kernel_img_addr)
+        .map_err(|err| serial!("failed to parse the kernel image: {:?}\n", err))?;
+    elf.debug();
+
+    unsafe {
+        KERNEL = Some(Kernel {
+            phy_offset,
+            rsdp_addr: limine::rsdp_addr(),
+            initramfs: limine::initramfs_info(),
+            frame_allocator: Locked::new(RegionAllocator::new()),
+            virt_allocator: VirtRegionAllocator::new(),
+            elf,
+            cmdline,
+        });
+    }
+
+    // the memory map could be wrong about these or get reclaimed later, they must never be
+    // handed out
+    reserve_frames(PhysAddr::new(0), LOW_MEMORY_END.as_usize())?;
+    let regions = kernel().frame_allocator().regions().len();
+    for index in 0..regions {
+        let region = kernel().frame_allocator().regions()[index].clone();
+        if matches!(region.kind, RegionKind::AcpiNvs | RegionKind::Framebuffer) {
+            reserve_frames(region.range.start(), region.range.len())?;
+        }
+    }
+    Ok(())
+}
+
+fn reserve_frames(start: PhysAddr, len: usize) -> Result<(), ()> {
+    kernel()
+        .frame_allocator()
+        .reserve(start, len)
+        .map_err(|()| serial!("failed to reserve {:#x}, too many reserved ranges\n", start))
+}
+
+pub fn init_vmm() -> Result<(), ()> {
+    memory::vmm::init();
+    // the bootloader may have left low memory mapped, null dereferences have to fault
+    let root_table = unsafe { memory::paging::current_root_table() };
+    root_table
+        .split_huge_page(memory::paging::NULL_PAGE)
+        .map_err(|err| serial!("failed to unmap the null page: {:?}\n", err))?;
+
+    if root_table.unmap(memory::paging::NULL_PAGE).is_some() {
+        log!("vmm: unmapped the null page\n");
+    }
+    Ok(())
+}
+
+pub fn init_physmap() -> Result<(), ()> {
+    let phy_offset = VirtAddr::new(kernel().phy_offset);
+
+    unsafe { memory::paging::map_physmap_1gib(phy_offset, *limine::MEMORY_END) }
+        .map_err(|err| serial!("failed to map the physmap: {:?}\n", err))
+}
+
+pub fn init_cpu() -> Result<(), ()> {
+    arch::init_cpu();
+    Ok(())
+}
+
+pub fn init_acpi() -> Result<(), ()> {
+    arch::init_acpi();
+    Ok(())
+}
+
+pub fn init_interrupts() -> Result<(), ()> {
+    arch::init_interrupts();
+    #[cfg(target_arch = "x86_64")]
+    serial!("booted at {} (UTC)\n", crate::drivers::rtc::now());
+    Ok(())
+}
+
+pub fn init_heap() -> Result<(), ()> {
+    // the physmap is mapped in 1GiB chunks, the framebuffer is in it until the terminal maps it
+    // in the vmm window
+    let physmap = VirtRange::from_len(
+        VirtAddr::new(limine::get_phy_offset()),
+        memory::align_up(*limine::MEMORY_END, memory::paging::HUGE_PAGE_1GIB),
+    );
+    let heap = memory::layout::heap(&[physmap, limine::get_framebuffer_range()])?;
+
+    memory::init(heap).map_err(|err| serial!("failed to map the heap: {:?}\n", err))
+}
+
+pub fn init_vfs() -> Result<(), ()> {
+    vfs::init();
+    Ok(())
+}
+
+pub fn init_net() -> Result<(), ()> {
+    #[cfg(target_arch = "x86_64")]
+    net::init();
+    Ok(())
+}
+
+pub fn init_terminal() -> Result<(), ()> {
+    // the only time the framebuffer can be timed as write back memory
+    #[cfg(feature = "bench")]
+    crate::bench::framebuffer_fill("framebuffer_fill_wb");
+
+    let (buffer, info) = limine::get_framebuffer();
+    // the bootloader maps the framebuffer as write back memory, the physmap alias is remapped
+    // with the new mapping, nothing wrote to it through the caches since `pat::init` flushed them
+    let buffer = match memory::vmm::map_framebuffer(
+        memory::virt_to_phys(VirtAddr::from_ptr(buffer.as_ptr())),
+        buffer.len(),
+    ) {
+        Some(addr) => unsafe { core::slice::from_raw_parts_mut(addr.as_mut_ptr(), buffer.len()) },
+        None => buffer,
+    };
+
+    let terminal: Terminal<'static> = Terminal::init(buffer, info);
+    unsafe { TERMINAL = Some(terminal) };
+    Ok(())
+}
+
+pub fn selftest() -> Result<(), ()> {
+    memory::selftest::selftest();
+    Ok(())
+}
+
+pub fn wxaudit() -> Result<(), ()> {
+    let table = unsafe { memory::paging::current_root_table() };
+    wxaudit::audit(
+        table,
+        wxaudit::ADDRESS_SPACE,
+        kernel().cmdline.wxaudit_strict(),
+    );
+    Ok(())
+}
+
+pub fn init_scheduler() -> Result<(), ()> {
+    let mut scheduler = Scheduler::init(kmain as usize, "kernel");
+
+    // init has to be created first so it gets pid 1
+    spawn_init(&mut scheduler);
+    scheduler.spawn(terminal::shell as usize, "shell");
+    scheduler.spawn(softirq::softirq_thread as usize, "softirq");
+    scheduler.spawn(terminal::serial_shell as usize, "serial-shell");
+    #[cfg(target_arch = "x86_64")]
+    if net::has_device() {
+        scheduler.spawn(net::net_thread as usize, "net");
+    }
+
+    let logger = scheduler.spawn(logger::logger_thread as usize, "logger");
+    let logger = scheduler.processes[&logger].threads[0];
+    scheduler.set_priority(logger, LOWEST_PRIORITY).unwrap();
+
+    unsafe { SCHEDULER = Some(scheduler) };
+
+    if kernel().cmdline.tickless() && timer::set_mode(TimerMode::Tickless).is_err() {
+        serial!("timer: no tsc deadline timer, staying periodic\n");
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// the kernel command line, `key=value` pairs separated by spaces, for example
+// `selftest=1 tickless=off wxaudit`
+// it comes from the `cmdline` of the limine.conf entry which build.rs fills from the
+// `NAVI_CMDLINE` env variable at build time (the iso is booted so qemu's -append doesn't reach us)
+// a key without a value is the same as `key=1`, when a key is given twice the last one wins
+
+/// the default of every key subsystems query, used when the key isn't given
+pub mod defaults {
+    pub const SELFTEST: bool = false;
+    pub const TICKLESS: bool = true;
+    pub const WXAUDIT: bool = false;
+    pub const WXAUDIT_STRICT: bool = false;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CmdLine {
+    raw: &'static str,
+}
+
+impl CmdLine {
+    pub const fn new(raw: &'static str) -> Self {
+        Self { raw }
+    }
+
+    /// parses the command line the bootloader gave us, empty if it isn't valid utf8
+    pub fn from_bytes(bytes: &'static [u8]) -> Self {
+        match core::str::from_utf8(bytes) {
+            Ok(raw) => Self::new(raw),
+            Err(_) => {
+                crate::serial!("cmdline: not valid utf8, ignoring it\n");
+                Self::new("")
+            }
+        }
+    }
+
+    #[inline]
+    pub const fn raw(&self) -> &'static str {
+        self.raw
+    }
+
+    /// every `(key, value)` pair in order
+    pub fn pairs(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        self.raw
+            .split_ascii_whitespace()
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "1")))
+    }
+
+    /// returns the value of `key` if it is given
+    pub fn get(&self, key: &str) -> Option<&'static str> {
+        self.pairs()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .last()
+    }
+
+    /// returns the value of `key` or `default` if it isn't given
+    #[inline]
+    pub fn get_or(&self, key: &str, default: &'static str) -> &'static str {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// returns `key` as a boolean (1/on/true/yes or 0/off/false/no), `default` if it isn't
+    /// given or is something else
+    pub fn flag(&self, key: &str, default: bool) -> bool {
+        match self.get(key) {
+            Some("1" | "on" | "true" | "yes") => true,
+            Some("0" | "off" | "false" | "no") => false,
+            Some(value) => {
+                crate::serial!("cmdline: `{}={}` is not a boolean\n", key, value);
+                default
+            }
+            None => default,
+        }
+    }
+
+    /// `selftest`, runs `memory::selftest` on boot
+    #[inline]
+    pub fn selftest(&self) -> bool {
+        self.flag("selftest", defaults::SELFTEST)
+    }
+
+    /// `tickless`, wether or not the timer stops while every thread is idle (see
+    /// `threading::timer`), it stays periodic without the tsc deadline timer
+    #[inline]
+    pub fn tickless(&self) -> bool {
+        self.flag("tickless", defaults::TICKLESS)
+    }
+
+    /// `wxaudit`, reports the writable and executable pages of the kernel on boot and of every
+    /// elf once it is loaded (see `memory::wxaudit`)
+    #[inline]
+    pub fn wxaudit(&self) -> bool {
+        self.flag("wxaudit", defaults::WXAUDIT)
+    }
+
+    /// `wxaudit.strict`, panics if `wxaudit` finds anything
+    #[inline]
+    pub fn wxaudit_strict(&self) -> bool {
+        self.flag("wxaudit.strict", defaults::WXAUDIT_STRICT)
+    }
+}
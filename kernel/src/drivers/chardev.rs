@@ -0,0 +1,89 @@
+// devices that are a stream of bytes, the shells read their lines from one without caring if it
+// is the serial port (`serial::SerialConsole`) or the keyboard and the framebuffer
+// (`terminal::TerminalConsole`)
+// text is utf8, a char can take more than one byte
+
+use alloc::{string::String, vec::Vec};
+
+use crate::threading::sync::WaitQueue;
+
+/// the threads blocked in `CharDevice::wait_byte` on any device
+static INPUT_READY: WaitQueue = WaitQueue::new();
+
+/// wakes the threads waiting for a byte, called by the devices when they receive one, safe to
+/// call from an interrupt handler
+#[inline]
+pub fn input_ready() {
+    INPUT_READY.wake_all();
+}
+
+pub trait CharDevice {
+    /// a received byte, None if there is nothing to read, doesn't wait
+    fn read_byte(&self) -> Option<u8>;
+    /// writes `byte`, does nothing if the device can't be written to
+    fn write_byte(&self, byte: u8);
+    /// wether or not `Self::read_byte` has a byte
+    fn readable(&self) -> bool;
+    /// wether or not `Self::write_byte` writes anywhere
+    fn writable(&self) -> bool;
+
+    /// blocks until a byte is received and returns it, the device has to call `input_ready`
+    /// when it receives one
+    fn wait_byte(&self) -> u8 {
+        loop {
+            INPUT_READY.wait_until(|| self.readable());
+
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    fn write_str(&self, str: &str) {
+        for byte in str.bytes() {
+            self.write_byte(byte);
+        }
+    }
+}
+
+/// an utf8 continuation byte, every byte of a char but the first is one
+#[inline]
+const fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// reads a line from `device` echoing it back, handles backspace
+/// the returned line doesn't include the line terminator (\r or \n), invaild utf8 is replaced
+/// with U+FFFD
+/// there must be only one reader of `device` at a time
+pub fn readline(device: &dyn CharDevice) -> String {
+    let mut line = Vec::new();
+
+    loop {
+        match device.wait_byte() {
+            b'\r' | b'\n' => {
+                device.write_byte(b'\n');
+                return String::from_utf8_lossy(&line).into_owned();
+            }
+            // backspace or delete, removes a whole char
+            0x08 | 0x7F => {
+                let mut removed = false;
+                while let Some(byte) = line.pop() {
+                    removed = true;
+                    if !is_continuation(byte) {
+                        break;
+                    }
+                }
+
+                if removed {
+                    device.write_str("\x08 \x08");
+                }
+            }
+            byte if byte.is_ascii_graphic() || byte == b' ' || !byte.is_ascii() => {
+                line.push(byte);
+                device.write_byte(byte);
+            }
+            _ => (),
+        }
+    }
+}
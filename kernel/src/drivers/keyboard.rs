@@ -1,8 +1,22 @@
 // no alloc vec
 use core::fmt::{Display, LowerHex, UpperHex};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use heapless::Vec;
 
-use crate::utils::Locked;
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::ps2::{self, DEVICE_ACK, DEVICE_RESEND};
+use crate::{arch::without_interrupts, serial};
+#[cfg(not(target_arch = "x86_64"))]
+use ps2::{DEVICE_ACK, DEVICE_RESEND};
+
+use super::{
+    chardev::{self, CharDevice},
+    keymapper::keymap,
+};
+use crate::threading::sync::WaitQueue;
+use crate::threading::wait_for_interrupt;
+use crate::time::{Duration, Instant};
+use crate::utils::{ring_buffer::RingBuffer, Locked};
 use bitflags::bitflags;
 use int_enum::IntEnum;
 use macros::EncodeKey;
@@ -11,6 +25,17 @@ use spin::MutexGuard;
 static mut CURRENT_UNENCODED_KEY: [u8; 8] = [0; 8]; // multibyte key
 static mut LATEST_UNENCODED_BYTE: usize = 0; // pointer in ^^^
 
+/// scancodes pushed by the keyboard interrupt handler waiting to be encoded by
+/// `encode_pending`, encoding takes locks so it can't happen in the interrupt handler
+static SCANCODES: RingBuffer<u8, 256> = RingBuffer::new();
+/// the characters typed while someone reads the keyboard as utf8, see `KeyboardInput`
+static INPUT: RingBuffer<u8, 256> = RingBuffer::new();
+/// the keys pressed while someone is in `getchar` or `getline`, the oldest ones are dropped when
+/// they fall behind
+static KEY_PRESSES: RingBuffer<Key, 64> = RingBuffer::new();
+/// how many threads are in `getchar` or `getline`, see `KeyReader`
+static KEY_READERS: AtomicUsize = AtomicUsize::new(0);
+
 const MAX_KEYS: usize = 256;
 static CURRENT_KEYS: Locked<Vec<Key, MAX_KEYS>> = Locked::new(Vec::new());
 fn current_keys() -> MutexGuard<'static, Vec<Key, MAX_KEYS>> {
@@ -353,6 +378,7 @@ fn add_pressed_keycode(code: KeyCode) {
     if code == KeyCode::CapsLock {
         if code.is_pressed() {
             remove_pressed_keycode(code);
+            update_leds();
             return;
         }
     }
@@ -363,6 +389,11 @@ fn add_pressed_keycode(code: KeyCode) {
         *current_keys().last_mut().unwrap() = attempt.unwrap_err();
     }
 
+    if code == KeyCode::CapsLock {
+        update_leds();
+    }
+
+    push_key_press(key);
     crate::__navi_key_pressed(key)
 }
 
@@ -417,3 +448,461 @@ pub fn encode_ps2_set_1(code: u8) {
 
     reset_unencoded_buffer()
 }
+
+/// queues a ps/2 set 1 scancode for `encode_pending`, safe to call from an interrupt handler
+/// the scancode is dropped if the queue is full
+#[inline]
+pub fn push_scancode(code: u8) {
+    _ = SCANCODES.push(code);
+}
+
+// raw mode, for the programs that want the keys as the keyboard sends them (a game, an editor)
+// and not as chars: the interrupt handler puts the scancodes together in `RawKeyEvent`s which go
+// to their own ring, the keymap and the pressed keys aren't touched and nothing goes to
+// `KeyboardInput`, `getchar` or `SCANCODES` until the mode is turned off
+// a set 1 scancode is a byte or 0xE0 followed by a byte, the break code of a key is its make
+// code with bit 7 of the last byte set
+// pause is the exception, its make code is the 6 bytes E1 1D 45 E1 9D C5 and it has no break code
+// so it is always a press
+
+/// the make code of pause, see `RawScancode::push`
+const PAUSE_SCANCODE: [u8; 6] = [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5];
+
+/// wether or not the keyboard is in raw mode, see `set_raw`
+static RAW: AtomicBool = AtomicBool::new(false);
+/// the events of raw mode, the oldest ones are dropped when nobody reads them
+static RAW_EVENTS: RingBuffer<RawKeyEvent, 64> = RingBuffer::new();
+/// the threads blocked in `wait_raw`
+static RAW_READY: WaitQueue = WaitQueue::new();
+
+/// a key pressed or released in raw mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawKeyEvent {
+    /// the bytes of the make code, the first one in the lowest byte like `Set1Key`
+    pub scancode: u64,
+    pub released: bool,
+}
+
+/// the bytes of the scancode being received, only locked by the keyboard interrupt handler and
+/// with interrupts disabled
+struct RawScancode {
+    bytes: [u8; PAUSE_SCANCODE.len()],
+    len: usize,
+}
+
+static RAW_SCANCODE: Locked<RawScancode> = Locked::new(RawScancode {
+    bytes: [0; PAUSE_SCANCODE.len()],
+    len: 0,
+});
+
+impl RawScancode {
+    /// adds `byte` to the scancode returning the event once it is complete
+    fn push(&mut self, byte: u8) -> Option<RawKeyEvent> {
+        self.bytes[self.len] = byte;
+        self.len += 1;
+
+        let complete = match self.bytes[0] {
+            0xE0 => self.len == 2,
+            0xE1 => self.len == PAUSE_SCANCODE.len(),
+            _ => true,
+        };
+        if !complete {
+            return None;
+        }
+
+        // the last byte of pause has bit 7 set but it is a press
+        let released = self.bytes[0] != 0xE1 && byte & 0x80 != 0;
+        if released {
+            self.bytes[self.len - 1] &= !0x80;
+        }
+        let scancode = self.bytes[..self.len]
+            .iter()
+            .rev()
+            .fold(0, |scancode, &byte| scancode << 8 | byte as u64);
+
+        self.len = 0;
+        Some(RawKeyEvent { scancode, released })
+    }
+}
+
+/// turns raw mode on or off, the keys held while leaving raw mode aren't pressed anymore as
+/// far as cooked mode knows since it never saw them being released
+pub fn set_raw(raw: bool) {
+    without_interrupts(|| {
+        RAW_SCANCODE.lock().len = 0;
+        RAW.store(raw, Ordering::Relaxed);
+
+        if !raw {
+            current_keys().clear();
+        }
+    });
+}
+
+/// wether or not the keyboard is in raw mode, see `set_raw`
+#[inline]
+pub fn is_raw() -> bool {
+    RAW.load(Ordering::Relaxed)
+}
+
+/// the oldest raw event that wasn't read yet
+#[inline]
+pub fn read_raw() -> Option<RawKeyEvent> {
+    RAW_EVENTS.pop()
+}
+
+/// waits for the next raw event
+pub fn wait_raw() -> RawKeyEvent {
+    loop {
+        RAW_READY.wait_until(|| !RAW_EVENTS.is_empty());
+
+        if let Some(event) = read_raw() {
+            return event;
+        }
+    }
+}
+
+// commands to the keyboard, the keyboard answers every byte we send with an ACK or asks for it
+// again with a RESEND through the same irq as the scancodes, so the commands are queued and
+// sent a byte at a time, the next byte only goes out once the last one is ACKed
+// while a command is in flight ACK and RESEND bytes go to the command queue and not to the
+// key decoder, 0xFA and 0xFE aren't scancodes in set 1 anyway
+// an answer that never comes is taken as a RESEND once `ACK_TIMEOUT` passed, checked whenever
+// the queue is used (see `CommandQueue::check_timeout`) so a lost ACK can't block it forever
+
+const COMMAND_SET_LEDS: u8 = 0xED;
+const COMMAND_SET_TYPEMATIC: u8 = 0xF3;
+
+/// how many times a byte is resent before its command is dropped
+const MAX_RESENDS: u8 = 3;
+/// how long the keyboard gets to answer a byte
+const ACK_TIMEOUT: Duration = Duration::from_millis(20);
+const MAX_COMMANDS: usize = 8;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Leds: u8 {
+        const SCROLL_LOCK = 1 << 0;
+        const NUM_LOCK = 1 << 1;
+        const CAPS_LOCK = 1 << 2;
+    }
+}
+
+/// there is no ps/2 controller off x86_64, every command fails to send
+#[cfg(not(target_arch = "x86_64"))]
+mod ps2 {
+    pub const DEVICE_ACK: u8 = 0xFA;
+    pub const DEVICE_RESEND: u8 = 0xFE;
+
+    pub fn send(_byte: u8) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+/// a command byte followed by its data byte
+type Command = [u8; 2];
+
+struct CommandQueue {
+    commands: Vec<Command, MAX_COMMANDS>,
+    /// the bytes of the first command that were ACKed
+    acked: usize,
+    resends: u8,
+    /// wether or not we are waiting for the keyboard to answer
+    in_flight: bool,
+    /// when the byte in flight was sent
+    sent_at: Option<Instant>,
+}
+
+/// only locked with interrupts disabled, the keyboard interrupt handler locks it
+static COMMANDS: Locked<CommandQueue> = Locked::new(CommandQueue {
+    commands: Vec::new(),
+    acked: 0,
+    resends: 0,
+    in_flight: false,
+    sent_at: None,
+});
+
+impl CommandQueue {
+    /// sends the next byte of the first command if there is one
+    fn send_next(&mut self) {
+        let Some(command) = self.commands.first() else {
+            self.in_flight = false;
+            return;
+        };
+
+        let byte = command[self.acked];
+        self.in_flight = ps2::send(byte).is_ok();
+        self.sent_at = Some(Instant::now());
+        if !self.in_flight {
+            serial!(
+                "keyboard: failed to send {:#x}, dropping the command\n",
+                byte
+            );
+            self.next_command();
+        }
+    }
+
+    /// drops the first command and sends the next one
+    fn next_command(&mut self) {
+        self.commands.remove(0);
+        self.acked = 0;
+        self.resends = 0;
+        self.send_next();
+    }
+
+    /// handles an ACK or RESEND to the byte in flight
+    fn on_response(&mut self, response: u8) {
+        if response == DEVICE_ACK {
+            self.acked += 1;
+            self.resends = 0;
+
+            if self.acked == self.commands[0].len() {
+                self.next_command();
+            } else {
+                self.send_next();
+            }
+        } else if self.resends < MAX_RESENDS {
+            self.resends += 1;
+            self.send_next();
+        } else {
+            serial!(
+                "keyboard: command {:#x} resent too many times, dropping it\n",
+                self.commands[0][0]
+            );
+            self.next_command();
+        }
+    }
+
+    /// resends the byte in flight or drops its command if the keyboard didn't answer it in time
+    fn check_timeout(&mut self) {
+        let timed_out = self
+            .sent_at
+            .is_some_and(|sent_at| sent_at.elapsed() > ACK_TIMEOUT);
+
+        if self.in_flight && timed_out {
+            serial!(
+                "keyboard: no answer to command {:#x}\n",
+                self.commands[0][0]
+            );
+            self.on_response(DEVICE_RESEND);
+        }
+    }
+}
+
+/// queues `command` and sends it right away if nothing else is in flight, Err(()) if the queue is
+/// full
+fn queue_command(command: Command) -> Result<(), ()> {
+    without_interrupts(|| {
+        let mut queue = COMMANDS.lock();
+        queue.check_timeout();
+        queue.commands.push(command).map_err(|_| ())?;
+
+        if !queue.in_flight {
+            queue.send_next();
+        }
+        Ok(())
+    })
+}
+
+/// turns the keyboard leds in `leds` on and the others off
+#[inline]
+pub fn set_leds(leds: Leds) -> Result<(), ()> {
+    queue_command([COMMAND_SET_LEDS, leds.bits()])
+}
+
+/// sets how fast a held key repeats, `rate` goes from 0 (30 repeats a second) to 31 (2 a second)
+/// and `delay` before the first repeat from 0 (250ms) to 3 (1s), Err(()) if one is out of range
+/// or the queue is full
+pub fn set_typematic(rate: u8, delay: u8) -> Result<(), ()> {
+    if rate > 0x1F || delay > 3 {
+        return Err(());
+    }
+
+    queue_command([COMMAND_SET_TYPEMATIC, delay << 5 | rate])
+}
+
+/// how many commands are waiting to be sent or to be ACKed
+pub fn pending_commands() -> usize {
+    without_interrupts(|| {
+        let mut queue = COMMANDS.lock();
+        queue.check_timeout();
+        queue.commands.len()
+    })
+}
+
+/// sets the leds to match the lock keys
+fn update_leds() {
+    let mut leds = Leds::empty();
+    if KeyCode::CapsLock.is_pressed() {
+        leds |= Leds::CAPS_LOCK;
+    }
+
+    if set_leds(leds).is_err() {
+        serial!("keyboard: too many commands queued, the leds are out of date\n");
+    }
+}
+
+/// handles a byte the keyboard sent, called by the keyboard interrupt handler
+pub fn handle_byte(byte: u8) {
+    if byte == DEVICE_ACK || byte == DEVICE_RESEND {
+        let mut queue = COMMANDS.lock();
+        if queue.in_flight {
+            queue.on_response(byte);
+            return;
+        }
+    }
+
+    if is_raw() {
+        if let Some(event) = RAW_SCANCODE.lock().push(byte) {
+            RAW_EVENTS.push_overwriting(event);
+            RAW_READY.wake_all();
+        }
+        return;
+    }
+
+    push_scancode(byte)
+}
+
+/// encodes the scancodes queued by `push_scancode`, the bottom half of the keyboard interrupt
+/// handler (see `softirq`)
+pub fn encode_pending() {
+    let mut encoded = false;
+    while let Some(code) = SCANCODES.pop() {
+        encode_ps2_set_1(code);
+        encoded = true;
+    }
+
+    // whoever waits for the keys gets to see them
+    if encoded {
+        crate::threading::timer::wake();
+    }
+}
+
+/// queues the utf8 bytes of a typed character for `KeyboardInput` waking its readers
+/// the bytes that don't fit are dropped
+pub fn push_char(c: char) {
+    let mut buffer = [0; 4];
+    for byte in c.encode_utf8(&mut buffer).bytes() {
+        _ = INPUT.push(byte);
+    }
+    chardev::input_ready();
+}
+
+/// the characters typed as a `CharDevice`, nothing can be written to it
+pub struct KeyboardInput;
+
+impl CharDevice for KeyboardInput {
+    fn read_byte(&self) -> Option<u8> {
+        INPUT.pop()
+    }
+
+    fn write_byte(&self, _byte: u8) {}
+
+    fn readable(&self) -> bool {
+        !INPUT.is_empty()
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+}
+
+/// queues a key press for `getchar`, every key the keyboard sends goes through here
+/// the key is dropped if nobody is reading, otherwise `getchar` would return the keys typed
+/// before it was called
+#[inline]
+pub fn push_key_press(key: Key) {
+    if reading_keys() {
+        KEY_PRESSES.push_overwriting(key);
+    }
+}
+
+/// wether or not someone is in `getchar` or `getline`
+#[inline]
+pub fn reading_keys() -> bool {
+    KEY_READERS.load(Ordering::SeqCst) != 0
+}
+
+/// a thread reading the key presses, they are queued for as long as one lives
+struct KeyReader;
+
+impl KeyReader {
+    fn new() -> Self {
+        // what is left was typed for the last reader, nothing is pushed while there is none
+        if !reading_keys() {
+            while KEY_PRESSES.pop().is_some() {}
+        }
+        KEY_READERS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for KeyReader {
+    fn drop(&mut self) {
+        KEY_READERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// the char `key` produces with the current keymap, None for the modifiers and the keys that
+/// don't produce one
+/// caps lock only affects the letters and undoes shift on them, so with both held 'a' stays 'a'
+pub fn key_to_char(key: Key) -> Option<char> {
+    let keymap = keymap();
+    let flags = key.flags - KeyFlags::SHIFT - KeyFlags::CAPS_LOCK;
+
+    match keymap.map(key.code, flags) {
+        Some(c) if c.is_alphabetic() => {
+            let upper =
+                key.flags.contains(KeyFlags::SHIFT) != key.flags.contains(KeyFlags::CAPS_LOCK);
+            if upper {
+                keymap
+                    .map(key.code, flags | KeyFlags::CAPS_LOCK)
+                    .or(Some(c))
+            } else {
+                Some(c)
+            }
+        }
+        _ => keymap.map(key.code, key.flags - KeyFlags::CAPS_LOCK),
+    }
+}
+
+/// waits for the next key press that produces a char, see `key_to_char`, only the keys pressed
+/// after it was called count
+pub fn getchar() -> char {
+    let _reader = KeyReader::new();
+    loop {
+        while let Some(key) = KEY_PRESSES.pop() {
+            if let Some(c) = key_to_char(key) {
+                return c;
+            }
+        }
+        wait_for_interrupt();
+    }
+}
+
+/// reads a line with `getchar` into `buffer` as utf8 until enter is pressed, backspace removes
+/// the last char, the chars that don't fit anymore are dropped
+/// nothing is echoed, returns the length of the line without the newline
+pub fn getline(buffer: &mut [u8]) -> usize {
+    // the keys typed between two `getchar`s are part of the line
+    let _reader = KeyReader::new();
+    let mut len = 0;
+
+    loop {
+        match getchar() {
+            '\n' => return len,
+            '\x08' => {
+                // the continuation bytes then the first byte of the last char
+                while len > 0 {
+                    len -= 1;
+                    if buffer[len] & 0xC0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            c if len + c.len_utf8() <= buffer.len() => {
+                len += c.encode_utf8(&mut buffer[len..]).len();
+            }
+            _ => (),
+        }
+    }
+}
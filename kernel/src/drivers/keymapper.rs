@@ -2,9 +2,24 @@
 // it is supposed to read a mapping from a file first but for now we will have a hardcoded built in
 // each KeyCode is an index to 16 different MappingEntries each MappingEntry has flags and a result
 // UTF8 char
-// default mapping in `DEFAULT_MAPPING` const
+// the keyboard driver only produces `KeyCode`s (what games and editors should use), turning them
+// into characters goes through the current `KeyMap` which can be swapped at runtime with
+// `set_keymap`, US QWERTY (`US_QWERTY`) is the default
+
+use spin::MutexGuard;
 
 use super::keyboard::{Key, KeyCode, KeyFlags};
+use crate::utils::Locked;
+
+/// a keyboard layout
+pub trait KeyMap: Sync {
+    /// the name the layout is selected with, see `LAYOUTS`
+    fn name(&self) -> &'static str;
+
+    /// returns the char `code` produces with the modifiers `flags`, None if it doesn't produce
+    /// any
+    fn map(&self, code: KeyCode, flags: KeyFlags) -> Option<char>;
+}
 
 #[derive(Clone, Copy)]
 pub struct MappingEntry {
@@ -33,6 +48,35 @@ impl KeyMapping {
     const fn get_const(&self, index: KeyCode) -> &[MappingEntry] {
         &self.keys[index as usize]
     }
+
+    /// returns self with the mappings of the keys `a` and `b` swapped, used to build layouts
+    /// that only move keys around from another one
+    pub const fn swapped(mut self, a: KeyCode, b: KeyCode) -> Self {
+        let mappings = self.keys[a as usize];
+        self.keys[a as usize] = self.keys[b as usize];
+        self.keys[b as usize] = mappings;
+        self
+    }
+}
+
+/// a `KeyMapping` with the name it is selected with
+pub struct KeyMappingLayout {
+    pub name: &'static str,
+    pub mapping: KeyMapping,
+}
+
+impl KeyMap for KeyMappingLayout {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn map(&self, code: KeyCode, flags: KeyFlags) -> Option<char> {
+        self.mapping
+            .get_const(code)
+            .iter()
+            .find(|mapping| mapping.result != '\0' && mapping.flags == flags)
+            .map(|mapping| mapping.result)
+    }
 }
 
 // beatuiful macro to create Mappings
@@ -63,7 +107,7 @@ macro_rules! create_mapping {
 }
 
 // remmber ONLY 16 mapping allowed for each keycode, each should have different flags
-pub const DEFAULT_MAPPING: KeyMapping = create_mapping!(
+pub const US_QWERTY_MAPPING: KeyMapping = create_mapping!(
     // Key Q mappings
     { KeyCode::KeyQ, {} } => 'q',
     { KeyCode::KeyQ, { KeyFlags::CAPS_LOCK } } => 'Q',
@@ -264,6 +308,46 @@ pub const DEFAULT_MAPPING: KeyMapping = create_mapping!(
     { KeyCode::Slash, { KeyFlags::SHIFT } } => '?',
 );
 
+pub static US_QWERTY: KeyMappingLayout = KeyMappingLayout {
+    name: "us",
+    mapping: US_QWERTY_MAPPING,
+};
+
+/// german QWERTZ, only the letters move the symbols stay US
+pub static QWERTZ: KeyMappingLayout = KeyMappingLayout {
+    name: "qwertz",
+    mapping: US_QWERTY_MAPPING.swapped(KeyCode::KeyY, KeyCode::KeyZ),
+};
+
+/// french AZERTY, only the letters move (M takes the place of ;) the symbols stay US
+pub static AZERTY: KeyMappingLayout = KeyMappingLayout {
+    name: "azerty",
+    mapping: US_QWERTY_MAPPING
+        .swapped(KeyCode::KeyA, KeyCode::KeyQ)
+        .swapped(KeyCode::KeyZ, KeyCode::KeyW)
+        .swapped(KeyCode::KeyM, KeyCode::Semicolon),
+};
+
+/// the built-in layouts
+pub static LAYOUTS: &[&'static dyn KeyMap] = &[&US_QWERTY, &QWERTZ, &AZERTY];
+
+static CURRENT_KEYMAP: Locked<&'static dyn KeyMap> = Locked::new(&US_QWERTY);
+
+/// the keymap keys are currently mapped with
+pub fn keymap() -> MutexGuard<'static, &'static dyn KeyMap> {
+    CURRENT_KEYMAP.inner.lock()
+}
+
+/// maps the keys with `keymap` from now on
+pub fn set_keymap(keymap: &'static dyn KeyMap) {
+    *CURRENT_KEYMAP.inner.lock() = keymap;
+}
+
+/// returns the built-in layout named `name`
+pub fn find_layout(name: &str) -> Option<&'static dyn KeyMap> {
+    LAYOUTS.iter().copied().find(|layout| layout.name() == name)
+}
+
 // returns '\0' if no char found
 // returns a UTF8 char in u32 form for ffi safety
 #[no_mangle]
@@ -276,13 +360,8 @@ extern "C" fn __navi_map_key(keycode: KeyCode, keyflags: KeyFlags) -> u32 {
 }
 
 impl Key {
+    /// maps self with the current keymap, returns '\0' if self doesn't produce a char
     pub fn map_key(&self) -> char {
-        let mappings = DEFAULT_MAPPING.get_const(self.code);
-        for mapping in mappings {
-            if mapping.flags == self.flags {
-                return mapping.result;
-            }
-        }
-        return '\0';
+        keymap().map(self.code, self.flags).unwrap_or('\0')
     }
 }
@@ -1,3 +1,13 @@
+pub mod chardev;
 pub mod keyboard;
 pub mod keymapper;
+#[cfg(target_arch = "x86_64")]
+pub mod net;
+#[cfg(target_arch = "x86_64")]
+pub mod pci;
+#[cfg(target_arch = "x86_64")]
+pub mod pit;
+#[cfg(target_arch = "x86_64")]
+pub mod rtc;
+pub mod serial;
 pub mod vfs;
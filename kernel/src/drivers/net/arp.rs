@@ -0,0 +1,68 @@
+// arp over ethernet for ipv4, what resolves an ip address to a mac address
+// only the requests for our address are answered, nothing is cached since every reply goes back
+// to the mac address the request came from
+
+use super::{ipv4::Ipv4Address, MacAddress};
+
+pub const PACKET_SIZE: usize = 28;
+
+const HARDWARE_ETHERNET: u16 = 1;
+const PROTOCOL_IPV4: u16 = 0x0800;
+
+pub const OPERATION_REQUEST: u16 = 1;
+pub const OPERATION_REPLY: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    /// None if `packet` is too short or isn't ethernet and ipv4 arp
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < PACKET_SIZE {
+            return None;
+        }
+
+        let hardware = u16::from_be_bytes([packet[0], packet[1]]);
+        let protocol = u16::from_be_bytes([packet[2], packet[3]]);
+        if hardware != HARDWARE_ETHERNET || protocol != PROTOCOL_IPV4 || packet[4..6] != [6, 4] {
+            return None;
+        }
+
+        Some(Self {
+            operation: u16::from_be_bytes([packet[6], packet[7]]),
+            sender_mac: packet[8..14].try_into().unwrap(),
+            sender_ip: packet[14..18].try_into().unwrap(),
+            target_mac: packet[18..24].try_into().unwrap(),
+            target_ip: packet[24..28].try_into().unwrap(),
+        })
+    }
+
+    /// the reply to this request from `mac` which has the target address
+    pub fn reply(&self, mac: MacAddress) -> Self {
+        Self {
+            operation: OPERATION_REPLY,
+            sender_mac: mac,
+            sender_ip: self.target_ip,
+            target_mac: self.sender_mac,
+            target_ip: self.sender_ip,
+        }
+    }
+
+    /// writes the packet to the start of `buffer` which must be at least `PACKET_SIZE` bytes
+    pub fn write(&self, buffer: &mut [u8]) {
+        buffer[0..2].copy_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        buffer[2..4].copy_from_slice(&PROTOCOL_IPV4.to_be_bytes());
+        buffer[4..6].copy_from_slice(&[6, 4]);
+        buffer[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        buffer[8..14].copy_from_slice(&self.sender_mac);
+        buffer[14..18].copy_from_slice(&self.sender_ip);
+        buffer[18..24].copy_from_slice(&self.target_mac);
+        buffer[24..28].copy_from_slice(&self.target_ip);
+    }
+}
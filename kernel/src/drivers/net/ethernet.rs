@@ -0,0 +1,38 @@
+// the ethernet header in front of every frame, the fields are big endian
+
+use super::MacAddress;
+
+pub const HEADER_SIZE: usize = 14;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    /// the header of `frame` and its payload, None if `frame` is too short
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let header = Self {
+            destination: frame[0..6].try_into().unwrap(),
+            source: frame[6..12].try_into().unwrap(),
+            ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+        };
+        Some((header, &frame[HEADER_SIZE..]))
+    }
+
+    /// writes the header to the start of `buffer` which must be at least `HEADER_SIZE` bytes
+    pub fn write(&self, buffer: &mut [u8]) {
+        buffer[0..6].copy_from_slice(&self.destination);
+        buffer[6..12].copy_from_slice(&self.source);
+        buffer[12..14].copy_from_slice(&self.ethertype.to_be_bytes());
+    }
+}
@@ -0,0 +1,31 @@
+// icmp over ipv4, only the echo requests (ping) are answered
+
+use super::ipv4::checksum;
+
+pub const HEADER_SIZE: usize = 8;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// writes the reply to the echo request `message` to the start of `buffer`, the identifier,
+/// sequence number and data are the request's
+/// returns the size of the reply, None if `message` isn't a valid echo request or doesn't fit in
+/// `buffer`
+pub fn echo_reply(message: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    if message.len() < HEADER_SIZE || message.len() > buffer.len() {
+        return None;
+    }
+
+    if message[0] != TYPE_ECHO_REQUEST || message[1] != 0 || checksum(message) != 0 {
+        return None;
+    }
+
+    let reply = &mut buffer[..message.len()];
+    reply.copy_from_slice(message);
+    reply[0] = TYPE_ECHO_REPLY;
+    reply[2..4].copy_from_slice(&[0, 0]);
+
+    let checksum = checksum(reply);
+    reply[2..4].copy_from_slice(&checksum.to_be_bytes());
+    Some(message.len())
+}
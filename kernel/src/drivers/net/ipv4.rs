@@ -0,0 +1,105 @@
+// the ipv4 header, without options when sending and ignoring them when receiving
+// fragments aren't reassembled, they are dropped
+
+pub type Ipv4Address = [u8; 4];
+
+pub const HEADER_SIZE: usize = 20;
+const VERSION: u8 = 4;
+/// the time to live of the packets we send
+const DEFAULT_TTL: u8 = 64;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+
+const FLAG_MORE_FRAGMENTS: u16 = 1 << 13;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+
+/// the internet checksum, the ones' complement of the ones' complement sum of the 16 bit big
+/// endian words of `data` (padded with a zero byte if its length is odd)
+/// a header with its checksum field filled in sums to 0
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub identification: u16,
+}
+
+impl Ipv4Header {
+    pub fn new(source: Ipv4Address, destination: Ipv4Address, protocol: u8) -> Self {
+        Self {
+            source,
+            destination,
+            protocol,
+            ttl: DEFAULT_TTL,
+            identification: 0,
+        }
+    }
+
+    /// the header of `packet` and its payload, None if it is malformed, its checksum is wrong or
+    /// it is a fragment
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_SIZE || packet[0] >> 4 != VERSION {
+            return None;
+        }
+
+        let header_len = (packet[0] & 0xF) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        // the frame may be padded after the packet
+        if header_len < HEADER_SIZE || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+
+        if checksum(&packet[..header_len]) != 0 {
+            return None;
+        }
+
+        let fragment = u16::from_be_bytes([packet[6], packet[7]]);
+        if fragment & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+            return None;
+        }
+
+        let header = Self {
+            source: packet[12..16].try_into().unwrap(),
+            destination: packet[16..20].try_into().unwrap(),
+            protocol: packet[9],
+            ttl: packet[8],
+            identification: u16::from_be_bytes([packet[4], packet[5]]),
+        };
+        Some((header, &packet[header_len..total_len]))
+    }
+
+    /// writes the header of a packet with `payload_len` bytes of payload to the start of `buffer`
+    /// which must be at least `HEADER_SIZE` bytes
+    pub fn write(&self, buffer: &mut [u8], payload_len: usize) {
+        let total_len = (HEADER_SIZE + payload_len) as u16;
+
+        buffer[0] = VERSION << 4 | (HEADER_SIZE / 4) as u8;
+        // no dscp nor ecn
+        buffer[1] = 0;
+        buffer[2..4].copy_from_slice(&total_len.to_be_bytes());
+        buffer[4..6].copy_from_slice(&self.identification.to_be_bytes());
+        // not fragmented
+        buffer[6..8].copy_from_slice(&[0, 0]);
+        buffer[8] = self.ttl;
+        buffer[9] = self.protocol;
+        buffer[10..12].copy_from_slice(&[0, 0]);
+        buffer[12..16].copy_from_slice(&self.source);
+        buffer[16..20].copy_from_slice(&self.destination);
+
+        let checksum = checksum(&buffer[..HEADER_SIZE]);
+        buffer[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
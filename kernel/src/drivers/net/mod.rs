@@ -0,0 +1,152 @@
+// network cards as raw ethernet frames in and out, there is no ip stack on top yet
+// a frame is the destination and source mac addresses, the ethertype and the payload, without
+// the preamble and the fcs which the card deals with
+// there is one card at most, `init` takes the first one a driver knows (only virtio-net for now)
+// on top of it `net_thread` answers the arp requests for `IP_ADDRESS` and the pings to it, the
+// address is static (the one qemu's user network gives its guest) and nothing else is handled
+
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod virtio;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{log, threading::sync::CondVar, utils::Locked};
+
+use arp::{ArpPacket, OPERATION_REQUEST};
+use ethernet::{EthernetHeader, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use ipv4::{Ipv4Address, Ipv4Header, PROTOCOL_ICMP};
+
+pub type MacAddress = [u8; 6];
+
+pub const BROADCAST: MacAddress = [0xFF; 6];
+/// the biggest frame without a vlan tag, 1500 bytes of payload
+pub const MAX_FRAME_SIZE: usize = 1514;
+/// the smallest frame, the card pads the shorter ones
+pub const MIN_FRAME_SIZE: usize = 60;
+
+pub const IP_ADDRESS: Ipv4Address = [10, 0, 2, 15];
+
+pub trait NetDevice: Send {
+    fn mac_address(&self) -> MacAddress;
+    /// queues `frame` to be sent, returns Err(()) if it is bigger than `MAX_FRAME_SIZE` or the
+    /// card has too many frames to send already
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), ()>;
+    /// the oldest frame received, doesn't wait
+    fn receive_frame(&mut self) -> Option<Vec<u8>>;
+    /// takes the frames the card received and the ones it sent since the last poll, the bottom
+    /// half of the card interrupt
+    fn poll(&mut self);
+}
+
+static DEVICE: Locked<Option<Box<dyn NetDevice>>> = Locked::new(None);
+/// notified with `DEVICE` locked once the card may have received frames
+static RECEIVED: CondVar = CondVar::new();
+
+/// looks for a network card, not having one isn't an error
+pub fn init() {
+    let Some(device) = virtio::VirtioNet::probe() else {
+        log!("net: no network card\n");
+        return;
+    };
+
+    log!("net: virtio-net {:02x?}\n", device.mac_address());
+    *DEVICE.lock() = Some(Box::new(device));
+}
+
+/// calls `f` with the network card, None if there is none
+pub fn with_device<R>(f: impl FnOnce(&mut dyn NetDevice) -> R) -> Option<R> {
+    let mut device = DEVICE.lock();
+    device.as_mut().map(|device| f(device.as_mut()))
+}
+
+/// the bottom half of the card interrupt, see `softirq`
+pub fn poll_device() {
+    let mut device = DEVICE.lock();
+    if let Some(device) = device.as_mut() {
+        device.poll();
+    }
+    RECEIVED.notify_all();
+}
+
+/// wether or not there is a network card
+#[inline]
+pub fn has_device() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// writes the answer of `mac` (with `IP_ADDRESS`) to `frame` in `reply`
+/// returns the size of the answer, None if `frame` doesn't need one
+pub fn respond(mac: MacAddress, frame: &[u8], reply: &mut [u8; MAX_FRAME_SIZE]) -> Option<usize> {
+    let (ethernet, payload) = EthernetHeader::parse(frame)?;
+    if ethernet.destination != mac && ethernet.destination != BROADCAST {
+        return None;
+    }
+
+    let payload_len = match ethernet.ethertype {
+        ETHERTYPE_ARP => {
+            let request = ArpPacket::parse(payload)?;
+            if request.operation != OPERATION_REQUEST || request.target_ip != IP_ADDRESS {
+                return None;
+            }
+
+            request
+                .reply(mac)
+                .write(&mut reply[ethernet::HEADER_SIZE..]);
+            arp::PACKET_SIZE
+        }
+        ETHERTYPE_IPV4 => {
+            let (ip, payload) = Ipv4Header::parse(payload)?;
+            if ip.destination != IP_ADDRESS || ip.protocol != PROTOCOL_ICMP {
+                return None;
+            }
+
+            let packet = &mut reply[ethernet::HEADER_SIZE..];
+            let len = icmp::echo_reply(payload, &mut packet[ipv4::HEADER_SIZE..])?;
+            Ipv4Header::new(IP_ADDRESS, ip.source, PROTOCOL_ICMP).write(packet, len);
+            ipv4::HEADER_SIZE + len
+        }
+        _ => return None,
+    };
+
+    EthernetHeader {
+        destination: ethernet.source,
+        source: mac,
+        ethertype: ethernet.ethertype,
+    }
+    .write(reply);
+
+    // padded here so what is left of the previous reply isn't sent
+    let len = ethernet::HEADER_SIZE + payload_len;
+    if len < MIN_FRAME_SIZE {
+        reply[len..MIN_FRAME_SIZE].fill(0);
+    }
+    Some(len.max(MIN_FRAME_SIZE))
+}
+
+/// answers the frames the card receives with `respond`, the card must be there
+pub fn net_thread() -> ! {
+    let mac = with_device(|device| device.mac_address()).expect("net thread without a card");
+    let mut reply = [0; MAX_FRAME_SIZE];
+
+    loop {
+        let mut device = DEVICE.lock();
+        let frame = loop {
+            match device.as_mut().and_then(|device| device.receive_frame()) {
+                Some(frame) => break frame,
+                None => device = RECEIVED.wait(&DEVICE, device),
+            }
+        };
+        drop(device);
+
+        let Some(len) = respond(mac, &frame, &mut reply) else {
+            continue;
+        };
+
+        if with_device(|device| device.send_frame(&reply[..len])) != Some(Ok(())) {
+            log!("net: dropped a reply\n");
+        }
+    }
+}
@@ -0,0 +1,506 @@
+// the virtio-net card qemu emulates (`-device virtio-net-pci`), through the modern (virtio 1.0)
+// pci interface: vendor capabilities point at the common, notify and device config structures
+// in the bars which are mapped with `map_mmio`
+// the card has a receive queue (0) and a transmit queue (1), a queue is split in 3 parts:
+// - the descriptors, each one is the physical address and length of a buffer
+// - the available ring, the descriptors given to the card
+// - the used ring, the descriptors the card is done with and how many bytes it wrote to them
+// every descriptor of a queue has its own `BUFFER_SIZE` buffer for good, the receive buffers
+// are all given to the card at once and given back as soon as their frame is copied out, the
+// transmit ones are given when there is a frame to send and free again once used
+// every frame starts with a `NET_HEADER_SIZE` header describing offloads, none are used so it is
+// zeroed for sending and skipped for receiving
+// the receive queue interrupts through msi-x on a vector from `idt::alloc_vector`, the handler
+// only raises `net::poll_device` as a softirq which moves the used buffers to `received`
+
+use core::sync::atomic::{fence, AtomicU8, Ordering};
+
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+
+use crate::{
+    arch::x86_64::interrupts::{
+        apic::send_eoi,
+        count,
+        idt::{self, ATTR_INT},
+        InterruptFrame,
+    },
+    drivers::pci::{self, Bar, PciDevice, CAPABILITY_VENDOR},
+    kernel, log,
+    memory::{frame_allocator::Frame, phys_to_virt, vmm::map_mmio, PhysAddr, VirtAddr},
+    threading::softirq,
+};
+
+use super::{poll_device, MacAddress, NetDevice, MAX_FRAME_SIZE};
+
+const VIRTIO_VENDOR: u16 = 0x1AF4;
+/// the transitional and the modern only virtio-net device ids
+const DEVICE_IDS: [u16; 2] = [0x1000, 0x1041];
+
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_DEVICE: u8 = 4;
+
+// the common config registers
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const MSIX_CONFIG: usize = 0x10;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+
+const FEATURE_MAC: u64 = 1 << 5;
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// no msi-x entry, the queue doesn't interrupt
+const NO_VECTOR: u16 = 0xFFFF;
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// the descriptors of a queue, less if the card can't take that many
+const MAX_QUEUE_SIZE: u16 = 64;
+const DESCRIPTOR_SIZE: usize = 16;
+/// where the parts of a queue are in its frame, big enough for `MAX_QUEUE_SIZE`
+const AVAILABLE_OFFSET: usize = 1024;
+const USED_OFFSET: usize = 2048;
+
+const DESCRIPTOR_WRITE: u16 = 2;
+
+/// `struct virtio_net_hdr` with `VIRTIO_F_VERSION_1`
+const NET_HEADER_SIZE: usize = 12;
+const BUFFER_SIZE: usize = 2048;
+const BUFFERS_PER_FRAME: usize = 4096 / BUFFER_SIZE;
+
+/// the frames kept received but not read yet, the newest are dropped past that
+const MAX_RECEIVED: usize = 128;
+
+/// the vector of the receive interrupt
+static VECTOR: AtomicU8 = AtomicU8::new(0);
+
+extern "x86-interrupt" fn interrupt_handler(_frame: InterruptFrame) {
+    count(VECTOR.load(Ordering::Relaxed));
+    _ = softirq::raise(poll_device);
+    send_eoi();
+}
+
+/// a split virtqueue with a buffer for each descriptor
+struct Queue {
+    index: u16,
+    size: u16,
+    /// holds the descriptors, the available ring and the used ring
+    frame: Frame,
+    /// `BUFFERS_PER_FRAME` buffers each
+    buffers: Vec<Frame>,
+    /// where the queue index is written to tell the card there is something new
+    notify: VirtAddr,
+    /// the available index as written next
+    available: u16,
+    /// the used index as read last
+    used: u16,
+}
+
+impl Queue {
+    fn new(index: u16, size: u16, notify: VirtAddr) -> Option<Self> {
+        let frame = kernel().frame_allocator().allocate_frame()?;
+        unsafe {
+            phys_to_virt(frame.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, 4096)
+        };
+
+        let mut queue = Self {
+            index,
+            size,
+            frame,
+            buffers: Vec::new(),
+            notify,
+            available: 0,
+            used: 0,
+        };
+
+        for _ in 0..(size as usize).div_ceil(BUFFERS_PER_FRAME) {
+            let frame = kernel().frame_allocator().allocate_frame()?;
+            queue.buffers.push(frame);
+        }
+
+        for descriptor in 0..size {
+            let addr = queue.buffer(descriptor);
+            let ptr = queue.descriptor(descriptor);
+            unsafe {
+                ptr.cast::<u64>().write_volatile(addr.as_u64());
+                ptr.add(8).cast::<u32>().write_volatile(BUFFER_SIZE as u32);
+            }
+        }
+
+        Some(queue)
+    }
+
+    #[inline]
+    fn base(&self) -> *mut u8 {
+        phys_to_virt(self.frame.start_address()).as_mut_ptr()
+    }
+
+    #[inline]
+    fn descriptor(&self, descriptor: u16) -> *mut u8 {
+        unsafe { self.base().add(descriptor as usize * DESCRIPTOR_SIZE) }
+    }
+
+    /// the physical address of the buffer of `descriptor`
+    fn buffer(&self, descriptor: u16) -> PhysAddr {
+        let descriptor = descriptor as usize;
+        self.buffers[descriptor / BUFFERS_PER_FRAME].start_address()
+            + (descriptor % BUFFERS_PER_FRAME) * BUFFER_SIZE
+    }
+
+    fn set_descriptor(&mut self, descriptor: u16, len: usize, flags: u16) {
+        let ptr = self.descriptor(descriptor);
+        unsafe {
+            ptr.add(8).cast::<u32>().write_volatile(len as u32);
+            ptr.add(12).cast::<u16>().write_volatile(flags);
+        }
+    }
+
+    /// gives `descriptor` to the card, it only sees it after `Self::notify`
+    fn make_available(&mut self, descriptor: u16) {
+        let ring = unsafe { self.base().add(AVAILABLE_OFFSET + 4) }.cast::<u16>();
+        unsafe {
+            ring.add((self.available % self.size) as usize)
+                .write_volatile(descriptor)
+        };
+        self.available = self.available.wrapping_add(1);
+    }
+
+    /// publishes the descriptors made available and tells the card
+    fn notify(&mut self) {
+        let index = unsafe { self.base().add(AVAILABLE_OFFSET + 2) }.cast::<u16>();
+        // the ring entries have to be written before the index
+        fence(Ordering::SeqCst);
+        unsafe { index.write_volatile(self.available) };
+        fence(Ordering::SeqCst);
+        unsafe { self.notify.as_mut_ptr::<u16>().write_volatile(self.index) };
+    }
+
+    /// the next descriptor the card is done with and the bytes it wrote to it
+    fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used = unsafe { self.base().add(USED_OFFSET) };
+        let index = unsafe { used.add(2).cast::<u16>().read_volatile() };
+        if index == self.used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let element = unsafe { used.add(4 + (self.used % self.size) as usize * 8) };
+        let (descriptor, len) = unsafe {
+            (
+                element.cast::<u32>().read_volatile(),
+                element.add(4).cast::<u32>().read_volatile(),
+            )
+        };
+        self.used = self.used.wrapping_add(1);
+        Some((descriptor as u16, len as usize))
+    }
+}
+
+/// only dropped when the setup failed, the card never got a buffer of the queue to write to
+impl Drop for Queue {
+    fn drop(&mut self) {
+        let mut frame_allocator = kernel().frame_allocator();
+        for &frame in &self.buffers {
+            frame_allocator.deallocate_frame(frame);
+        }
+        frame_allocator.deallocate_frame(self.frame);
+    }
+}
+
+/// the common config structure, every register is read and written as a whole
+struct CommonConfig(VirtAddr);
+
+impl CommonConfig {
+    fn read<T>(&self, offset: usize) -> T {
+        unsafe { (self.0 + offset).as_ptr::<T>().read_volatile() }
+    }
+
+    fn write<T>(&self, offset: usize, value: T) {
+        unsafe { (self.0 + offset).as_mut_ptr::<T>().write_volatile(value) }
+    }
+
+    /// the 64 bit registers take 2 writes, the card doesn't have to take 8 byte accesses
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.write::<u32>(offset, value as u32);
+        self.write::<u32>(offset + 4, (value >> 32) as u32);
+    }
+
+    fn features(&self) -> u64 {
+        self.write::<u32>(DEVICE_FEATURE_SELECT, 0);
+        let low = self.read::<u32>(DEVICE_FEATURE) as u64;
+        self.write::<u32>(DEVICE_FEATURE_SELECT, 1);
+        (self.read::<u32>(DEVICE_FEATURE) as u64) << 32 | low
+    }
+
+    fn set_features(&self, features: u64) {
+        self.write::<u32>(DRIVER_FEATURE_SELECT, 0);
+        self.write::<u32>(DRIVER_FEATURE, features as u32);
+        self.write::<u32>(DRIVER_FEATURE_SELECT, 1);
+        self.write::<u32>(DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    /// creates the queue `index` and hands it to the card, `vector` is the msi-x entry it
+    /// interrupts through
+    fn setup_queue(
+        &self,
+        index: u16,
+        notify: VirtAddr,
+        multiplier: usize,
+        vector: u16,
+    ) -> Result<Queue, ()> {
+        self.write::<u16>(QUEUE_SELECT, index);
+        let max = self.read::<u16>(QUEUE_SIZE);
+        if max == 0 {
+            return Err(());
+        }
+
+        // split queues have power of 2 sizes
+        let size = MAX_QUEUE_SIZE.min(max);
+        let notify_offset = self.read::<u16>(QUEUE_NOTIFY_OFF) as usize * multiplier;
+        let queue = Queue::new(index, size, notify + notify_offset).ok_or(())?;
+        let base = queue.frame.start_address();
+
+        self.write::<u16>(QUEUE_SIZE, size);
+        self.write_u64(QUEUE_DESC, base.as_u64());
+        self.write_u64(QUEUE_DRIVER, (base + AVAILABLE_OFFSET).as_u64());
+        self.write_u64(QUEUE_DEVICE, (base + USED_OFFSET).as_u64());
+
+        self.write::<u16>(QUEUE_MSIX_VECTOR, vector);
+        if self.read::<u16>(QUEUE_MSIX_VECTOR) != vector {
+            return Err(());
+        }
+
+        self.write::<u16>(QUEUE_ENABLE, 1);
+        Ok(queue)
+    }
+}
+
+pub struct VirtioNet {
+    mac: MacAddress,
+    receive: Queue,
+    transmit: Queue,
+    /// the transmit descriptors the card isn't using
+    free: Vec<u16>,
+    received: VecDeque<Vec<u8>>,
+}
+
+// the queue frames are only touched through `&mut self`
+unsafe impl Send for VirtioNet {}
+
+/// the memory `cap` (a virtio vendor capability) points at
+fn map_capability(pci: &PciDevice, cap: u8) -> Option<VirtAddr> {
+    let bar = pci.read_u8(cap + 4);
+    let offset = pci.read(cap + 8) as usize;
+    let len = pci.read(cap + 12) as usize;
+
+    let Some(Bar::Memory { addr, .. }) = pci.bar(bar) else {
+        return None;
+    };
+    map_mmio(addr + offset, len)
+}
+
+impl VirtioNet {
+    /// finds, resets and sets up the first virtio-net card, None if there is none or it can't be
+    /// used
+    pub fn probe() -> Option<Self> {
+        let pci = pci::find(VIRTIO_VENDOR, &DEVICE_IDS)?;
+        pci.enable_bus_master();
+
+        let mut common = None;
+        let mut notify = None;
+        let mut device = None;
+        for (id, cap) in pci.capabilities() {
+            if id != CAPABILITY_VENDOR {
+                continue;
+            }
+
+            match pci.read_u8(cap + 3) {
+                CFG_COMMON if common.is_none() => common = map_capability(&pci, cap),
+                CFG_NOTIFY if notify.is_none() => {
+                    let multiplier = pci.read(cap + 16) as usize;
+                    notify = map_capability(&pci, cap).map(|addr| (addr, multiplier));
+                }
+                CFG_DEVICE if device.is_none() => device = map_capability(&pci, cap),
+                _ => {}
+            }
+        }
+
+        let (Some(common), Some((notify, multiplier))) = (common, notify) else {
+            log!("virtio-net: no modern interface\n");
+            return None;
+        };
+
+        let common = CommonConfig(common);
+        match Self::init(pci, &common, notify, multiplier, device) {
+            Ok(net) => Some(net),
+            Err(()) => {
+                log!("virtio-net: the card refused to be set up\n");
+                // the card stays reset
+                common.write::<u8>(DEVICE_STATUS, 0);
+                None
+            }
+        }
+    }
+
+    fn init(
+        pci: PciDevice,
+        common: &CommonConfig,
+        notify: VirtAddr,
+        multiplier: usize,
+        device: Option<VirtAddr>,
+    ) -> Result<Self, ()> {
+        // reset, the card reads 0 once it is done
+        common.write::<u8>(DEVICE_STATUS, 0);
+        if !(0..1_000_000).any(|_| common.read::<u8>(DEVICE_STATUS) == 0) {
+            return Err(());
+        }
+        common.write::<u8>(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let features = common.features();
+        if features & FEATURE_VERSION_1 == 0 {
+            return Err(());
+        }
+
+        let features = features & (FEATURE_VERSION_1 | FEATURE_MAC);
+        common.set_features(features);
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        common.write::<u8>(DEVICE_STATUS, status);
+        if common.read::<u8>(DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            return Err(());
+        }
+
+        let vector = idt::alloc_vector().ok_or(())?;
+        Self::setup(pci, common, notify, multiplier, device, features, vector).inspect_err(|_| {
+            // nothing raises the vector anymore once it is freed
+            common.write::<u8>(DEVICE_STATUS, 0);
+            pci.disable_msix();
+            _ = idt::free_vector(vector);
+        })
+    }
+
+    /// the part of `Self::init` after the features are negotiated, it interrupts through
+    /// `vector`
+    fn setup(
+        pci: PciDevice,
+        common: &CommonConfig,
+        notify: VirtAddr,
+        multiplier: usize,
+        device: Option<VirtAddr>,
+        features: u64,
+        vector: u8,
+    ) -> Result<Self, ()> {
+        VECTOR.store(vector, Ordering::Relaxed);
+        idt::set_handler(vector, interrupt_handler, ATTR_INT)?;
+        pci.enable_msix(0, vector)?;
+
+        common.write::<u16>(MSIX_CONFIG, NO_VECTOR);
+        let receive = common.setup_queue(RECEIVE_QUEUE, notify, multiplier, 0)?;
+        let transmit = common.setup_queue(TRANSMIT_QUEUE, notify, multiplier, NO_VECTOR)?;
+
+        let mut mac = [0; 6];
+        if let Some(device) = device.filter(|_| features & FEATURE_MAC != 0) {
+            for (index, byte) in mac.iter_mut().enumerate() {
+                *byte = unsafe { (device + index).as_ptr::<u8>().read_volatile() };
+            }
+        }
+
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        common.write::<u8>(DEVICE_STATUS, status | STATUS_DRIVER_OK);
+
+        let mut net = Self {
+            mac,
+            free: (0..transmit.size).rev().collect(),
+            receive,
+            transmit,
+            received: VecDeque::new(),
+        };
+
+        // every receive buffer goes to the card
+        for descriptor in 0..net.receive.size {
+            net.receive
+                .set_descriptor(descriptor, BUFFER_SIZE, DESCRIPTOR_WRITE);
+            net.receive.make_available(descriptor);
+        }
+        net.receive.notify();
+
+        Ok(net)
+    }
+}
+
+impl NetDevice for VirtioNet {
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), ()> {
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(());
+        }
+
+        self.poll();
+        let descriptor = self.free.pop().ok_or(())?;
+
+        let buffer = phys_to_virt(self.transmit.buffer(descriptor)).as_mut_ptr::<u8>();
+        unsafe {
+            buffer.write_bytes(0, NET_HEADER_SIZE);
+            buffer
+                .add(NET_HEADER_SIZE)
+                .copy_from_nonoverlapping(frame.as_ptr(), frame.len());
+        }
+
+        self.transmit
+            .set_descriptor(descriptor, NET_HEADER_SIZE + frame.len(), 0);
+        self.transmit.make_available(descriptor);
+        self.transmit.notify();
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Option<Vec<u8>> {
+        self.poll();
+        self.received.pop_front()
+    }
+
+    fn poll(&mut self) {
+        while let Some((descriptor, _)) = self.transmit.pop_used() {
+            self.free.push(descriptor);
+        }
+
+        let mut received = false;
+        while let Some((descriptor, len)) = self.receive.pop_used() {
+            received = true;
+            let len = len.saturating_sub(NET_HEADER_SIZE).min(MAX_FRAME_SIZE);
+            let buffer = phys_to_virt(self.receive.buffer(descriptor) + NET_HEADER_SIZE);
+
+            if self.received.len() < MAX_RECEIVED {
+                let frame = unsafe { core::slice::from_raw_parts(buffer.as_ptr::<u8>(), len) };
+                self.received.push_back(frame.to_vec());
+            }
+
+            self.receive.make_available(descriptor);
+        }
+
+        if received {
+            self.receive.notify();
+            // whoever waits for a frame gets to see it
+            crate::threading::timer::wake();
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// the pci bus through the legacy configuration mechanism, the address of a config register
+// (bus, device, function, offset) is written to `CONFIG_ADDRESS` then the register is read
+// through `CONFIG_DATA` 4 bytes at a time, the writes are 2 bytes because the registers we
+// write are next to a status register (writing 1 to a status bit clears it, a 4 byte write would
+// clear every bit that was set)
+// the firmware already assigned the bars so drivers only read them, the devices are found by
+// checking every bus, device and function for a vendor id
+// the interrupts are msi-x (see `PciDevice::enable_msix`), the legacy INTx lines would need the
+// acpi `_PRT` to know where they end up on the ioapic
+
+use alloc::vec::Vec;
+
+use crate::{
+    arch::x86_64::{inl, interrupts::apic, outl, outw},
+    memory::{vmm::map_mmio, PhysAddr},
+};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const REGISTER_VENDOR_DEVICE: u8 = 0x00;
+const REGISTER_COMMAND: u8 = 0x04;
+const REGISTER_CLASS: u8 = 0x08;
+const REGISTER_HEADER_TYPE: u8 = 0x0E;
+const REGISTER_BAR0: u8 = 0x10;
+const REGISTER_CAPABILITIES: u8 = 0x34;
+
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTI_FUNCTION: u8 = 1 << 7;
+/// no device answers at this address
+const NO_VENDOR: u16 = 0xFFFF;
+
+pub const CAPABILITY_MSIX: u8 = 0x11;
+pub const CAPABILITY_VENDOR: u8 = 0x09;
+
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_TABLE_SIZE: u16 = 0x7FF;
+const MSIX_ENTRY_SIZE: usize = 16;
+/// where msi writes go to reach the local apic, the destination apic id is at bit 12
+const MSI_ADDRESS: u32 = 0xFEE0_0000;
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    CONFIG_ENABLE
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset & 0xFC) as u32
+}
+
+/// reads the 4 bytes of config space `offset` is in
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    outl(
+        CONFIG_ADDRESS,
+        config_address(bus, device, function, offset),
+    );
+    inl(CONFIG_DATA)
+}
+
+/// writes the 2 bytes of config space at `offset`, it must be 2 byte aligned
+pub fn write_config_u16(bus: u8, device: u8, function: u8, offset: u8, value: u16) {
+    debug_assert!(
+        offset & 1 == 0,
+        "unaligned config space write at {:#x}",
+        offset
+    );
+    outl(
+        CONFIG_ADDRESS,
+        config_address(bus, device, function, offset),
+    );
+    outw(CONFIG_DATA + (offset & 2) as u16, value)
+}
+
+/// a base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { addr: PhysAddr, prefetchable: bool },
+    Io { port: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+}
+
+impl PciDevice {
+    /// None if there is no device at `bus`:`device`.`function`
+    fn probe(bus: u8, device: u8, function: u8) -> Option<Self> {
+        let ids = read_config(bus, device, function, REGISTER_VENDOR_DEVICE);
+        let vendor_id = ids as u16;
+        if vendor_id == NO_VENDOR {
+            return None;
+        }
+
+        let class = read_config(bus, device, function, REGISTER_CLASS);
+        Some(Self {
+            bus,
+            device,
+            function,
+            vendor_id,
+            device_id: (ids >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+        })
+    }
+
+    #[inline]
+    pub fn read(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    #[inline]
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// writes only these 2 bytes, the other 2 of the register aren't touched
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        write_config_u16(self.bus, self.device, self.function, offset, value)
+    }
+
+    /// the bar `index` (0 to 5), None if it isn't implemented
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        debug_assert!(index < 6, "there is no bar {}", index);
+        let offset = REGISTER_BAR0 + index * 4;
+        let bar = self.read(offset);
+
+        if bar & 1 != 0 {
+            return Some(Bar::Io {
+                port: (bar & !0x3) as u16,
+            });
+        }
+
+        let mut addr = (bar & !0xF) as u64;
+        // a 64 bit bar takes the next register too
+        if (bar >> 1) & 0b11 == 0b10 {
+            addr |= (self.read(offset + 4) as u64) << 32;
+        }
+
+        (addr != 0).then_some(Bar::Memory {
+            addr: PhysAddr::new(addr as usize),
+            prefetchable: bar & (1 << 3) != 0,
+        })
+    }
+
+    /// lets the device answer memory accesses and access memory itself (dma), its INTx line is
+    /// disabled since only msi-x is used
+    pub fn enable_bus_master(&self) {
+        let command = self.read_u16(REGISTER_COMMAND);
+        self.write_u16(
+            REGISTER_COMMAND,
+            command | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE,
+        );
+    }
+
+    /// the capabilities of the device as (id, offset in config space)
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let status = (self.read(REGISTER_COMMAND) >> 16) as u16;
+        let mut next = if status & STATUS_CAPABILITIES != 0 {
+            self.read_u8(REGISTER_CAPABILITIES) & 0xFC
+        } else {
+            0
+        };
+
+        // a broken list can't loop forever, there are at most 48 capabilities in 256 bytes
+        (0..48).map_while(move |_| {
+            if next == 0 {
+                return None;
+            }
+
+            let offset = next;
+            let header = self.read(offset);
+            next = (header >> 8) as u8 & 0xFC;
+            Some((header as u8, offset))
+        })
+    }
+
+    /// the offset of the first capability `id`
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities()
+            .find(|&(capability, _)| capability == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// points the msi-x entry `entry` at `vector` on the current cpu and enables msi-x, returns
+    /// Err(()) if the device doesn't have msi-x, has less entries or its table can't be mapped
+    pub fn enable_msix(&self, entry: u16, vector: u8) -> Result<(), ()> {
+        let capability = self.find_capability(CAPABILITY_MSIX).ok_or(())?;
+        let control = self.read_u16(capability + 2);
+        if entry > control & MSIX_TABLE_SIZE {
+            return Err(());
+        }
+
+        let table = self.read(capability + 4);
+        let Some(Bar::Memory { addr, .. }) = self.bar((table & 0x7) as u8) else {
+            return Err(());
+        };
+
+        let table_addr = addr + (table & !0x7) as usize + entry as usize * MSIX_ENTRY_SIZE;
+        let table = map_mmio(table_addr, MSIX_ENTRY_SIZE).ok_or(())?;
+        let table = table.as_mut_ptr::<u32>();
+
+        unsafe {
+            table.write_volatile(MSI_ADDRESS | apic::local_apic_id() << 12);
+            table.add(1).write_volatile(0);
+            // edge triggered fixed delivery
+            table.add(2).write_volatile(vector as u32);
+            // unmasked
+            table.add(3).write_volatile(0);
+        }
+
+        self.write_u16(
+            capability + 2,
+            (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+        );
+        Ok(())
+    }
+
+    /// stops the device from sending msi-x interrupts, does nothing if it can't
+    pub fn disable_msix(&self) {
+        if let Some(capability) = self.find_capability(CAPABILITY_MSIX) {
+            let control = self.read_u16(capability + 2);
+            self.write_u16(capability + 2, control & !MSIX_ENABLE);
+        }
+    }
+}
+
+/// every device on every bus
+pub fn devices() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=u8::MAX {
+        for device in 0..32 {
+            let Some(first) = PciDevice::probe(bus, device, 0) else {
+                continue;
+            };
+            devices.push(first);
+
+            if first.read_u8(REGISTER_HEADER_TYPE) & HEADER_MULTI_FUNCTION == 0 {
+                continue;
+            }
+
+            for function in 1..8 {
+                devices.extend(PciDevice::probe(bus, device, function));
+            }
+        }
+    }
+
+    devices
+}
+
+/// the first device with `vendor_id` and one of `device_ids`
+pub fn find(vendor_id: u16, device_ids: &[u16]) -> Option<PciDevice> {
+    devices()
+        .into_iter()
+        .find(|device| device.vendor_id == vendor_id && device_ids.contains(&device.device_id))
+}
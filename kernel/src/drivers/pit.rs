@@ -0,0 +1,103 @@
+// the 8254 programmable interval timer, it counts down at a known `PIT_FREQUENCY` which makes it
+// the reference the other timers are measured against (`tsc::calibrate` and the periodic apic
+// timer), channel 0 is wired to isa irq 0 and can drive the tick itself when nothing better is
+// there (see `apic::TickSource::Pit`)
+// channel 2 is the pc speaker one, its gate and output are in `SPEAKER_PORT` so it can be
+// polled without touching the irq 0 channel, `reference` uses it
+
+use crate::arch::x86_64::{inb, outb};
+
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+const SPEAKER_PORT: u16 = 0x61;
+
+/// channel 0, low then high byte, mode 2 (fires every time the count reaches 0)
+const CHANNEL_0_PERIODIC: u8 = 0b0011_0100;
+/// channel 0, low then high byte, mode 0 (fires once the count reaches 0)
+const CHANNEL_0_ONE_SHOT: u8 = 0b0011_0000;
+/// latches the count of channel 0 so both of its bytes are from the same moment
+const CHANNEL_0_LATCH: u8 = 0b0000_0000;
+/// channel 2, low then high byte, mode 0 (the output goes high once the count reaches 0)
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+const SPEAKER_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// the biggest count, a count of 0 is written for it
+const MAX_COUNT: u64 = 0x10000;
+/// how many times the channel 2 output is polled before giving up, a port read takes about a
+/// microsecond so with `MAX_COUNT` this is way past the time the count takes
+const REFERENCE_POLLS: usize = 10_000_000;
+
+fn load_channel_0(command: u8, count: u64) {
+    debug_assert!((1..=MAX_COUNT).contains(&count));
+    outb(COMMAND, command);
+    outb(CHANNEL_0, count as u8);
+    outb(CHANNEL_0, (count >> 8) as u8);
+}
+
+/// fires irq 0 `hz` times a second, returns Err(()) if the pit can't count that fast or that
+/// slow (the slowest is about 18hz)
+pub fn set_periodic(hz: u64) -> Result<(), ()> {
+    if hz == 0 {
+        return Err(());
+    }
+
+    let count = PIT_FREQUENCY / hz;
+    if !(1..=MAX_COUNT).contains(&count) {
+        return Err(());
+    }
+
+    load_channel_0(CHANNEL_0_PERIODIC, count);
+    Ok(())
+}
+
+/// fires irq 0 once after `us` microseconds, returns Err(()) if that is longer than the pit can
+/// count (about 55ms)
+pub fn set_oneshot(us: u64) -> Result<(), ()> {
+    let count = (PIT_FREQUENCY * us / 1_000_000).max(1);
+    if count > MAX_COUNT {
+        return Err(());
+    }
+
+    load_channel_0(CHANNEL_0_ONE_SHOT, count);
+    Ok(())
+}
+
+/// the count channel 0 is at, it goes down `PIT_FREQUENCY` times a second
+pub fn read_count() -> u16 {
+    outb(COMMAND, CHANNEL_0_LATCH);
+    let low = inb(CHANNEL_0);
+    let high = inb(CHANNEL_0);
+    u16::from_le_bytes([low, high])
+}
+
+/// reads `read` right after starting a `ms` milliseconds count on channel 2 and again once it
+/// ran out, returns None if the pit doesn't answer or `ms` is longer than it can count (about
+/// 55ms)
+/// used to measure how fast something counts, the time between both reads is `ms` give or take
+/// a port read
+pub fn reference<T>(ms: u64, read: impl Fn() -> T) -> Option<(T, T)> {
+    let count = PIT_FREQUENCY * ms / 1000;
+    if !(1..=MAX_COUNT).contains(&count) {
+        return None;
+    }
+
+    let speaker = inb(SPEAKER_PORT);
+    outb(SPEAKER_PORT, (speaker & !SPEAKER_ENABLE) | SPEAKER_GATE);
+
+    outb(COMMAND, CHANNEL_2_ONE_SHOT);
+    outb(CHANNEL_2, count as u8);
+    outb(CHANNEL_2, (count >> 8) as u8);
+
+    let start = read();
+    let done = (0..REFERENCE_POLLS).any(|_| inb(SPEAKER_PORT) & CHANNEL_2_OUTPUT != 0);
+    let end = read();
+    outb(SPEAKER_PORT, speaker);
+
+    done.then_some((start, end))
+}
@@ -0,0 +1,122 @@
+// the cmos real time clock, registers are selected by writing their index to `CMOS_ADDRESS`
+// then read from `CMOS_DATA`
+// the clock might be updating while we read it so we read it until we get the same value twice
+
+use core::fmt::Display;
+
+use crate::arch::x86_64::{inb, outb};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// set in the hours register in 12 hour mode if it is pm
+const HOURS_PM: u8 = 1 << 7;
+
+/// the rtc only gives us the last 2 digits of the year
+const CENTURY: u16 = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    // bit 7 disables nmis we keep it clear
+    outb(CMOS_ADDRESS, register & 0x7F);
+    inb(CMOS_DATA)
+}
+
+#[inline]
+fn update_in_progress() -> bool {
+    read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+/// reads the raw registers as they are stored (maybe bcd, maybe 12 hour)
+fn read_raw() -> [u8; 6] {
+    while update_in_progress() {}
+
+    [
+        read_register(REGISTER_SECONDS),
+        read_register(REGISTER_MINUTES),
+        read_register(REGISTER_HOURS),
+        read_register(REGISTER_DAY),
+        read_register(REGISTER_MONTH),
+        read_register(REGISTER_YEAR),
+    ]
+}
+
+#[inline]
+const fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// reads the current wall clock time from the rtc, the rtc is assumed to be in utc
+pub fn now() -> DateTime {
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let [seconds, minutes, hours, day, month, year] = raw;
+    let status_b = read_register(REGISTER_STATUS_B);
+
+    let pm = hours & HOURS_PM != 0;
+    let hours = hours & !HOURS_PM;
+
+    let convert = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+
+    let mut hours = convert(hours);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12am is 0 and 12pm is 12
+        hours %= 12;
+        if pm {
+            hours += 12;
+        }
+    }
+
+    DateTime {
+        year: CENTURY + convert(year) as u16,
+        month: convert(month),
+        day: convert(day),
+        hours,
+        minutes: convert(minutes),
+        seconds: convert(seconds),
+    }
+}
@@ -0,0 +1,37 @@
+// serial input, the serial interrupt handler pushes the received bytes here so we can read
+// lines typed in the host terminal (qemu `-serial stdio`)
+
+use crate::{arch::serial::console, utils::ring_buffer::RingBuffer};
+
+use super::chardev::{self, CharDevice};
+
+static INPUT: RingBuffer<u8, 256> = RingBuffer::new();
+
+/// queues a received byte waking its readers, safe to call from an interrupt handler
+/// the byte is dropped if the queue is full
+#[inline]
+pub fn push_byte(byte: u8) {
+    _ = INPUT.push(byte);
+    chardev::input_ready();
+}
+
+/// the serial console (COM1 on x86_64) as a `CharDevice`, reads what `push_byte` queued and writes to the port
+pub struct SerialConsole;
+
+impl CharDevice for SerialConsole {
+    fn read_byte(&self) -> Option<u8> {
+        INPUT.pop()
+    }
+
+    fn write_byte(&self, byte: u8) {
+        console().write(byte)
+    }
+
+    fn readable(&self) -> bool {
+        !INPUT.is_empty()
+    }
+
+    fn writable(&self) -> bool {
+        console().is_present()
+    }
+}
@@ -0,0 +1,137 @@
+// the initramfs is a cpio archive in the "newc" format, the bootloader passes it to us as the
+// first limine module, if there is no module we use the image embedded in the kernel instead
+// each archive entry is a 110 bytes ascii header, followed by the entry name then the entry
+// data, both the name and the data are padded to 4 bytes, the archive ends with an entry named
+// "TRAILER!!!"
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::str;
+
+use crate::{kernel, memory::phys_to_virt, serial};
+
+use super::{ramfs::RamFS, FSError, FSResult, FS};
+
+/// where the initramfs is mounted, it is the root of everything
+pub const MOUNT_POINT: &str = "/";
+/// the path of the first process the kernel runs
+pub const INIT_PATH: &str = "/bin/init";
+
+static EMBEDDED_IMAGE: &[u8] = include_bytes!("../../../initramfs.cpio");
+
+const HEADER_SIZE: usize = 110;
+const MAGIC: &[u8] = b"070701";
+const TRAILER: &str = "TRAILER!!!";
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_FILE: u32 = 0o100000;
+
+/// returns the initramfs passed by the bootloader, or the embedded one if none was passed
+pub fn image() -> &'static [u8] {
+    match kernel().initramfs {
+        Some((addr, size)) => unsafe {
+            core::slice::from_raw_parts(phys_to_virt(addr).as_ptr::<u8>(), size)
+        },
+        None => {
+            serial!("no initramfs was passed by the bootloader, using the embedded one\n");
+            EMBEDDED_IMAGE
+        }
+    }
+}
+
+struct CpioEntry<'a> {
+    name: &'a str,
+    mode: u32,
+    data: &'a [u8],
+}
+
+/// parses the hex field number `index` of a cpio header
+fn header_field(header: &[u8], index: usize) -> FSResult<u32> {
+    let start = MAGIC.len() + index * 8;
+    let field = str::from_utf8(&header[start..start + 8]).or(Err(FSError::InvaildImage))?;
+
+    u32::from_str_radix(field, 16).or(Err(FSError::InvaildImage))
+}
+
+/// parses the entry starting at `offset` returning it and the offset of the next entry
+fn parse_entry(image: &[u8], offset: usize) -> FSResult<(CpioEntry, usize)> {
+    let header = image
+        .get(offset..offset + HEADER_SIZE)
+        .ok_or(FSError::InvaildImage)?;
+
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(FSError::InvaildImage);
+    }
+
+    let mode = header_field(header, 1)?;
+    let file_size = header_field(header, 6)? as usize;
+    let name_size = header_field(header, 11)? as usize;
+
+    let name_start = offset + HEADER_SIZE;
+    // name_size includes the null terminator
+    let name_end = (name_start + name_size)
+        .checked_sub(1)
+        .ok_or(FSError::InvaildImage)?;
+    let name = image
+        .get(name_start..name_end)
+        .ok_or(FSError::InvaildImage)?;
+    let name = str::from_utf8(name).or(Err(FSError::InvaildImage))?;
+
+    let data_start = align_4(name_start + name_size);
+    let data = image
+        .get(data_start..data_start + file_size)
+        .ok_or(FSError::InvaildImage)?;
+
+    let next = align_4(data_start + file_size);
+    Ok((CpioEntry { name, mode, data }, next))
+}
+
+#[inline]
+const fn align_4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// unpacks a cpio newc archive into a new `RamFS`
+/// directories must come before the entries they contain
+pub fn unpack(image: &[u8]) -> FSResult<RamFS> {
+    let mut ramfs = RamFS::new();
+    let mut offset = 0;
+
+    loop {
+        let (entry, next) = parse_entry(image, offset)?;
+        offset = next;
+
+        if entry.name == TRAILER {
+            break;
+        }
+
+        let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+        if name.is_empty() || name == "." {
+            continue;
+        }
+
+        let (dir, entry_name) = match name.rfind('/') {
+            Some(index) => (&name[..index], &name[index + 1..]),
+            None => ("", name),
+        };
+        let dir_path = String::from("/") + dir;
+
+        match entry.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => ramfs.createdir(&dir_path, entry_name.to_string())?,
+            MODE_FILE => {
+                ramfs.create(&dir_path, entry_name.to_string())?;
+
+                let path = format!("{}/{}", dir_path.trim_end_matches('/'), entry_name);
+                let mut file = ramfs.open(&path)?;
+                ramfs.write(&mut file, entry.data)?;
+                ramfs.close(file)?;
+            }
+            _ => serial!("initramfs: skipping `{}` unsupported entry type\n", name),
+        }
+    }
+
+    Ok(ramfs)
+}
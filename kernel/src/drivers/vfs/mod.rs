@@ -1,5 +1,7 @@
 use crate::{serial, utils::Locked};
+pub mod initramfs;
 pub mod ramfs;
+pub mod tmpfs;
 
 use alloc::{
     boxed::Box,
@@ -22,12 +24,25 @@ pub fn vfs() -> MutexGuard<'static, VFS> {
 pub fn init() {
     serial!("initing the vfs...\n");
     let mut vfs = vfs();
-    let ramfs = Box::new(ramfs::RamFS::new());
-    vfs.mount(b"ram", ramfs).unwrap();
+
+    match initramfs::unpack(initramfs::image()) {
+        Ok(initramfs) => vfs
+            .mount(initramfs::MOUNT_POINT, Box::new(initramfs))
+            .unwrap(),
+        Err(err) => {
+            serial!("failed to unpack the initramfs, error: {:?}\n", err);
+            vfs.mount("/", Box::new(ramfs::RamFS::new())).unwrap();
+        }
+    }
+
+    vfs.mount(ramfs::MOUNT_POINT, Box::new(ramfs::RamFS::new()))
+        .unwrap();
+    vfs.mount(tmpfs::MOUNT_POINT, Box::new(tmpfs::TmpFS::new()))
+        .unwrap();
     serial!("init done ...\n");
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct FileDescriptor {
     pub mountpoint: *mut dyn FS,
@@ -54,8 +69,8 @@ pub enum FSError {
     NotAFile,
     NotADirectory,
     NoSuchAFileOrDirectory,
-    InvaildDrive,
     InvaildPath,
+    InvaildImage,
 }
 
 pub type FSResult<T> = Result<T, FSError>;
@@ -105,6 +120,12 @@ pub trait InodeOps: Send {
     /// from offset
     /// extends the nodes data and node size if `buffer.len` + `offset` is greater then node size
     fn write(&mut self, buffer: &[u8], offset: usize) -> FSResult<()>;
+    /// attempts to resize node data to `size` bytes if it is a file, the bytes added if it grows
+    /// are zeros
+    fn truncate(&mut self, size: usize) -> FSResult<()> {
+        _ = size;
+        Err(FSError::OperationNotSupported)
+    }
 
     /// attempts to insert a node to self
     /// returns an FSError::NotADirectory if not a directory
@@ -200,90 +221,138 @@ pub trait FS: Send {
     fn create(&mut self, path: Path, name: String) -> FSResult<()>;
     /// creates an empty dir named `name` in `path`
     fn createdir(&mut self, path: Path, name: String) -> FSResult<()>;
+    /// attempts to resize the file `file_descriptor` refers to to `size` bytes
+    fn truncate(&mut self, file_descriptor: &mut FileDescriptor, size: usize) -> FSResult<()> {
+        unsafe { (*file_descriptor.node).ops.truncate(size) }
+    }
+}
+
+/// turns an absolute `path` into the path it refers to without `.`, `..`, repeated or trailing
+/// slashes, `..` at the root stays at the root
+pub fn normalize(path: Path) -> FSResult<String> {
+    if !path.starts_with(['/', '\\']) {
+        return Err(FSError::InvaildPath);
+    }
+
+    let mut depths = Vec::new();
+    for depth in path.split(['/', '\\']) {
+        match depth {
+            "" | "." => {}
+            ".." => _ = depths.pop(),
+            depth => depths.push(depth),
+        }
+    }
+
+    Ok(String::from("/") + &depths.join("/"))
+}
+
+/// the rest of `path` in the fs mounted at `mount_point` starting with `/`, None if `path` isn't
+/// under `mount_point`, both must be normalized
+fn path_in_mount<'a>(path: &'a str, mount_point: &str) -> Option<&'a str> {
+    if mount_point == "/" {
+        return Some(path);
+    }
+
+    match path.strip_prefix(mount_point)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// the directory a normalized `path` is in, the root is in itself
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(index) => &path[..index],
+    }
 }
 
+/// the namespace every path goes through, each fs is mounted at an absolute path and a path
+/// belongs to the fs mounted at its longest prefix, the fs gets the rest of it, `/tmp/foo` is
+/// `/foo` in the fs mounted at `/tmp` if there is one, otherwise `/tmp/foo` in the one at `/`
+/// paths are normalized before that so `..` can leave a mounted fs
 pub struct VFS {
-    pub drivers: BTreeMap<Vec<u8>, Box<dyn FS>>,
+    /// the mounted filesystems by their normalized mount point
+    pub mounts: BTreeMap<String, Box<dyn FS>>,
 }
 
 impl VFS {
     pub fn new() -> Self {
         Self {
-            drivers: BTreeMap::new(),
+            mounts: BTreeMap::new(),
         }
     }
-    /// mounts a file system as a drive
-    /// returns Err(()) if not enough memory or there is an already mounted driver with that
-    /// name
-    pub fn mount(&mut self, name: &[u8], value: Box<dyn FS>) -> Result<(), ()> {
-        let name = name.to_vec();
-
-        if self.drivers.contains_key(&name) {
-            Err(())
-        } else {
-            self.drivers.insert(name, value);
-            Ok(())
-        }
-    }
-
-    /// unmounts a drive returns Err(()) if there is no such a drive
-    pub fn umount(&mut self, name: &[u8]) -> Result<(), ()> {
-        self.drivers.remove(name).ok_or(())?;
-        Ok(())
-    }
 
-    /// gets a drive from `self` named "`name`"
-    /// or "`name`:" muttabily
-    pub(self) fn get_with_name_mut(&mut self, name: &[u8]) -> Option<&mut Box<dyn FS>> {
-        let mut name = name;
+    /// mounts a file system at `path`, the directory it is mounted in doesn't have to exist in
+    /// the parent fs
+    /// returns Err(()) if `path` is invaild or there is an already mounted fs there
+    pub fn mount(&mut self, path: Path, mut value: Box<dyn FS>) -> Result<(), ()> {
+        let path = normalize(path).map_err(|_| ())?;
 
-        if name.ends_with(b":") {
-            name = &name[..name.len() - 1];
+        if self.mounts.contains_key(&path) {
+            return Err(());
         }
 
-        self.drivers.get_mut(name)
+        // so it shows up with the right name when its parent is listed
+        value.root_inode_mut().name = path.rsplit('/').next().unwrap().to_string();
+        self.mounts.insert(path, value);
+        Ok(())
     }
 
-    /// gets a drive from `self` named "`name`"
-    /// or "`name`:"
-    pub fn get_with_name(&mut self, name: &[u8]) -> Option<&Box<dyn FS>> {
-        let mut name = name;
-
-        if name.ends_with(b":") {
-            name = &name[..name.len() - 1];
-        }
+    /// unmounts the fs mounted at `path` returns Err(()) if there is no such a mount point
+    pub fn umount(&mut self, path: Path) -> Result<(), ()> {
+        let path = normalize(path).map_err(|_| ())?;
+        self.mounts.remove(&path).ok_or(())?;
+        Ok(())
+    }
 
-        self.drivers.get(name)
+    /// finds the fs `path` belongs to returning it with the path in it
+    pub fn reslove_mount(&mut self, path: Path) -> FSResult<(&mut Box<dyn FS>, String)> {
+        let path = normalize(path)?;
+
+        self.mounts
+            .iter_mut()
+            .filter_map(|(mount_point, fs)| {
+                path_in_mount(&path, mount_point).map(|rest| (mount_point.len(), fs, rest))
+            })
+            .max_by_key(|(len, _, _)| *len)
+            .map(|(_, fs, rest)| (fs, rest.to_string()))
+            .ok_or(FSError::NoSuchAFileOrDirectory)
     }
 
-    /// gets the drive name from `path` then gets the drive
-    /// path must be absolute starting with DRIVE_NAME:/
-    pub(self) fn get_from_path(&mut self, path: Path) -> FSResult<Option<&mut Box<dyn FS>>> {
-        let mut path = path.split(&['/', '\\']);
+    /// the roots of the filesystems mounted directly in the directory `dir` refers to
+    fn mounted_in(&mut self, dir: &FileDescriptor) -> Vec<FileDescriptor> {
+        let mount_points: Vec<String> = self
+            .mounts
+            .keys()
+            .filter(|mount_point| *mount_point != "/")
+            .cloned()
+            .collect();
+
+        let mut mounted = Vec::new();
+        for mount_point in mount_points {
+            let Ok(parent) = self.open(parent(&mount_point)) else {
+                continue;
+            };
 
-        let drive = path.next().ok_or(FSError::InvaildDrive)?;
-        if !(drive.ends_with(':')) {
-            return Err(FSError::InvaildDrive);
-        }
+            let is_dir = parent.node == dir.node;
+            _ = self.close(parent);
 
-        Ok(self.get_with_name_mut(drive.as_bytes()))
-    }
+            if is_dir {
+                if let Ok(root) = self.mounts.get_mut(&mount_point).unwrap().open("/") {
+                    mounted.push(root);
+                }
+            }
+        }
 
-    /// gets the path in mountpoint from a given path
-    /// basiclly removes the drive name
-    pub(self) fn mountpoint_path(path: Path) -> String {
-        let index = path.find(':').unwrap();
-        let path_in_mountpoint = &path[index + 1..path.len()];
-        path_in_mountpoint.to_string()
+        mounted
     }
 
     /// checks if a path is a vaild dir returns Err if path has an error
     pub fn verify_path_dir(&mut self, path: Path) -> FSResult<()> {
-        let mountpoint = self
-            .get_from_path(path)?
-            .ok_or(FSError::NoSuchAFileOrDirectory)?;
-
-        let res = mountpoint.reslove_path(path)?;
+        let (mountpoint, path) = self.reslove_mount(path)?;
+        let res = mountpoint.reslove_path(&path)?;
 
         if !res.is_dir() {
             return Err(FSError::NotADirectory);
@@ -306,21 +375,23 @@ impl FS for VFS {
     }
 
     fn open(&mut self, path: Path) -> FSResult<FileDescriptor> {
-        let mountpoint = self
-            .get_from_path(path)?
-            .ok_or(FSError::NoSuchAFileOrDirectory)?;
-
-        let file = mountpoint.open(&Self::mountpoint_path(path))?;
-
-        Ok(file)
+        let (mountpoint, path) = self.reslove_mount(path)?;
+        mountpoint.open(&path)
     }
 
     fn read(&mut self, file_descriptor: &mut FileDescriptor, buffer: &mut [u8]) -> FSResult<usize> {
         unsafe { (*file_descriptor.mountpoint).read(file_descriptor, buffer) }
     }
 
+    /// the filesystems mounted in the directory are listed as well hiding whatever they are
+    /// mounted over
     fn readdir(&mut self, file_descriptor: &mut FileDescriptor) -> FSResult<Vec<FileDescriptor>> {
-        unsafe { (*file_descriptor.mountpoint).readdir(file_descriptor) }
+        let mut entries = unsafe { (*file_descriptor.mountpoint).readdir(file_descriptor)? };
+        let mounted = self.mounted_in(file_descriptor);
+
+        entries.retain(|entry| !mounted.iter().any(|root| root.name() == entry.name()));
+        entries.extend(mounted);
+        Ok(entries)
     }
 
     fn write(&mut self, file_descriptor: &mut FileDescriptor, buffer: &[u8]) -> FSResult<()> {
@@ -328,19 +399,17 @@ impl FS for VFS {
     }
 
     fn create(&mut self, path: Path, name: String) -> FSResult<()> {
-        let mountpoint = self
-            .get_from_path(path)?
-            .ok_or(FSError::NoSuchAFileOrDirectory)?;
-
-        mountpoint.create(path, name)
+        let (mountpoint, path) = self.reslove_mount(path)?;
+        mountpoint.create(&path, name)
     }
 
     fn createdir(&mut self, path: Path, name: String) -> FSResult<()> {
-        let mountpoint = self
-            .get_from_path(path)?
-            .ok_or(FSError::NoSuchAFileOrDirectory)?;
+        let (mountpoint, path) = self.reslove_mount(path)?;
+        mountpoint.createdir(&path, name)
+    }
 
-        mountpoint.createdir(path, name)
+    fn truncate(&mut self, file_descriptor: &mut FileDescriptor, size: usize) -> FSResult<()> {
+        unsafe { (*file_descriptor.mountpoint).truncate(file_descriptor, size) }
     }
 
     fn close(&mut self, file: FileDescriptor) -> FSResult<()> {
@@ -3,6 +3,9 @@ use spin::Mutex;
 
 use super::{FSError, FSResult, FileDescriptor, Inode, InodeOps, InodeType, Path, FS};
 
+/// where the ramfs is mounted
+pub const MOUNT_POINT: &str = "/ram";
+
 pub enum RamInode {
     Data(Vec<u8>),
     Children(BTreeMap<String, Inode>),
@@ -0,0 +1,265 @@
+// a writable in-memory fs for scratch files, unlike `RamFS` file data isn't one big Vec that is
+// copied around when it grows, it is a list of `EXTENT_SIZE` buffers allocated from the heap as
+// needed
+// the bytes of the last extent past the file size are always kept zeroed so writing or
+// truncating past the end of a file reads back zeros in the gap
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, string::String, vec, vec::Vec};
+
+use super::{FSError, FSResult, FileDescriptor, Inode, InodeOps, InodeType, Path, FS};
+
+/// where the tmpfs is mounted
+pub const MOUNT_POINT: &str = "/tmp";
+pub const EXTENT_SIZE: usize = 4096;
+
+pub enum TmpInode {
+    File {
+        extents: Vec<Box<[u8]>>,
+        size: usize,
+    },
+    Children(BTreeMap<String, Inode>),
+}
+
+impl TmpInode {
+    fn new_file(name: String) -> Inode {
+        Inode {
+            name,
+            inode_type: InodeType::File,
+            ops: Box::new(TmpInode::File {
+                extents: Vec::new(),
+                size: 0,
+            }),
+        }
+    }
+
+    fn new_dir(name: String) -> Inode {
+        Inode {
+            name,
+            inode_type: InodeType::Directory,
+            ops: Box::new(TmpInode::Children(BTreeMap::new())),
+        }
+    }
+}
+
+/// allocates zeroed extents until `extents` can hold `size` bytes
+fn reserve(extents: &mut Vec<Box<[u8]>>, size: usize) {
+    let needed = size.div_ceil(EXTENT_SIZE);
+
+    while extents.len() < needed {
+        // not `Box::new([0; EXTENT_SIZE])` it would be built on the stack first
+        extents.push(vec![0u8; EXTENT_SIZE].into_boxed_slice());
+    }
+}
+
+impl InodeOps for TmpInode {
+    fn new_root() -> Inode {
+        Self::new_dir(String::new())
+    }
+
+    fn get(&mut self, name: Path) -> FSResult<Option<&mut Inode>> {
+        match self {
+            Self::Children(tree) => Ok(tree.get_mut(name)),
+            _ => Err(FSError::NotADirectory),
+        }
+    }
+
+    fn contains(&self, name: Path) -> bool {
+        match self {
+            Self::Children(tree) => tree.contains_key(name),
+            _ => false,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::File { size, .. } => *size,
+            Self::Children(children) => children.values().map(|child| child.size()).sum(),
+        }
+    }
+
+    fn read(&self, buffer: &mut [u8], offset: usize, count: usize) -> FSResult<()> {
+        let Self::File { extents, size } = self else {
+            return Err(FSError::NotAFile);
+        };
+        assert!(
+            offset + count <= *size,
+            "tmpfs: read past the end of a file"
+        );
+
+        let mut done = 0;
+        while done < count {
+            let pos = offset + done;
+            let start = pos % EXTENT_SIZE;
+            let len = (EXTENT_SIZE - start).min(count - done);
+
+            buffer[done..done + len]
+                .copy_from_slice(&extents[pos / EXTENT_SIZE][start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    fn readdir(&mut self) -> FSResult<Vec<&mut Inode>> {
+        match self {
+            Self::Children(tree) => Ok(tree.values_mut().collect()),
+            _ => Err(FSError::NotADirectory),
+        }
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> FSResult<()> {
+        let Self::File { extents, size } = self else {
+            return Err(FSError::NotAFile);
+        };
+
+        let end = offset + buffer.len();
+        reserve(extents, end);
+
+        let mut done = 0;
+        while done < buffer.len() {
+            let pos = offset + done;
+            let start = pos % EXTENT_SIZE;
+            let len = (EXTENT_SIZE - start).min(buffer.len() - done);
+
+            extents[pos / EXTENT_SIZE][start..start + len]
+                .copy_from_slice(&buffer[done..done + len]);
+            done += len;
+        }
+
+        *size = (*size).max(end);
+        Ok(())
+    }
+
+    fn truncate(&mut self, new_size: usize) -> FSResult<()> {
+        let Self::File { extents, size } = self else {
+            return Err(FSError::NotAFile);
+        };
+
+        if new_size < *size {
+            extents.truncate(new_size.div_ceil(EXTENT_SIZE));
+
+            let tail = new_size % EXTENT_SIZE;
+            if let Some(last) = extents.last_mut().filter(|_| tail != 0) {
+                last[tail..].fill(0);
+            }
+        } else {
+            reserve(extents, new_size);
+        }
+
+        *size = new_size;
+        Ok(())
+    }
+
+    fn insert(&mut self, name: String, node: Inode) -> FSResult<()> {
+        match self {
+            Self::Children(tree) => {
+                tree.insert(name, node);
+                Ok(())
+            }
+            _ => Err(FSError::NotADirectory),
+        }
+    }
+}
+
+pub struct TmpFS {
+    root_inode: Inode,
+}
+
+impl TmpFS {
+    pub fn new() -> Self {
+        Self {
+            root_inode: TmpInode::new_root(),
+        }
+    }
+}
+
+impl FS for TmpFS {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn open(&mut self, path: Path) -> FSResult<FileDescriptor> {
+        let file = self.reslove_path(path)?;
+
+        let file = file as *mut Inode;
+        Ok(FileDescriptor {
+            mountpoint: self,
+
+            write_pos: 0,
+            read_pos: 0,
+            node: file,
+        })
+    }
+
+    fn read(&mut self, file_descriptor: &mut FileDescriptor, buffer: &mut [u8]) -> FSResult<usize> {
+        let node = unsafe { &*file_descriptor.node };
+        let count = buffer
+            .len()
+            .min(node.size().saturating_sub(file_descriptor.read_pos));
+
+        node.ops.read(buffer, file_descriptor.read_pos, count)?;
+
+        file_descriptor.read_pos += count;
+        Ok(count)
+    }
+
+    fn readdir(&mut self, file_descriptor: &mut FileDescriptor) -> FSResult<Vec<FileDescriptor>> {
+        let node = unsafe { &mut *file_descriptor.node };
+        let read = node.ops.readdir()?;
+
+        let mut files = Vec::new();
+        for node in read {
+            let node = node as *mut Inode;
+            files.push(FileDescriptor {
+                mountpoint: self,
+                write_pos: 0,
+                read_pos: 0,
+                node,
+            })
+        }
+
+        Ok(files)
+    }
+
+    fn write(&mut self, file_descriptor: &mut FileDescriptor, buffer: &[u8]) -> FSResult<()> {
+        unsafe {
+            (*file_descriptor.node)
+                .ops
+                .write(buffer, file_descriptor.write_pos)?;
+        }
+
+        file_descriptor.write_pos += buffer.len();
+        Ok(())
+    }
+
+    fn create(&mut self, path: Path, name: String) -> FSResult<()> {
+        let node = TmpInode::new_file(name);
+
+        let resloved = self.reslove_path(path)?;
+        if resloved.inode_type != InodeType::Directory {
+            return Err(FSError::NotADirectory);
+        }
+
+        resloved.ops.insert(node.name.clone(), node)
+    }
+
+    fn createdir(&mut self, path: Path, name: String) -> FSResult<()> {
+        let node = TmpInode::new_dir(name);
+
+        let resloved = self.reslove_path(path)?;
+        if resloved.inode_type != InodeType::Directory {
+            return Err(FSError::NotADirectory);
+        }
+
+        resloved.ops.insert(node.name.clone(), node)
+    }
+
+    fn close(&mut self, file: FileDescriptor) -> FSResult<()> {
+        drop(file);
+        Ok(())
+    }
+
+    fn root_inode_mut(&mut self) -> &mut Inode {
+        &mut self.root_inode
+    }
+}
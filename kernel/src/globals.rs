@@ -1,27 +1,47 @@
 use spin::Mutex;
 
 use crate::{
-    memory::{allocator::LinkedListAllocator, frame_allocator::RegionAllocator},
+    cmdline::CmdLine,
+    memory::{
+        allocator::{AllocatorStats, LinkedListAllocator},
+        frame_allocator::RegionAllocator,
+        virt_allocator::VirtRegionAllocator,
+        PhysAddr,
+    },
+    serial,
     terminal::framebuffer::Terminal,
     threading::Scheduler,
-    utils::{elf::Elf, Locked},
+    utils::{elf::Elf, ExclusiveGuard, Locked},
 };
 
 /// boot info
 #[derive(Debug)]
 pub struct Kernel {
-    pub frame_allocator: RegionAllocator,
+    pub frame_allocator: Locked<RegionAllocator>,
+    pub virt_allocator: VirtRegionAllocator,
 
     pub phy_offset: usize,
     pub rsdp_addr: Option<u64>,
+    /// physical address and size of the initramfs passed by the bootloader
+    pub initramfs: Option<(PhysAddr, usize)>,
     pub elf: Elf<'static>,
+    pub cmdline: CmdLine,
 }
 
 impl Kernel {
-    // TODO: lock the frame_allocator!!!
+    /// the frame allocator for as long as the guard lives, it must not be taken again before the
+    /// guard is dropped (that panics) so keep it to one statement and don't hold it across
+    /// anything that may allocate frames itself, like the heap growing
+    #[track_caller]
     #[inline]
-    pub fn frame_allocator(&'static mut self) -> &mut RegionAllocator {
-        &mut self.frame_allocator
+    pub fn frame_allocator(&'static self) -> ExclusiveGuard<'static, RegionAllocator> {
+        self.frame_allocator.lock_exclusive()
+    }
+
+    // TODO: lock the virt_allocator too
+    #[inline]
+    pub fn virt_allocator(&'static mut self) -> &mut VirtRegionAllocator {
+        &mut self.virt_allocator
     }
 }
 pub static mut KERNEL: Option<Kernel> = None;
@@ -51,9 +71,42 @@ pub fn scheduler() -> &'static mut Scheduler {
     unsafe { SCHEDULER.as_mut().unwrap() }
 }
 
+/// the allocator behind `alloc` (`Box`, `Vec`, `String`...), it is empty until `memory::init`
+/// maps the heap (the `Heap` boot phase) so nothing can allocate before that, allocating
+/// earlier panics
 #[global_allocator]
 static GLOBAL_ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
 
 pub fn global_allocator() -> &'static Mutex<LinkedListAllocator> {
     &GLOBAL_ALLOCATOR.inner
 }
+
+/// the heap stats without waiting for the allocator, None if it is locked
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    GLOBAL_ALLOCATOR
+        .inner
+        .try_lock()
+        .map(|allocator| allocator.stats())
+}
+
+/// steals the locks the panic output needs from whoever held them when we panicked so printing
+/// can't deadlock, for example the terminal allocates so a panic while allocating would never
+/// get to the screen (the serial doesn't lock)
+pub unsafe fn unlock_for_panic() {
+    if GLOBAL_ALLOCATOR.force_unlock() {
+        match GLOBAL_ALLOCATOR.last_locker() {
+            Some(location) => serial!("panic: stole the allocator lock taken at {}\n", location),
+            None => serial!("panic: stole the allocator lock\n"),
+        }
+    }
+
+    if kernel_inited() && kernel().frame_allocator.force_unlock() {
+        match kernel().frame_allocator.last_locker() {
+            Some(location) => serial!(
+                "panic: stole the frame allocator lock taken at {}\n",
+                location
+            ),
+            None => serial!("panic: stole the frame allocator lock\n"),
+        }
+    }
+}
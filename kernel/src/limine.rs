@@ -7,12 +7,15 @@ use limine::request::HhdmRequest;
 use limine::request::KernelAddressRequest;
 use limine::request::KernelFileRequest;
 use limine::request::MemoryMapRequest;
+use limine::request::ModuleRequest;
 use limine::request::RsdpRequest;
 
 use limine::response::MemoryMapResponse;
 use limine::BaseRevision;
 
 use crate::memory::align_up;
+use crate::memory::PhysAddr;
+use crate::memory::{VirtAddr, VirtRange};
 use crate::terminal::framebuffer::FrameBufferInfo;
 use crate::terminal::framebuffer::PixelFormat;
 
@@ -40,6 +43,10 @@ static KERNEL_FILE_REQUEST: KernelFileRequest = KernelFileRequest::new();
 #[link_section = ".requests"]
 static MMAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 
+#[used]
+#[link_section = ".requests"]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
 #[used]
 #[link_section = ".requests"]
 static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
@@ -56,6 +63,11 @@ pub fn kernel_file() -> &'static File {
     KERNEL_FILE_REQUEST.get_response().unwrap().file()
 }
 
+/// the command line limine.conf gives the kernel
+pub fn kernel_cmdline() -> &'static [u8] {
+    kernel_file().cmdline()
+}
+
 /// returns addr to the kernel image and it's size
 pub fn kernel_image_info() -> (*const u8, usize) {
     let file = kernel_file();
@@ -65,6 +77,15 @@ pub fn kernel_image_info() -> (*const u8, usize) {
     (ptr, size)
 }
 
+/// returns the physical address and the size of the initramfs module if the bootloader loaded
+/// one, the initramfs is expected to be the first module in limine.conf
+pub fn initramfs_info() -> Option<(PhysAddr, usize)> {
+    let module = MODULE_REQUEST.get_response()?.modules().first()?;
+    let addr = PhysAddr::new(module.addr() as usize - get_phy_offset());
+
+    Some((addr, module.size() as usize))
+}
+
 pub fn mmap_request() -> &'static MemoryMapResponse {
     MMAP_REQUEST.get_response().unwrap()
 }
@@ -96,6 +117,15 @@ pub fn get_phy_offset_end() -> usize {
     get_phy_offset() + *MEMORY_END
 }
 
+/// the addresses of the framebuffer `get_framebuffer` returns, without making a slice of it
+pub fn get_framebuffer_range() -> VirtRange {
+    let mut buffers = FRAMEBUFFER_REQUEST.get_response().unwrap().framebuffers();
+    let first = buffers.next().unwrap();
+
+    let size = (first.width() * first.height() * first.bpp() as u64 / 8) as usize;
+    VirtRange::from_len(VirtAddr::new(first.addr() as usize), size)
+}
+
 pub fn get_framebuffer() -> (&'static mut [u8], FrameBufferInfo) {
     let mut buffers = FRAMEBUFFER_REQUEST.get_response().unwrap().framebuffers();
     let first = buffers.next().unwrap();
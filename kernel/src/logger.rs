@@ -0,0 +1,69 @@
+// `log!` doesn't write to the uart itself, it pushes the bytes to `RING` and the low priority
+// logger thread writes them to the log port (see `arch::serial::log_port`), so logging
+// never waits on anything and is fine from anywhere: interrupt handlers, the allocator or the
+// scheduler
+// - when the logger falls behind the oldest bytes are dropped, the next flush says how many
+// - the panic handler flushes what is left synchronously
+// - until the logger thread runs `log!` writes to the port directly
+// `println!` is still synchronous and doesn't go through `RING`, the terminal parses its escape
+// sequences a whole write at a time so it can't be fed bytes from a ring, so it is only for
+// thread context (see `print!`), `serial!` only waits if COM1's transmit ring is full (see
+// `arch::serial`)
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{arch::serial::log_port, threading, utils::ring_buffer::RingBuffer};
+
+pub const RING_SIZE: usize = 4096;
+
+static RING: RingBuffer<u8, RING_SIZE> = RingBuffer::new();
+/// the bytes dropped since the last flush
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static LOGGER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+struct RingWriter;
+
+impl Write for RingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if RING.push_overwriting(byte) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn _log(args: fmt::Arguments) {
+    if LOGGER_RUNNING.load(Ordering::Relaxed) {
+        RingWriter.write_fmt(args).unwrap();
+        threading::timer::wake();
+    } else {
+        log_port().write_fmt(args).unwrap();
+    }
+}
+
+/// writes everything queued by `log!` to the log port, can be called from anywhere
+pub fn flush() {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped != 0 {
+        _ = write!(log_port(), "\n[logger: dropped {} bytes]\n", dropped);
+    }
+
+    while let Some(byte) = RING.pop() {
+        log_port().write(byte);
+    }
+}
+
+/// the logger thread, there must be only one, see the top of this file
+pub fn logger_thread() -> ! {
+    LOGGER_RUNNING.store(true, Ordering::Relaxed);
+
+    loop {
+        flush();
+        threading::wait_for_interrupt();
+    }
+}
@@ -1,42 +1,55 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(const_mut_refs)]
 #![feature(custom_test_frameworks)]
 #[cfg(feature = "test")]
 mod test;
 
+#[cfg(feature = "bench")]
+mod bench;
+
 mod arch;
+mod boot;
+mod cmdline;
 mod drivers;
 mod globals;
 mod limine;
+mod logger;
 mod memory;
+#[cfg(target_arch = "x86_64")]
+mod syscalls;
 mod terminal;
 mod threading;
+mod time;
 mod utils;
 
 extern crate alloc;
-use arch::threading::restore_cpu_status;
-use arch::x86_64::serial;
+use arch::serial;
+use arch::Arch;
+use boot::Phase;
 
 use drivers::keyboard::Key;
 use drivers::vfs;
 use globals::*;
 
-use limine::get_phy_offset;
-use limine::get_phy_offset_end;
-use limine::MEMORY_SIZE;
 use memory::frame_allocator::RegionAllocator;
+use memory::virt_allocator::VirtRegionAllocator;
 pub use memory::PhysAddr;
 pub use memory::VirtAddr;
 use terminal::framebuffer::Terminal;
 use threading::Scheduler;
 
+/// writes to the framebuffer terminal right away, unlike `log!` it doesn't go through the log
+/// ring (see `logger`) and the terminal isn't locked so it can't be used where another print may
+/// be interrupted: interrupt handlers, the allocator or the scheduler, use `log!` there
 #[macro_export]
 macro_rules! print {
    ($($arg:tt)*) => ($crate::terminal::_print(format_args!($($arg)*)));
 }
 
+/// `print!` with a newline, the same limits apply
 #[macro_export]
 macro_rules! println {
     () => (print!("\n"));
@@ -46,11 +59,19 @@ macro_rules! println {
 #[macro_export]
 macro_rules! serial {
     ($($arg:tt)*) => {
-        crate::arch::x86_64::serial::_serial(format_args!($($arg)*))
+        crate::arch::serial::_serial(format_args!($($arg)*))
     };
 }
 
-use core::arch::asm;
+/// like `serial!` but for logs, never blocks see `logger`
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        crate::logger::_log(format_args!($($arg)*))
+    };
+}
+
+use core::{alloc::Layout, arch::asm};
 #[inline]
 pub fn khalt() -> ! {
     loop {
@@ -84,96 +105,121 @@ macro_rules! cross_println {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    #[cfg(feature = "test")]
+    test::catch_expected_panic();
+
     unsafe { asm!("cli") }
-    cross_println!(
-        "kernel panic:\n{}, at {}",
-        info.message(),
-        info.location().unwrap()
-    );
-    print_stack_trace();
+    arch::halt_others();
+    // the transmit interrupt won't come anymore
+    serial::synchronous();
+    unsafe { globals::unlock_for_panic() };
+    // the panic may come from an exhausted heap
+    memory::allocator::emergency(|| {
+        logger::flush();
+        cross_println!(
+            "kernel panic:\n{}, at {}",
+            info.message(),
+            info.location().unwrap()
+        );
+        arch::backtrace::print();
+        arch::cpu::dump_registers();
+    });
+
+    #[cfg(feature = "test")]
+    if test::is_testing() {
+        test::test_failed()
+    }
 
     khalt()
 }
 
-#[allow(unused)]
-fn print_stack_trace() {
-    let mut fp: usize;
-
-    unsafe {
-        core::arch::asm!("mov {}, rbp", out(reg) fp);
-
-        cross_println!("stack trace: ");
-        while fp != 0 {
-            let return_address = *(fp as *const usize).offset(1);
-            let name = {
-                if kernel_inited() {
-                    let sym = kernel().elf.sym_from_value_range(return_address);
-
-                    if sym.is_some() {
-                        kernel().elf.string_table_index(sym.unwrap().name_index)
-                    } else {
-                        "??"
-                    }
-                } else {
-                    "??"
-                }
-            };
-
-            cross_println!("  {:#x} <{}>", return_address, name);
-            fp = *(fp as *const usize);
+/// `handle_alloc_error` lands here, the heap is exhausted so nothing here waits for a lock and
+/// whatever allocates gets the emergency pool (see `allocator::emergency`): it logs the failed
+/// layout, the heap and frame stats (a `largest_free` way below `free_bytes` is fragmentation)
+/// and the backtrace, which is where the allocation came from since there is no `Location` for
+/// it, then halts
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    unsafe { asm!("cli") }
+    arch::halt_others();
+    // the transmit interrupt won't come anymore
+    serial::synchronous();
+
+    memory::allocator::emergency(|| {
+        log!(
+            "out of memory: failed to allocate {} bytes aligned to {}\n",
+            layout.size(),
+            layout.align()
+        );
+        match allocator_stats() {
+            Some(stats) => log!(
+                "heap: {} bytes, {} free in {} nodes, the largest is {} bytes\n",
+                stats.heap_size,
+                stats.free_bytes,
+                stats.free_nodes,
+                stats.largest_free
+            ),
+            None => log!("heap: the allocator is locked\n"),
+        }
+        if kernel_inited() && !kernel().frame_allocator.is_locked() {
+            log!(
+                "frames: {} used\n",
+                kernel().frame_allocator().used_frames()
+            );
         }
+        arch::backtrace::log();
+    });
+    logger::flush();
+
+    #[cfg(feature = "test")]
+    if test::is_testing() {
+        arch::qemu::exit(arch::qemu::ExitCode::Failed);
     }
+    // a panicking benchmark would otherwise keep the host script waiting for `bench: done`
+    #[cfg(feature = "bench")]
+    arch::qemu::exit(arch::qemu::ExitCode::Failed);
+
+    khalt()
 }
 
 #[no_mangle]
 pub extern "C" fn kinit() {
-    // initing globals
-    let phy_offset = get_phy_offset();
-    let kernel_img = limine::kernel_image_info();
-
-    serial!(
-        "image at: 0x{:x}\nlen: 0x{:x}\nphy_offset: 0x{:x}..0x{:x}\nmemory size: 0x{:x}\n",
-        kernel_img.0 as usize,
-        kernel_img.1,
-        phy_offset,
-        limine::get_phy_offset_end(),
-        *MEMORY_SIZE
-    );
-
-    let kernel_img_addr = unsafe { &*kernel_img.0 };
-    let elf = utils::elf::Elf::parse(kernel_img_addr).unwrap();
-    elf.debug();
-
-    unsafe {
-        KERNEL = Some(Kernel {
-            phy_offset,
-            rsdp_addr: limine::rsdp_addr(),
-            frame_allocator: RegionAllocator::new(),
-            elf,
-        });
+    // see `boot` for what each phase depends on
+    boot::run(Phase::Globals, boot::init_globals);
+    // the arch maps mmio so the vmm has to be ready first
+    boot::run(Phase::Vmm, boot::init_vmm);
+    // has to happen before the heap is mapped since the heap lives in the same level 4 entry
+    boot::run(Phase::Physmap, boot::init_physmap);
+    boot::run(Phase::Cpu, boot::init_cpu);
+    boot::run(Phase::Acpi, boot::init_acpi);
+    boot::run(Phase::Interrupts, boot::init_interrupts);
+    boot::run(Phase::Heap, boot::init_heap);
+    boot::run(Phase::Vfs, boot::init_vfs);
+    boot::run(Phase::Terminal, boot::init_terminal);
+    boot::run(Phase::Net, boot::init_net);
+    if cfg!(feature = "selftest") || kernel().cmdline.selftest() {
+        boot::run(Phase::SelfTest, boot::selftest);
     }
-
-    // initing the arch
-    arch::init();
-
-    unsafe {
-        memory::init(get_phy_offset_end());
-        vfs::init();
-
-        let (buffer, info) = limine::get_framebuffer();
-        let terminal: Terminal<'static> = Terminal::init(buffer, info);
-        TERMINAL = Some(terminal);
+    if kernel().cmdline.wxaudit() {
+        boot::run(Phase::WxAudit, boot::wxaudit);
     }
+    boot::run(Phase::Scheduler, boot::init_scheduler);
 
-    serial!("kernel init phase 1 done\n");
-
-    unsafe {
-        let mut scheduler = Scheduler::init(kmain as usize, "kernel");
-
-        scheduler.create_process(terminal::shell as usize, "shell");
-        SCHEDULER = Some(scheduler);
+    unsafe { arch::Current::switch_to(&(*SCHEDULER.as_ref().unwrap().current_thread).context) }
+}
 
-        restore_cpu_status(&(*SCHEDULER.as_ref().unwrap().current_process).context)
+/// loads `INIT_PATH` from the initramfs and adds it to `scheduler`
+fn spawn_init(scheduler: &mut Scheduler) {
+    let path = vfs::initramfs::INIT_PATH;
+
+    match scheduler.spawn_elf(path, "init") {
+        Ok(pid) => serial!("loaded `{}` as pid {}\n", path, pid),
+        Err(err) => serial!(
+            "failed to load `{}`, skipping init, error: {:?}\n",
+            path,
+            err
+        ),
     }
 }
 
@@ -193,20 +239,30 @@ fn kstart() -> ! {
 
 #[no_mangle]
 fn kmain() -> ! {
-    serial!("Hello, world!, running tests...\n");
+    #[cfg(feature = "bench")]
+    bench::run();
 
-    #[cfg(feature = "test")]
-    test::testing_module::test_main();
+    // the benchmarks exit qemu instead of booting the rest of the way
+    #[cfg(not(feature = "bench"))]
+    {
+        serial!("Hello, world!, running tests...\n");
 
-    println!("finished running tests...");
-    println!(
-        "\\[fg: (0, 255, 0) ||Boot success! press ctrl + shift + C to clear screen (and enter input mode)\n||]"
-    );
+        #[cfg(feature = "test")]
+        test::testing_module::test_main();
 
-    serial!("finished initing...\n");
-    serial!("idle!\n");
+        println!("finished running tests...");
+        println!(
+            "\\[fg: (0, 255, 0) ||Boot success! press ctrl + shift + C to clear screen (and enter input mode)\n||]"
+        );
 
-    khalt()
+        serial!("finished initing...\n");
+        serial!("idle!\n");
+
+        // not `khalt` so the timer can stop while we are idle
+        loop {
+            threading::wait_for_interrupt();
+        }
+    }
 }
 
 // whenever a key is pressed this function should be called
@@ -0,0 +1,266 @@
+// physical and virtual addresses are different types so that using one as the other doesn't
+// compile, the only way to go from one to the other is `phys_to_virt` and `virt_to_phys` which
+// go through the physical memory window at `phy_offset`
+// both support adding/subtracting a usize and subtracting an address of the same kind gives the
+// distance between them
+// `PhysRange` and `VirtRange` are the half open ranges `start..end` of each kind, every overlap
+// check between 2 ranges should go through them, an empty range contains and overlaps nothing
+
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+use crate::kernel;
+
+use super::{
+    align_down, align_up,
+    frame_allocator::Frame,
+    paging::{Page, PAGE_SIZE},
+};
+
+macro_rules! address {
+    ($name: ident) => {
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        #[repr(transparent)]
+        pub struct $name(usize);
+
+        impl $name {
+            #[inline]
+            pub const fn new(addr: usize) -> Self {
+                Self(addr)
+            }
+
+            #[inline]
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+
+            #[inline]
+            pub const fn as_u64(self) -> u64 {
+                self.0 as u64
+            }
+
+            #[inline]
+            pub const fn align_down(self, alignment: usize) -> Self {
+                Self(align_down(self.0, alignment))
+            }
+
+            #[inline]
+            pub const fn align_up(self, alignment: usize) -> Self {
+                Self(align_up(self.0, alignment))
+            }
+
+            #[inline]
+            pub const fn is_aligned(self, alignment: usize) -> bool {
+                self.0 & (alignment - 1) == 0
+            }
+
+            #[inline]
+            pub fn checked_add(self, rhs: usize) -> Option<Self> {
+                Some(Self(self.0.checked_add(rhs)?))
+            }
+
+            #[inline]
+            pub const fn saturating_add(self, rhs: usize) -> Self {
+                Self(self.0.saturating_add(rhs))
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = Self;
+            #[inline]
+            fn add(self, rhs: usize) -> Self::Output {
+                Self(self.0 + rhs)
+            }
+        }
+
+        impl AddAssign<usize> for $name {
+            #[inline]
+            fn add_assign(&mut self, rhs: usize) {
+                self.0 += rhs;
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = Self;
+            #[inline]
+            fn sub(self, rhs: usize) -> Self::Output {
+                Self(self.0 - rhs)
+            }
+        }
+
+        impl SubAssign<usize> for $name {
+            #[inline]
+            fn sub_assign(&mut self, rhs: usize) {
+                self.0 -= rhs;
+            }
+        }
+
+        /// the distance between 2 addresses
+        impl Sub<$name> for $name {
+            type Output = usize;
+            #[inline]
+            fn sub(self, rhs: $name) -> Self::Output {
+                self.0 - rhs.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({:#x})", stringify!($name), self.0)
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+address!(PhysAddr);
+address!(VirtAddr);
+
+impl VirtAddr {
+    #[inline]
+    pub fn from_ptr<T: ?Sized>(ptr: *const T) -> Self {
+        Self(ptr as *const u8 as usize)
+    }
+
+    #[inline]
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    #[inline]
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+macro_rules! range {
+    ($name: ident, $addr: ident, $unit: ident, $units: ident) => {
+        /// the addresses from `start` included to `end` excluded
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        pub struct $name {
+            start: $addr,
+            end: $addr,
+        }
+
+        // both kinds get the same methods whether or not each one uses them all yet
+        #[allow(dead_code)]
+        impl $name {
+            /// `start` can't be after `end`
+            #[inline]
+            pub const fn new(start: $addr, end: $addr) -> Self {
+                assert!(start.0 <= end.0, "the range ends before it starts");
+                Self { start, end }
+            }
+
+            /// the `len` bytes from `start`, the range is cut at the end of the address space
+            #[inline]
+            pub const fn from_len(start: $addr, len: usize) -> Self {
+                Self::new(start, start.saturating_add(len))
+            }
+
+            #[inline]
+            pub const fn start(&self) -> $addr {
+                self.start
+            }
+
+            #[inline]
+            pub const fn end(&self) -> $addr {
+                self.end
+            }
+
+            #[inline]
+            pub const fn len(&self) -> usize {
+                self.end.0 - self.start.0
+            }
+
+            #[inline]
+            pub const fn is_empty(&self) -> bool {
+                self.start.0 == self.end.0
+            }
+
+            #[inline]
+            pub const fn contains(&self, addr: $addr) -> bool {
+                self.start.0 <= addr.0 && addr.0 < self.end.0
+            }
+
+            /// wether or not every address of `other` is in self, true if `other` is empty
+            #[inline]
+            pub const fn contains_range(&self, other: &Self) -> bool {
+                other.is_empty() || (self.start.0 <= other.start.0 && other.end.0 <= self.end.0)
+            }
+
+            /// wether or not an address is in both ranges, ranges that only touch don't overlap
+            #[inline]
+            pub const fn overlaps(&self, other: &Self) -> bool {
+                self.start.0 < other.end.0 && other.start.0 < self.end.0
+            }
+
+            /// the addresses in both ranges, None if they don't overlap
+            pub fn intersect(&self, other: &Self) -> Option<Self> {
+                self.overlaps(other)
+                    .then(|| Self::new(self.start.max(other.start), self.end.min(other.end)))
+            }
+
+            /// self grown to whole pages
+            #[inline]
+            pub const fn align_out(&self) -> Self {
+                Self::new(
+                    self.start.align_down(PAGE_SIZE),
+                    self.end.align_up(PAGE_SIZE),
+                )
+            }
+
+            /// the $units with at least an address in self, from the first one, none if self is
+            /// empty even if it starts in the middle of one
+            pub fn $units(&self) -> impl DoubleEndedIterator<Item = $unit> + ExactSizeIterator {
+                let first = $unit::containing_address(self.start);
+                let count = if self.is_empty() {
+                    0
+                } else {
+                    self.align_out().len() / PAGE_SIZE
+                };
+                (0..count).map(move |index| first + index)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(
+                    f,
+                    "{}({:#x}..{:#x})",
+                    stringify!($name),
+                    self.start.0,
+                    self.end.0
+                )
+            }
+        }
+    };
+}
+
+range!(PhysRange, PhysAddr, Frame, frames);
+range!(VirtRange, VirtAddr, Page, pages);
+
+/// returns the address `addr` is mapped to in the physical memory window
+#[inline]
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr(addr.0 + kernel().phy_offset)
+}
+
+/// returns the physical address of `addr`, `addr` must be in the physical memory window (for
+/// example a page table we got from `phys_to_virt`) use `PageTable::translate_addr` otherwise
+#[inline]
+pub fn virt_to_phys(addr: VirtAddr) -> PhysAddr {
+    debug_assert!(
+        addr.0 >= kernel().phy_offset,
+        "{:?} is not in the physical memory window",
+        addr
+    );
+    PhysAddr(addr.0 - kernel().phy_offset)
+}
@@ -1,19 +1,31 @@
 use crate::kernel;
 use core::{
     alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
     ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 use crate::{
+    arch::without_interrupts,
     memory::{
         align_up,
-        paging::{EntryFlags, IterPage, Page, PAGE_SIZE},
+        frame_allocator::Frame,
+        paging::{EntryFlags, Page, TlbBatch, PAGE_SIZE},
+        phys_to_virt, VirtAddr,
     },
     utils::Locked,
 };
 
 use super::paging::current_root_table;
 
+/// what the `heap-poison` feature fills allocations with before handing them out, reading it
+/// means reading memory that was never written
+pub const ALLOC_POISON: u8 = 0xAA;
+/// what the `heap-poison` feature fills freed blocks with (except for their `Node`), reading it
+/// means using memory after freeing it
+pub const FREE_POISON: u8 = 0xDE;
+
 #[derive(Debug)]
 pub struct Node {
     size: usize,
@@ -33,29 +45,161 @@ impl Node {
         self.start_addr() + self.size
     }
 
-    /// checks if a node can hold `size` bytes aligned to `align_amount`
-    pub fn can_hold(&self, size: usize, align_amount: usize) -> Result<usize, ()> {
-        let start = align_up(self.start_addr(), align_amount);
+    /// checks if a node can hold `size` bytes aligned to `align_amount` returning where they go,
+    /// the bytes before and after them have to be 0 or fit a node so they can go back to the
+    /// free list
+    pub fn can_hold(&self, size: usize, align_amount: usize) -> Result<Fit, ()> {
+        let mut start = align_up(self.start_addr(), align_amount);
+        // the bytes skipped to align start become a node of their own so they have to fit one
+        if start != self.start_addr() && start - self.start_addr() < size_of::<Node>() {
+            start = align_up(self.start_addr() + size_of::<Node>(), align_amount);
+        }
         let end = start.checked_add(size).ok_or(())?;
 
         if end > self.end_addr() {
             return Err(());
         }
 
-        let ecess_size = self.end_addr() - end;
-        if ecess_size > 0 && ecess_size < size_of::<Node>() {
+        let excess = self.end_addr() - end;
+        if excess > 0 && excess < size_of::<Node>() {
             // if we have an excess we check if we can use it for a new node or not if not Err
             return Err(());
         }
 
-        Ok(start)
+        Ok(Fit {
+            addr: start,
+            front: start - self.start_addr(),
+            excess,
+        })
     }
 }
+
+/// where an allocation goes in a free node, see `Node::can_hold`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fit {
+    pub addr: usize,
+    /// the padding between the start of the node and `addr`
+    pub front: usize,
+    /// the bytes between the end of the allocation and the end of the node
+    pub excess: usize,
+}
+
+/// how `LinkedListAllocator::find_free_node` picks the node an allocation goes in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+    /// the first node that can hold it, the fastest but small allocations end up taking large
+    /// nodes which fragments the heap
+    FirstFit,
+    /// the node that leaves the fewest bytes unused, scans the whole free list but keeps the
+    /// large nodes for the large allocations
+    BestFit,
+}
+
+/// how the heap grows once it runs out of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapGrowth {
+    /// extends by the same number of pages every time
+    Fixed(usize),
+    /// extends by `initial` pages then doubles the number of pages on every extend up to `max`
+    /// pages, amortizes the cost of extending for heaps that grow fast
+    Geometric { initial: usize, max: usize },
+}
+
+impl HeapGrowth {
+    pub const DEFAULT: Self = Self::Fixed(128);
+
+    #[inline]
+    const fn initial_pages(&self) -> usize {
+        match *self {
+            Self::Fixed(pages) => pages,
+            Self::Geometric { initial, .. } => initial,
+        }
+    }
+
+    /// the number of pages to extend by after extending by `pages`
+    #[inline]
+    fn next_pages(&self, pages: usize) -> usize {
+        match *self {
+            Self::Fixed(pages) => pages,
+            Self::Geometric { max, .. } => pages.saturating_mul(2).min(max),
+        }
+    }
+}
+
+/// frames reserved by `LinkedListAllocator::extend_heap` or unmapped and waiting for a tlb flush
+/// before they can be deallocated, they are kept in a stack linked through the physical memory
+/// window (each frame starts with the address of the one under it) so reserving any number of
+/// frames doesn't take any memory
+pub(super) struct ReservedFrames {
+    top: Option<Frame>,
+    count: usize,
+}
+
+impl ReservedFrames {
+    pub(super) const fn new() -> Self {
+        Self {
+            top: None,
+            count: 0,
+        }
+    }
+
+    pub(super) fn push(&mut self, frame: Frame) {
+        let link = phys_to_virt(frame.start_address()).as_mut_ptr::<Option<Frame>>();
+        unsafe { link.write(self.top) };
+
+        self.top = Some(frame);
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        let frame = self.top?;
+        self.top = unsafe {
+            phys_to_virt(frame.start_address())
+                .as_ptr::<Option<Frame>>()
+                .read()
+        };
+        self.count -= 1;
+
+        Some(frame)
+    }
+
+    /// deallocates the frames left
+    pub(super) fn free(&mut self) {
+        while let Some(frame) = self.pop() {
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+    }
+}
+
+/// a snapshot of the heap, see `LinkedListAllocator::stats`
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    pub heap_start: usize,
+    pub heap_size: usize,
+    /// how far the heap can extend, see `LinkedListAllocator::set_heap_limit`
+    pub heap_limit: usize,
+    pub free_bytes: usize,
+    pub free_nodes: usize,
+    /// the biggest allocation that still fits without extending, way less than `free_bytes`
+    /// means the heap is fragmented
+    pub largest_free: usize,
+}
+
 #[derive(Debug)]
 pub struct LinkedListAllocator {
     head: Node,
+    /// where the heap starts, used by `Self::check_integrity`
+    pub heap_start: usize,
     /// keeps track of the current heap_end so we can extend it later
     pub heap_end: usize,
+    /// where `Self::init` left the heap end, `Self::shrink_heap` doesn't go below it
+    initial_heap_end: usize,
+    /// the heap doesn't extend past it, see `Self::set_heap_limit`
+    heap_limit: usize,
+    growth: HeapGrowth,
+    /// the number of pages the next extend maps, see `HeapGrowth`
+    pages_per_extend: usize,
+    policy: FitPolicy,
 }
 
 impl LinkedListAllocator {
@@ -66,69 +210,277 @@ impl LinkedListAllocator {
                 next: None,
             },
 
+            heap_start: 0,
             heap_end: 0,
+            initial_heap_end: 0,
+            heap_limit: usize::MAX,
+            growth: HeapGrowth::DEFAULT,
+            pages_per_extend: HeapGrowth::DEFAULT.initial_pages(),
+            policy: FitPolicy::FirstFit,
         }
     }
 
     /// size may not be equal to `size`, heap_start may not be equal to `possible_start` these are
     /// just boundaries
+    /// the heap then grows as `growth` says
+    /// returns Err(()) without touching the heap if there isn't room for a node once
+    /// `possible_start` is aligned
     /// unsafe because possible_start has to be mapped first
-    pub unsafe fn init(&mut self, possible_start: usize, size: usize) {
+    pub unsafe fn init(
+        &mut self,
+        possible_start: usize,
+        size: usize,
+        growth: HeapGrowth,
+    ) -> Result<(), ()> {
         let heap_start = align_up(possible_start, size_of::<Node>());
-        let size = size - (heap_start - possible_start);
+        let size = size
+            .checked_sub(heap_start - possible_start)
+            .filter(|&size| size >= size_of::<Node>())
+            .ok_or(())?;
 
         let heap_end = heap_start + size;
+        self.heap_start = heap_start;
         self.heap_end = heap_end;
+        self.initial_heap_end = heap_end;
+        self.set_growth(growth);
 
         self.add_free_node(heap_start, size);
+        Ok(())
     }
 
+    /// wether or not `Self::init` was called
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        self.heap_start != 0
+    }
+
+    /// zero sized allocations don't touch the heap they get a dangling pointer aligned to
+    /// `layout.align()`, with the `heap-poison` feature the others are filled with `ALLOC_POISON`
     pub unsafe fn alloc_mut(&mut self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return layout.align() as *mut u8;
+        }
+
+        #[cfg(feature = "heap-integrity")]
+        self.check_integrity();
+
         let (size, align) = Self::size_align(layout);
 
-        if let Some((node, addr)) = self.find_free_node(size, align) {
-            let alloc_end = addr.checked_add(size).expect("overflow");
+        let ptr = if let Some((node, fit)) = self.find_free_node(size, align) {
+            let node_start = node.start_addr();
+            debug_assert_eq!(node_start + fit.front + size + fit.excess, node.end_addr());
+
             // divide block
-            let excess_size = node.end_addr() - alloc_end;
-            if excess_size > 0 {
-                self.add_free_node(alloc_end, excess_size);
+            if fit.excess > 0 {
+                self.add_free_node(fit.addr + size, fit.excess);
             }
 
-            addr as *mut u8
+            // the padding before an aligned allocation
+            if fit.front > 0 {
+                self.add_free_node(node_start, fit.front);
+            }
+
+            #[cfg(feature = "heap-poison")]
+            ptr::write_bytes(fit.addr as *mut u8, ALLOC_POISON, size);
+
+            fit.addr as *mut u8
         } else {
             ptr::null_mut()
-        }
+        };
+
+        #[cfg(feature = "heap-integrity")]
+        self.check_integrity();
+        ptr
     }
 
+    /// freeing a zero sized allocation does nothing, see `Self::alloc_mut`, with the `heap-poison`
+    /// feature the others are filled with `FREE_POISON`
     pub unsafe fn dealloc_mut(&mut self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+
+        #[cfg(feature = "heap-integrity")]
+        self.check_integrity();
+
         let (size, _) = Self::size_align(layout);
-        self.add_free_node(ptr as usize, size)
+        debug_assert!(
+            ptr as usize >= self.heap_start && ptr as usize + size <= self.heap_end,
+            "deallocating 0x{:x} which isn't in the heap 0x{:x}..0x{:x}",
+            ptr as usize,
+            self.heap_start,
+            self.heap_end
+        );
+        // before the node is written over the start of it
+        #[cfg(feature = "heap-poison")]
+        ptr::write_bytes(ptr, FREE_POISON, size);
+        let end = self.free_block(ptr as usize, size);
+
+        // the end of a spike, the block merged with whatever was free up to the heap end
+        if end == self.heap_end {
+            self.shrink_heap();
+        }
+
+        #[cfg(feature = "heap-integrity")]
+        self.check_integrity();
     }
 
+    /// walks the free list panicking if a node is outside of the heap, misaligned, too small or
+    /// if the list has a cycle, used to catch heap corruption (for example a write after free)
+    /// right after the operation that caused it, see the `heap-integrity` feature
+    pub fn check_integrity(&self) {
+        // every node takes at least size_of::<Node>() bytes so a list longer then this must have
+        // a cycle
+        let max_nodes = (self.heap_end - self.heap_start) / size_of::<Node>();
+
+        let mut current = &self.head;
+        let mut count = 0;
+
+        while let Some(ref node) = current.next {
+            let addr = node.start_addr();
+
+            assert!(
+                addr >= self.heap_start && addr < self.heap_end,
+                "heap corruption: node 0x{:x} is outside of the heap 0x{:x}..0x{:x}",
+                addr,
+                self.heap_start,
+                self.heap_end
+            );
+            assert_eq!(
+                align_up(addr, align_of::<Node>()),
+                addr,
+                "heap corruption: node 0x{:x} is misaligned",
+                addr
+            );
+            assert!(
+                node.size >= size_of::<Node>(),
+                "heap corruption: node 0x{:x} is too small, size: 0x{:x}",
+                addr,
+                node.size
+            );
+            assert!(
+                node.end_addr() <= self.heap_end,
+                "heap corruption: node 0x{:x} with size 0x{:x} goes past the heap end 0x{:x}",
+                addr,
+                node.size,
+                self.heap_end
+            );
+
+            count += 1;
+            assert!(
+                count <= max_nodes,
+                "heap corruption: the free list has a cycle"
+            );
+
+            current = node;
+        }
+    }
+
+    /// finds and removes a free node that can hold `size` bytes aligned to `align` extending the
+    /// heap until one can, returns the node with where the allocation goes in it
+    /// the node is picked as `Self::policy` says
     pub fn find_free_node(
         &mut self,
         size: usize,
         align: usize,
-    ) -> Option<(&'static mut Node, usize)> {
+    ) -> Option<(&'static mut Node, Fit)> {
+        loop {
+            let found = match self.policy {
+                FitPolicy::FirstFit => self.take_first_fit(size, align),
+                FitPolicy::BestFit => self.best_fit(size, align).map(|index| {
+                    let node = self.remove_node(index);
+                    let fit = node.can_hold(size, align).unwrap();
+                    (node, fit)
+                }),
+            };
+
+            if found.is_some() {
+                return found;
+            }
+
+            // extends merge with the node at the end of the heap so this ends once that node is
+            // big enough or we run out of frames
+            //  TODO: add a heap_max that prevents heap from extending further
+            self.extend_heap().ok()?;
+        }
+    }
+
+    /// removes the first node that can hold the allocation, a single pass over the free list
+    fn take_first_fit(&mut self, size: usize, align: usize) -> Option<(&'static mut Node, Fit)> {
         let mut current = &mut self.head;
 
         while let Some(ref mut node) = current.next {
-            if let Ok(addr) = node.can_hold(size, align) {
+            if let Ok(fit) = node.can_hold(size, align) {
                 let next = node.next.take();
                 let node = current.next.take().unwrap();
 
                 current.next = next;
 
-                return Some((node, addr));
+                return Some((node, fit));
             } else {
                 current = current.next.as_mut().unwrap();
             }
         }
 
-        //  TODO: add an extend_by function to extend the heap by size
-        //  TODO: add a heap_max that prevents heap from extending further
-        self.extend_heap().ok()?;
-        self.find_free_node(size, align)
+        None
+    }
+
+    /// the index in the free list of the node that leaves the fewest bytes around the
+    /// allocation, stops early at a node it fits exactly
+    fn best_fit(&self, size: usize, align: usize) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None;
+        let mut current = &self.head;
+        let mut index = 0;
+
+        while let Some(ref node) = current.next {
+            if let Ok(fit) = node.can_hold(size, align) {
+                let unused = fit.front + fit.excess;
+                if best.is_none_or(|(_, best_unused)| unused < best_unused) {
+                    best = Some((index, unused));
+                }
+
+                if unused == 0 {
+                    break;
+                }
+            }
+
+            index += 1;
+            current = node;
+        }
+
+        best.map(|(index, _)| index)
+    }
+
+    /// unlinks the node at `index` in the free list, panics if there is none
+    fn remove_node(&mut self, index: usize) -> &'static mut Node {
+        let mut current = &mut self.head;
+        for _ in 0..index {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let node = current.next.take().unwrap();
+        current.next = node.next.take();
+        node
+    }
+
+    /// adds the block at `addr` to the free list merged with the free nodes right before and
+    /// right after it, returns where the merged node ends
+    /// every freed block goes through here so no two free nodes are ever next to each other,
+    /// otherwise a heap that went up and came back down would stay split in nodes too small for
+    /// the next spike and `Self::shrink_heap` would only see the last one
+    unsafe fn free_block(&mut self, addr: usize, size: usize) -> usize {
+        let mut start = addr;
+        let mut end = addr + size;
+
+        if let Some(before) = self.take_node_ending_at(start) {
+            start = before.start_addr();
+        }
+        if let Some(after) = self.take_node_starting_at(end) {
+            end = after.end_addr();
+        }
+
+        self.add_free_node(start, end - start);
+        end
     }
 
     pub unsafe fn add_free_node(&mut self, addr: usize, size: usize) {
@@ -144,55 +496,255 @@ impl LinkedListAllocator {
         self.head.next = Some(&mut *node_ptr);
     }
 
-    pub const PAGES_PER_EXTEND: usize = 128;
-    /// extends the heap by `PAGES_PER_EXTEND` pages
-    pub fn extend_heap(&mut self) -> Result<(), ()> {
-        let start_page = Page::containing_address(self.heap_end + PAGE_SIZE);
-        let end_page = Page::containing_address(self.heap_end + PAGE_SIZE * Self::PAGES_PER_EXTEND);
-        let iter = IterPage {
-            start: start_page,
-            end: end_page,
+    /// returns the number of bytes in the free list
+    pub fn free_bytes(&self) -> usize {
+        let mut total = 0;
+        let mut current = &self.head;
+
+        while let Some(ref node) = current.next {
+            total += node.size;
+            current = node;
+        }
+
+        total
+    }
+
+    /// walks the free list, doesn't allocate
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            heap_start: self.heap_start,
+            heap_size: self.heap_end - self.heap_start,
+            heap_limit: self.heap_limit,
+            free_bytes: 0,
+            free_nodes: 0,
+            largest_free: 0,
         };
+        let mut current = &self.head;
+
+        while let Some(ref node) = current.next {
+            stats.free_bytes += node.size;
+            stats.free_nodes += 1;
+            stats.largest_free = stats.largest_free.max(node.size);
+            current = node;
+        }
+
+        stats
+    }
 
-        for page in iter {
-            unsafe {
-                let allocated_frame = kernel().frame_allocator().allocate_frame().ok_or(())?;
-
-                current_root_table()
-                    .map_to(
-                        page,
-                        allocated_frame,
-                        EntryFlags::PRESENT | EntryFlags::WRITABLE,
-                    )
-                    .or(Err(()))?;
+    /// the number of pages the next `Self::extend_heap` maps
+    #[inline]
+    pub fn pages_per_extend(&self) -> usize {
+        self.pages_per_extend
+    }
+
+    #[inline]
+    pub fn policy(&self) -> FitPolicy {
+        self.policy
+    }
+
+    /// changes how the next allocations pick their node, see `FitPolicy`
+    #[inline]
+    pub fn set_policy(&mut self, policy: FitPolicy) {
+        self.policy = policy;
+    }
+
+    /// the heap won't extend past `limit`, the kernel heap is limited to its region of the
+    /// higher half (see `memory::layout::HEAP`) otherwise it is only limited to its canonical half
+    pub fn set_heap_limit(&mut self, limit: usize) {
+        self.heap_limit = limit;
+    }
+
+    /// see `Self::set_heap_limit`
+    #[inline]
+    pub fn heap_limit(&self) -> usize {
+        self.heap_limit
+    }
+
+    /// changes how the heap grows from the next extend on
+    pub fn set_growth(&mut self, growth: HeapGrowth) {
+        self.growth = growth;
+        self.pages_per_extend = growth.initial_pages();
+    }
+
+    /// extends the heap by `Self::pages_per_extend` pages right after its end
+    /// returns Err(()) if there aren't enough frames or if the pages would go past the heap
+    /// limit or the end of the canonical half of the address space the heap is in
+    pub fn extend_heap(&mut self) -> Result<(), ()> {
+        let pages = self.pages_per_extend;
+        let (start, size) = extend_range(self.heap_end, pages, self.heap_limit).ok_or(())?;
+        let start_page = Page::containing_address(start);
+        let end_page = start_page + (pages - 1);
+
+        // we reserve all the frames first so running out of frames doesn't leave the heap half
+        // mapped
+        let mut reserved = ReservedFrames::new();
+        while reserved.count < pages {
+            let frame = kernel().frame_allocator().allocate_frame();
+            let Some(frame) = frame else {
+                reserved.free();
+                return Err(());
+            };
+
+            reserved.push(frame);
+        }
+
+        let mut batch = TlbBatch::new();
+        for (index, page) in Page::iter_pages(start_page, end_page).enumerate() {
+            let frame = reserved.pop().unwrap();
+            let result = unsafe {
+                current_root_table().map_to_batched(
+                    page,
+                    frame,
+                    EntryFlags::PRESENT | EntryFlags::WRITABLE,
+                    &mut batch,
+                )
+            };
+
+            // map_to can only fail allocating a page table, the tables it allocated before
+            // failing are kept they are still in use
+            if result.is_err() {
+                reserved.push(frame);
+                for page in Page::iter_pages(start_page, page).take(index) {
+                    if let Some(frame) =
+                        unsafe { current_root_table().unmap_batched(page, &mut batch) }
+                    {
+                        reserved.push(frame);
+                    }
+                }
+
+                // the frames can't be handed out while their old mappings can still be used
+                batch.flush();
+                reserved.free();
+                return Err(());
             }
         }
-        unsafe {
-            self.add_free_node(start_page.start_address, PAGE_SIZE * Self::PAGES_PER_EXTEND);
+
+        // the new node is written to the pages
+        batch.flush();
+
+        // the heap grows contiguously so the extend merges with the free node at the end of the
+        // heap if there is one
+        if !self.grow_node_ending_at(start.as_usize(), size) {
+            unsafe { self.add_free_node(start.as_usize(), size) };
+        }
+
+        self.heap_end = (start + size).as_usize();
+        self.pages_per_extend = self.growth.next_pages(pages);
+        Ok(())
+    }
+
+    /// unmaps the pages of the free node at the end of the heap giving their frames back and lowers
+    /// `Self::heap_end`, returns the number of pages unmapped
+    /// the first `HeapGrowth` extend worth of pages of the node stays mapped so a heap going up
+    /// and down around the same size doesn't map and unmap the same pages every time, and the
+    /// heap never goes below its size after `Self::init`
+    pub fn shrink_heap(&mut self) -> usize {
+        let heap_end = self.heap_end;
+        let Some(node) = self.take_node_ending_at(heap_end) else {
+            return 0;
+        };
+        let node_start = node.start_addr();
+
+        let kept = (self.growth.initial_pages() * PAGE_SIZE).max(size_of::<Node>());
+        let new_end = node_start
+            .saturating_add(kept)
+            .max(self.initial_heap_end)
+            .min(heap_end);
+        // the page `new_end` is in stays mapped so the node can go up to its end
+        let new_end = align_up(new_end, PAGE_SIZE);
+
+        if new_end >= heap_end {
+            unsafe { self.add_free_node(node_start, node.size) };
+            return 0;
         }
-        // self.head.next should contain our extended Node we combine all the extended Nodes
-        // togther
-        while let Some(ref mut node) = self.head.next.as_mut().unwrap().next {
-            if !(node.size % (PAGE_SIZE * Self::PAGES_PER_EXTEND) == 0) {
-                break;
+
+        let end_page = Page::containing_address(VirtAddr::new(heap_end - PAGE_SIZE));
+        let pages = Page::iter_pages(Page::containing_address(VirtAddr::new(new_end)), end_page);
+        let mut frames = ReservedFrames::new();
+        let mut batch = TlbBatch::new();
+
+        for page in pages {
+            if let Some(frame) = unsafe { current_root_table().unmap_batched(page, &mut batch) } {
+                frames.push(frame);
             }
+        }
+
+        // the frames can't be handed out while their old mappings can still be used
+        batch.flush();
+        let count = frames.count;
+        frames.free();
+
+        self.heap_end = new_end;
+        unsafe { self.add_free_node(node_start, new_end - node_start) };
+        count
+    }
 
-            let node_next = node.next.take();
-            let node_size = node.size;
+    /// removes the free node that ends at `addr` from the free list and returns it
+    fn take_node_ending_at(&mut self, addr: usize) -> Option<&'static mut Node> {
+        self.take_node_where(|node| node.end_addr() == addr)
+    }
+
+    /// removes the free node that starts at `addr` from the free list and returns it
+    fn take_node_starting_at(&mut self, addr: usize) -> Option<&'static mut Node> {
+        self.take_node_where(|node| node.start_addr() == addr)
+    }
+
+    /// removes the first free node `f` returns true for from the free list and returns it
+    fn take_node_where(&mut self, f: impl Fn(&Node) -> bool) -> Option<&'static mut Node> {
+        let mut current = &mut self.head;
 
-            let to_combine = self.head.next.take().unwrap();
-            to_combine.next = node_next;
+        while let Some(ref mut node) = current.next {
+            if f(node) {
+                let next = node.next.take();
+                let node = current.next.take().unwrap();
+                current.next = next;
 
-            to_combine.size = to_combine.size + node_size;
+                return Some(node);
+            }
 
-            self.head.next = Some(to_combine);
+            current = current.next.as_mut().unwrap();
         }
 
-        self.heap_end = end_page.start_address + PAGE_SIZE;
-        Ok(())
+        None
+    }
+
+    /// grows the free node that ends at `addr` by `size` bytes, returns false if there is none
+    fn grow_node_ending_at(&mut self, addr: usize, size: usize) -> bool {
+        let mut current = self.head.next.as_deref_mut();
+
+        while let Some(node) = current {
+            if node.end_addr() == addr {
+                node.size += size;
+                return true;
+            }
+
+            current = node.next.as_deref_mut();
+        }
+
+        false
+    }
+
+    /// returns the size and alignment an allocation of `layout` takes in the heap, the size is
+    /// at least a `Node` so the block can become a free node again when it is deallocated
+    #[inline]
+    pub fn size_align(layout: Layout) -> (usize, usize) {
+        let (size, align) = (layout.size(), layout.align());
+
+        // a power of two size is already a multiple of any power of two alignment up to it so
+        // there is nothing to pad, this is the case of most allocations
+        if size.is_power_of_two()
+            && size >= size_of::<Node>()
+            && align >= align_of::<Node>()
+            && align <= size
+        {
+            return (size, align);
+        }
+
+        Self::padded_size_align(layout)
     }
 
-    fn size_align(layout: Layout) -> (usize, usize) {
+    /// the general case of `Self::size_align`
+    pub fn padded_size_align(layout: Layout) -> (usize, usize) {
         let layout = layout
             .align_to(align_of::<Node>())
             .expect("adjusting alignment failed")
@@ -203,14 +755,145 @@ impl LinkedListAllocator {
     }
 }
 
+/// the end of the lower half of the address space, the higher half ends at 2^64
+const LOWER_HALF_END: usize = 0x0000_8000_0000_0000;
+
+/// the page aligned start and the size of `pages` pages right after `heap_end`, None if there
+/// are no pages, if they go past `limit` or if they don't fit in the canonical half `heap_end` is
+/// in, without this an overflowing end would wrap below the start and map nothing
+fn extend_range(heap_end: usize, pages: usize, limit: usize) -> Option<(VirtAddr, usize)> {
+    if pages == 0 {
+        return None;
+    }
+
+    let size = pages.checked_mul(PAGE_SIZE)?;
+    let start = heap_end.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1);
+    let end = start.checked_add(size)?;
+
+    if (start < LOWER_HALF_END && end > LOWER_HALF_END) || end > limit {
+        return None;
+    }
+    Some((VirtAddr::new(start), size))
+}
+
+// the emergency pool, a few bytes for the paths reporting that the heap is exhausted (the oom
+// handler, the panic logger) which would have nothing to allocate from otherwise
+// the pool is only used inside of `emergency` and only once the heap can't extend anymore, it is
+// a bump allocator: freeing an allocation from it does nothing so it can never hand out more
+// than `EMERGENCY_POOL_SIZE` bytes, once it is used up the allocations fail like without it
+
+pub const EMERGENCY_POOL_SIZE: usize = 4096;
+
+#[repr(C, align(4096))]
+pub struct EmergencyPool {
+    bytes: UnsafeCell<[u8; EMERGENCY_POOL_SIZE]>,
+    /// the bytes handed out, they are never given back
+    used: AtomicUsize,
+}
+
+// the bytes are only reached through the pointers `EmergencyPool::alloc` hands out
+unsafe impl Sync for EmergencyPool {}
+
+impl EmergencyPool {
+    pub const fn new() -> Self {
+        Self {
+            bytes: UnsafeCell::new([0; EMERGENCY_POOL_SIZE]),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// the bytes left
+    #[inline]
+    pub fn free_bytes(&self) -> usize {
+        EMERGENCY_POOL_SIZE - self.used.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        let start = self.bytes.get() as usize;
+        (start..start + EMERGENCY_POOL_SIZE).contains(&(ptr as usize))
+    }
+
+    /// null if the pool doesn't have `layout.size()` bytes aligned to `layout.align()` left
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        let start = self.bytes.get() as usize;
+        let mut offset = 0;
+
+        let reserved = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                offset = align_up(start + used, layout.align()) - start;
+                let end = offset.checked_add(layout.size())?;
+                (end <= EMERGENCY_POOL_SIZE).then_some(end)
+            });
+
+        match reserved {
+            Ok(_) => (start + offset) as *mut u8,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+}
+
+/// the pool of the global allocator
+static EMERGENCY_POOL: EmergencyPool = EmergencyPool::new();
+/// set by `emergency`, there is a single cpu and interrupts are disabled while it is set so only
+/// the thread that set it can see it
+static EMERGENCY: AtomicBool = AtomicBool::new(false);
+
+/// runs `f` with interrupts disabled letting its allocations fall back to the emergency pool
+/// once the heap is exhausted, for reporting errors only
+pub fn emergency<R>(f: impl FnOnce() -> R) -> R {
+    without_interrupts(|| {
+        let previous = EMERGENCY.swap(true, Ordering::Relaxed);
+        let result = f();
+        EMERGENCY.store(previous, Ordering::Relaxed);
+        result
+    })
+}
+
+/// the bytes left in the emergency pool of the global allocator
+#[inline]
+pub fn emergency_pool_free() -> usize {
+    EMERGENCY_POOL.free_bytes()
+}
+
+impl Locked<LinkedListAllocator> {
+    /// allocates from the heap falling back to `pool` inside of `emergency`, what `GlobalAlloc`
+    /// does with the global pool
+    pub unsafe fn alloc_with_pool(&self, layout: Layout, pool: &EmergencyPool) -> *mut u8 {
+        let mut allocator = self.lock();
+        // an uninitialized heap would be "extended" at address 0
+        assert!(
+            allocator.is_initialized(),
+            "allocated {:?} before the heap was initialized",
+            layout
+        );
+        let ptr = allocator.alloc_mut(layout);
+        drop(allocator);
+
+        if ptr.is_null() && EMERGENCY.load(Ordering::Relaxed) {
+            return pool.alloc(layout);
+        }
+        ptr
+    }
+
+    /// gives `ptr` back to the heap, does nothing if it is from `pool`
+    pub unsafe fn dealloc_with_pool(&self, ptr: *mut u8, layout: Layout, pool: &EmergencyPool) {
+        if pool.contains(ptr) {
+            return;
+        }
+
+        let mut allocator = self.lock();
+        allocator.dealloc_mut(ptr, layout)
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.inner.lock();
-        allocator.alloc_mut(layout)
+        self.alloc_with_pool(layout, &EMERGENCY_POOL)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.inner.lock();
-        allocator.dealloc_mut(ptr, layout)
+        self.dealloc_with_pool(ptr, layout, &EMERGENCY_POOL)
     }
 }
@@ -1,33 +1,142 @@
 // a pmm i believe
 
-use core::slice;
+use core::{
+    ops::{Add, Sub},
+    slice,
+};
+
+use heapless::Vec;
+use limine::memory_map::EntryType;
 
 use crate::serial;
 
-use super::{align_down, align_up, paging::PAGE_SIZE, PhysAddr};
+use super::{align_down, align_up, paging::PAGE_SIZE, PhysAddr, PhysRange};
+/// a page of physical memory, it always starts page aligned which is why the address is only
+/// set by the constructors
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Frame {
-    pub start_address: PhysAddr,
+    start_address: PhysAddr,
 }
 
 impl Frame {
+    #[inline]
+    pub const fn start_address(&self) -> PhysAddr {
+        self.start_address
+    }
+
+    /// the frame starting at `start_address` which must be page aligned
+    #[inline]
+    pub const fn from_start_address(start_address: PhysAddr) -> Self {
+        debug_assert!(
+            start_address.is_aligned(PAGE_SIZE),
+            "frames start at page aligned addresses"
+        );
+        Self { start_address }
+    }
+
     #[inline]
     // returns the frame that contains an address
     pub fn containing_address(address: PhysAddr) -> Self {
         Self {
-            start_address: align_down(address, PAGE_SIZE), // for now frames can only be 1 normal page sized
+            start_address: address.align_down(PAGE_SIZE), // for now frames can only be 1 normal page sized
+        }
+    }
+}
+
+/// the frame `rhs` frames after self
+impl Add<usize> for Frame {
+    type Output = Self;
+    #[inline]
+    fn add(self, rhs: usize) -> Self::Output {
+        Self {
+            start_address: self.start_address + rhs * PAGE_SIZE,
+        }
+    }
+}
+
+/// the frame `rhs` frames before self
+impl Sub<usize> for Frame {
+    type Output = Self;
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        Self {
+            start_address: self.start_address - rhs * PAGE_SIZE,
         }
     }
 }
 
 pub type Bitmap = &'static mut [u8];
 
+/// what a physical memory range is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    BootloaderReclaimable,
+    KernelAndModules,
+    Framebuffer,
+    /// anything that isn't in the memory map, device memory like the local apic lives here
+    Mmio,
+}
+
+impl RegionKind {
+    fn from_limine(entry_type: EntryType) -> Self {
+        let kinds = [
+            (EntryType::USABLE, Self::Usable),
+            (EntryType::ACPI_RECLAIMABLE, Self::AcpiReclaimable),
+            (EntryType::ACPI_NVS, Self::AcpiNvs),
+            (EntryType::BAD_MEMORY, Self::BadMemory),
+            (
+                EntryType::BOOTLOADER_RECLAIMABLE,
+                Self::BootloaderReclaimable,
+            ),
+            (EntryType::KERNEL_AND_MODULES, Self::KernelAndModules),
+            (EntryType::FRAMEBUFFER, Self::Framebuffer),
+        ];
+
+        kinds
+            .iter()
+            .find(|(limine_type, _)| *limine_type == entry_type)
+            .map_or(Self::Reserved, |(_, kind)| *kind)
+    }
+
+    /// wether or not the region belongs to a device so it is fine to map as mmio
+    pub fn is_device(&self) -> bool {
+        matches!(self, Self::Mmio | Self::Reserved | Self::Framebuffer)
+    }
+}
+
+/// the max number of memory map entries we keep track of
+const MAX_REGIONS: usize = 128;
+/// the max number of ranges `RegionAllocator::reserve` keeps track of
+const MAX_RESERVED: usize = 32;
+
+/// the end of the first MiB, the ap trampoline has to live below it and so do the legacy
+/// devices and the bios data
+pub const LOW_MEMORY_END: PhysAddr = PhysAddr::new(0x10_0000);
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub range: PhysRange,
+    pub kind: RegionKind,
+}
+
 #[derive(Debug)]
 pub struct RegionAllocator {
     /// keeps track of which frame is used or not
     bitmap: Bitmap,
     /// the index of the frame we start searching from in the bitmap
     search_from: usize,
+    /// the memory map, used by `Self::region_kind`
+    regions: Vec<Region, MAX_REGIONS>,
+    /// the ranges passed to `Self::reserve`
+    reserved: Vec<PhysRange, MAX_RESERVED>,
+    /// how many frames we allocate before failing, used to test allocation failures
+    #[cfg(feature = "test")]
+    fail_after: Option<usize>,
 }
 
 impl RegionAllocator {
@@ -83,29 +192,49 @@ impl RegionAllocator {
         );
 
         // allocates and setups bitmap
-        let bitmap_base = best_region.unwrap().base as usize;
+        let bitmap_base = PhysAddr::new(best_region.unwrap().base as usize);
         let bitmap_length = best_region.unwrap().length as usize;
 
-        let addr = (bitmap_base + crate::limine::get_phy_offset()) as *mut u8;
+        // the kernel doesn't exist yet so we can't use `phys_to_virt`
+        let addr = (bitmap_base.as_usize() + crate::limine::get_phy_offset()) as *mut u8;
 
         let bitmap = unsafe { slice::from_raw_parts_mut(addr, bytes) };
         bitmap.fill(0xFF);
 
         assert!(bitmap[0] == 0xFF);
 
+        let mut regions = Vec::new();
+        for entry in mmap.entries() {
+            let region = Region {
+                range: PhysRange::from_len(
+                    PhysAddr::new(entry.base as usize),
+                    entry.length as usize,
+                ),
+                kind: RegionKind::from_limine(entry.entry_type),
+            };
+
+            if regions.push(region).is_err() {
+                serial!("too many memory map entries, the rest are treated as reserved\n");
+                break;
+            }
+        }
+
         let mut this = Self {
+            regions,
+            reserved: Vec::new(),
             bitmap,
-            search_from: Self::bitmap_index_from_addr(align_up(
-                first_usable_entry.unwrap().base as usize,
-                PAGE_SIZE,
-            )),
+            #[cfg(feature = "test")]
+            fail_after: None,
+            search_from: Self::bitmap_index_from_addr(
+                PhysAddr::new(first_usable_entry.unwrap().base as usize).align_up(PAGE_SIZE),
+            ),
         };
 
         serial!("bitmap allocation successful!\n");
         // sets all unusable frames as used
         for entry in mmap.entries() {
             if entry.entry_type == limine::memory_map::EntryType::USABLE {
-                this.set_unused_from(entry.base as PhysAddr, entry.length as usize);
+                this.set_unused_from(PhysAddr::new(entry.base as usize), entry.length as usize);
             }
 
             if entry.base == last_usable_entry.base {
@@ -144,7 +273,7 @@ impl RegionAllocator {
     /// takes an addr and turns it into a bitmap (row, col)
     #[inline]
     fn bitmap_loc_from_addr(addr: PhysAddr) -> (usize, usize) {
-        Self::bitmap_loc_from_index(align_down(addr, PAGE_SIZE) / PAGE_SIZE)
+        Self::bitmap_loc_from_index(addr.as_usize() / PAGE_SIZE)
     }
 
     #[inline]
@@ -170,9 +299,9 @@ impl RegionAllocator {
 
             for col in scol..8 {
                 if (self.bitmap[row] >> col) & 1 == 0 {
-                    return Some(Frame {
-                        start_address: Self::bitmap_index_from_loc(row, col) * PAGE_SIZE,
-                    });
+                    return Some(Frame::from_start_address(PhysAddr::new(
+                        Self::bitmap_index_from_loc(row, col) * PAGE_SIZE,
+                    )));
                 }
             }
         }
@@ -181,12 +310,87 @@ impl RegionAllocator {
     }
 
     pub fn allocate_frame(&mut self) -> Option<Frame> {
+        #[cfg(feature = "test")]
+        if let Some(count) = self.fail_after.as_mut() {
+            if *count == 0 {
+                return None;
+            }
+            *count -= 1;
+        }
+
         let frame = self.search_for_free_frame()?;
         self.set_used(frame.start_address);
 
+        debug_assert_eq!(
+            self.region_kind(frame.start_address),
+            RegionKind::Usable,
+            "allocated a non usable frame {:#x}",
+            frame.start_address
+        );
+        debug_assert!(
+            !self.is_reserved(frame),
+            "allocated a reserved frame {:#x}",
+            frame.start_address
+        );
         Some(frame)
     }
 
+    /// makes `Self::allocate_frame` fail after `count` more allocations, None stops failing
+    #[cfg(feature = "test")]
+    pub fn fail_after(&mut self, count: Option<usize>) {
+        self.fail_after = count;
+    }
+
+    /// returns the number of used frames including the unusable ones
+    pub fn used_frames(&self) -> usize {
+        self.bitmap
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    /// the memory map
+    #[inline]
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// makes sure no frame in `start..start + len` is ever returned by `Self::allocate_frame`
+    /// whatever the memory map says about them, the frames in it that are already allocated are
+    /// kept out of the free frames once they are deallocated, fails if there are too many
+    /// reserved ranges
+    pub fn reserve(&mut self, start: PhysAddr, len: usize) -> Result<(), ()> {
+        let range = PhysRange::from_len(start, len).align_out();
+        self.reserved.push(range).map_err(|_| ())?;
+
+        // the bitmap ends at the last usable frame
+        let bitmap = PhysRange::new(
+            PhysAddr::new(0),
+            PhysAddr::new(self.bitmap.len() * 8 * PAGE_SIZE),
+        );
+        if let Some(range) = range.intersect(&bitmap) {
+            for frame in range.frames() {
+                self.set_used(frame.start_address);
+            }
+        }
+        Ok(())
+    }
+
+    /// wether or not `frame` was reserved with `Self::reserve`
+    pub fn is_reserved(&self, frame: Frame) -> bool {
+        self.reserved
+            .iter()
+            .any(|range| range.contains(frame.start_address))
+    }
+
+    /// returns what `addr` is used for according to the memory map
+    pub fn region_kind(&self, addr: PhysAddr) -> RegionKind {
+        self.regions
+            .iter()
+            .find(|region| region.range.contains(addr))
+            .map_or(RegionKind::Mmio, |region| region.kind)
+    }
+
     fn set_unused(&mut self, addr: PhysAddr) {
         let (row, col) = Self::bitmap_loc_from_addr(addr);
         self.bitmap[row] = self.bitmap[row] ^ (1 << col)
@@ -197,7 +401,22 @@ impl RegionAllocator {
         self.bitmap[row] = self.bitmap[row] | (1 << col)
     }
 
+    /// non usable frames are ignored so a reserved frame never ends up in the free frames,
+    /// frames reserved while they were allocated are ignored as well (see `Self::reserve`)
     pub fn deallocate_frame(&mut self, frame: Frame) {
+        if self.is_reserved(frame) {
+            return;
+        }
+
+        if self.region_kind(frame.start_address) != RegionKind::Usable {
+            debug_assert!(
+                false,
+                "deallocated a non usable frame {:#x}",
+                frame.start_address
+            );
+            return;
+        }
+
         self.set_unused(frame.start_address);
     }
 }
@@ -0,0 +1,108 @@
+// prints memory as a classic hex dump, 16 bytes per line followed by their ascii:
+// ffff800000001000: 48 65 6c 6c 6f 00 00 00 00 00 00 00 00 00 00 00  |Hello...........|
+// the whole range is checked to be mapped before reading anything so dumping can't fault
+
+use core::fmt;
+
+use crate::{cross_println, println, serial, terminal, terminal_inited};
+
+use super::{
+    paging::{current_root_table, Page, PAGE_SIZE},
+    VirtAddr,
+};
+
+const BYTES_PER_LINE: usize = 16;
+
+struct Line<'a> {
+    addr: VirtAddr,
+    bytes: &'a [u8],
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}: ", self.addr)?;
+
+        for i in 0..BYTES_PER_LINE {
+            match self.bytes.get(i) {
+                Some(byte) => write!(f, "{:02x} ", byte)?,
+                None => write!(f, "   ")?,
+            }
+        }
+
+        write!(f, " |")?;
+        for byte in self.bytes {
+            let c = if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        write!(f, "|")
+    }
+}
+
+/// returns the first page of `addr`..`addr` + `len` that isn't mapped in the current page
+/// table, goes through huge pages
+pub fn first_unmapped(addr: VirtAddr, len: usize) -> Option<VirtAddr> {
+    if len == 0 {
+        return None;
+    }
+
+    let Some(last) = addr.checked_add(len - 1) else {
+        return Some(addr);
+    };
+    let table = unsafe { current_root_table() };
+
+    Page::iter_pages(
+        Page::containing_address(addr),
+        Page::containing_address(last),
+    )
+    .map(|page| page.start_address)
+    .find(|page_addr| table.translate_addr(*page_addr).is_none())
+}
+
+/// prints `len` bytes starting from `addr` to the serial and the terminal, returns the first
+/// unmapped page in the range without printing anything if there is one
+pub fn hexdump(addr: VirtAddr, len: usize) -> Result<(), VirtAddr> {
+    if let Some(unmapped) = first_unmapped(addr, len) {
+        return Err(unmapped);
+    }
+
+    let mut buffer = [0u8; BYTES_PER_LINE];
+    let mut offset = 0;
+
+    while offset < len {
+        let line_len = (len - offset).min(BYTES_PER_LINE);
+        let line_addr = addr + offset;
+
+        for (i, byte) in buffer[..line_len].iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((line_addr + i).as_ptr::<u8>()) };
+        }
+
+        cross_println!(
+            "{}",
+            Line {
+                addr: line_addr,
+                bytes: &buffer[..line_len],
+            }
+        );
+        offset += line_len;
+    }
+
+    Ok(())
+}
+
+/// the range worth dumping when something goes wrong at `addr`: its line with the lines before
+/// and after it, without leaving `addr`'s page
+pub fn around(addr: VirtAddr) -> (VirtAddr, usize) {
+    let line = addr.align_down(BYTES_PER_LINE);
+    let page = addr.align_down(PAGE_SIZE);
+
+    let start = VirtAddr::new(line.as_usize().saturating_sub(BYTES_PER_LINE)).max(page);
+    let end = line
+        .saturating_add(2 * BYTES_PER_LINE)
+        .min(page.saturating_add(PAGE_SIZE));
+
+    (start, end - start)
+}
@@ -0,0 +1,53 @@
+// the higher half memory map, each region has its own level 4 entries so none of them can grow
+// into another:
+//   0xFFFF_8000_0000_0000..0xFFFF_A000_0000_0000  physmap, every physical address at
+//                                                 `phy_offset` (the bootloader picks it)
+//   0xFFFF_A000_0000_0000..0xFFFF_A080_0000_0000  the kernel heap (see `allocator`)
+//   0xFFFF_C000_0000_0000..0xFFFF_C080_0000_0000  the vmm window: mmio, the framebuffer, stacks
+//                                                 and page sized buffers (see `vmm`)
+//   0xFFFF_FF00_0000_0000..0xFFFF_FF80_0000_0000  the recursive slot (with `recursive-paging`)
+//   0xFFFF_FFFF_8000_0000..                       the kernel image (see linker.ld)
+// the physmap is the only region we don't place ourselves so `heap` checks it (and whatever the
+// bootloader put in the higher half) doesn't overlap the heap before it is mapped
+
+use super::{vmm, VirtAddr, VirtRange, INIT_HEAP_SIZE};
+
+/// where the bootloader is expected to put the physmap
+pub const PHYSMAP: VirtRange = VirtRange::new(
+    VirtAddr::new(0xFFFF_8000_0000_0000),
+    VirtAddr::new(0xFFFF_A000_0000_0000),
+);
+/// the addresses the kernel heap starts at and can extend up to, one level 4 entry (512GiB)
+pub const HEAP: VirtRange = VirtRange::from_len(
+    VirtAddr::new(0xFFFF_A000_0000_0000),
+    512 * 1024 * 1024 * 1024,
+);
+/// see `vmm::VMM_START`
+pub const VMM: VirtRange = VirtRange::from_len(vmm::VMM_START, vmm::VMM_SIZE);
+/// where the linker script puts the kernel image, it goes up to the end of the address space
+pub const KERNEL_IMAGE: VirtRange = VirtRange::new(
+    VirtAddr::new(0xFFFF_FFFF_8000_0000),
+    VirtAddr::new(usize::MAX),
+);
+
+const _: () = assert!(!HEAP.overlaps(&PHYSMAP) && !HEAP.overlaps(&VMM));
+const _: () = assert!(!HEAP.overlaps(&KERNEL_IMAGE) && !VMM.overlaps(&KERNEL_IMAGE));
+const _: () = assert!(INIT_HEAP_SIZE <= HEAP.len());
+
+/// the range the heap may use, Err(()) if one of `mapped` (the ranges the bootloader mapped in
+/// the higher half) overlaps it
+pub fn heap(mapped: &[VirtRange]) -> Result<VirtRange, ()> {
+    match mapped.iter().find(|range| range.overlaps(&HEAP)) {
+        Some(range) => {
+            crate::serial!(
+                "layout: {:#x}..{:#x} is mapped in the heap {:#x}..{:#x}\n",
+                range.start(),
+                range.end(),
+                HEAP.start(),
+                HEAP.end()
+            );
+            Err(())
+        }
+        None => Ok(HEAP),
+    }
+}
@@ -0,0 +1,102 @@
+// typed access to memory mapped registers, a driver declares its registers as `RegOffset`s (the
+// offset and the width of each one) and reaches them through the `RegBlock` of its mapping (see
+// `vmm::map_mmio`), every access is a single volatile access of the register's width so the
+// compiler can neither drop, merge nor split it
+// the mmio mappings are uncached so the accesses reach the device in order without barriers
+
+use core::{marker::PhantomData, mem::size_of};
+
+use super::VirtAddr;
+
+/// a register of `T` (u8, u16, u32 or u64) at a mapped address
+#[derive(Debug)]
+pub struct Reg<T> {
+    addr: VirtAddr,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> Reg<T> {
+    /// unsafe because `addr` must be a mapped register that takes `T` wide accesses
+    #[inline]
+    pub const unsafe fn new(addr: VirtAddr) -> Self {
+        debug_assert!(
+            addr.is_aligned(size_of::<T>()),
+            "mmio registers are aligned to their width"
+        );
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { self.addr.as_ptr::<T>().read_volatile() }
+    }
+
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { self.addr.as_mut_ptr::<T>().write_volatile(value) }
+    }
+}
+
+/// where a register of `T` is in its `RegBlock`
+#[derive(Debug, Clone, Copy)]
+pub struct RegOffset<T> {
+    offset: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> RegOffset<T> {
+    pub const fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub const fn offset(self) -> usize {
+        self.offset
+    }
+}
+
+/// the registers of a device mapped at `base`
+#[derive(Debug, Clone, Copy)]
+pub struct RegBlock {
+    base: VirtAddr,
+}
+
+impl RegBlock {
+    /// unsafe because `base` must be mapped far enough for every `RegOffset` used with it
+    #[inline]
+    pub const unsafe fn new(base: VirtAddr) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// the register at `reg`
+    #[inline]
+    pub fn reg<T: Copy>(&self, reg: RegOffset<T>) -> Reg<T> {
+        unsafe { Reg::new(self.base + reg.offset()) }
+    }
+
+    #[inline]
+    pub fn read<T: Copy>(&self, reg: RegOffset<T>) -> T {
+        self.reg(reg).read()
+    }
+
+    #[inline]
+    pub fn write<T: Copy>(&self, reg: RegOffset<T>, value: T) {
+        self.reg(reg).write(value)
+    }
+}
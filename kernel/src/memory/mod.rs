@@ -1,13 +1,25 @@
+pub mod address;
 pub mod allocator;
 pub mod frame_allocator;
+pub mod hexdump;
+pub mod layout;
+pub mod mmio;
 pub mod paging;
+#[cfg(feature = "recursive-paging")]
+pub mod recursive_paging;
+pub mod selftest;
+#[cfg(target_arch = "x86_64")]
+pub mod user;
+pub mod virt_allocator;
+pub mod vmm;
+pub mod wxaudit;
 
-// types for better code reability
-pub type VirtAddr = usize;
-pub type PhysAddr = usize;
+pub use address::{phys_to_virt, virt_to_phys, PhysAddr, PhysRange, VirtAddr, VirtRange};
+pub use hexdump::hexdump;
 
+use allocator::HeapGrowth;
 use frame_allocator::Frame;
-use paging::{current_root_table, EntryFlags, MapToError, Page};
+use paging::{current_root_table, EntryFlags, MapToError, Page, TlbBatch};
 
 use crate::{
     globals::{global_allocator, kernel},
@@ -20,7 +32,7 @@ pub fn identity_map_present(addr: PhysAddr) {
     unsafe {
         current_root_table()
             .map_to(
-                Page::containing_address(addr),
+                Page::containing_address(VirtAddr::new(addr.as_usize())),
                 Frame::containing_address(addr),
                 EntryFlags::PRESENT,
             )
@@ -28,35 +40,24 @@ pub fn identity_map_present(addr: PhysAddr) {
     }
 }
 
-#[inline]
-pub fn identity_map_writeable(addr: PhysAddr) {
-    unsafe {
-        current_root_table()
-            .map_to(
-                Page::containing_address(addr),
-                Frame::containing_address(addr),
-                EntryFlags::PRESENT | EntryFlags::WRITABLE,
-            )
-            .unwrap();
-    }
-}
-
 fn p4_index(addr: VirtAddr) -> usize {
-    (addr >> 39) & 0x1FF
+    (addr.as_usize() >> 39) & 0x1FF
 }
 fn p3_index(addr: VirtAddr) -> usize {
-    (addr >> 30) & 0x1FF
+    (addr.as_usize() >> 30) & 0x1FF
 }
 fn p2_index(addr: VirtAddr) -> usize {
-    (addr >> 21) & 0x1FF
+    (addr.as_usize() >> 21) & 0x1FF
 }
 fn p1_index(addr: VirtAddr) -> usize {
-    (addr >> 12) & 0x1FF
+    (addr.as_usize() >> 12) & 0x1FF
 }
 
-pub fn translate(addr: VirtAddr) -> (PhysAddr, usize, usize, usize, usize) {
+/// returns the offset of `addr` in its page followed by the indices of the tables it is mapped
+/// with from level 1 to 4
+pub fn translate(addr: VirtAddr) -> (usize, usize, usize, usize, usize) {
     (
-        addr & 0xFFF,
+        addr.as_usize() & 0xFFF,
         p1_index(addr),
         p2_index(addr),
         p3_index(addr),
@@ -73,44 +74,59 @@ pub const fn align_down(x: usize, alignment: usize) -> usize {
 }
 
 pub const INIT_HEAP_SIZE: usize = 4 * 9 * 1024 * 1024;
+/// how the heap grows after using the first `INIT_HEAP_SIZE` bytes
+pub const HEAP_GROWTH: HeapGrowth = HeapGrowth::DEFAULT;
 
 // TODO: make the memory module more generic for different architectures; for now we can only support x86_64 because of the bootloader crate so take into account making our own bootloader for aarch64
 // TODO: maybe make the heap live in physical space instead?
-// TODO: add a heap MAX_SIZE
-/// unsafe because `heap_start`..`INIT_HEAP_SIZE` must be unmapped
-unsafe fn init_heap(heap_start: usize) -> Result<(), MapToError> {
+/// maps the first `INIT_HEAP_SIZE` bytes of `heap` and gives them to the allocator, the heap
+/// then extends up to the end of `heap`
+/// unsafe because `heap` must be unmapped
+unsafe fn init_heap(heap: VirtRange) -> Result<(), MapToError> {
+    let heap_start = heap.start().as_usize();
     serial!(
-        "initing the heap... 0x{:x}..0x{:x}\n",
+        "initing the heap... 0x{:x}..0x{:x} (up to 0x{:x})\n",
         heap_start,
-        heap_start + INIT_HEAP_SIZE
+        heap_start + INIT_HEAP_SIZE,
+        heap.end()
     );
     let page_range = {
-        let heap_start = heap_start;
         let heap_end = heap_start + INIT_HEAP_SIZE;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
+        let heap_start_page = Page::containing_address(VirtAddr::new(heap_start));
+        let heap_end_page = Page::containing_address(VirtAddr::new(heap_end - 1));
         Page::iter_pages(heap_start_page, heap_end_page)
     };
     serial!("Iter created!\n");
 
     let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+    let mut batch = TlbBatch::new();
     for page in page_range {
         let frame = kernel()
             .frame_allocator()
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
 
-        unsafe {
-            current_root_table().map_to(page, frame, flags)?;
-            paging::flush()
-        };
+        current_root_table().map_to_batched(page, frame, flags, &mut batch)?;
     }
+    // before the allocator writes its first node
+    batch.flush();
 
-    global_allocator().lock().init(heap_start, INIT_HEAP_SIZE);
+    let mut allocator = global_allocator().lock();
+    allocator
+        .init(heap_start, INIT_HEAP_SIZE, HEAP_GROWTH)
+        .expect("the initial heap is too small for a single node");
+    allocator.set_heap_limit(heap.end().as_usize());
     serial!("init done\n");
     Ok(())
 }
 
-pub fn init(heap_start: usize) {
-    unsafe { init_heap(heap_start).unwrap() }
+/// `heap` is the range the heap may use, see `layout::heap`
+/// with `recursive-paging` the recursive slot is installed in the kernel pml4 first, every pml4
+/// copies it (see `PageTable::install_recursive`)
+pub fn init(heap: VirtRange) -> Result<(), MapToError> {
+    assert!(heap.len() >= INIT_HEAP_SIZE && heap.start().is_aligned(paging::PAGE_SIZE));
+    #[cfg(feature = "recursive-paging")]
+    unsafe { paging::current_root_table() }
+        .install_recursive(recursive_paging::DEFAULT_RECURSIVE_SLOT);
+    unsafe { init_heap(heap) }
 }
@@ -1,72 +1,212 @@
 const ENTRY_COUNT: usize = 512;
 const HIGHER_HALF_ENTRY: usize = 256;
+/// the level of the top table, a pml4
+pub const PAGE_TABLE_LEVELS: u8 = 4;
 
 pub const PAGE_SIZE: usize = 4096;
+/// the size of a page mapped by a level 2 entry with `EntryFlags::HUGE_PAGE`
+pub const HUGE_PAGE_2MIB: usize = 512 * PAGE_SIZE;
+/// the size of a page mapped by a level 3 entry with `EntryFlags::HUGE_PAGE`
+pub const HUGE_PAGE_1GIB: usize = 512 * HUGE_PAGE_2MIB;
+/// the size of the address space a level 4 entry covers
+const LEVEL_4_ENTRY_SIZE: usize = 512 * HUGE_PAGE_1GIB;
 use crate::{
+    arch::{Arch, Current},
     kernel,
-    memory::{translate, PhysAddr},
+    memory::{phys_to_virt, translate, virt_to_phys, PhysAddr},
+    serial,
 };
+use alloc::vec::Vec;
 use bitflags::bitflags;
+#[cfg(target_arch = "x86_64")]
+use core::arch::asm;
 use core::{
-    arch::asm,
-    ops::{Index, IndexMut},
+    fmt::{self, Display},
+    ops::{Add, Index, IndexMut, Sub},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use crate::memory::frame_allocator::Frame;
 
-use super::{align_down, frame_allocator::RegionAllocator, VirtAddr};
+use super::{align_up, VirtAddr};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Page {
     pub start_address: VirtAddr,
 }
-#[derive(Debug)]
+/// iterates over the pages from `start` to `end` both included, from either side, by
+/// `PAGE_SIZE` or by the size of a huge page (see `Page::iter_huge`)
+#[derive(Debug, Clone)]
 pub struct IterPage {
+    /// the next page from the front
     pub start: Page,
+    /// the next page from the back
     pub end: Page,
+    /// the bytes between two pages
+    stride: usize,
+    /// set once `start` and `end` met, we can't move them past each other since they might be
+    /// at the edges of the address space
+    exhausted: bool,
+}
+
+/// the sizes of the pages mapped by an entry with `EntryFlags::HUGE_PAGE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// mapped by a level 2 entry
+    Size2MiB,
+    /// mapped by a level 3 entry, see `supports_1gib_pages`
+    Size1GiB,
 }
 
+impl HugePageSize {
+    #[inline]
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Size2MiB => HUGE_PAGE_2MIB,
+            Self::Size1GiB => HUGE_PAGE_1GIB,
+        }
+    }
+}
+
+/// the page at address 0, never mapped so dereferencing a null pointer page faults
+pub const NULL_PAGE: Page = Page::containing_address(VirtAddr::new(0));
+
 impl Page {
     pub const fn containing_address(address: VirtAddr) -> Self {
         Self {
-            start_address: align_down(address, PAGE_SIZE),
+            start_address: address.align_down(PAGE_SIZE),
         }
     }
 
     pub const fn iter_pages(start: Page, end: Page) -> IterPage {
-        IterPage { start, end }
+        IterPage {
+            start,
+            end,
+            stride: PAGE_SIZE,
+            exhausted: false,
+        }
+    }
+
+    /// iterates over the huge pages of `size` from `start` to `end` both included, `end` is the
+    /// start of the last huge page not the last 4KiB page of it
+    /// returns None if `start` or `end` isn't aligned to `size`
+    pub const fn iter_huge(start: Page, end: Page, size: HugePageSize) -> Option<IterPage> {
+        if !start.start_address.is_aligned(size.size())
+            || !end.start_address.is_aligned(size.size())
+        {
+            return None;
+        }
+
+        Some(IterPage {
+            start,
+            end,
+            stride: size.size(),
+            exhausted: false,
+        })
+    }
+}
+
+/// the page `rhs` pages after self
+impl Add<usize> for Page {
+    type Output = Self;
+    #[inline]
+    fn add(self, rhs: usize) -> Self::Output {
+        Self {
+            start_address: self.start_address + rhs * PAGE_SIZE,
+        }
+    }
+}
+
+/// the page `rhs` pages before self
+impl Sub<usize> for Page {
+    type Output = Self;
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        Self {
+            start_address: self.start_address - rhs * PAGE_SIZE,
+        }
+    }
+}
+
+/// the number of pages from `rhs` to self, `rhs` can't be after self
+impl Sub<Page> for Page {
+    type Output = usize;
+    #[inline]
+    fn sub(self, rhs: Page) -> Self::Output {
+        (self.start_address - rhs.start_address) / PAGE_SIZE
+    }
+}
+
+impl IterPage {
+    /// takes the next page from the back if `back` otherwise from the front
+    fn take(&mut self, back: bool) -> Option<Page> {
+        if self.exhausted || self.start.start_address > self.end.start_address {
+            return None;
+        }
+
+        if self.start == self.end {
+            self.exhausted = true;
+            return Some(self.start);
+        }
+
+        let pages = self.stride / PAGE_SIZE;
+        if back {
+            let page = self.end;
+            self.end = self.end - pages;
+            Some(page)
+        } else {
+            let page = self.start;
+            self.start = self.start + pages;
+            Some(page)
+        }
     }
 }
 
 impl Iterator for IterPage {
     type Item = Page;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.start.start_address <= self.end.start_address {
-            let page = self.start;
+        self.take(false)
+    }
 
-            let max_page_addr = usize::MAX - (PAGE_SIZE - 1);
-            if self.start.start_address < max_page_addr {
-                self.start.start_address += PAGE_SIZE;
-            } else {
-                self.end.start_address -= PAGE_SIZE;
-            }
-            Some(page)
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = if self.exhausted || self.start.start_address > self.end.start_address {
+            0
         } else {
-            None
-        }
+            (self.end - self.start) / (self.stride / PAGE_SIZE) + 1
+        };
+
+        (len, Some(len))
+    }
+}
+
+impl DoubleEndedIterator for IterPage {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.take(true)
     }
 }
 
+impl ExactSizeIterator for IterPage {}
+
 #[derive(Debug, Clone)]
-pub struct Entry(PhysAddr);
+pub struct Entry(usize);
 // address of the next table or physial frame in 0x000FFFFF_FFFFF000 (the fs is the address are the fs the rest are flags or reserved)
 
-#[cfg(target_arch = "x86_64")]
+/// the bits of an `Entry` holding the address
+const ENTRY_ADDRESS_MASK: usize = 0x000FFFFF_FFFFF000;
+/// the bits of a 2MiB huge page `Entry` holding the address
+const HUGE_PAGE_2MIB_ADDRESS_MASK: usize = 0x000FFFFF_FFE00000;
+/// the bits of a 1GiB huge page `Entry` holding the address
+const HUGE_PAGE_1GIB_ADDRESS_MASK: usize = 0x000FFFFF_C0000000;
+/// the pat bit of a huge page entry, a level 1 entry has it where huge entries have `HUGE_PAGE`
+const HUGE_PAGE_PAT: usize = 1 << 12;
+
 impl Entry {
     pub fn frame(&self) -> Option<Frame> {
         if self.flags().contains(EntryFlags::PRESENT) {
             // TODO: figure out more info about the max physical address width
-            return Some(Frame::containing_address(self.0 & 0x000FFFFF_FFFFF000));
+            return Some(Frame::containing_address(PhysAddr::new(
+                self.0 & ENTRY_ADDRESS_MASK,
+            )));
         }
         None
     }
@@ -75,29 +215,126 @@ impl Entry {
         EntryFlags::from_bits_truncate(self.0 as u64)
     }
 
+    /// the whole 64 bits of the entry as the cpu sees them, reserved bits included
+    #[inline]
+    pub const fn raw(&self) -> u64 {
+        self.0 as u64
+    }
+
+    /// splits the entry into the address it points to (even if it isn't present) and its
+    /// flags, useful for printing entries see `DecodedEntry`
+    pub fn decode(&self) -> DecodedEntry {
+        DecodedEntry {
+            addr: PhysAddr::new(self.0 & ENTRY_ADDRESS_MASK),
+            flags: self.flags(),
+        }
+    }
+
+    /// `addr` must be page aligned, its low bits would be taken as flags otherwise
     pub const fn new(flags: EntryFlags, addr: PhysAddr) -> Self {
-        Self(addr | flags.bits() as usize)
+        debug_assert!(
+            addr.is_aligned(PAGE_SIZE),
+            "page table entries take page aligned addresses"
+        );
+        Self(addr.as_usize() | flags.bits() as usize)
+    }
+
+    /// returns None if `addr` isn't page aligned, see `Self::new`
+    pub const fn checked_new(flags: EntryFlags, addr: PhysAddr) -> Option<Self> {
+        if !addr.is_aligned(PAGE_SIZE) {
+            return None;
+        }
+        Some(Self::new(flags, addr))
     }
 
     pub const fn set(&mut self, flags: EntryFlags, addr: PhysAddr) {
         *self = Self::new(flags, addr)
     }
 
-    /// deallocates an entry depending on it's level if it is 1 it should just deallocate the frame
-    /// otherwise treat the frame as a page table and deallocate it
-    /// &mut self becomes invaild after
+    /// points the entry at `frame` keeping its flags
+    #[inline]
+    pub fn set_frame(&mut self, frame: Frame) {
+        self.0 = (self.0 & !ENTRY_ADDRESS_MASK) | frame.start_address().as_usize();
+    }
+
+    /// replaces the entry flags with `flags` keeping the address it points to
+    #[inline]
+    pub fn set_flags(&mut self, flags: EntryFlags) {
+        self.0 = (self.0 & ENTRY_ADDRESS_MASK) | flags.bits() as usize;
+    }
+
+    /// the protection key of the page the entry maps, see `EntryFlags::protection_key`
+    #[inline]
+    pub fn protection_key(&self) -> u8 {
+        self.flags().protection_key()
+    }
+
+    /// tags the page the entry maps with `key` keeping the rest of the entry, the tlb still has
+    /// the old key until the page is flushed
+    #[inline]
+    pub fn set_protection_key(&mut self, key: u8) {
+        self.set_flags(self.flags().with_protection_key(key));
+    }
+
+    /// deallocates what the entry points at, `level` is its level, 0 for a frame which is just
+    /// deallocated otherwise the frame is a level `level` table freed with `PageTable::free`
+    /// the entry is cleared after
     pub unsafe fn free(&mut self, level: u8) {
+        debug_assert!(
+            level < PAGE_TABLE_LEVELS,
+            "freed an entry of level {}",
+            level
+        );
+        debug_assert!(
+            !self.flags().contains(EntryFlags::HUGE_PAGE),
+            "huge pages aren't freed one frame at a time"
+        );
         let frame = self.frame().unwrap();
+        debug_assert!(
+            !is_shared_table(frame),
+            "freeing {:#x} which every address space shares (a level {} entry)",
+            frame.start_address(),
+            level + 1
+        );
 
         if level == 0 {
             kernel().frame_allocator().deallocate_frame(frame);
+        } else {
+            let table = &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>();
+            table.free(level);
         }
-        let table = &mut *((frame.start_address + kernel().phy_offset) as *mut PageTable);
-        table.free(level)
+        self.0 = 0;
+    }
+
+    /// wether or not the cpu has accessed the page this entry maps since the accessed bit was
+    /// last cleared
+    #[inline]
+    pub fn is_accessed(&self) -> bool {
+        self.flags().contains(EntryFlags::ACCESSED)
+    }
+
+    /// wether or not the cpu has written to the page this entry maps since the dirty bit was
+    /// last cleared
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.flags().contains(EntryFlags::DIRTY)
+    }
+
+    /// clears only the accessed bit then flushes `page` (the page this entry maps) so the cpu
+    /// sets it again on the next access
+    pub fn clear_accessed(&mut self, page: Page) {
+        self.0 &= !(EntryFlags::ACCESSED.bits() as usize);
+        unsafe { flush_page(page) }
+    }
+
+    /// clears only the dirty bit then flushes `page` (the page this entry maps) so the cpu
+    /// sets it again on the next write
+    pub fn clear_dirty(&mut self, page: Page) {
+        self.0 &= !(EntryFlags::DIRTY.bits() as usize);
+        unsafe { flush_page(page) }
     }
 }
 
-#[cfg(target_arch = "x86_64")]
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct EntryFlags: u64 {
@@ -110,10 +347,92 @@ bitflags! {
         const DIRTY =           1 << 6;
         const HUGE_PAGE =       1 << 7;
         const GLOBAL =          1 << 8;
+        /// the 4 bits of the protection key of a user page, see `arch::x86_64::pku`
+        const PROTECTION_KEY =  0xF << 59;
         const NO_EXECUTE =      1 << 63;
     }
 }
 
+impl EntryFlags {
+    const PROTECTION_KEY_SHIFT: u64 = 59;
+
+    /// the protection key of the page, 0 unless set with `Self::with_protection_key`
+    #[inline]
+    pub const fn protection_key(self) -> u8 {
+        ((self.bits() & Self::PROTECTION_KEY.bits()) >> Self::PROTECTION_KEY_SHIFT) as u8
+    }
+
+    /// self with the protection key `key` replacing the previous one, only used for user pages
+    /// once `pku::init` enabled the keys otherwise the bits are ignored
+    #[inline]
+    pub const fn with_protection_key(self, key: u8) -> Self {
+        debug_assert!(key < 16, "protection keys are 4 bits");
+        let bits = self.bits() & !Self::PROTECTION_KEY.bits();
+        Self::from_bits_retain(bits | ((key as u64) << Self::PROTECTION_KEY_SHIFT))
+    }
+}
+
+impl Display for EntryFlags {
+    /// renders each flag as its short name or as dashes if it isn't set for example
+    /// "P RW -- -- -- A D - G NX"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [(EntryFlags, &str); 10] = [
+            (EntryFlags::PRESENT, "P"),
+            (EntryFlags::WRITABLE, "RW"),
+            (EntryFlags::USER_ACCESSIBLE, "US"),
+            (EntryFlags::WRITE_THROUGH, "WT"),
+            (EntryFlags::NO_CACHE, "NC"),
+            (EntryFlags::ACCESSED, "A"),
+            (EntryFlags::DIRTY, "D"),
+            (EntryFlags::HUGE_PAGE, "H"),
+            (EntryFlags::GLOBAL, "G"),
+            (EntryFlags::NO_EXECUTE, "NX"),
+        ];
+
+        for (index, (flag, name)) in NAMES.iter().enumerate() {
+            if index != 0 {
+                f.write_str(" ")?;
+            }
+
+            if self.contains(*flag) {
+                f.write_str(name)?;
+            } else {
+                for _ in 0..name.len() {
+                    f.write_str("-")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// an `Entry` split into the address it points to and its flags, displayed as
+/// "P RW -- -- -- A D - G NX addr=0x1000"
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedEntry {
+    pub addr: PhysAddr,
+    pub flags: EntryFlags,
+}
+
+impl Display for DecodedEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} addr={:#x}", self.flags, self.addr)
+    }
+}
+
+impl Display for Entry {
+    /// the raw entry followed by its decoded flags and address for example
+    /// "0x8000000000001063 P RW -- -- -- A D - - NX addr=0x1000", or "0x0000000000000000 (empty)"
+    /// for an entry that is all zeros
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.raw() == 0 {
+            return write!(f, "{:#018x} (empty)", self.raw());
+        }
+        write!(f, "{:#018x} {}", self.raw(), self.decode())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PageTable {
     pub entries: [Entry; ENTRY_COUNT],
@@ -127,92 +446,166 @@ impl PageTable {
     }
 
     /// copies the higher half entries of the current pml4 to this page table
-    pub fn copy_higher_half(&mut self) {
+    /// the tables are shared so this can't fail today, it returns a Result so a copying
+    /// variant can fail without changing the callers
+    pub fn copy_higher_half(&mut self) -> Result<(), MapToError> {
         unsafe {
             self.entries[HIGHER_HALF_ENTRY..ENTRY_COUNT]
                 .clone_from_slice(&current_root_table().entries[HIGHER_HALF_ENTRY..ENTRY_COUNT])
         }
+        Ok(())
     }
-    /// deallocates a page table including it's entries, doesn't deallocate the higher half!
+
+    /// wether or not the higher half of self looks like the kernel's, the entry the kernel
+    /// image is in is present and every present entry points at a table in physical memory
+    /// (level 4 entries can't map huge pages), catches a bad pml4 being copied around
+    pub fn higher_half_is_valid(&self) -> bool {
+        let (.., kernel_entry) = translate(VirtAddr::new(allocate_pml4 as *const () as usize));
+        let memory_end = *crate::limine::MEMORY_END;
+
+        self.entries[kernel_entry].is_mapped()
+            && self.entries[HIGHER_HALF_ENTRY..ENTRY_COUNT]
+                .iter()
+                .filter(|entry| entry.is_mapped())
+                .all(|entry| {
+                    !entry.flags().contains(EntryFlags::HUGE_PAGE)
+                        && entry.decode().addr.as_usize() < memory_end
+                })
+    }
+    /// deallocates a page table of level `level` (`PAGE_TABLE_LEVELS` for a pml4) including
+    /// the tables and frames its entries point at, doesn't deallocate the higher half of a pml4!
     /// unsafe because self becomes invaild after
     pub unsafe fn free(&mut self, level: u8) {
-        for entry in &mut self.entries[0..HIGHER_HALF_ENTRY] {
-            if entry.0 != 0 {
+        debug_assert!(
+            (1..=PAGE_TABLE_LEVELS).contains(&level),
+            "freed a table of level {}",
+            level
+        );
+        let end = if level == PAGE_TABLE_LEVELS {
+            HIGHER_HALF_ENTRY
+        } else {
+            ENTRY_COUNT
+        };
+
+        for entry in &mut self.entries[0..end] {
+            if entry.is_mapped() {
                 entry.free(level - 1);
             }
         }
 
-        let table_addr = self as *mut PageTable as VirtAddr;
+        let table_addr = VirtAddr::from_ptr(self);
 
-        let frame = Frame::containing_address(table_addr - kernel().phy_offset);
+        let frame = Frame::containing_address(virt_to_phys(table_addr));
+        debug_assert!(
+            !is_shared_table(frame),
+            "freeing the level {} table {:#x} which every address space shares",
+            level,
+            frame.start_address()
+        );
         kernel().frame_allocator().deallocate_frame(frame)
     }
 }
 
+impl PageTable {
+    /// returns the entry at `index` or None if `index` is out of the table
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&Entry> {
+        self.entries.get(index)
+    }
+
+    /// returns the entry at `index` or None if `index` is out of the table
+    #[inline]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Entry> {
+        self.entries.get_mut(index)
+    }
+}
+
 impl Index<usize> for PageTable {
     type Output = Entry;
     fn index(&self, index: usize) -> &Self::Output {
+        debug_assert!(
+            index < ENTRY_COUNT,
+            "page table index overflow: {} >= {}",
+            index,
+            ENTRY_COUNT
+        );
         &self.entries[index]
     }
 }
 
 impl IndexMut<usize> for PageTable {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        debug_assert!(
+            index < ENTRY_COUNT,
+            "page table index overflow: {} >= {}",
+            index,
+            ENTRY_COUNT
+        );
         &mut self.entries[index]
     }
 }
 
-/// returns the current pml4 from cr3
-#[cfg(target_arch = "x86_64")]
+/// returns the current pml4 (see `Arch::current_root_table`)
+#[inline]
 pub unsafe fn current_root_table() -> &'static mut PageTable {
-    use crate::kernel;
-
-    let phys_addr: PhysAddr;
-    unsafe {
-        asm!("mov {}, cr3", out(reg) phys_addr);
-    }
-    let frame = Frame::containing_address(phys_addr);
-
-    let virt_addr = frame.start_address + kernel().phy_offset;
-
-    &mut *(virt_addr as *mut PageTable)
+    Current::current_root_table()
 }
 
 #[derive(Debug)]
 pub enum MapToError {
     FrameAllocationFailed,
+    /// a user accessible page would have been mapped through a table that only kernel pages use
+    KernelOnlyTable,
+    /// the page at address 0 was mapped, so null dereferences keep faulting it can only be
+    /// mapped with `PageTable::map_null_page`
+    NullPage,
+}
+
+/// the flags of a table entry already used by `old` pages that `new` pages are now mapped
+/// through, the cpu checks the permissions at every level so a page is only writable, user
+/// accessible or executable if every entry on the way allows it, a table entry has to allow
+/// everything any page under it allows: writable and user accessible are kept if either has
+/// them while no execute is only kept if both have it, each page then restricts itself with its
+/// level 1 entry
+/// a kernel only table never goes through here with user accessible (see `Entry::map`)
+fn merge_table_flags(old: EntryFlags, new: EntryFlags) -> EntryFlags {
+    let no_execute = old & new & EntryFlags::NO_EXECUTE;
+    ((old | new) - EntryFlags::NO_EXECUTE) | no_execute
 }
 
 impl Entry {
-    /// changes the entry flags to `flags`
     /// if the entry is not present it allocates a new frame and uses it's address as entry's
+    /// with `flags`, otherwise `flags` are merged into the entry flags (see `merge_table_flags`)
     /// then returns the entry address as a pagetable
-    #[cfg(target_arch = "x86_64")]
-    fn map(
-        &mut self,
-        flags: EntryFlags,
-        frame_allocator: &mut RegionAllocator,
-    ) -> Result<&'static mut PageTable, MapToError> {
-        use crate::kernel;
-
+    /// user pages get tables of their own, mapping one through a present table that isn't user
+    /// accessible fails with `MapToError::KernelOnlyTable` instead of opening that table to
+    /// userspace
+    /// the frame allocator is only held while allocating the table's frame
+    fn map(&mut self, flags: EntryFlags) -> Result<&'static mut PageTable, MapToError> {
         if self.is_mapped() {
-            let addr = self.frame().unwrap().start_address;
+            let addr = self.frame().unwrap().start_address();
+            let old_flags = self.flags();
+
+            if flags.contains(EntryFlags::USER_ACCESSIBLE)
+                && !old_flags.contains(EntryFlags::USER_ACCESSIBLE)
+            {
+                return Err(MapToError::KernelOnlyTable);
+            }
 
-            self.set(flags | self.flags(), addr);
-            let virt_addr = addr + kernel().phy_offset;
-            let entry_ptr = virt_addr as *mut PageTable;
+            self.set_flags(merge_table_flags(old_flags, flags));
+            let entry_ptr = phys_to_virt(addr).as_mut_ptr::<PageTable>();
 
             Ok(unsafe { &mut *(entry_ptr) })
         } else {
-            let frame = frame_allocator
+            let frame = kernel()
+                .frame_allocator()
                 .allocate_frame()
                 .ok_or(MapToError::FrameAllocationFailed)?;
 
-            let addr = frame.start_address;
+            let addr = frame.start_address();
             self.set(flags, addr);
 
-            let virt_addr = addr + kernel().phy_offset;
-            let table_ptr = virt_addr as *mut PageTable;
+            let table_ptr = phys_to_virt(addr).as_mut_ptr::<PageTable>();
 
             Ok(unsafe {
                 (*table_ptr).zeroize();
@@ -225,9 +618,8 @@ impl Entry {
     #[inline]
     pub fn mapped_to(&self) -> Option<&'static mut PageTable> {
         if self.is_mapped() {
-            let addr = self.frame().unwrap().start_address;
-            let virt_addr = addr + kernel().phy_offset;
-            let entry_ptr = virt_addr as *mut PageTable;
+            let addr = self.frame().unwrap().start_address();
+            let entry_ptr = phys_to_virt(addr).as_mut_ptr::<PageTable>();
 
             return Some(unsafe { &mut *entry_ptr });
         }
@@ -235,6 +627,16 @@ impl Entry {
         None
     }
 
+    /// like `Self::mapped_to` but returns None if the entry maps a huge page instead of a table
+    #[inline]
+    pub fn table(&self) -> Option<&'static mut PageTable> {
+        if self.flags().contains(EntryFlags::HUGE_PAGE) {
+            return None;
+        }
+
+        self.mapped_to()
+    }
+
     #[inline]
     pub fn is_mapped(&self) -> bool {
         self.flags().contains(EntryFlags::PRESENT)
@@ -242,26 +644,109 @@ impl Entry {
 }
 
 impl PageTable {
-    /// maps a virtual `Page` to physical `Frame`
+    /// maps a virtual `Page` to physical `Frame` and flushes `page`, use `Self::map_to_batched`
+    /// when mapping many pages
+    /// fails with `MapToError::NullPage` for `NULL_PAGE` (see `Self::map_null_page`)
+    #[inline]
     pub fn map_to(
         &mut self,
         page: Page,
         frame: Frame,
         flags: EntryFlags,
     ) -> Result<(), MapToError> {
+        self.map_to_entry(page, frame, flags).map(|_| ())
+    }
+
+    /// like `Self::map_to` but returns the level 1 entry `page` is now mapped with so the caller
+    /// can keep working on it without walking the tables again (see `Self::get_entry`)
+    /// `page` is flushed before this returns, changing the entry after only needs another flush
+    /// if the cpu uses what changed, the ignored bits don't
+    pub fn map_to_entry(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+    ) -> Result<&mut Entry, MapToError> {
+        if page == NULL_PAGE {
+            return Err(MapToError::NullPage);
+        }
+
+        self.map_entry(page, frame, flags, &mut TlbBatch::new(), |_, _| {})
+    }
+
+    /// like `Self::map_to` but `page` is flushed with the rest of `batch`
+    pub fn map_to_batched(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        batch: &mut TlbBatch,
+    ) -> Result<(), MapToError> {
+        if page == NULL_PAGE {
+            return Err(MapToError::NullPage);
+        }
+
+        self.map_entry(page, frame, flags, batch, |_, _| {})
+            .map(|_| ())
+    }
+
+    /// maps the null page to `frame` which `Self::map_to` refuses to do, only for the rare code
+    /// that needs low memory mapped at 0 like a real mode trampoline, unmap it as soon as
+    /// possible
+    /// # Safety
+    /// while the null page is mapped dereferencing a null pointer doesn't fault
+    pub unsafe fn map_null_page(
+        &mut self,
+        frame: Frame,
+        flags: EntryFlags,
+    ) -> Result<(), MapToError> {
+        self.map_entry(NULL_PAGE, frame, flags, &mut TlbBatch::new(), |_, _| {})
+            .map(|_| ())
+    }
+
+    /// `save` is called with every entry on the way and its level right before it may change, it
+    /// may allocate since the frame allocator isn't held while it runs
+    /// returns the level 1 entry `page` is mapped with
+    fn map_entry(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        batch: &mut TlbBatch,
+        mut save: impl FnMut(&mut Entry, u8),
+    ) -> Result<&'static mut Entry, MapToError> {
         let (_, level_1_index, level_2_index, level_3_index, level_4_index) =
             translate(page.start_address);
-        let frame_allocator = &mut kernel().frame_allocator();
-        let level_3_table = self[level_4_index].map(flags, frame_allocator)?;
+        // the cache bits of a table entry select the memory type of the next table not the page
+        let table_flags = flags - (EntryFlags::WRITE_THROUGH | EntryFlags::NO_CACHE);
+        let level_4_entry = &mut self[level_4_index];
+        save(level_4_entry, 4);
+        let level_3_table = level_4_entry.map(table_flags)?;
 
-        let level_2_table = level_3_table[level_3_index].map(flags, frame_allocator)?;
+        let level_3_entry = &mut level_3_table[level_3_index];
+        save(level_3_entry, 3);
+        let level_2_table = level_3_entry.map(table_flags)?;
 
-        let level_1_table = level_2_table[level_2_index].map(flags, frame_allocator)?;
+        let level_2_entry = &mut level_2_table[level_2_index];
+        save(level_2_entry, 2);
+        let level_1_table = level_2_entry.map(table_flags)?;
 
         let entry = &mut level_1_table[level_1_index];
+        save(entry, 1);
 
-        *entry = Entry::new(flags, frame.start_address);
-        Ok(())
+        *entry = Entry::new(flags, frame.start_address());
+        batch.touch(page);
+        Ok(entry)
+    }
+
+    /// starts a `MappingTransaction` on self
+    pub fn begin_mapping(&mut self) -> MappingTransaction<'_> {
+        MappingTransaction {
+            table: self,
+            saved: Vec::new(),
+            frames: Vec::new(),
+            batch: TlbBatch::new(),
+        }
     }
 
     /// maps a kernel only `Page` to `Frame` with present and writeable flags
@@ -291,25 +776,572 @@ impl PageTable {
 
         entry.is_mapped()
     }
+
+    /// returns the level 1 entry `page` is mapped with, returns None if one of the tables on the
+    /// way is not mapped or `page` is in a huge page
+    pub fn get_entry(&mut self, page: Page) -> Option<&mut Entry> {
+        let (_, level_1_index, level_2_index, level_3_index, level_4_index) =
+            translate(page.start_address);
+
+        let level_3_table = self[level_4_index].mapped_to()?;
+        let level_2_table = level_3_table[level_3_index].table()?;
+        let level_1_table = level_2_table[level_2_index].table()?;
+
+        Some(&mut level_1_table[level_1_index])
+    }
+
+    /// splits the 2MiB huge page `page` is in into a level 1 table of 512 pages mapped to the
+    /// same frames with the same flags so each of them can be changed on its own
+    /// a 1GiB huge page is split into 2MiB ones first, does nothing if `page` isn't in a huge page
+    pub fn split_huge_page(&mut self, page: Page) -> Result<(), MapToError> {
+        let (_, _, level_2_index, level_3_index, level_4_index) = translate(page.start_address);
+
+        let Some(level_3_table) = self[level_4_index].mapped_to() else {
+            return Ok(());
+        };
+        Self::split_1gib_page(&mut level_3_table[level_3_index], page)?;
+
+        let Some(level_2_table) = level_3_table[level_3_index].table() else {
+            return Ok(());
+        };
+
+        let entry = &mut level_2_table[level_2_index];
+        let huge_flags = entry.flags();
+        if !entry.is_mapped() || !huge_flags.contains(EntryFlags::HUGE_PAGE) {
+            return Ok(());
+        }
+
+        let mut flags = huge_flags - EntryFlags::HUGE_PAGE;
+        if entry.0 & HUGE_PAGE_PAT != 0 {
+            flags |= EntryFlags::HUGE_PAGE;
+        }
+
+        let start = entry.0 & HUGE_PAGE_2MIB_ADDRESS_MASK;
+        let (level_1_table, frame) = allocate_table()?;
+        for (index, page_entry) in level_1_table.entries.iter_mut().enumerate() {
+            *page_entry = Entry::new(flags, PhysAddr::new(start + index * PAGE_SIZE));
+        }
+
+        *entry = Entry::new(Self::split_table_flags(huge_flags), frame.start_address());
+
+        // invalidates the whole huge page
+        unsafe { flush_page(page) };
+        Ok(())
+    }
+
+    /// splits the 1GiB huge page `entry` maps into a level 2 table of 512 2MiB huge pages with
+    /// the same flags and pat bit, `page` is in it, does nothing if `entry` isn't a huge page
+    fn split_1gib_page(entry: &mut Entry, page: Page) -> Result<(), MapToError> {
+        let huge_flags = entry.flags();
+        if !entry.is_mapped() || !huge_flags.contains(EntryFlags::HUGE_PAGE) {
+            return Ok(());
+        }
+
+        // the flags and the pat bit are where they are in a 2MiB page entry
+        let start = entry.0 & HUGE_PAGE_1GIB_ADDRESS_MASK;
+        let flags = entry.0 & !HUGE_PAGE_1GIB_ADDRESS_MASK;
+        let (level_2_table, frame) = allocate_table()?;
+        for (index, page_entry) in level_2_table.entries.iter_mut().enumerate() {
+            *page_entry = Entry(flags | (start + index * HUGE_PAGE_2MIB));
+        }
+
+        *entry = Entry::new(Self::split_table_flags(huge_flags), frame.start_address());
+
+        unsafe { flush_page(page) };
+        Ok(())
+    }
+
+    /// the flags of the table a huge page mapped with `huge_flags` is split into, the same
+    /// permissions so nothing changes until a page is updated
+    #[inline]
+    fn split_table_flags(huge_flags: EntryFlags) -> EntryFlags {
+        huge_flags
+            - (EntryFlags::HUGE_PAGE
+                | EntryFlags::GLOBAL
+                | EntryFlags::DIRTY
+                | EntryFlags::WRITE_THROUGH
+                | EntryFlags::NO_CACHE)
+    }
+
+    /// replaces the flags `page` is mapped with keeping the frame, a 2MiB huge page `page` is in
+    /// is split first (see `Self::split_huge_page`)
+    /// returns None if `page` isn't mapped or is in a huge page that couldn't be split
+    pub fn update_flags(&mut self, page: Page, flags: EntryFlags) -> Option<()> {
+        self.split_huge_page(page).ok()?;
+
+        let entry = self.get_entry(page)?;
+        if !entry.is_mapped() {
+            return None;
+        }
+
+        entry.set_flags(flags);
+        unsafe { flush_page(page) };
+        Some(())
+    }
+
+    /// translates `addr` to the physical address it is mapped to, walks through 1GiB and 2MiB
+    /// huge pages
+    pub fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let (offset, level_1_index, level_2_index, level_3_index, level_4_index) = translate(addr);
+
+        let level_3_table = self[level_4_index].mapped_to()?;
+
+        let level_3_entry = &level_3_table[level_3_index];
+        if level_3_entry.is_mapped() && level_3_entry.flags().contains(EntryFlags::HUGE_PAGE) {
+            let frame = PhysAddr::new(level_3_entry.0 & HUGE_PAGE_1GIB_ADDRESS_MASK);
+            return Some(frame + (addr.as_usize() & (HUGE_PAGE_1GIB - 1)));
+        }
+
+        let level_2_table = level_3_entry.mapped_to()?;
+
+        let level_2_entry = &level_2_table[level_2_index];
+        if level_2_entry.is_mapped() && level_2_entry.flags().contains(EntryFlags::HUGE_PAGE) {
+            let frame = PhysAddr::new(level_2_entry.0 & HUGE_PAGE_2MIB_ADDRESS_MASK);
+            return Some(frame + (addr.as_usize() & (HUGE_PAGE_2MIB - 1)));
+        }
+
+        let level_1_table = level_2_entry.mapped_to()?;
+        let frame = level_1_table[level_1_index].frame()?;
+
+        Some(frame.start_address() + offset)
+    }
+
+    /// unmaps `page` returning the frame it was mapped to, doesn't deallocate the frame or any
+    /// of the tables on the way
+    #[inline]
+    pub fn unmap(&mut self, page: Page) -> Option<Frame> {
+        self.unmap_batched(page, &mut TlbBatch::new())
+    }
+
+    /// like `Self::unmap` but `page` is flushed with the rest of `batch`
+    pub fn unmap_batched(&mut self, page: Page, batch: &mut TlbBatch) -> Option<Frame> {
+        let entry = self.get_entry(page)?;
+        let frame = entry.frame()?;
+
+        entry.0 = 0;
+        batch.touch(page);
+        Some(frame)
+    }
+
+    /// copies the page table at `level` (`PAGE_TABLE_LEVELS` for a pml4) into a new one returning
+    /// its physical address, the lower half tables and the frames they map are copied as well so
+    /// writes to the copy never reach `self`, the higher half is shared
+    /// the lower half must not use huge pages
+    pub unsafe fn clone_deep(&self, level: u8) -> Result<PhysAddr, MapToError> {
+        let (table, frame) = allocate_table()?;
+        let end = if level == PAGE_TABLE_LEVELS {
+            HIGHER_HALF_ENTRY
+        } else {
+            ENTRY_COUNT
+        };
+
+        for (index, entry) in self.entries[0..end].iter().enumerate() {
+            if !entry.is_mapped() {
+                continue;
+            }
+
+            let flags = entry.flags();
+            debug_assert!(!flags.contains(EntryFlags::HUGE_PAGE));
+
+            let copy = if level == 1 {
+                copy_frame(entry.frame().unwrap())
+            } else {
+                entry.mapped_to().unwrap().clone_deep(level - 1)
+            };
+
+            match copy {
+                Ok(addr) => table[index] = Entry::new(flags, addr),
+                Err(err) => {
+                    table.free(level);
+                    return Err(err);
+                }
+            }
+        }
+
+        if level == PAGE_TABLE_LEVELS {
+            table.entries[HIGHER_HALF_ENTRY..ENTRY_COUNT]
+                .clone_from_slice(&self.entries[HIGHER_HALF_ENTRY..ENTRY_COUNT]);
+        }
+
+        Ok(frame.start_address())
+    }
+}
+
+/// the virtual address mapped by the entry `index` of a level `level` table whose first entry
+/// maps `base`, sign extended so a higher half address is canonical
+#[inline]
+const fn entry_address(base: usize, index: usize, level: u8) -> usize {
+    let addr = base | (index << (12 + 9 * (level as usize - 1)));
+    (((addr << 16) as isize) >> 16) as usize
+}
+
+impl PageTable {
+    /// calls `f` with every present entry of self which must be a pml4, the virtual address the
+    /// entry maps and the level of the table the entry is in (4 for the pml4 entries)
+    /// tables are visited before the entries in them, huge pages aren't walked into
+    pub fn for_each_mapping(&self, mut f: impl FnMut(VirtAddr, &Entry, u8)) {
+        self.walk(0, 4, &mut f);
+    }
+
+    /// like `Self::for_each_mapping` but `f` can change the entries, an entry `f` makes
+    /// non-present isn't walked into
+    pub fn for_each_mapping_mut(&mut self, mut f: impl FnMut(VirtAddr, &mut Entry, u8)) {
+        self.walk_mut(0, 4, &mut f);
+    }
+
+    fn walk(&self, base: usize, level: u8, f: &mut dyn FnMut(VirtAddr, &Entry, u8)) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !entry.is_mapped() {
+                continue;
+            }
+
+            let addr = entry_address(base, index, level);
+            f(VirtAddr::new(addr), entry, level);
+
+            if level > 1 {
+                if let Some(table) = entry.table() {
+                    table.walk(addr, level - 1, f);
+                }
+            }
+        }
+    }
+
+    fn walk_mut(&mut self, base: usize, level: u8, f: &mut dyn FnMut(VirtAddr, &mut Entry, u8)) {
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            if !entry.is_mapped() {
+                continue;
+            }
+
+            let addr = entry_address(base, index, level);
+            f(VirtAddr::new(addr), entry, level);
+
+            if level > 1 {
+                if let Some(table) = entry.table() {
+                    table.walk_mut(addr, level - 1, f);
+                }
+            }
+        }
+    }
+}
+
+/// copies the content of `frame` into a newly allocated frame returning its address
+fn copy_frame(frame: Frame) -> Result<PhysAddr, MapToError> {
+    let copy = kernel()
+        .frame_allocator()
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+            phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+            PAGE_SIZE,
+        );
+    }
+
+    Ok(copy.start_address())
 }
 
-pub unsafe fn flush() {
+/// bumped every time the whole tlb is flushed so a `TlbBatch` can tell its pages were flushed
+/// since it touched them
+static TLB_GENERATION: AtomicU64 = AtomicU64::new(0);
+/// flushing more pages than this one by one costs more than flushing everything and refilling
+const FLUSH_ALL_THRESHOLD: usize = 64;
+
+#[inline]
+pub fn tlb_generation() -> u64 {
+    TLB_GENERATION.load(Ordering::Acquire)
+}
+
+/// records that the whole tlb was flushed, for code that loads cr3 itself like a context
+/// switch, global pages survive that so they must not be touched through a `TlbBatch`
+#[inline]
+pub fn tlb_flushed() {
+    TLB_GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// invalidates the tlb entry of `page`
+pub unsafe fn flush_page(page: Page) {
     #[cfg(target_arch = "x86_64")]
-    asm!("invlpg [{}]", in(reg) 0 as *const u8);
+    asm!("invlpg [{}]", in(reg) page.start_address.as_usize(), options(nostack, preserves_flags));
 }
 
-/// allocates a pml4 and returns its physical address
-pub fn allocate_pml4() -> Result<PhysAddr, MapToError> {
+/// invalidates the tlb entries of the pages from `start` to `end` both included, flushes
+/// everything if there are more than `FLUSH_ALL_THRESHOLD`
+pub unsafe fn flush_range(start: Page, end: Page) {
+    let pages = end - start + 1;
+    if pages > FLUSH_ALL_THRESHOLD {
+        return flush_all();
+    }
+
+    for page in Page::iter_pages(start, end) {
+        flush_page(page)
+    }
+}
+
+/// collects the pages whose mappings changed and flushes them all at once when it is dropped
+/// (or when `Self::flush` is called), the batched variants of the `PageTable` methods touch it
+/// instead of flushing every page, which is a lot cheaper for loops mapping many pages
+/// an old mapping of a touched page may still be in the tlb until the batch is flushed so
+/// nothing may access a touched page before that, mappings of pages that weren't mapped aren't
+/// cached so they can be used right away
+/// if the whole tlb is flushed after the last touch (`flush_all` or a context switch) there is
+/// nothing left to flush
+#[derive(Debug)]
+pub struct TlbBatch {
+    /// the lowest and the highest touched page
+    range: Option<(Page, Page)>,
+    /// the tlb generation at the last touch
+    generation: u64,
+}
+
+impl TlbBatch {
+    pub const fn new() -> Self {
+        Self {
+            range: None,
+            generation: 0,
+        }
+    }
+
+    /// records that the mapping of `page` changed, call this after changing it
+    pub fn touch(&mut self, page: Page) {
+        self.range = Some(match self.range {
+            None => (page, page),
+            Some((start, end)) => (start.min(page), end.max(page)),
+        });
+        self.generation = tlb_generation();
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.range.is_none()
+    }
+
+    /// flushes the touched pages
+    pub fn flush(&mut self) {
+        let Some((start, end)) = self.range.take() else {
+            return;
+        };
+
+        if self.generation == tlb_generation() {
+            unsafe { flush_range(start, end) }
+        }
+    }
+}
+
+impl Drop for TlbBatch {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// mappings that are either all kept or all undone, for code mapping many pages that can fail
+/// halfway like the elf loader
+/// every entry a mapping changes is saved right before, `Self::rollback` (or dropping the
+/// transaction) puts them back in reverse order then frees the tables and the frames the
+/// transaction allocated, `Self::commit` keeps the changes and flushes them at once
+/// nothing else may map or unmap pages in the same table while the transaction is alive
+pub struct MappingTransaction<'a> {
+    table: &'a mut PageTable,
+    /// the changed entries with their level and the value they had before, in the order they
+    /// were changed
+    saved: Vec<(*mut Entry, u8, Entry)>,
+    /// the frames allocated by `Self::map_new`
+    frames: Vec<Frame>,
+    batch: TlbBatch,
+}
+
+impl MappingTransaction<'_> {
+    /// like `PageTable::map_to`, `frame` still belongs to the caller if the transaction is rolled
+    /// back
+    pub fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+    ) -> Result<(), MapToError> {
+        if page == NULL_PAGE {
+            return Err(MapToError::NullPage);
+        }
+
+        let saved = &mut self.saved;
+        self.table
+            .map_entry(page, frame, flags, &mut self.batch, |entry, level| {
+                saved.push((entry as *mut Entry, level, entry.clone()))
+            })
+            .map(|_| ())
+    }
+
+    /// allocates a frame and maps `page` to it returning the frame, which isn't zeroed, the frame
+    /// is deallocated if the transaction is rolled back
+    pub fn map_new(&mut self, page: Page, flags: EntryFlags) -> Result<Frame, MapToError> {
+        let frame = kernel()
+            .frame_allocator()
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        self.frames.push(frame);
+        self.map_to(page, frame, flags)?;
+        Ok(frame)
+    }
+
+    /// keeps every mapping and flushes them
+    pub fn commit(mut self) {
+        self.saved.clear();
+        self.frames.clear();
+    }
+
+    /// undoes every mapping, the same as dropping self
+    #[inline]
+    pub fn rollback(self) {}
+
+    fn undo(&mut self) {
+        for (entry, level, old) in self.saved.drain(..).rev() {
+            let entry = unsafe { &mut *entry };
+
+            // a table the transaction allocated
+            if level > 1 && !old.is_mapped() {
+                if let Some(table) = entry.frame() {
+                    self.frames.push(table);
+                }
+            }
+
+            *entry = old;
+        }
+
+        // the old mappings are gone before the frames can be handed out again
+        self.batch.flush();
+        for frame in self.frames.drain(..) {
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+    }
+}
+
+impl Drop for MappingTransaction<'_> {
+    fn drop(&mut self) {
+        self.undo();
+    }
+}
+
+/// wether or not the cpu supports 1GiB pages (cpuid pdpe1gb)
+#[cfg(target_arch = "x86_64")]
+pub fn supports_1gib_pages() -> bool {
+    let extended_features = unsafe { core::arch::x86_64::__cpuid(0x8000_0001) };
+    extended_features.edx & (1 << 26) != 0
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn supports_1gib_pages() -> bool {
+    false
+}
+
+/// allocates a zeroed page table returning it and its frame
+fn allocate_table() -> Result<(&'static mut PageTable, Frame), MapToError> {
     let frame = kernel()
         .frame_allocator()
         .allocate_frame()
         .ok_or(MapToError::FrameAllocationFailed)?;
 
-    let virt_start_addr = frame.start_address + kernel().phy_offset;
-    let table = unsafe { &mut *(virt_start_addr as *mut PageTable) };
-
+    let table = unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() };
     table.zeroize();
-    table.copy_higher_half();
 
-    Ok(frame.start_address)
+    Ok((table, frame))
+}
+
+/// remaps the physical memory window (`phy_offset`..`phy_offset` + `size`) of the current
+/// pml4 using 1GiB pages if the cpu supports them, 2MiB pages otherwise (every x86_64 cpu
+/// supports those)
+/// the new tables are built on the side then each level 4 entry is replaced at once so we never
+/// lose the window we are using to build them, the old tables are not freed they belong to the
+/// bootloader
+/// `phy_offset` must be aligned to a level 4 entry (512GiB)
+pub unsafe fn map_physmap_1gib(phy_offset: VirtAddr, size: usize) -> Result<(), MapToError> {
+    assert!(phy_offset.is_aligned(LEVEL_4_ENTRY_SIZE));
+
+    let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::GLOBAL;
+    let use_1gib = supports_1gib_pages();
+    serial!(
+        "mapping the physmap using {} pages...\n",
+        if use_1gib { "1GiB" } else { "2MiB" }
+    );
+
+    let size = align_up(size, HUGE_PAGE_1GIB);
+    let root_table = current_root_table();
+
+    for level_4_start in (0..size).step_by(LEVEL_4_ENTRY_SIZE) {
+        let (level_3_table, level_3_frame) = allocate_table()?;
+
+        let level_4_end = size.min(level_4_start + LEVEL_4_ENTRY_SIZE);
+        let pages = Page::iter_huge(
+            Page::containing_address(phy_offset + level_4_start),
+            Page::containing_address(phy_offset + (level_4_end - HUGE_PAGE_1GIB)),
+            HugePageSize::Size1GiB,
+        )
+        .unwrap();
+        for page in pages {
+            let phys_addr = page.start_address - phy_offset;
+            let (_, _, _, level_3_index, _) = translate(page.start_address);
+
+            if use_1gib {
+                level_3_table[level_3_index] =
+                    Entry::new(flags | EntryFlags::HUGE_PAGE, PhysAddr::new(phys_addr));
+                continue;
+            }
+
+            let (level_2_table, level_2_frame) = allocate_table()?;
+            for level_2_index in 0..ENTRY_COUNT {
+                level_2_table[level_2_index] = Entry::new(
+                    flags | EntryFlags::HUGE_PAGE,
+                    PhysAddr::new(phys_addr + level_2_index * HUGE_PAGE_2MIB),
+                );
+            }
+
+            level_3_table[level_3_index] = Entry::new(flags, level_2_frame.start_address());
+        }
+
+        let (_, _, _, _, level_4_index) = translate(phy_offset + level_4_start);
+        root_table[level_4_index] = Entry::new(flags, level_3_frame.start_address());
+    }
+
+    flush_all();
+    Ok(())
+}
+
+/// wether or not `frame` is the current pml4 or one of the level 3 tables of its higher half
+/// which every pml4 points at (see `PageTable::copy_higher_half`), freeing one of them would
+/// unmap the kernel from every address space
+/// the tables below the level 3 ones aren't checked, walking the whole higher half for every
+/// freed frame would take too long
+pub fn is_shared_table(frame: Frame) -> bool {
+    let root_table = unsafe { current_root_table() };
+    let root_frame = Frame::containing_address(virt_to_phys(VirtAddr::from_ptr(root_table)));
+
+    root_frame == frame
+        || root_table.entries[HIGHER_HALF_ENTRY..ENTRY_COUNT]
+            .iter()
+            .any(|entry| entry.frame() == Some(frame))
+}
+
+/// flushes the whole tlb (except global pages) by reloading cr3
+pub unsafe fn flush_all() {
+    #[cfg(target_arch = "x86_64")]
+    asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(nostack, preserves_flags));
+    tlb_flushed();
+}
+
+/// allocates a pml4 sharing the higher half of the current one and returns its physical
+/// address, the frame is given back if anything fails
+pub fn allocate_pml4() -> Result<PhysAddr, MapToError> {
+    let (table, frame) = allocate_table()?;
+
+    if let Err(err) = table.copy_higher_half() {
+        kernel().frame_allocator().deallocate_frame(frame);
+        return Err(err);
+    }
+    debug_assert!(
+        table.higher_half_is_valid(),
+        "allocate_pml4: the current pml4 doesn't have a valid kernel higher half"
+    );
+
+    Ok(frame.start_address())
 }
@@ -0,0 +1,104 @@
+// recursive paging, an alternative to accessing page tables through `phy_offset`
+// a pml4 entry (the recursive slot) points back to the pml4 itself so walking through it makes
+// the cpu treat a page table as if it was the next level, going through the slot 4 times gives
+// the pml4 itself, 3 times gives a level 3 table and so on
+// this only needs the recursive slot to be mapped instead of the whole physical memory
+
+use super::{
+    frame_allocator::Frame,
+    paging::{current_root_table, Entry, EntryFlags, PageTable},
+    VirtAddr,
+};
+
+/// a pml4 slot that isn't used for anything else
+pub const DEFAULT_RECURSIVE_SLOT: usize = 510;
+
+impl PageTable {
+    /// points the pml4 entry `slot` at self, self must be a pml4 and `slot` must not be used
+    /// if `slot` is in the higher half the entry is copied into every pml4 created after using
+    /// `PageTable::copy_higher_half` pointing them at self, use a lower half slot on each pml4
+    /// if that isn't wanted
+    /// self is found using the current pml4 so it must be mapped in it
+    /// the entry isn't executable, the tables it maps as pages are only data
+    pub fn install_recursive(&mut self, slot: usize) {
+        assert!(slot < self.entries.len());
+        assert!(
+            !self[slot].is_mapped(),
+            "the recursive slot {} is used",
+            slot
+        );
+
+        let table_addr = VirtAddr::from_ptr(self);
+        let phys_addr = unsafe { current_root_table() }
+            .translate_addr(table_addr)
+            .expect("the pml4 isn't mapped");
+
+        let frame = Frame::containing_address(phys_addr);
+        self[slot] = Entry::new(
+            EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            frame.start_address(),
+        );
+    }
+}
+
+/// sign extends bit 47 making `addr` canonical
+#[inline]
+const fn canonical(addr: usize) -> VirtAddr {
+    VirtAddr::new((((addr << 16) as isize) >> 16) as usize)
+}
+
+/// returns the virtual address of the level `level` table (4 for the pml4) used to map `addr`
+/// through the recursive slot `slot`
+pub const fn table_addr(slot: usize, level: u8, addr: VirtAddr) -> VirtAddr {
+    assert!(level >= 1 && level <= 4);
+    let level = level as usize;
+
+    // the indices of the tables above `level` used to map `addr`
+    let indices_count = 4 - level;
+    let indices = (addr.as_usize() >> (12 + 9 * level)) & ((1 << (9 * indices_count)) - 1);
+
+    let mut result = indices << 12;
+    let mut i = 0;
+    while i < level {
+        result |= slot << (12 + 9 * (indices_count + i));
+        i += 1;
+    }
+
+    canonical(result)
+}
+
+/// returns the level `level` table (4 for the pml4) used to map `addr` through the recursive
+/// slot `slot`
+/// the table must exist and `PageTable::install_recursive(slot)` must have been called on the
+/// current pml4
+#[inline]
+pub unsafe fn table(slot: usize, level: u8, addr: VirtAddr) -> &'static mut PageTable {
+    &mut *table_addr(slot, level, addr).as_mut_ptr::<PageTable>()
+}
+
+/// returns the current pml4 through the recursive slot `slot`
+#[inline]
+pub unsafe fn root_table(slot: usize) -> &'static mut PageTable {
+    table(slot, 4, VirtAddr::new(0))
+}
+
+/// wether or not `entry` points to a next level table
+#[inline]
+fn is_table(entry: &Entry) -> bool {
+    entry.is_mapped() && !entry.flags().contains(EntryFlags::HUGE_PAGE)
+}
+
+/// returns the level 1 entry `addr` is mapped with through the recursive slot `slot`, returns
+/// None if one of the tables on the way is not present or `addr` is in a huge page
+pub unsafe fn get_entry(slot: usize, addr: VirtAddr) -> Option<&'static mut Entry> {
+    let (_, level_1_index, level_2_index, level_3_index, level_4_index) = super::translate(addr);
+
+    if !is_table(&root_table(slot)[level_4_index])
+        || !is_table(&table(slot, 3, addr)[level_3_index])
+        || !is_table(&table(slot, 2, addr)[level_2_index])
+    {
+        return None;
+    }
+
+    Some(&mut table(slot, 1, addr)[level_1_index])
+}
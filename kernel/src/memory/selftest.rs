@@ -0,0 +1,119 @@
+// a quick check of the memory subsystems ran on every boot with the `selftest` feature, before
+// the scheduler starts, unlike the tests this doesn't need the test harness
+// it panics with what went wrong when something doesn't add up
+
+use core::alloc::Layout;
+
+use alloc::alloc::{alloc, dealloc};
+
+use crate::{
+    globals::{global_allocator, kernel},
+    serial,
+};
+
+use super::{
+    paging::{current_root_table, PAGE_SIZE},
+    phys_to_virt, vmm,
+};
+
+const SIZES: [usize; 8] = [1, 7, 8, 24, 100, 4095, 4096, 65536];
+const ALIGNS: [usize; 5] = [1, 8, 16, 64, 4096];
+const PAGES: usize = 16;
+
+/// bytes of the heap that aren't in the free list
+fn used_heap_bytes() -> usize {
+    let allocator = global_allocator().lock();
+    allocator.heap_end - allocator.heap_start - allocator.free_bytes()
+}
+
+/// allocates every size with every alignment at once, checks they don't overlap then frees
+/// them making sure the heap gets every byte back
+fn allocator() {
+    let used = used_heap_bytes();
+    let mut allocations = [(core::ptr::null_mut::<u8>(), Layout::new::<u8>()); 40];
+
+    for (index, allocation) in allocations.iter_mut().enumerate() {
+        let size = SIZES[index / ALIGNS.len()];
+        let align = ALIGNS[index % ALIGNS.len()];
+        let layout = Layout::from_size_align(size, align).unwrap();
+
+        let ptr = unsafe { alloc(layout) };
+        assert!(!ptr.is_null(), "selftest: failed to allocate {:?}", layout);
+        assert_eq!(ptr as usize % align, 0, "selftest: misaligned {:?}", layout);
+
+        unsafe { ptr.write_bytes(index as u8, size) };
+        *allocation = (ptr, layout);
+    }
+
+    for (index, (ptr, layout)) in allocations.iter().enumerate() {
+        let bytes = unsafe { core::slice::from_raw_parts(*ptr, layout.size()) };
+        assert!(
+            bytes.iter().all(|byte| *byte == index as u8),
+            "selftest: the allocation of {:?} at {:?} was overwritten",
+            layout,
+            ptr
+        );
+    }
+
+    // every other first to free between used nodes too
+    for (ptr, layout) in allocations
+        .iter()
+        .step_by(2)
+        .chain(allocations.iter().skip(1).step_by(2))
+    {
+        unsafe { dealloc(*ptr, *layout) };
+    }
+
+    assert_eq!(
+        used_heap_bytes(),
+        used,
+        "selftest: the heap leaked after freeing everything"
+    );
+}
+
+/// maps and unmaps pages through the vmm checking the frames are given back and the pages are
+/// translated to the frames they are backed by
+fn paging() {
+    // the page tables allocated on the first mapping are kept so the baseline is taken after
+    let addr = vmm::alloc_pages(PAGES).expect("selftest: failed to map pages");
+    vmm::free_pages(addr, PAGES);
+
+    let used_frames = kernel().frame_allocator().used_frames();
+    let addr = vmm::alloc_pages(PAGES).expect("selftest: failed to map pages");
+
+    let table = unsafe { current_root_table() };
+    for i in 0..PAGES {
+        let page_addr = addr + i * PAGE_SIZE;
+        let phys_addr = table
+            .translate_addr(page_addr)
+            .expect("selftest: a mapped page doesn't translate");
+
+        // the same frame through the physical memory window
+        unsafe { page_addr.as_mut_ptr::<usize>().write(i) };
+        assert_eq!(
+            unsafe { phys_to_virt(phys_addr).as_ptr::<usize>().read() },
+            i,
+            "selftest: {:?} doesn't translate to the frame it is mapped to",
+            page_addr
+        );
+        // and back through the physmap huge pages
+        assert_eq!(
+            table.translate_addr(phys_to_virt(phys_addr)),
+            Some(phys_addr)
+        );
+    }
+
+    vmm::free_pages(addr, PAGES);
+    assert_eq!(
+        kernel().frame_allocator().used_frames(),
+        used_frames,
+        "selftest: unmapping leaked frames"
+    );
+    assert!(table.translate_addr(addr).is_none());
+}
+
+pub fn selftest() {
+    allocator();
+    paging();
+    serial!("selftest: the allocator and paging are fine\n");
+}
@@ -0,0 +1,89 @@
+// copying from and to the pages of a process, the kernel shouldn't touch them any other way:
+// with SMAP (see `cpu::enable_features`) ring 0 faults on user pages unless rflags.AC is set so
+// the copies are bracketed with stac/clac, and a bad pointer from a process faults in the middle
+// of the copy which the page fault handler turns into an error instead of a panic
+// the copy is the `rep movsb` at `copy_user_movsb`, it is the only instruction that can fault on
+// the user pages and its entry in the exception table (see `arch::x86_64::extable`) resumes the
+// fault at `copy_user_fixup` which returns 1 instead of 0
+// only the range is validated (see `validate_user_range`), the pages themselves are checked by
+// the cpu while copying
+
+use core::arch::global_asm;
+
+use crate::arch::{
+    without_interrupts,
+    x86_64::cpu::{self, CpuFeatures},
+};
+
+use super::{VirtAddr, VirtRange};
+
+/// the end of the lower half, the processes own everything below it
+pub const USER_END: VirtAddr = VirtAddr::new(0x0000_8000_0000_0000);
+
+global_asm!(
+    "
+.global copy_user
+
+// rdi = dst, rsi = src, rdx = len
+copy_user:
+    mov rcx, rdx
+copy_user_movsb:
+    rep movsb
+    xor eax, eax
+    ret
+
+copy_user_fixup:
+    mov eax, 1
+    ret
+",
+    crate::extable_entry!("copy_user_movsb", "copy_user_fixup")
+);
+
+extern "C" {
+    fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> u64;
+}
+
+/// the `len` bytes at `addr` as a range, Err(()) if any of them isn't in the lower half
+pub fn validate_user_range(addr: VirtAddr, len: usize) -> Result<VirtRange, ()> {
+    let end = addr.checked_add(len).ok_or(())?;
+    if end > USER_END {
+        return Err(());
+    }
+    Ok(VirtRange::new(addr, end))
+}
+
+/// copies `len` bytes with user accesses allowed, Err(()) if the copy faulted
+fn copy(dst: *mut u8, src: *const u8, len: usize) -> Result<(), ()> {
+    let smap = cpu::enabled().contains(CpuFeatures::SMAP);
+
+    // interrupt handlers mustn't run with user accesses allowed
+    let faulted = without_interrupts(|| unsafe {
+        if smap {
+            core::arch::asm!("stac", options(nomem, nostack));
+        }
+        let faulted = copy_user(dst, src, len);
+        if smap {
+            core::arch::asm!("clac", options(nomem, nostack));
+        }
+        faulted
+    });
+
+    match faulted {
+        0 => Ok(()),
+        _ => Err(()),
+    }
+}
+
+/// fills `dst` with the bytes at `src` in the current address space, Err(()) if they aren't all
+/// in the lower half or aren't all mapped
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), ()> {
+    validate_user_range(src, dst.len())?;
+    copy(dst.as_mut_ptr(), src.as_ptr(), dst.len())
+}
+
+/// writes `src` to `dst` in the current address space, Err(()) if the bytes aren't all in the
+/// lower half or aren't all mapped writable, what was before the bad page is written anyway
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), ()> {
+    validate_user_range(dst, src.len())?;
+    copy(dst.as_mut_ptr(), src.as_ptr(), src.len())
+}
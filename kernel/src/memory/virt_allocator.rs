@@ -0,0 +1,108 @@
+// the virtual address space counterpart of the frame allocator, keeps track of which kernel
+// virtual ranges are free so that mmio mappings, stacks and `vmm::alloc_pages` never overlap
+// the free ranges are kept in a fixed size list so this works without the heap
+
+use heapless::Vec;
+
+use super::{align_up, paging::PAGE_SIZE, VirtAddr, VirtRange};
+
+/// the max number of free ranges, releasing a range that can't be merged with another range
+/// when the list is full leaks that range
+const MAX_FREE_RANGES: usize = 256;
+
+#[derive(Debug)]
+pub struct VirtRegionAllocator {
+    /// sorted by start address, no 2 ranges touch each other
+    free_ranges: Vec<VirtRange, MAX_FREE_RANGES>,
+}
+
+impl VirtRegionAllocator {
+    /// creates an allocator that has no free ranges, give it some using `Self::release`
+    pub const fn new() -> Self {
+        Self {
+            free_ranges: Vec::new(),
+        }
+    }
+
+    /// reserves `size` bytes (rounded up to pages) aligned to `align` (at least a page)
+    /// returns None if there is no free range big enough
+    pub fn reserve(&mut self, size: usize, align: usize) -> Option<VirtAddr> {
+        let size = align_up(size, PAGE_SIZE);
+        let align = align.max(PAGE_SIZE);
+
+        for index in 0..self.free_ranges.len() {
+            let range = self.free_ranges[index];
+
+            let start = range.start().align_up(align);
+            let Some(end) = start.checked_add(size) else {
+                continue;
+            };
+
+            if end > range.end() {
+                continue;
+            }
+
+            let before = VirtRange::new(range.start(), start);
+            let after = VirtRange::new(end, range.end());
+
+            match (!before.is_empty(), !after.is_empty()) {
+                (false, false) => {
+                    self.free_ranges.remove(index);
+                }
+                (true, false) => self.free_ranges[index] = before,
+                (false, true) => self.free_ranges[index] = after,
+                (true, true) => {
+                    // splitting needs one more slot
+                    self.free_ranges.insert(index + 1, after).ok()?;
+                    self.free_ranges[index] = before;
+                }
+            }
+
+            return Some(start);
+        }
+
+        None
+    }
+
+    /// gives back `size` bytes (rounded up to pages) starting from `addr`, merging it with the
+    /// free ranges around it, the range can't be free already
+    pub fn release(&mut self, addr: VirtAddr, size: usize) {
+        let mut range = VirtRange::from_len(addr, align_up(size, PAGE_SIZE));
+        if range.is_empty() {
+            return;
+        }
+
+        debug_assert!(
+            !self.free_ranges.iter().any(|free| free.overlaps(&range)),
+            "released {:?} which is already free",
+            range
+        );
+
+        let index = self
+            .free_ranges
+            .iter()
+            .position(|free| free.start() > addr)
+            .unwrap_or(self.free_ranges.len());
+
+        // merging with the next range
+        if index < self.free_ranges.len() && self.free_ranges[index].start() == range.end() {
+            let next = self.free_ranges.remove(index);
+            range = VirtRange::new(range.start(), next.end());
+        }
+
+        // merging with the previous range
+        if index > 0 && self.free_ranges[index - 1].end() == range.start() {
+            let previous = self.free_ranges[index - 1];
+            self.free_ranges[index - 1] = VirtRange::new(previous.start(), range.end());
+            return;
+        }
+
+        if self.free_ranges.insert(index, range).is_err() {
+            crate::serial!(
+                "virt allocator: no room for the free range 0x{:x}..0x{:x}, leaking it\n",
+                range.start(),
+                range.end()
+            );
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// kernel virtual memory manager, hands out whole mapped pages for subsystems that don't want to
+// go through the heap (stacks, page sized buffers, ...) and maps mmio
+// virtual ranges come from `kernel().virt_allocator()` which manages a window in the higher half
+// that nothing else maps into
+
+use crate::{
+    arch::{Arch, Current},
+    kernel,
+};
+
+use super::{
+    allocator::ReservedFrames,
+    frame_allocator::Frame,
+    p4_index,
+    paging::{current_root_table, EntryFlags, Page, PageTable, TlbBatch, PAGE_SIZE},
+    phys_to_virt, PhysAddr, VirtAddr,
+};
+
+/// the start of the window `kernel().virt_allocator()` allocates from
+pub const VMM_START: VirtAddr = VirtAddr::new(0xFFFF_C000_0000_0000);
+/// one pml4 entry worth of address space (512GiB)
+pub const VMM_SIZE: usize = 512 * 1024 * 1024 * 1024;
+
+/// allocates the level 3 table of the window up-front so every pml4 that copies the higher
+/// half (see `allocate_pml4`) shares the pages we map later, then gives the window to the virt
+/// allocator
+pub fn init() {
+    let root_table = unsafe { current_root_table() };
+    let entry = &mut root_table[p4_index(VMM_START)];
+
+    if !entry.is_mapped() {
+        let frame = kernel()
+            .frame_allocator()
+            .allocate_frame()
+            .expect("failed to allocate the vmm level 3 table");
+
+        let table = phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>();
+        unsafe { (*table).zeroize() };
+
+        entry.set(
+            EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            frame.start_address(),
+        );
+    }
+
+    kernel().virt_allocator().release(VMM_START, VMM_SIZE);
+}
+
+/// allocates `count` frames and maps them to `count` contiguous kernel pages (present and
+/// writable) returning the address of the first page
+/// returns None if `count` is 0, there is not enough frames or virtual space
+pub fn alloc_pages(count: usize) -> Option<VirtAddr> {
+    if count == 0 {
+        return None;
+    }
+
+    let start = kernel()
+        .virt_allocator()
+        .reserve(count.checked_mul(PAGE_SIZE)?, PAGE_SIZE)?;
+
+    let mut transaction = unsafe { current_root_table() }.begin_mapping();
+    let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+    for i in 0..count {
+        let page = Page::containing_address(start) + i;
+
+        if transaction.map_new(page, flags).is_err() {
+            transaction.rollback();
+            kernel().virt_allocator().release(start, count * PAGE_SIZE);
+            return None;
+        }
+    }
+
+    transaction.commit();
+    Some(start)
+}
+
+/// unmaps `count` pages starting from `addr` and deallocates the frames they were mapped to
+/// without releasing the virtual range
+fn free_mapped(addr: VirtAddr, count: usize) {
+    let mut frames = ReservedFrames::new();
+    let mut batch = TlbBatch::new();
+    for i in 0..count {
+        let page = Page::containing_address(addr) + i;
+
+        if let Some(frame) = unsafe { current_root_table() }.unmap_batched(page, &mut batch) {
+            frames.push(frame);
+        }
+    }
+
+    // the frames can't be handed out while their old mappings can still be used
+    batch.flush();
+    frames.free();
+}
+
+/// unmaps `count` pages starting from `addr`, deallocates the frames they were mapped to and
+/// releases the virtual range
+/// `addr` must be returned by `alloc_pages`
+pub fn free_pages(addr: VirtAddr, count: usize) {
+    free_mapped(addr, count);
+    kernel().virt_allocator().release(addr, count * PAGE_SIZE);
+}
+
+/// maps `size` bytes of mmio starting from `phys_addr` as uncached kernel pages returning the
+/// virtual address `phys_addr` is mapped to
+pub fn map_mmio(phys_addr: PhysAddr, size: usize) -> Option<VirtAddr> {
+    map_device(phys_addr, size, EntryFlags::NO_CACHE)
+}
+
+/// maps `size` bytes of framebuffer starting from `phys_addr` as write combining kernel pages
+/// (see `Arch::write_combining`) returning the virtual address `phys_addr` is mapped to, unmap it with
+/// `unmap_mmio`
+/// the physmap maps the framebuffer too, it is remapped as write combining first since mapping
+/// the same memory with 2 memory types is undefined, returns None if it couldn't be
+pub fn map_framebuffer(phys_addr: PhysAddr, size: usize) -> Option<VirtAddr> {
+    let cache_flags = Current::write_combining();
+    set_physmap_cache(phys_addr, size, cache_flags)?;
+    map_device(phys_addr, size, cache_flags)
+}
+
+/// remaps the physmap pages of `size` bytes from `phys_addr` with the cache flags `cache_flags`
+/// splitting the huge pages they are in, None if they aren't all mapped or a split failed, the
+/// pages already remapped stay remapped
+fn set_physmap_cache(phys_addr: PhysAddr, size: usize, cache_flags: EntryFlags) -> Option<()> {
+    let phys_start = phys_addr.align_down(PAGE_SIZE);
+    let size = (phys_addr + size).align_up(PAGE_SIZE) - phys_start;
+    let table = unsafe { current_root_table() };
+
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let page = Page::containing_address(phys_to_virt(phys_start + offset));
+        table.split_huge_page(page).ok()?;
+
+        // the pat bit of a level 1 entry is where huge pages have HUGE_PAGE
+        let flags = table.get_entry(page)?.flags()
+            - (EntryFlags::WRITE_THROUGH | EntryFlags::NO_CACHE | EntryFlags::HUGE_PAGE);
+        table.update_flags(page, flags | cache_flags)?;
+    }
+    Some(())
+}
+
+/// maps device memory as present and writable kernel pages with the cache flags `cache_flags`
+fn map_device(phys_addr: PhysAddr, size: usize, cache_flags: EntryFlags) -> Option<VirtAddr> {
+    let kind = kernel().frame_allocator().region_kind(phys_addr);
+    assert!(
+        kind.is_device(),
+        "mapping {:#x} as mmio but it is {:?} memory",
+        phys_addr,
+        kind
+    );
+
+    let phys_start = phys_addr.align_down(PAGE_SIZE);
+    let size = (phys_addr + size).align_up(PAGE_SIZE) - phys_start;
+
+    let start = kernel().virt_allocator().reserve(size, PAGE_SIZE)?;
+    let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | cache_flags;
+
+    let mut batch = TlbBatch::new();
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let page = Page::containing_address(start + offset);
+        let frame = Frame::containing_address(phys_start + offset);
+
+        if unsafe { current_root_table() }
+            .map_to_batched(page, frame, flags, &mut batch)
+            .is_err()
+        {
+            unmap_mmio(start, size);
+            return None;
+        }
+    }
+
+    Some(start + (phys_addr - phys_start))
+}
+
+/// unmaps `size` bytes of mmio mapped by `map_mmio` or `map_framebuffer` at `addr`
+pub fn unmap_mmio(addr: VirtAddr, size: usize) {
+    let start = addr.align_down(PAGE_SIZE);
+    let size = (addr + size).align_up(PAGE_SIZE) - start;
+
+    let mut batch = TlbBatch::new();
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        unsafe { current_root_table() }
+            .unmap_batched(Page::containing_address(start + offset), &mut batch);
+    }
+    batch.flush();
+
+    kernel().virt_allocator().release(start, size);
+}
@@ -0,0 +1,96 @@
+// the w^x audit, no page should be both writable and executable or a write to it (a heap
+// overflow, a bad segment of an elf) is code that runs
+// a page is writable only if every entry on the way to it is writable and executable unless one
+// of them is NO_EXECUTE so the flags are combined down the tables and checked at the leaves (level
+// 1 entries and the huge pages)
+// without EFER.NXE the no execute bit is ignored and every writable page is executable
+
+use alloc::vec::Vec;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::msr;
+use crate::log;
+
+use super::{
+    paging::{EntryFlags, PageTable, PAGE_SIZE},
+    VirtAddr, VirtRange,
+};
+
+#[cfg(target_arch = "x86_64")]
+const EFER_NXE: u64 = 1 << 11;
+
+/// every address
+pub const ADDRESS_SPACE: VirtRange = VirtRange::new(VirtAddr::new(0), VirtAddr::new(usize::MAX));
+/// the addresses the processes own, the higher half is the kernel's
+pub const LOWER_HALF: VirtRange =
+    VirtRange::new(VirtAddr::new(0), VirtAddr::new(0x0000_8000_0000_0000));
+
+/// wether or not the cpu honors the no execute bit
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub fn nx_enabled() -> bool {
+    msr::read(msr::IA32_EFER) & EFER_NXE != 0
+}
+
+/// it can't be turned off anywhere else
+#[cfg(not(target_arch = "x86_64"))]
+#[inline]
+pub fn nx_enabled() -> bool {
+    true
+}
+
+/// the writable and executable ranges `table` (a pml4) maps, adjacent pages are merged
+pub fn writable_executable(table: &PageTable) -> Vec<VirtRange> {
+    let nx = nx_enabled();
+    // what the entries above the one visited allow, indexed by level (5 for above the pml4)
+    let mut writable = [true; 6];
+    let mut executable = [true; 6];
+    let mut ranges: Vec<VirtRange> = Vec::new();
+
+    table.for_each_mapping(|addr, entry, level| {
+        let level = level as usize;
+        let flags = entry.flags();
+        writable[level] = writable[level + 1] && flags.contains(EntryFlags::WRITABLE);
+        executable[level] =
+            executable[level + 1] && !(nx && flags.contains(EntryFlags::NO_EXECUTE));
+
+        let leaf = level == 1 || (level < 4 && flags.contains(EntryFlags::HUGE_PAGE));
+        if !leaf || !writable[level] || !executable[level] {
+            return;
+        }
+
+        let range = VirtRange::from_len(addr, PAGE_SIZE << (9 * (level - 1)));
+        match ranges.last_mut() {
+            Some(last) if last.end() == addr => *last = VirtRange::new(last.start(), range.end()),
+            _ => ranges.push(range),
+        }
+    });
+
+    ranges
+}
+
+/// logs the writable and executable ranges of `table` in `within` and returns how many there
+/// are, panics if there are any and `strict`
+pub fn audit(table: &PageTable, within: VirtRange, strict: bool) -> usize {
+    if !nx_enabled() {
+        log!("wxaudit: the no execute bit is disabled, every writable page is executable\n");
+    }
+
+    let ranges: Vec<VirtRange> = writable_executable(table)
+        .iter()
+        .filter_map(|range| range.intersect(&within))
+        .collect();
+
+    for range in &ranges {
+        log!(
+            "wxaudit: {:#x}..{:#x} is writable and executable\n",
+            range.start(),
+            range.end()
+        );
+    }
+
+    if strict && !ranges.is_empty() {
+        panic!("wxaudit: {} writable and executable ranges", ranges.len());
+    }
+    ranges.len()
+}
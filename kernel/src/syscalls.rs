@@ -0,0 +1,105 @@
+// syscalls are called using `int 0x80` with the syscall number in rax and the arguments in
+// rdi, rsi and rdx, the result is returned in rax
+// returning to the caller is done by iretq-ing the captured context so every register the
+// syscall doesn't return in is preserved
+// the pointers the processes pass are only read through `memory::user`
+
+use crate::{
+    arch::threading::CPUStatus,
+    drivers::{chardev::CharDevice, serial::SerialConsole},
+    memory::user,
+    scheduler, serial, threading, VirtAddr,
+};
+
+/// returned in rax if the syscall failed
+pub const SYSCALL_FAILED: u64 = u64::MAX;
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_FORK: u64 = 1;
+pub const SYS_WRITE: u64 = 2;
+pub const SYS_YIELD: u64 = 3;
+
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// how much `sys_write` copies at once
+const WRITE_CHUNK_SIZE: usize = 256;
+
+/// handles the syscall `context` was captured in, writing the result to `context.rax`
+pub fn handle(context: &mut CPUStatus) {
+    context.rax = match context.rax {
+        SYS_EXIT => sys_exit(context.rdi),
+        SYS_FORK => sys_fork(context),
+        SYS_WRITE => sys_write(context.rdi, context.rsi, context.rdx),
+        SYS_YIELD => sys_yield(),
+        number => {
+            serial!("unknown syscall {}\n", number);
+            SYSCALL_FAILED
+        }
+    };
+}
+
+/// exits the current process with the code `code`, doesn't return to the caller since the
+/// syscall switches away from it
+fn sys_exit(code: u64) -> u64 {
+    let pid = unsafe { (*scheduler().current_thread).pid };
+
+    match scheduler().exit(pid, code as usize) {
+        Ok(()) => {
+            threading::set_need_resched();
+            0
+        }
+        Err(()) => SYSCALL_FAILED,
+    }
+}
+
+/// duplicates the current process returning the child pid to the parent and 0 to the child
+fn sys_fork(context: &CPUStatus) -> u64 {
+    match scheduler().fork(context) {
+        Ok(pid) => pid,
+        Err(err) => {
+            serial!("fork failed: {:?}\n", err);
+            SYSCALL_FAILED
+        }
+    }
+}
+
+/// writes the `len` bytes at `buf` to `fd` which is `STDOUT` or `STDERR`, both go to the serial
+/// console, returns how many bytes were written which is less than `len` if the buffer stops
+/// being mapped half way
+fn sys_write(fd: u64, buf: u64, len: u64) -> u64 {
+    if fd != STDOUT && fd != STDERR {
+        return SYSCALL_FAILED;
+    }
+    let buf = VirtAddr::new(buf as usize);
+    let len = len as usize;
+    if user::validate_user_range(buf, len).is_err() {
+        return SYSCALL_FAILED;
+    }
+
+    let mut chunk = [0; WRITE_CHUNK_SIZE];
+    let mut written = 0;
+    while written < len {
+        let size = (len - written).min(WRITE_CHUNK_SIZE);
+        if user::copy_from_user(&mut chunk[..size], buf + written).is_err() {
+            break;
+        }
+
+        for &byte in &chunk[..size] {
+            SerialConsole.write_byte(byte);
+        }
+        written += size;
+    }
+
+    match written {
+        0 if len != 0 => SYSCALL_FAILED,
+        written => written as u64,
+    }
+}
+
+/// gives up the rest of the time slice, the syscall switches to the next thread on the way out
+/// even if it is the one that yielded
+fn sys_yield() -> u64 {
+    threading::set_need_resched();
+    0
+}
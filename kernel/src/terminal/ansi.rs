@@ -0,0 +1,205 @@
+// a subset of the ansi escape sequences (the `ESC [` ones) so programs written for a normal
+// terminal render right, there is no vga text console to drive (limine gives us a framebuffer
+// even when booting through the bios) so these drive `Terminal` which draws its own text
+// - SGR `ESC[...m`: 0 resets, 30-37 and 90-97 are the 16 foreground colors and 40-47 and
+//   100-107 the background ones, 39 and 49 go back to the default colors, 38;2;r;g;b and
+//   48;2;r;g;b are any color, 38;5;n and 48;5;n one of the 16 colors, the rest is ignored
+// - cursor moves: `ESC[nA` up, `ESC[nB` down, `ESC[nC` forward, `ESC[nD` back and
+//   `ESC[row;colH` (or `f`) which is 1 based like everywhere else
+// - `ESC[2J` clears the screen
+// a sequence that isn't supported or isn't complete by the end of a write is dropped, the
+// terminal parses one write at a time
+
+use heapless::Vec;
+
+pub type Color = (u8, u8, u8);
+
+const ESC: char = '\x1b';
+const MAX_PARAMS: usize = 16;
+
+/// the vga palette, the first 8 are the normal colors and the last 8 the bright ones
+pub const COLORS: [Color; 16] = [
+    (0, 0, 0),
+    (170, 0, 0),
+    (0, 170, 0),
+    (170, 85, 0),
+    (0, 0, 170),
+    (170, 0, 170),
+    (0, 170, 170),
+    (170, 170, 170),
+    (85, 85, 85),
+    (255, 85, 85),
+    (85, 255, 85),
+    (255, 255, 85),
+    (85, 85, 255),
+    (255, 85, 255),
+    (85, 255, 255),
+    (255, 255, 255),
+];
+
+pub type Params = Vec<u16, MAX_PARAMS>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ansi<'a> {
+    Text(&'a str),
+    /// select graphic rendition, see `Rendition::apply`
+    Sgr(Params),
+    CursorUp(usize),
+    CursorDown(usize),
+    CursorForward(usize),
+    CursorBack(usize),
+    /// 0 based unlike the escape sequence
+    CursorTo {
+        row: usize,
+        col: usize,
+    },
+    ClearScreen,
+}
+
+/// splits a str into text and the escape sequences in it
+pub struct AnsiIter<'a> {
+    rest: &'a str,
+}
+
+impl<'a> AnsiIter<'a> {
+    pub const fn new(str: &'a str) -> Self {
+        Self { rest: str }
+    }
+
+    /// parses the `[params final` after an ESC, returns the sequence (if it is supported) and
+    /// how many bytes it took, None if it isn't complete
+    fn parse_sequence(seq: &str) -> Option<(Option<Ansi<'a>>, usize)> {
+        let bytes = seq.as_bytes();
+        if bytes.first() != Some(&b'[') {
+            // not a sequence we know, only the ESC is dropped
+            return Some((None, 0));
+        }
+
+        let mut params = Params::new();
+        let mut current: Option<u16> = None;
+
+        for (index, &byte) in bytes.iter().enumerate().skip(1) {
+            match byte {
+                b'0'..=b'9' => {
+                    let digit = (byte - b'0') as u16;
+                    current = Some(
+                        current
+                            .unwrap_or(0)
+                            .saturating_mul(10)
+                            .saturating_add(digit),
+                    );
+                }
+                b';' => {
+                    _ = params.push(current.take().unwrap_or(0));
+                }
+                0x40..=0x7E => {
+                    if let Some(param) = current {
+                        _ = params.push(param);
+                    }
+
+                    return Some((Self::sequence(byte, params), index + 1));
+                }
+                // intermediate bytes and private markers, the sequence is skipped at its final
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn sequence(final_byte: u8, params: Params) -> Option<Ansi<'a>> {
+        // a missing or 0 count means 1
+        let count = params.first().map_or(1, |&count| count.max(1) as usize);
+        let param = |index: usize| params.get(index).map_or(1, |&p| p.max(1) as usize);
+
+        Some(match final_byte {
+            b'm' => Ansi::Sgr(params),
+            b'A' => Ansi::CursorUp(count),
+            b'B' => Ansi::CursorDown(count),
+            b'C' => Ansi::CursorForward(count),
+            b'D' => Ansi::CursorBack(count),
+            b'H' | b'f' => Ansi::CursorTo {
+                row: param(0) - 1,
+                col: param(1) - 1,
+            },
+            b'J' if params.first() == Some(&2) => Ansi::ClearScreen,
+            _ => return None,
+        })
+    }
+}
+
+impl<'a> Iterator for AnsiIter<'a> {
+    type Item = Ansi<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            let Some(rest) = self.rest.strip_prefix(ESC) else {
+                let end = self.rest.find(ESC).unwrap_or(self.rest.len());
+                let (text, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                return Some(Ansi::Text(text));
+            };
+
+            let Some((sequence, len)) = Self::parse_sequence(rest) else {
+                self.rest = "";
+                return None;
+            };
+
+            self.rest = &rest[len..];
+            if sequence.is_some() {
+                return sequence;
+            }
+        }
+
+        None
+    }
+}
+
+/// the colors text is drawn with, None is the default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rendition {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+impl Rendition {
+    /// applies the parameters of an SGR sequence, no parameters is the same as 0
+    pub fn apply(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Self::default();
+            return;
+        }
+
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::default(),
+                30..=37 => self.fg = Some(COLORS[(param - 30) as usize]),
+                90..=97 => self.fg = Some(COLORS[(param - 90 + 8) as usize]),
+                40..=47 => self.bg = Some(COLORS[(param - 40) as usize]),
+                100..=107 => self.bg = Some(COLORS[(param - 100 + 8) as usize]),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => Some((r as u8, g as u8, b as u8)),
+                            _ => None,
+                        },
+                        Some(5) => params
+                            .next()
+                            .and_then(|index| COLORS.get(index as usize).copied()),
+                        _ => None,
+                    };
+
+                    if param == 38 {
+                        self.fg = color.or(self.fg);
+                    } else {
+                        self.bg = color.or(self.bg);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -2,15 +2,18 @@ use alloc::{string::String, vec::Vec};
 
 use core::{fmt, ptr};
 
-use noto_sans_mono_bitmap::{FontWeight, RasterHeight, RasterizedChar};
+use noto_sans_mono_bitmap::{get_raster_width, FontWeight, RasterHeight, RasterizedChar};
 
 use crate::{
-    drivers::keyboard::{Key, KeyCode, KeyFlags},
+    drivers::keyboard::{self, Key, KeyCode, KeyFlags},
     memory::align_down,
-    println,
+    println, serial,
 };
 
-use super::navitts::{Attributes, NaviTTES};
+use super::{
+    ansi::{Ansi, AnsiIter, Color, Rendition},
+    navitts::{Attributes, NaviTTES},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TerminalMode {
@@ -33,6 +36,8 @@ pub struct FrameBufferInfo {
 }
 
 const RASTER_HEIGHT: RasterHeight = RasterHeight::Size20;
+/// the font is monospace
+const CHAR_WIDTH: usize = get_raster_width(FontWeight::Bold, RASTER_HEIGHT);
 const WRITE_COLOR: (u8, u8, u8) = (255, 255, 255);
 #[derive(Debug)]
 pub struct Terminal<'a> {
@@ -54,6 +59,11 @@ pub struct Terminal<'a> {
     pub y_pos: usize,
     /// wether or not the current panic happend in another panic because of the terminal
     pub panicked: bool,
+    /// wether or not the output is also written to the serial port, set by the serial shell
+    pub mirror_to_serial: bool,
+    /// the colors output is drawn with, overrides the navitts colors, set by ansi SGR sequences
+    /// (see `ansi`)
+    pub rendition: Rendition,
 }
 
 impl<'a> Terminal<'a> {
@@ -73,12 +83,14 @@ impl<'a> Terminal<'a> {
             mode: TerminalMode::Init,
             stdin_buffer: String::new(),
             stdout_buffer: String::new(),
-            current_dir: String::from("ram:/"),
+            current_dir: String::from("/"),
 
             info,
             x_pos: 0,
             y_pos: 0,
             panicked: false,
+            mirror_to_serial: false,
+            rendition: Rendition::default(),
         }
     }
 
@@ -94,12 +106,13 @@ impl<'a> Terminal<'a> {
             _ => (),
         }
 
+        // echoed by whoever reads it, see `super::TerminalConsole`
         match self.mode {
             TerminalMode::Stdin => {
                 let mapped = key.map_key();
 
                 if mapped != '\0' {
-                    self.stdin_putc(mapped)
+                    keyboard::push_char(mapped)
                 }
             }
 
@@ -120,6 +133,19 @@ impl<'a> Terminal<'a> {
 
     pub fn clear(&mut self) {
         println!("clearing");
+        self.stdin_buffer = String::new();
+        self.stdout_buffer = String::new();
+
+        if self.mode == TerminalMode::Init {
+            self.mode = TerminalMode::Stdin;
+        }
+
+        self.clear_screen();
+    }
+
+    /// clears the screen and the scrollback and moves the cursor to the top left, unlike
+    /// `Self::clear` the buffers and the mode are left alone
+    pub fn clear_screen(&mut self) {
         self.viewport_start = 0;
         self.viewport.truncate(self.buffer.len());
         self.viewport.fill(0);
@@ -127,14 +153,40 @@ impl<'a> Terminal<'a> {
         self.x_pos = 0;
         self.y_pos = 0;
 
-        self.stdin_buffer = String::new();
-        self.stdout_buffer = String::new();
+        self.draw_viewport();
+    }
 
-        if self.mode == TerminalMode::Init {
-            self.mode = TerminalMode::Stdin;
-        }
+    /// the number of (rows, columns) of text that fit on the screen
+    pub fn size(&self) -> (usize, usize) {
+        let line = self.info.stride * self.info.bytes_per_pixel * RASTER_HEIGHT.val();
+        (self.buffer.len() / line, self.info.stride / CHAR_WIDTH)
+    }
 
-        self.draw_viewport();
+    /// the y position of the top of the screen in the viewport
+    #[inline]
+    fn top_y(&self) -> usize {
+        self.viewport_start / (self.info.stride * self.info.bytes_per_pixel)
+    }
+
+    /// the (row, column) of the cursor relative to the top left of the screen
+    pub fn cursor(&self) -> (usize, usize) {
+        (
+            self.y_pos.saturating_sub(self.top_y()) / RASTER_HEIGHT.val(),
+            self.x_pos / CHAR_WIDTH,
+        )
+    }
+
+    /// moves the cursor to `row` and `col` relative to the top left of the screen, they are
+    /// clamped to the screen
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        let (rows, cols) = self.size();
+        let (row, col) = (
+            row.min(rows.saturating_sub(1)),
+            col.min(cols.saturating_sub(1)),
+        );
+
+        self.y_pos = self.top_y() + row * RASTER_HEIGHT.val();
+        self.x_pos = col * CHAR_WIDTH;
     }
 
     fn get_byte_offset(&self, x: usize, y: usize) -> usize {
@@ -262,14 +314,20 @@ impl<'a> Terminal<'a> {
         }
     }
 
-    fn draw_char(&mut self, glyph: RasterizedChar, color: (u8, u8, u8)) {
+    fn draw_char(&mut self, glyph: RasterizedChar, color: (u8, u8, u8), bg: Option<Color>) {
         if (self.x_pos + glyph.width()) > self.info.stride {
             self.newline();
         }
 
         for (row, rows) in glyph.raster().iter().enumerate() {
             for (col, byte) in rows.iter().enumerate() {
-                self.set_pixel(self.x_pos + col, self.y_pos + row, *byte as u32, color);
+                let (x, y) = (self.x_pos + col, self.y_pos + row);
+
+                match bg {
+                    // 256 keeps the blended color as is
+                    Some(bg) => self.set_pixel(x, y, 256, blend(color, bg, *byte)),
+                    None => self.set_pixel(x, y, *byte as u32, color),
+                }
             }
         }
 
@@ -285,6 +343,11 @@ impl<'a> Terminal<'a> {
     }
 
     pub fn putc(&mut self, c: char, color: (u8, u8, u8)) {
+        self.putc_on(c, color, None)
+    }
+
+    /// like `Self::putc` but with the background `bg`, None leaves the background as is
+    pub fn putc_on(&mut self, c: char, color: (u8, u8, u8), bg: Option<Color>) {
         match c {
             '\n' => {
                 self.newline();
@@ -292,7 +355,7 @@ impl<'a> Terminal<'a> {
             '\x08' => self.backspace(),
 
             _ => {
-                self.draw_char(Self::raster(c), color);
+                self.draw_char(Self::raster(c), color, bg);
             }
         }
     }
@@ -320,9 +383,38 @@ impl<'a> Terminal<'a> {
         let old_mode = self.mode;
         self.mode = TerminalMode::Stdout;
         self.stdout_buffer.push_str(str);
+        if self.mirror_to_serial {
+            serial!("{}", str);
+        }
 
-        for c in str.chars() {
-            self.putc(c, attributes.fg);
+        for seq in AnsiIter::new(str) {
+            match seq {
+                Ansi::Text(text) => {
+                    let fg = self.rendition.fg.unwrap_or(attributes.fg);
+                    for c in text.chars() {
+                        self.putc_on(c, fg, self.rendition.bg);
+                    }
+                }
+                Ansi::Sgr(params) => self.rendition.apply(&params),
+                Ansi::CursorUp(count) => {
+                    let (row, col) = self.cursor();
+                    self.set_cursor(row.saturating_sub(count), col)
+                }
+                Ansi::CursorDown(count) => {
+                    let (row, col) = self.cursor();
+                    self.set_cursor(row.saturating_add(count), col)
+                }
+                Ansi::CursorForward(count) => {
+                    let (row, col) = self.cursor();
+                    self.set_cursor(row, col.saturating_add(count))
+                }
+                Ansi::CursorBack(count) => {
+                    let (row, col) = self.cursor();
+                    self.set_cursor(row, col.saturating_sub(count))
+                }
+                Ansi::CursorTo { row, col } => self.set_cursor(row, col),
+                Ansi::ClearScreen => self.clear_screen(),
+            }
         }
 
         self.draw_viewport();
@@ -354,6 +446,14 @@ impl<'a> Terminal<'a> {
     }
 }
 
+/// mixes `fg` over `bg`, `intensity` is how much of the pixel the glyph covers
+fn blend(fg: Color, bg: Color, intensity: u8) -> Color {
+    let mix = |fg: u8, bg: u8| {
+        ((fg as u32 * intensity as u32 + bg as u32 * (255 - intensity as u32)) / 255) as u8
+    };
+    (mix(fg.0, bg.0), mix(fg.1, bg.1), mix(fg.2, bg.2))
+}
+
 impl fmt::Write for Terminal<'static> {
     // i can add color escapes later on like parsing \(u8, u8, u8)str$ as coloring str into (u8, u8, u8)
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -1,3 +1,4 @@
+pub mod ansi;
 pub mod framebuffer;
 pub mod navitts;
 
@@ -11,10 +12,25 @@ use alloc::{
 use framebuffer::TerminalMode;
 
 use crate::{
-    arch,
-    drivers::vfs::{vfs, FS},
-    globals::terminal,
+    arch::{self, interrupt_stats},
+    drivers::{
+        chardev::{self, CharDevice},
+        keyboard::KeyboardInput,
+        keymapper,
+        serial::SerialConsole,
+        vfs::{self, vfs, FS},
+    },
+    global_allocator,
+    globals::{terminal, terminal_inited},
+    kernel,
+    memory::{
+        self,
+        paging::{current_root_table, EntryFlags, Page, PAGE_SIZE},
+        VirtAddr,
+    },
     print, println, scheduler, serial,
+    threading::timer::{self, TimerMode},
+    utils::Locked,
 };
 
 #[doc(hidden)]
@@ -24,21 +40,61 @@ pub fn _print(args: fmt::Arguments) {
     terminal().write_fmt(args).unwrap();
 }
 
-pub fn readln() -> String {
-    let old_mode = terminal().mode;
-    terminal().mode = TerminalMode::Stdin;
+/// the keyboard and the framebuffer terminal as a `CharDevice`, what is written is echoed as
+/// typed input
+pub struct TerminalConsole;
 
-    loop {
-        if terminal().stdin_buffer.ends_with('\n') {
-            terminal().stdin_buffer.pop();
+/// the bytes of a char that was only partly written to `TerminalConsole`
+static PENDING_UTF8: Locked<heapless::Vec<u8, 4>> = Locked::new(heapless::Vec::new());
+
+impl CharDevice for TerminalConsole {
+    fn read_byte(&self) -> Option<u8> {
+        KeyboardInput.read_byte()
+    }
 
-            let buffer = terminal().stdin_buffer.clone();
-            terminal().stdin_buffer.clear();
+    fn write_byte(&self, byte: u8) {
+        if !self.writable() {
+            return;
+        }
+
+        let mut pending = PENDING_UTF8.lock();
+        if pending.push(byte).is_err() {
+            pending.clear();
+            return;
+        }
 
-            terminal().mode = old_mode;
-            return buffer;
+        match str::from_utf8(&pending) {
+            Ok(str) => {
+                let c = str.chars().next().unwrap();
+                pending.clear();
+                drop(pending);
+                terminal().stdin_putc(c);
+            }
+            // invaild
+            Err(err) if err.error_len().is_some() => pending.clear(),
+            // not complete yet
+            Err(_) => (),
         }
     }
+
+    fn readable(&self) -> bool {
+        KeyboardInput.readable()
+    }
+
+    fn writable(&self) -> bool {
+        terminal_inited()
+    }
+}
+
+pub fn readln() -> String {
+    let old_mode = terminal().mode;
+    terminal().mode = TerminalMode::Stdin;
+
+    let line = chardev::readline(&TerminalConsole);
+    terminal().stdin_buffer.clear();
+
+    terminal().mode = old_mode;
+    line
 }
 
 pub fn echo(args: Vec<&str>) {
@@ -60,26 +116,12 @@ fn help(args: Vec<&str>) {
         "info:
     scroll up using `page up` and scroll down using `page down`,
     this shell supports string slices starting with '\"'
-commands:
-    help, ?: displays this
-    echo `text`: echoes back text
-    clear: clears the screen
-
-    shutdown: shutdowns qemu and bochs only for now
-    reboot: force-reboots the PC for now
-
-    plist: list the avalible process' pids and names
-    pkill `pid`: kills a process with pid `pid`
-    pkillall `name`: kills all processs with name `name`
-
-    touch `new_file_path`: creates a new empty file, the path of the new file would be equal to `new_file_path`
-    mkdir `new_dir_path`: creates a new empty directory, the path of the new directory would be equal to `new_dir_path` 
-    ls: lists all files and directories in the current dir
-    cd `target_dir`: changes the current dir to `target_dir`
-
-    cat `src_files`: echoes the contents of a file
-    write `target_file` `src_text`: writes `src_text` to `target_file`"
+commands:"
     );
+
+    for command in COMMANDS {
+        println!("    {}", command.help);
+    }
 }
 
 fn clear(args: Vec<&str>) {
@@ -120,15 +162,11 @@ fn plist(args: Vec<&str>) {
         return;
     }
 
-    let mut process_list: Vec<(u64, [u8; 64])> = Vec::new();
-
-    let mut current = &scheduler().head;
-    process_list.push((current.pid, current.name));
-
-    while let Some(ref process) = current.next {
-        process_list.push((process.pid, process.name));
-        current = process;
-    }
+    let process_list: Vec<(u64, [u8; 64])> = scheduler()
+        .processes
+        .values()
+        .map(|process| (process.pid, process.name))
+        .collect();
 
     println!("{} process(s) is currently running:", process_list.len());
     println!("name:  pid");
@@ -181,14 +219,8 @@ fn pkillall(args: Vec<&str>) {
 /// returns the absloutel path of a given path respecting `Ternminal.current_dir`
 /// returned path won't end with / if it is a directory
 fn get_path(path: &str) -> String {
-    for c in path.chars() {
-        if c == '/' || c == '\\' {
-            break;
-        }
-
-        if c == ':' {
-            return path.to_string();
-        }
+    if path.starts_with(['/', '\\']) {
+        return path.to_string();
     }
 
     return terminal().current_dir.clone() + path;
@@ -205,7 +237,7 @@ fn mkdir(args: Vec<&str>) {
     let mut spilt: Vec<&str> = path.split(['/', '\\']).collect();
 
     let dir_name = spilt.pop().unwrap();
-    let path = spilt.join("/");
+    let path = spilt.join("/") + "/";
 
     let result = vfs().createdir(&path, dir_name.to_string());
     if result.is_err() {
@@ -227,7 +259,7 @@ fn touch(args: Vec<&str>) {
     let mut spilt: Vec<&str> = path.split(['/', '\\']).collect();
 
     let file_name = spilt.pop().unwrap();
-    let path = spilt.join("/");
+    let path = spilt.join("/") + "/";
 
     let result = vfs().create(&path, file_name.to_string());
     if result.is_err() {
@@ -257,16 +289,17 @@ fn cd(args: Vec<&str>) {
         return;
     }
 
-    let mut path = get_path(args[1]);
+    let path = get_path(args[1]);
     let verify = vfs().verify_path_dir(&path);
 
     if verify.is_err() {
         println!("{}: path error: {:?}", args[0], verify.unwrap_err())
     } else {
-        // must add / because it is stupid, if for example we set the current_dir to ram:/test
-        // using `touch` will create an empty file with path ram:/test`file_name`
+        // must add / because it is stupid, if for example we set the current_dir to /ram/test
+        // using `touch` will create an empty file with path /ram/test`file_name`
         // FIXME: consider fixing this next, the code is already spaghetti, the next update should
         // fix all of this
+        let mut path = vfs::normalize(&path).unwrap();
         if !path.ends_with('/') {
             path.push('/');
         }
@@ -341,6 +374,381 @@ fn write(args: Vec<&str>) {
 }
 
 // bad shell
+fn mem(args: Vec<&str>) {
+    if args.len() != 1 {
+        println!("{}: expected 0 args", args[0]);
+        return;
+    }
+
+    let (heap_start, heap_size, heap_limit, heap_free) = {
+        let allocator = global_allocator().lock();
+        (
+            allocator.heap_start,
+            allocator.heap_end - allocator.heap_start,
+            allocator.heap_limit(),
+            allocator.free_bytes(),
+        )
+    };
+    let used_frames = kernel().frame_allocator().used_frames();
+
+    println!(
+        "heap: {} bytes used, {} bytes free, {} bytes total",
+        heap_size - heap_free,
+        heap_free,
+        heap_size
+    );
+    println!(
+        "heap at 0x{:x}, extends up to 0x{:x}",
+        heap_start, heap_limit
+    );
+    println!(
+        "frames: {} used ({} bytes)",
+        used_frames,
+        used_frames * PAGE_SIZE
+    );
+}
+
+fn ps(args: Vec<&str>) {
+    if args.len() != 1 {
+        println!("{}: expected 0 args", args[0]);
+        return;
+    }
+
+    println!("tid:  pid:  status");
+    let mut current = Some(&*scheduler().head);
+    while let Some(thread) = current {
+        println!("{}:  {}:  {:?}", thread.tid, thread.pid, thread.status);
+        current = thread.next.as_deref();
+    }
+}
+
+fn pt(args: Vec<&str>) {
+    if args.len() > 2 {
+        println!("{}: expected 0 or 1 args", args[0]);
+        return;
+    }
+
+    let table = unsafe { current_root_table() };
+
+    if args.len() == 1 {
+        println!("present level 4 entries:");
+        for (index, entry) in table.entries.iter().enumerate() {
+            if entry.is_mapped() {
+                println!("{}: {}", index, entry);
+            }
+        }
+
+        // the pages mapped by level 1, 2 and 3 entries
+        let mut pages = [0usize; 3];
+        table.for_each_mapping(|_, entry, level| {
+            if level == 1 || entry.flags().contains(EntryFlags::HUGE_PAGE) {
+                pages[level as usize - 1] += 1;
+            }
+        });
+        println!(
+            "mapped: {} 4KiB pages, {} 2MiB pages, {} 1GiB pages",
+            pages[0], pages[1], pages[2]
+        );
+        return;
+    }
+
+    let Ok(addr) = usize::from_str_radix(args[1].trim_start_matches("0x"), 16) else {
+        println!("{}: expected a hex address", args[0]);
+        return;
+    };
+
+    let addr = VirtAddr::new(addr);
+    match table.get_entry(Page::containing_address(addr)) {
+        Some(entry) => println!("{:#x}: {}", addr, entry),
+        None => println!("{:#x} is not mapped", addr),
+    }
+}
+
+fn wxaudit(args: Vec<&str>) {
+    if args.len() != 1 {
+        println!("{}: expected no args", args[0]);
+        return;
+    }
+
+    if !memory::wxaudit::nx_enabled() {
+        println!("the no execute bit is disabled, every writable page is executable");
+    }
+
+    let ranges = memory::wxaudit::writable_executable(unsafe { current_root_table() });
+    for range in &ranges {
+        println!("{:#x}..{:#x}", range.start(), range.end());
+    }
+    println!("{} writable and executable ranges", ranges.len());
+}
+
+fn hexdump(args: Vec<&str>) {
+    if args.len() != 2 && args.len() != 3 {
+        println!(
+            "{}: expected the address and optionally the length",
+            args[0]
+        );
+        return;
+    }
+
+    let Ok(addr) = usize::from_str_radix(args[1].trim_start_matches("0x"), 16) else {
+        println!("{}: expected a hex address", args[0]);
+        return;
+    };
+
+    let len = match args.get(2).map(|len| len.parse()) {
+        None => 64,
+        Some(Ok(len)) => len,
+        Some(Err(_)) => {
+            println!("{}: expected a decimal length", args[0]);
+            return;
+        }
+    };
+
+    if let Err(unmapped) = memory::hexdump(VirtAddr::new(addr), len) {
+        println!("{:#x} is not mapped", unmapped);
+    }
+}
+
+fn regs(args: Vec<&str>) {
+    if args.len() != 1 {
+        println!("{}: expected 0 args", args[0]);
+        return;
+    }
+
+    arch::cpu::dump_registers();
+}
+
+fn irqs(args: Vec<&str>) {
+    if args.len() != 1 {
+        println!("{}: expected 0 args", args[0]);
+        return;
+    }
+
+    println!("vector:  count");
+    for (vector, count) in interrupt_stats() {
+        println!("{:#x}:  {}", vector, count);
+    }
+}
+
+fn timer_cmd(args: Vec<&str>) {
+    let mode = match args.len() {
+        1 => {
+            println!("timer: {:?}", timer::mode());
+            return;
+        }
+        2 if args[1] == "periodic" => TimerMode::Periodic,
+        2 if args[1] == "tickless" => TimerMode::Tickless,
+        2 => {
+            println!("{}: unknown mode {}", args[0], args[1]);
+            return;
+        }
+        _ => {
+            println!("{}: expected 0 or 1 args", args[0]);
+            return;
+        }
+    };
+
+    if timer::set_mode(mode).is_err() {
+        println!("{}: no tsc deadline timer, can't be tickless", args[0]);
+    }
+}
+
+fn keymap(args: Vec<&str>) {
+    match args.len() {
+        1 => {
+            println!("current layout: {}, layouts:", keymapper::keymap().name());
+            for layout in keymapper::LAYOUTS {
+                println!("{}", layout.name());
+            }
+        }
+        2 => match keymapper::find_layout(args[1]) {
+            Some(layout) => keymapper::set_keymap(layout),
+            None => println!("{}: unknown layout {}", args[0], args[1]),
+        },
+        _ => println!("{}: expected 0 or 1 args", args[0]),
+    }
+}
+
+fn run(args: Vec<&str>) {
+    if args.len() != 2 {
+        println!("{}: expected the elf path", args[0]);
+        return;
+    }
+
+    let path = get_path(args[1]);
+    let name = path.rsplit('/').next().unwrap_or(&path);
+
+    match scheduler().spawn_elf(&path, name) {
+        Ok(pid) => println!("started `{}` with pid {}", path, pid),
+        Err(err) => println!("couldn't run `{}`: {:?}", path, err),
+    }
+}
+
+/// a built-in shell command
+pub struct Command {
+    pub name: &'static str,
+    /// other names the command can be called with
+    pub aliases: &'static [&'static str],
+    /// the usage and description displayed by `help`
+    pub help: &'static str,
+    pub run: fn(Vec<&str>),
+}
+
+/// the shell built-in commands, adding a command is adding an entry here
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        aliases: &["?"],
+        help: "help, ?: displays this",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        aliases: &[],
+        help: "echo `text`: echoes back text",
+        run: echo,
+    },
+    Command {
+        name: "clear",
+        aliases: &[],
+        help: "clear: clears the screen",
+        run: clear,
+    },
+    Command {
+        name: "shutdown",
+        aliases: &[],
+        help: "shutdown: shutdowns qemu and bochs only for now",
+        run: shutdown_cmd,
+    },
+    Command {
+        name: "reboot",
+        aliases: &[],
+        help: "reboot: force-reboots the PC for now",
+        run: reboot_cmd,
+    },
+    Command {
+        name: "plist",
+        aliases: &[],
+        help: "plist: list the avalible process' pids and names",
+        run: plist,
+    },
+    Command {
+        name: "ps",
+        aliases: &[],
+        help: "ps: lists the threads with their tids, pids and status",
+        run: ps,
+    },
+    Command {
+        name: "pkill",
+        aliases: &[],
+        help: "pkill `pid`: kills a process with pid `pid`",
+        run: pkill,
+    },
+    Command {
+        name: "pkillall",
+        aliases: &[],
+        help: "pkillall `name`: kills all processs with name `name`",
+        run: pkillall,
+    },
+    Command {
+        name: "run",
+        aliases: &[],
+        help: "run `elf_path`: runs the elf file at `elf_path` in a new process",
+        run,
+    },
+    Command {
+        name: "mem",
+        aliases: &[],
+        help: "mem: displays the heap and frames usage",
+        run: mem,
+    },
+    Command {
+        name: "pt",
+        aliases: &[],
+        help: "pt `addr`: displays the entry `addr` (hex) is mapped with, or the present level 4 entries if no `addr` is given",
+        run: pt,
+    },
+    Command {
+        name: "wxaudit",
+        aliases: &[],
+        help: "wxaudit: displays the ranges mapped both writable and executable",
+        run: wxaudit,
+    },
+    Command {
+        name: "hexdump",
+        aliases: &["xxd"],
+        help: "hexdump `addr` `len`: displays `len` (64 if not given) bytes starting from `addr` (hex)",
+        run: hexdump,
+    },
+    Command {
+        name: "regs",
+        aliases: &[],
+        help: "regs: displays the general purpose and control registers",
+        run: regs,
+    },
+    Command {
+        name: "irqs",
+        aliases: &[],
+        help: "irqs: displays how many times each interrupt vector fired since boot",
+        run: irqs,
+    },
+    Command {
+        name: "timer",
+        aliases: &[],
+        help: "timer `periodic|tickless`: sets the scheduler timer mode, or displays it if no mode is given",
+        run: timer_cmd,
+    },
+    Command {
+        name: "keymap",
+        aliases: &[],
+        help: "keymap `layout`: maps the keyboard with `layout`, or lists the layouts if no `layout` is given",
+        run: keymap,
+    },
+    Command {
+        name: "touch",
+        aliases: &[],
+        help: "touch `new_file_path`: creates a new empty file, the path of the new file would be equal to `new_file_path`",
+        run: touch,
+    },
+    Command {
+        name: "mkdir",
+        aliases: &[],
+        help: "mkdir `new_dir_path`: creates a new empty directory, the path of the new directory would be equal to `new_dir_path`",
+        run: mkdir,
+    },
+    Command {
+        name: "ls",
+        aliases: &[],
+        help: "ls: lists all files and directories in the current dir",
+        run: ls,
+    },
+    Command {
+        name: "cd",
+        aliases: &[],
+        help: "cd `target_dir`: changes the current dir to `target_dir`",
+        run: cd,
+    },
+    Command {
+        name: "cat",
+        aliases: &[],
+        help: "cat `src_files`: echoes the contents of a file",
+        run: cat,
+    },
+    Command {
+        name: "write",
+        aliases: &[],
+        help: "write `target_file` `src_text`: writes `src_text` to `target_file`",
+        run: write,
+    },
+];
+
+/// returns the built-in command named or aliased `name`
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+}
+
 pub fn process_command(command: String) {
     let mut unterminated_str_slice = false;
     let command: Vec<&str> = command
@@ -360,37 +768,39 @@ pub fn process_command(command: String) {
         return;
     }
 
-    (match command[0] {
-        "echo" => echo,
-        "?" | "help" => help,
-        "clear" => clear,
-        "reboot" => reboot_cmd,
-        "shutdown" => shutdown_cmd,
-
-        "plist" => plist,
-        "pkill" => pkill,
-        "pkillall" => pkillall,
+    if command[0].is_empty() {
+        return;
+    }
 
-        "ls" => ls,
-        "touch" => touch,
-        "mkdir" => mkdir,
-        "cd" => cd,
+    match find_command(command[0]) {
+        Some(found) => (found.run)(command),
+        None => println!("unknown command {}", command[0]),
+    }
+}
 
-        "cat" => cat,
-        "write" => write,
-        "" => return,
-        _ => {
-            println!("unknown command {}", command[0]);
-            return;
+/// a shell reading commands from the serial port, the terminal output is mirrored to the
+/// serial port while a command runs
+pub fn serial_shell() {
+    loop {
+        serial!("# ");
+        let line = chardev::readline(&SerialConsole);
+        if line.is_empty() {
+            continue;
         }
-    })(command)
+
+        terminal().mirror_to_serial = true;
+        process_command(line);
+        terminal().mirror_to_serial = false;
+    }
 }
 
 // badly written shell process
 pub fn shell() {
     serial!("shell!\n");
     // waits until we leave init mode which happens on the first terminal().clear()
-    while terminal().mode != TerminalMode::Stdin {}
+    while terminal().mode != TerminalMode::Stdin {
+        crate::threading::wait_for_interrupt();
+    }
     serial!("entering stdin... {:?}\n", terminal().mode);
 
     print!(
@@ -1,18 +1,141 @@
+use core::any::type_name;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 use macros::test_module;
 
+use crate::arch::qemu::{self, ExitCode};
+use crate::{cross_println, khalt, println, serial, terminal, terminal_inited};
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        cross_println!("running {} test...", type_name::<T>());
+        self();
+        cross_println!("[ok]");
+    }
+}
+
+/// wether or not we are currently running a test, a panic while testing is a failed test
+static TESTING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_testing() -> bool {
+    TESTING.load(Ordering::Relaxed)
+}
+
+/// runs each `#[test_case]` in `testing_module`, a failing test exits qemu with
+/// `ExitCode::Failed`, once all tests passed the kernel continues booting
+/// (src/main.rs waits for the "all tests passed" line in `cargo test`)
+pub fn test_runner(tests: &[&dyn Testable]) {
+    cross_println!("running {} tests...", tests.len());
+
+    TESTING.store(true, Ordering::Relaxed);
+    for test in tests {
+        test.run();
+    }
+    TESTING.store(false, Ordering::Relaxed);
+
+    cross_println!("all {} tests passed", tests.len());
+}
+
+/// called by the panic handler if a test panicked
+/// the tid of the thread `testing_module::expect_panic` runs its function in plus one, 0 if
+/// there is none
+static EXPECTED_PANIC: AtomicU64 = AtomicU64::new(0);
+/// set once that thread panicked
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/// called first by the panic handler, if the thread `testing_module::expect_panic` runs its
+/// function in panicked that is the result it waits for: the thread's process exits and it waits
+/// to be buried, otherwise it returns and the panic goes on
+pub fn catch_expected_panic() {
+    let expected = EXPECTED_PANIC.load(Ordering::SeqCst);
+    if expected == 0 || crate::threading::current_thread().map(|tid| tid + 1) != Some(expected) {
+        return;
+    }
+
+    EXPECTED_PANIC.store(0, Ordering::SeqCst);
+    PANICKED.store(true, Ordering::SeqCst);
+    crate::arch::without_interrupts(|| {
+        let pid = unsafe { (*crate::scheduler().current_thread).pid };
+        crate::scheduler().exit(pid, 1).unwrap();
+    });
+
+    <crate::arch::Current as crate::arch::Arch>::enable_interrupts();
+    loop {
+        crate::threading::wait_for_interrupt();
+    }
+}
+
+pub fn test_failed() -> ! {
+    cross_println!("[FAILED]");
+    qemu::exit(ExitCode::Failed);
+
+    khalt()
+}
+
 #[test_module]
 pub mod testing_module {
-    use alloc::vec::Vec;
+    use alloc::{
+        alloc::{alloc, dealloc},
+        boxed::Box,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+    use core::alloc::Layout;
 
-    use crate::{cross_println, serial, terminal, terminal_inited};
-    use crate::{global_allocator, println};
+    use crate::arch::x86_64::interrupts::{
+        apic, idt, interrupt_count, interrupt_stats, InterruptFrame,
+    };
+    use crate::arch::x86_64::serial::{self, COM1, COM2, COM3, COM4};
+    use crate::arch::{
+        without_interrupts,
+        x86_64::{pku, rdtsc, tsc},
+    };
+    use crate::arch::{Arch, Current};
+    use crate::cmdline::{self, CmdLine};
+    use crate::drivers::chardev::{self, CharDevice};
+    use crate::drivers::keyboard::{self, Key, KeyCode, KeyFlags, Leds, RawKeyEvent};
+    use crate::drivers::keymapper::{self, KeyMap, QWERTZ, US_QWERTY};
+    use crate::drivers::vfs::{
+        normalize,
+        tmpfs::{TmpFS, EXTENT_SIZE},
+        FSError, FS, VFS,
+    };
+    use crate::drivers::{net, pit};
+    use crate::memory::allocator::{Fit, FitPolicy, HeapGrowth, LinkedListAllocator, Node};
+    use crate::memory::frame_allocator::Frame;
+    use crate::memory::paging::{
+        self, allocate_pml4, current_root_table, flush_all, tlb_generation, Entry, EntryFlags,
+        HugePageSize, MapToError, Page, PageTable, TlbBatch, HUGE_PAGE_1GIB, HUGE_PAGE_2MIB,
+        NULL_PAGE, PAGE_SIZE, PAGE_TABLE_LEVELS,
+    };
+    use crate::memory::{layout, phys_to_virt, virt_to_phys, vmm, PhysAddr, VirtAddr, VirtRange};
+    use crate::terminal::ansi::{Ansi, AnsiIter, Rendition, COLORS};
+    use crate::threading::{
+        self,
+        priority::LOWEST_PRIORITY,
+        softirq,
+        timer::{self, TimerMode},
+    };
+    use crate::time::{Duration, Instant};
+    use crate::utils::ring_buffer::{Full, RingBuffer};
+    use crate::utils::Locked;
+    use crate::{allocator_stats, global_allocator, kernel, log, logger, println, scheduler};
     use core::arch::asm;
+    use core::hint::black_box;
+    use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 
+    #[test_case]
     fn print() {
         assert_eq!(1, 1);
     }
 
     #[cfg(target_arch = "x86_64")]
+    #[test_case]
     fn long_mode() {
         let rax: u64;
         unsafe {
@@ -29,11 +152,13 @@ pub mod testing_module {
     }
 
     #[cfg(target_arch = "x86_64")]
+    #[test_case]
     fn interrupts() {
         unsafe { asm!("int3") }
         assert_eq!(true, true);
     }
 
+    #[test_case]
     fn allocator() {
         let mut test = Vec::new();
 
@@ -44,7 +169,34 @@ pub mod testing_module {
         println!("{:#?}\nAllocated Vec with len {}", test, test.len());
     }
 
+    #[test_case]
+    fn big_vec() {
+        const COUNT: usize = 10000;
+        let mut vec = Vec::new();
+        // a reallocation either grows in place or moves the items
+        let mut reallocations = 0;
+
+        for i in 0..COUNT {
+            let capacity = vec.capacity();
+            vec.push(i * 3);
+
+            if vec.capacity() != capacity {
+                reallocations += 1;
+            }
+        }
+
+        assert_eq!(vec.len(), COUNT);
+        assert!(vec.iter().enumerate().all(|(i, item)| *item == i * 3));
+        assert!(global_allocator().lock().is_initialized());
+
+        println!(
+            "pushed {} items with {} reallocations",
+            COUNT, reallocations
+        );
+    }
+
     // TODO: add asserts for the extend_test
+    #[test_case]
     fn extending_the_heap() {
         global_allocator()
             .lock()
@@ -53,6 +205,7 @@ pub mod testing_module {
         println!("extended the heap successfully!");
     }
 
+    #[test_case]
     fn double_extending_the_heap() {
         global_allocator()
             .lock()
@@ -65,4 +218,3201 @@ pub mod testing_module {
 
         println!("double extended the heap successfully!");
     }
+
+    #[test_case]
+    fn allocation_size_align() {
+        for size in (0..16)
+            .map(|shift| 1 << shift)
+            .chain([3, 24, 72, 100, 4095])
+        {
+            for align in (0..12).map(|shift| 1 << shift) {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                assert_eq!(
+                    LinkedListAllocator::size_align(layout),
+                    LinkedListAllocator::padded_size_align(layout),
+                    "{:?}",
+                    layout
+                );
+            }
+        }
+
+        // the average cycles an allocation and deallocation of `size` bytes takes
+        let measure = |size| {
+            const COUNT: u64 = 1000;
+            let layout = Layout::from_size_align(size, 8).unwrap();
+
+            let start = rdtsc();
+            for _ in 0..COUNT {
+                unsafe { dealloc(alloc(layout), layout) };
+            }
+            (rdtsc() - start) / COUNT
+        };
+
+        println!(
+            "alloc latency: 64 bytes: {} cycles, 72 bytes: {} cycles",
+            measure(64),
+            measure(72)
+        );
+    }
+
+    #[test_case]
+    fn framebuffer_write_combining() {
+        let (buffer, _) = crate::limine::get_framebuffer();
+        let alias = VirtAddr::from_ptr(buffer.as_ptr());
+        let phys_addr = virt_to_phys(alias);
+        let size = buffer.len();
+        let write_combining = vmm::map_framebuffer(phys_addr, size).unwrap();
+
+        // both mappings of the framebuffer have the same memory type, the physmap one included
+        let cache_flags = |addr: VirtAddr| {
+            let entry = unsafe { current_root_table() }
+                .get_entry(Page::containing_address(addr))
+                .unwrap();
+            entry.flags()
+                & (EntryFlags::WRITE_THROUGH | EntryFlags::NO_CACHE | EntryFlags::HUGE_PAGE)
+        };
+        for offset in [0, size / 2, size - 1] {
+            assert_eq!(
+                cache_flags(write_combining + offset).bits(),
+                Current::write_combining().bits()
+            );
+            assert_eq!(
+                cache_flags(alias + offset).bits(),
+                Current::write_combining().bits()
+            );
+        }
+
+        // a write combining write reaches the memory once the buffers are drained, how fast
+        // the framebuffer fills is in the `bench` feature (see `bench::framebuffer_fill`)
+        let ptr = write_combining.as_mut_ptr::<u32>();
+        unsafe {
+            ptr.write_volatile(0x00AB_CDEF);
+            asm!("sfence");
+        }
+        assert_eq!(
+            unsafe { alias.as_ptr::<u32>().read_volatile() },
+            0x00AB_CDEF
+        );
+        unsafe { ptr.write_volatile(0) };
+        vmm::unmap_mmio(write_combining, size);
+    }
+
+    #[test_case]
+    fn iter_pages_both_ends() {
+        let start = Page::containing_address(VirtAddr::new(0x1000));
+        let end = Page::containing_address(VirtAddr::new(0x9000));
+
+        let forward: Vec<Page> = Page::iter_pages(start, end).collect();
+        let mut backward: Vec<Page> = Page::iter_pages(start, end).rev().collect();
+        assert_eq!(Page::iter_pages(start, end).len(), forward.len());
+        assert_eq!(forward.len(), 9);
+        assert!(backward
+            .windows(2)
+            .all(|pages| pages[0].start_address > pages[1].start_address));
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        // meeting in the middle
+        let mut iter = Page::iter_pages(start, end);
+        assert_eq!(iter.next(), Some(start));
+        assert_eq!(iter.next_back(), Some(end));
+        assert_eq!(iter.len(), 7);
+        assert_eq!(iter.by_ref().count(), 7);
+        assert_eq!(iter.next_back(), None);
+
+        // the top of the address space
+        let last = Page::containing_address(VirtAddr::new(usize::MAX));
+        let before_last = Page::containing_address(VirtAddr::new(usize::MAX - PAGE_SIZE));
+        let mut iter = Page::iter_pages(before_last, last);
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.next_back(), Some(last));
+        assert_eq!(iter.next_back(), Some(before_last));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.len(), 0);
+    }
+
+    #[test_case]
+    fn cmdline_parsing() {
+        let cmdline = CmdLine::new("log.level=debug selftest tickless=on tickless=off quiet=maybe");
+
+        assert_eq!(cmdline.get("log.level"), Some("debug"));
+        assert_eq!(cmdline.get_or("log.level", "info"), "debug");
+        assert!(cmdline.selftest());
+        assert!(!cmdline.tickless());
+        assert!(cmdline.flag("quiet", true));
+        assert_eq!(cmdline.get("missing"), None);
+
+        let empty = CmdLine::new("");
+        assert_eq!(empty.get_or("log.level", "info"), "info");
+        assert_eq!(empty.selftest(), cmdline::defaults::SELFTEST);
+        assert_eq!(empty.tickless(), cmdline::defaults::TICKLESS);
+    }
+
+    #[test_case]
+    fn keymap_layouts() {
+        let q = Key::new(KeyCode::KeyQ, KeyFlags::empty());
+        assert_eq!(US_QWERTY.map(KeyCode::KeyQ, KeyFlags::CAPS_LOCK), Some('Q'));
+        assert_eq!(US_QWERTY.map(KeyCode::KeyQ, KeyFlags::CTRL), None);
+        assert_eq!(QWERTZ.map(KeyCode::KeyZ, KeyFlags::empty()), Some('y'));
+
+        let previous = *keymapper::keymap();
+        keymapper::set_keymap(keymapper::find_layout("azerty").unwrap());
+        assert_eq!(q.map_key(), 'a');
+        keymapper::set_keymap(previous);
+        assert_eq!(q.map_key(), 'q');
+    }
+
+    #[test_case]
+    fn tmpfs_sparse_write_and_truncate() {
+        let mut tmpfs = TmpFS::new();
+        tmpfs.create("/", "scratch".to_string()).unwrap();
+        let mut file = tmpfs.open("/scratch").unwrap();
+
+        // crosses an extent boundary leaving a gap before it
+        let offset = EXTENT_SIZE + 10;
+        file.write_pos = offset;
+        tmpfs.write(&mut file, &[0xAA; EXTENT_SIZE]).unwrap();
+        assert_eq!(file.size(), offset + EXTENT_SIZE);
+
+        let mut buffer = vec![0xFF; file.size() + 16];
+        assert_eq!(
+            tmpfs.read(&mut file, &mut buffer).unwrap(),
+            offset + EXTENT_SIZE
+        );
+        assert!(buffer[..offset].iter().all(|byte| *byte == 0));
+        assert!(buffer[offset..offset + EXTENT_SIZE]
+            .iter()
+            .all(|byte| *byte == 0xAA));
+
+        // shrinking then growing again reads back zeros not the old data
+        tmpfs.truncate(&mut file, offset + 1).unwrap();
+        tmpfs.truncate(&mut file, offset + 8).unwrap();
+        file.read_pos = offset;
+        let mut buffer = [0xFF; 16];
+        assert_eq!(tmpfs.read(&mut file, &mut buffer).unwrap(), 8);
+        assert_eq!(buffer[..8], [0xAA, 0, 0, 0, 0, 0, 0, 0]);
+
+        tmpfs.close(file).unwrap();
+    }
+
+    #[test_case]
+    fn entry_set_frame_and_flags() {
+        let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+        let mut entry = Entry::new(flags, PhysAddr::new(0x1000));
+
+        entry.set_frame(Frame::containing_address(PhysAddr::new(0x5000)));
+        assert_eq!(entry.decode().addr, PhysAddr::new(0x5000));
+        assert_eq!(entry.flags().bits(), flags.bits());
+
+        entry.set_flags(EntryFlags::PRESENT);
+        assert_eq!(entry.decode().addr, PhysAddr::new(0x5000));
+        assert_eq!(entry.flags().bits(), EntryFlags::PRESENT.bits());
+
+        // the low bits of an unaligned address would have been taken as flags
+        assert!(Entry::checked_new(EntryFlags::PRESENT, PhysAddr::new(0x5004)).is_none());
+        assert!(Entry::checked_new(EntryFlags::PRESENT, PhysAddr::new(0x5000)).is_some());
+    }
+
+    #[test_case]
+    fn misaligned_entries_and_frames_panic_in_debug() {
+        // the assertions are only there in debug builds
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        assert!(expect_panic(|| {
+            black_box(Entry::new(EntryFlags::PRESENT, PhysAddr::new(0x5004)));
+        }));
+        assert!(expect_panic(|| {
+            let mut entry = Entry::new(EntryFlags::PRESENT, PhysAddr::new(0x5000));
+            entry.set(EntryFlags::PRESENT, PhysAddr::new(0x5800));
+            black_box(entry);
+        }));
+        assert!(expect_panic(|| {
+            black_box(Frame::from_start_address(PhysAddr::new(0x5004)));
+        }));
+        // aligned, and `containing_address` aligns down
+        assert!(!expect_panic(|| {
+            black_box(Entry::new(EntryFlags::PRESENT, PhysAddr::new(0x5000)));
+            let frame = black_box(Frame::containing_address(PhysAddr::new(0x5004)));
+            assert_eq!(frame.start_address(), PhysAddr::new(0x5000));
+        }));
+    }
+
+    #[test_case]
+    fn accessed_and_dirty_bits() {
+        let mut value = Box::new(0u64);
+        let ptr = &mut *value as *mut u64;
+        let page = Page::containing_address(VirtAddr::from_ptr(ptr));
+
+        let entry = unsafe { current_root_table() }.get_entry(page).unwrap();
+
+        entry.clear_accessed(page);
+        assert!(!entry.is_accessed());
+        unsafe { core::ptr::read_volatile(ptr) };
+        assert!(entry.is_accessed());
+
+        entry.clear_dirty(page);
+        assert!(!entry.is_dirty());
+        unsafe { core::ptr::write_volatile(ptr, 0xdead) };
+        assert!(entry.is_dirty());
+
+        println!("accessed and dirty bits were set by the cpu!");
+    }
+
+    /// a pml4 sharing the kernel's higher half for a test to map its pages in, its lower half and
+    /// every frame mapped there are freed when it is dropped
+    struct TestPml4(PhysAddr);
+
+    impl TestPml4 {
+        fn new() -> Self {
+            Self(allocate_pml4().unwrap())
+        }
+
+        fn table(&mut self) -> &mut PageTable {
+            unsafe { &mut *phys_to_virt(self.0).as_mut_ptr::<PageTable>() }
+        }
+
+        /// runs `f` with this pml4 in cr3 then loads the previous one back
+        fn load<R>(&mut self, f: impl FnOnce(&mut PageTable) -> R) -> R {
+            let root = self.0.as_usize();
+            without_interrupts(|| {
+                let previous: usize;
+                unsafe {
+                    asm!("mov {}, cr3", out(reg) previous);
+                    asm!("mov cr3, {}", in(reg) root);
+                }
+                let result = f(self.table());
+
+                unsafe { asm!("mov cr3, {}", in(reg) previous) };
+                paging::tlb_flushed();
+                result
+            })
+        }
+    }
+
+    impl Drop for TestPml4 {
+        fn drop(&mut self) {
+            unsafe { self.table().free(PAGE_TABLE_LEVELS) };
+        }
+    }
+
+    #[test_case]
+    fn fresh_pml4_shares_the_higher_half() {
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let current = unsafe { current_root_table() };
+
+        assert!(table.higher_half_is_valid());
+        assert!(table.entries[..256].iter().all(|entry| !entry.is_mapped()));
+        // the same kernel tables
+        for (entry, kernel_entry) in table.entries[256..].iter().zip(&current.entries[256..]) {
+            assert_eq!(entry.decode().addr, kernel_entry.decode().addr);
+            assert_eq!(entry.flags().bits(), kernel_entry.flags().bits());
+        }
+
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[test_case]
+    fn walking_the_mappings() {
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let frame = || kernel().frame_allocator().allocate_frame().unwrap();
+
+        let pages = [0x40_0000, 0x40_1000, 0x7F_FFFF_F000].map(VirtAddr::new);
+        for addr in pages {
+            table
+                .map_to_writeable(Page::containing_address(addr), frame())
+                .unwrap();
+        }
+
+        let mut found = Vec::new();
+        table.for_each_mapping(|addr, _, level| {
+            if level == 1 {
+                found.push(addr);
+            }
+        });
+        assert_eq!(found, pages);
+
+        // kernel tables are visited too with canonical addresses
+        let mut higher_half = false;
+        table.for_each_mapping(|addr, _, level| {
+            higher_half |= level == 4 && addr.as_usize() >= 0xFFFF_8000_0000_0000
+        });
+        assert!(higher_half);
+
+        table.for_each_mapping_mut(|addr, entry, level| {
+            if level == 1 && addr == pages[1] {
+                entry.set_flags(entry.flags() | EntryFlags::NO_EXECUTE);
+            }
+        });
+        for (index, addr) in pages.iter().enumerate() {
+            let flags = table
+                .get_entry(Page::containing_address(*addr))
+                .unwrap()
+                .flags();
+            assert_eq!(flags.contains(EntryFlags::NO_EXECUTE), index == 1);
+        }
+
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[test_case]
+    fn freeing_a_page_table_returns_every_frame() {
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let frame = || kernel().frame_allocator().allocate_frame().unwrap();
+
+        // pages sharing tables, and ones in the upper half of a level 3 and a level 2 table
+        let pages = [
+            0x40_0000,
+            0x40_1000,
+            0x4B_0000_0000,
+            0x7F_F000_0000,
+            0x7F_FFFF_F000,
+        ]
+        .map(VirtAddr::new);
+        for addr in pages {
+            table
+                .map_to_writeable(Page::containing_address(addr), frame())
+                .unwrap();
+        }
+        assert!(kernel().frame_allocator().used_frames() > used_frames + pages.len());
+
+        // a frame freed twice would be counted as used again
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[test_case]
+    fn clone_deep() {
+        let page = Page::containing_address(VirtAddr::new(0x400000));
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        let data = phys_to_virt(frame.start_address()).as_mut_ptr::<u64>();
+        unsafe { *data = 0xdead };
+
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        table.map_to_writeable(page, frame).unwrap();
+
+        let mut copy = TestPml4(unsafe { table.clone_deep(PAGE_TABLE_LEVELS) }.unwrap());
+        let copied_frame = copy.table().translate_addr(page.start_address).unwrap();
+        assert_ne!(copied_frame, frame.start_address());
+        assert_eq!(
+            unsafe { *phys_to_virt(copied_frame).as_ptr::<u64>() },
+            0xdead
+        );
+
+        println!("deep cloned a page table!");
+    }
+
+    #[test_case]
+    fn update_flags_splits_huge_pages() {
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+
+        // builds the tables down to level 2 then maps 2MiB..4MiB with a single huge page
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        table
+            .map_to(Page::containing_address(VirtAddr::new(0)), frame, flags)
+            .unwrap();
+        let level_2_table = table[0].mapped_to().unwrap()[0].mapped_to().unwrap();
+        level_2_table[1] = Entry::new(flags | EntryFlags::HUGE_PAGE, PhysAddr::new(HUGE_PAGE_2MIB));
+
+        let start = VirtAddr::new(HUGE_PAGE_2MIB);
+        let page = Page::containing_address(start + 5 * PAGE_SIZE);
+        assert!(table.get_entry(page).is_none());
+
+        table
+            .update_flags(page, flags | EntryFlags::NO_EXECUTE)
+            .unwrap();
+        assert!(!level_2_table[1].flags().contains(EntryFlags::HUGE_PAGE));
+
+        for index in 0..512 {
+            let addr = start + index * PAGE_SIZE + 8;
+            assert_eq!(
+                table.translate_addr(addr),
+                Some(PhysAddr::new(HUGE_PAGE_2MIB + index * PAGE_SIZE + 8))
+            );
+
+            let entry_flags = table
+                .get_entry(Page::containing_address(addr))
+                .unwrap()
+                .flags();
+            assert_eq!(entry_flags.contains(EntryFlags::NO_EXECUTE), index == 5);
+            assert!(entry_flags.contains(flags));
+        }
+
+        // already split
+        let level_1_table = level_2_table[1].frame().unwrap();
+        table.split_huge_page(page).unwrap();
+        assert_eq!(level_2_table[1].frame().unwrap(), level_1_table);
+
+        // 2MiB..4MiB was never allocated, only the tables go back when the pml4 is freed
+        for index in 0..512 {
+            table.unmap(Page::containing_address(start + index * PAGE_SIZE));
+        }
+    }
+
+    #[test_case]
+    fn user_pages_dont_share_kernel_tables() {
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let frame = || kernel().frame_allocator().allocate_frame().unwrap();
+        let user = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::USER_ACCESSIBLE;
+        // the pml4 entry of a lower half address
+        let level_4_index = |page: Page| (page.start_address.as_usize() >> 39) & 0x1FF;
+
+        let kernel_page = Page::containing_address(VirtAddr::new(0x4000_0000));
+        table.map_to_writeable(kernel_page, frame()).unwrap();
+
+        let next_page = Page::containing_address(kernel_page.start_address + PAGE_SIZE);
+        let result = table.map_to(next_page, frame(), user);
+        assert!(matches!(result, Err(MapToError::KernelOnlyTable)));
+        assert!(!table.is_mapped(next_page));
+        assert!(!table[level_4_index(kernel_page)]
+            .flags()
+            .contains(EntryFlags::USER_ACCESSIBLE));
+
+        // a kernel page under a user table is still protected by its own entry
+        let user_page = Page::containing_address(VirtAddr::new(0x80_0000_0000));
+        table.map_to(user_page, frame(), user).unwrap();
+        let shared_page = Page::containing_address(user_page.start_address + PAGE_SIZE);
+        table.map_to_writeable(shared_page, frame()).unwrap();
+
+        assert!(table[level_4_index(user_page)]
+            .flags()
+            .contains(EntryFlags::USER_ACCESSIBLE));
+        assert!(!table
+            .get_entry(shared_page)
+            .unwrap()
+            .flags()
+            .contains(EntryFlags::USER_ACCESSIBLE));
+    }
+
+    #[test_case]
+    fn force_unlocking_a_lost_lock() {
+        static LOCK: Locked<usize> = Locked::new(0);
+
+        // the guard of a thread that panicked
+        core::mem::forget(LOCK.lock());
+        assert!(LOCK.is_locked());
+        if cfg!(debug_assertions) {
+            assert!(LOCK.last_locker().unwrap().file().ends_with("test.rs"));
+        }
+
+        assert!(unsafe { LOCK.force_unlock() });
+        assert!(!unsafe { LOCK.force_unlock() });
+        *LOCK.lock() += 1;
+        assert_eq!(*LOCK.lock(), 1);
+    }
+
+    #[test_case]
+    fn serial_ports_are_probed() {
+        // the runner attaches stdio as COM1 and the log file as COM2
+        assert!(COM1.is_present());
+        assert!(COM2.is_present());
+        assert!(!COM3.is_present() && !COM4.is_present());
+        assert_eq!(serial::log_port().base(), COM2.base());
+
+        // a missing port is skipped instead of waiting for its fifo forever
+        COM4.write_string("nobody is listening\n");
+        log!("logged from the tests\n");
+    }
+
+    #[test_case]
+    fn ring_buffer() {
+        static EVENTS: RingBuffer<usize, 4> = RingBuffer::new();
+        // pretends to be an interrupt handler firing between pops
+        let interrupt = |event| EVENTS.push(event);
+
+        for event in 0..4 {
+            interrupt(event).unwrap();
+        }
+        assert_eq!(interrupt(4), Err(Full));
+
+        let mut next = 4;
+        for expected in 0..32 {
+            assert_eq!(EVENTS.pop(), Some(expected));
+
+            interrupt(next).unwrap();
+            next += 1;
+        }
+
+        while EVENTS.pop().is_some() {}
+        assert!(EVENTS.is_empty());
+        println!("ring buffer kept the events in order!");
+    }
+
+    #[test_case]
+    fn ring_buffer_drops_the_oldest() {
+        static BYTES: RingBuffer<u8, 4> = RingBuffer::new();
+
+        for byte in 0..4 {
+            assert!(!BYTES.push_overwriting(byte));
+        }
+        assert!(BYTES.push_overwriting(4));
+        assert!(BYTES.push_overwriting(5));
+
+        for expected in 2..6 {
+            assert_eq!(BYTES.pop(), Some(expected));
+        }
+        assert_eq!(BYTES.pop(), None);
+
+        // goes through the logger ring once it runs
+        log!("the logger flushes this\n");
+        logger::flush();
+    }
+
+    #[test_case]
+    fn extending_the_heap_failure() {
+        let mut allocator = global_allocator().lock();
+        let heap_end = allocator.heap_end;
+        let used_frames = kernel().frame_allocator().used_frames();
+
+        kernel()
+            .frame_allocator()
+            .fail_after(Some(allocator.pages_per_extend() / 2));
+        let result = allocator.extend_heap();
+        kernel().frame_allocator().fail_after(None);
+
+        let first_page = Page::containing_address(VirtAddr::new(heap_end));
+        let mapped = unsafe { current_root_table() }.is_mapped(first_page);
+        drop(allocator);
+
+        assert!(result.is_err());
+        assert_eq!(global_allocator().lock().heap_end, heap_end);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+        assert!(!mapped);
+
+        println!("a failed heap extend didn't leak!");
+    }
+
+    #[test_case]
+    fn extending_the_heap_past_the_address_space() {
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut allocator = LinkedListAllocator::new();
+        allocator.set_growth(HeapGrowth::Fixed(4));
+
+        // the last 2 pages of the address space then the last 2 pages of the lower half
+        for heap_end in [usize::MAX - 2 * PAGE_SIZE + 1, 0x7FFF_FFFF_E000] {
+            allocator.heap_end = heap_end;
+
+            assert!(allocator.extend_heap().is_err());
+            assert_eq!(allocator.heap_end, heap_end);
+        }
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[repr(C, align(64))]
+    struct NodeBuffer([u8; 256]);
+
+    /// writes a free node of the bytes of `buffer` from `offset` to the end
+    fn node_at(buffer: &mut NodeBuffer, offset: usize) -> &mut Node {
+        let node = unsafe { buffer.0.as_mut_ptr().add(offset) }.cast::<Node>();
+        unsafe {
+            node.write(Node::new(buffer.0.len() - offset));
+            &mut *node
+        }
+    }
+
+    #[test_case]
+    fn nodes_fit_exactly_with_gaps_and_excess() {
+        let node_size = size_of::<Node>();
+        let mut buffer = NodeBuffer([0; 256]);
+        let start = buffer.0.as_ptr() as usize;
+        let fit = |addr, front, excess| Fit {
+            addr,
+            front,
+            excess,
+        };
+
+        let node = node_at(&mut buffer, 0);
+        assert_eq!(node.can_hold(256, 8), Ok(fit(start, 0, 0)));
+        assert_eq!(node.can_hold(128, 8), Ok(fit(start, 0, 128)));
+        // the excess couldn't be a node
+        assert!(node.can_hold(256 - 8, 8).is_err());
+        assert!(node.can_hold(257, 8).is_err());
+
+        // a gap big enough to be a node
+        let node = node_at(&mut buffer, node_size);
+        assert_eq!(
+            node.can_hold(64, 64),
+            Ok(fit(start + 64, 64 - node_size, 128))
+        );
+        assert_eq!(
+            node.can_hold(192, 64),
+            Ok(fit(start + 64, 64 - node_size, 0))
+        );
+
+        // a gap too small to be a node moves the allocation to the next aligned address
+        let node = node_at(&mut buffer, 64 - 8);
+        assert_eq!(node.can_hold(64, 64), Ok(fit(start + 128, 72, 64)));
+        assert!(node.can_hold(192, 64).is_err());
+    }
+
+    #[test_case]
+    fn allocating_returns_the_gap_and_the_excess() {
+        let mut buffer = NodeBuffer([0; 256]);
+        let start = buffer.0.as_mut_ptr() as usize;
+        let node_size = size_of::<Node>();
+
+        let mut allocator = LinkedListAllocator::new();
+        unsafe { allocator.init(start + node_size, 256 - node_size, HeapGrowth::DEFAULT) }.unwrap();
+
+        let layout = Layout::from_size_align(64, 64).unwrap();
+        let ptr = unsafe { allocator.alloc_mut(layout) };
+        assert_eq!(ptr as usize, start + 64);
+        // the gap in front and the 128 bytes after are free
+        assert_eq!(allocator.free_bytes(), 256 - node_size - 64);
+        allocator.check_integrity();
+
+        // merged back with the gap and the excess
+        unsafe { allocator.dealloc_mut(ptr, layout) };
+        assert_eq!(allocator.free_bytes(), 256 - node_size);
+        assert_eq!(allocator.stats().free_nodes, 1);
+
+        // an exact fit takes the whole node
+        let layout = Layout::from_size_align(256 - node_size, 8).unwrap();
+        let ptr = unsafe { allocator.alloc_mut(layout) };
+        assert_eq!(ptr as usize, start + node_size);
+        assert_eq!(allocator.free_bytes(), 0);
+        allocator.check_integrity();
+    }
+
+    #[test_case]
+    fn freeing_merges_the_free_neighbours() {
+        let mut buffer = NodeBuffer([0; 256]);
+        let start = buffer.0.as_mut_ptr() as usize;
+
+        let mut allocator = LinkedListAllocator::new();
+        unsafe { allocator.init(start, 256, HeapGrowth::Fixed(0)) }.unwrap();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let blocks = [0; 4].map(|_| unsafe { allocator.alloc_mut(layout) });
+        assert_eq!(allocator.free_bytes(), 0);
+
+        // 0 and 2 have no free neighbour, 1 merges with both and 3 with the one before
+        for (index, free_nodes) in [(0, 1), (2, 2), (1, 1), (3, 1)] {
+            unsafe { allocator.dealloc_mut(blocks[index], layout) };
+            assert_eq!(allocator.stats().free_nodes, free_nodes);
+            allocator.check_integrity();
+        }
+        assert_eq!(allocator.free_bytes(), 256);
+
+        // the whole heap is one node again
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        assert_eq!(unsafe { allocator.alloc_mut(layout) } as usize, start);
+    }
+
+    #[test_case]
+    fn zero_sized_allocations_dont_touch_the_heap() {
+        let (free_bytes, heap_end) = {
+            let allocator = global_allocator().lock();
+            (allocator.free_bytes(), allocator.heap_end)
+        };
+
+        for align in [1, 8, 64, 4096] {
+            let layout = Layout::from_size_align(0, align).unwrap();
+            for _ in 0..64 {
+                let ptr = unsafe { alloc(layout) };
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                unsafe { dealloc(ptr, layout) };
+            }
+        }
+
+        let allocator = global_allocator().lock();
+        assert_eq!(allocator.free_bytes(), free_bytes);
+        assert_eq!(allocator.heap_end, heap_end);
+        allocator.check_integrity();
+    }
+
+    #[test_case]
+    fn heap_growth_strategies() {
+        const GROW_BY: usize = 4 * 1024 * 1024;
+        // enough for the last extend of either strategy to overshoot
+        const REGION_SIZE: usize = GROW_BY + 2 * 1024 * 1024 + PAGE_SIZE;
+
+        // grows a heap of one page by at least `GROW_BY` bytes returning the number of extends,
+        // how long the allocations that grow it take is in the `bench` feature (see
+        // `bench::heap_growth`)
+        let extends = |growth| {
+            let start = kernel()
+                .virt_allocator()
+                .reserve(REGION_SIZE, PAGE_SIZE)
+                .unwrap();
+            let frame = kernel().frame_allocator().allocate_frame().unwrap();
+            unsafe { current_root_table() }
+                .map_to_writeable(Page::containing_address(start), frame)
+                .unwrap();
+
+            let mut allocator = LinkedListAllocator::new();
+            unsafe { allocator.init(start.as_usize(), PAGE_SIZE, growth) }.unwrap();
+
+            let mut extends = 0;
+            while allocator.heap_end - allocator.heap_start < GROW_BY + PAGE_SIZE {
+                allocator.extend_heap().unwrap();
+                extends += 1;
+            }
+
+            // the whole heap is a single free node
+            let layout = Layout::from_size_align(allocator.heap_end - start.as_usize(), 8).unwrap();
+            assert_eq!(unsafe { allocator.alloc_mut(layout) }, start.as_mut_ptr());
+
+            let end = Page::containing_address(VirtAddr::new(allocator.heap_end - 1));
+            for page in Page::iter_pages(Page::containing_address(start), end) {
+                let frame = unsafe { current_root_table() }.unmap(page).unwrap();
+                kernel().frame_allocator().deallocate_frame(frame);
+            }
+            kernel().virt_allocator().release(start, REGION_SIZE);
+
+            extends
+        };
+
+        let fixed = extends(HeapGrowth::Fixed(128));
+        let geometric = extends(HeapGrowth::Geometric {
+            initial: 16,
+            max: 512,
+        });
+        assert!(
+            geometric < fixed,
+            "geometric: {} extends, fixed: {} extends",
+            geometric,
+            fixed
+        );
+    }
+
+    /// spawns a kernel process running `function` returning its pid and the tid of its thread,
+    /// for the tests that need another thread, it exits with `exit_test_thread`
+    fn spawn_test_thread(function: fn(), name: &str) -> (threading::process::Pid, threading::Tid) {
+        without_interrupts(|| {
+            let pid = scheduler().spawn(function as usize, name);
+            (pid, scheduler().processes[&pid].threads[0])
+        })
+    }
+
+    /// exits the process of the current thread, it keeps waiting until it is buried
+    fn exit_test_thread() -> ! {
+        without_interrupts(|| {
+            let pid = unsafe { (*scheduler().current_thread).pid };
+            scheduler().exit(pid, 0).unwrap();
+        });
+
+        loop {
+            threading::wait_for_interrupt();
+        }
+    }
+
+    static PANIC_FUNCTION: Locked<Option<fn()>> = Locked::new(None);
+    static RETURNED: AtomicBool = AtomicBool::new(false);
+
+    fn expect_panic_thread() {
+        let function = PANIC_FUNCTION.lock().take().unwrap();
+        function();
+        RETURNED.store(true, Ordering::SeqCst);
+        exit_test_thread();
+    }
+
+    /// runs `function` in a thread of its own returning wether or not it panicked, for the debug
+    /// assertions since a panic can't be caught otherwise (see `catch_expected_panic`)
+    /// nothing unwinds so `function` must not hold a lock when it panics
+    fn expect_panic(function: fn()) -> bool {
+        *PANIC_FUNCTION.lock() = Some(function);
+        super::PANICKED.store(false, Ordering::SeqCst);
+        RETURNED.store(false, Ordering::SeqCst);
+
+        // the thread can't run before its tid is stored
+        without_interrupts(|| {
+            let (_, tid) = spawn_test_thread(expect_panic_thread, "expect panic");
+            super::EXPECTED_PANIC.store(tid + 1, Ordering::SeqCst);
+        });
+
+        while !super::PANICKED.load(Ordering::SeqCst) && !RETURNED.load(Ordering::SeqCst) {
+            threading::wait_for_interrupt();
+        }
+        super::EXPECTED_PANIC.store(0, Ordering::SeqCst);
+        super::PANICKED.load(Ordering::SeqCst)
+    }
+
+    const SSE_ROUNDS: usize = 8;
+    static MAIN_ROUNDS: AtomicUsize = AtomicUsize::new(0);
+    static THREAD_ROUNDS: AtomicUsize = AtomicUsize::new(0);
+    static THREAD_SUM: AtomicU64 = AtomicU64::new(0);
+
+    /// adds `step` to xmm0 `SSE_ROUNDS` times, after each addition it waits for the other
+    /// thread to do its own so both sums are in the middle of being computed at each context
+    /// switch
+    fn sse_sum(step: f64, mine: &AtomicUsize, other: &AtomicUsize) -> f64 {
+        unsafe { asm!("xorpd xmm0, xmm0") };
+
+        for round in 1..=SSE_ROUNDS {
+            unsafe { asm!("movq xmm1, {}", "addsd xmm0, xmm1", in(reg) step.to_bits()) };
+            mine.store(round, Ordering::SeqCst);
+
+            while other.load(Ordering::SeqCst) < round {
+                unsafe { asm!("hlt") };
+            }
+        }
+
+        let sum: u64;
+        unsafe { asm!("movq {}, xmm0", out(reg) sum) };
+        f64::from_bits(sum)
+    }
+
+    fn sse_thread() {
+        let sum = sse_sum(3.0, &THREAD_ROUNDS, &MAIN_ROUNDS);
+        THREAD_SUM.store(sum.to_bits(), Ordering::SeqCst);
+
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn per_thread_sse_state() {
+        spawn_test_thread(sse_thread, "sse-test");
+
+        let sum = sse_sum(2.0, &MAIN_ROUNDS, &THREAD_ROUNDS);
+        while THREAD_SUM.load(Ordering::SeqCst) == 0 {
+            unsafe { asm!("hlt") };
+        }
+
+        assert_eq!(sum, 2.0 * SSE_ROUNDS as f64);
+        assert_eq!(
+            f64::from_bits(THREAD_SUM.load(Ordering::SeqCst)),
+            3.0 * SSE_ROUNDS as f64
+        );
+
+        println!("the sse registers survived the context switches!");
+    }
+
+    const GPR_ROUNDS: u64 = 16;
+    /// 0 while the thread is running then 1 if its registers were fine or 2 if they weren't
+    static GPR_THREAD_RESULT: AtomicU8 = AtomicU8::new(0);
+
+    /// increments 12 general purpose registers starting from `seed` + a different value for
+    /// each `GPR_ROUNDS` times halting after each round so the timer switches to the other
+    /// thread in between, rcx is the round counter, returns wether or not every register ended
+    /// up where it should
+    fn gpr_count(seed: u64) -> bool {
+        let start: [u64; 12] = core::array::from_fn(|i| seed + (i as u64) * 0x1_0000);
+        let mut regs = start;
+
+        unsafe {
+            asm!(
+                "2:",
+                "inc rax",
+                "inc rdx",
+                "inc rsi",
+                "inc rdi",
+                "inc r8",
+                "inc r9",
+                "inc r10",
+                "inc r11",
+                "inc r12",
+                "inc r13",
+                "inc r14",
+                "inc r15",
+                "hlt",
+                "dec rcx",
+                "jnz 2b",
+                inout("rax") regs[0],
+                inout("rdx") regs[1],
+                inout("rsi") regs[2],
+                inout("rdi") regs[3],
+                inout("r8") regs[4],
+                inout("r9") regs[5],
+                inout("r10") regs[6],
+                inout("r11") regs[7],
+                inout("r12") regs[8],
+                inout("r13") regs[9],
+                inout("r14") regs[10],
+                inout("r15") regs[11],
+                inout("rcx") GPR_ROUNDS => _,
+            )
+        }
+
+        regs.iter()
+            .zip(start.iter())
+            .all(|(reg, start)| *reg == start + GPR_ROUNDS)
+    }
+
+    fn gpr_thread() {
+        let ok = gpr_count(0xBBBB_0000_0000_0000);
+        GPR_THREAD_RESULT.store(if ok { 1 } else { 2 }, Ordering::SeqCst);
+
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn context_switch_keeps_registers() {
+        spawn_test_thread(gpr_thread, "gpr-test");
+
+        assert!(gpr_count(0xAAAA_0000_0000_0000));
+        while GPR_THREAD_RESULT.load(Ordering::SeqCst) == 0 {
+            unsafe { asm!("hlt") };
+        }
+        assert_eq!(GPR_THREAD_RESULT.load(Ordering::SeqCst), 1);
+
+        println!("the general purpose registers survived the context switches!");
+    }
+
+    static RUN_ORDER: AtomicUsize = AtomicUsize::new(0);
+    /// when each thread ran first, 0 if it didn't yet
+    static HIGH_RAN_AT: AtomicUsize = AtomicUsize::new(0);
+    static LOW_RAN_AT: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_run_and_exit(ran_at: &AtomicUsize) -> ! {
+        ran_at.store(
+            RUN_ORDER.fetch_add(1, Ordering::SeqCst) + 1,
+            Ordering::SeqCst,
+        );
+
+        exit_test_thread();
+    }
+
+    fn high_priority_thread() {
+        record_run_and_exit(&HIGH_RAN_AT);
+    }
+
+    fn low_priority_thread() {
+        record_run_and_exit(&LOW_RAN_AT);
+    }
+
+    #[test_case]
+    fn high_priority_runs_first() {
+        unsafe {
+            asm!("cli");
+            // the low priority thread is queued first
+            let low = scheduler().spawn(low_priority_thread as usize, "low-priority");
+            let high = scheduler().spawn(high_priority_thread as usize, "high-priority");
+
+            let low = scheduler().processes[&low].threads[0];
+            let high = scheduler().processes[&high].threads[0];
+            scheduler().set_priority(low, LOWEST_PRIORITY).unwrap();
+            scheduler().set_priority(high, 1).unwrap();
+            assert!(scheduler().set_priority(high, LOWEST_PRIORITY + 1).is_err());
+            asm!("sti");
+        }
+
+        while LOW_RAN_AT.load(Ordering::SeqCst) == 0 || HIGH_RAN_AT.load(Ordering::SeqCst) == 0 {
+            threading::wait_for_interrupt();
+        }
+
+        assert!(HIGH_RAN_AT.load(Ordering::SeqCst) < LOW_RAN_AT.load(Ordering::SeqCst));
+    }
+
+    #[test_case]
+    fn keyboard_commands_are_acked() {
+        assert!(keyboard::set_typematic(0x20, 0).is_err());
+        assert!(keyboard::set_typematic(0, 4).is_err());
+
+        keyboard::set_leds(Leds::CAPS_LOCK | Leds::NUM_LOCK).unwrap();
+        keyboard::set_leds(Leds::empty()).unwrap();
+        keyboard::set_typematic(0x0B, 1).unwrap();
+
+        // every ack comes with its own irq
+        for _ in 0..100 {
+            if keyboard::pending_commands() == 0 {
+                break;
+            }
+            threading::wait_for_interrupt();
+        }
+        assert_eq!(keyboard::pending_commands(), 0);
+    }
+
+    #[test_case]
+    fn arch_maps_into_the_current_address_space() {
+        let addr = kernel()
+            .virt_allocator()
+            .reserve(PAGE_SIZE, PAGE_SIZE)
+            .unwrap();
+        let page = Page::containing_address(addr);
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+
+        Current::map(page, frame, EntryFlags::PRESENT | EntryFlags::WRITABLE).unwrap();
+        unsafe { *addr.as_mut_ptr::<u64>() = 0xbeef };
+        assert_eq!(
+            unsafe { *phys_to_virt(frame.start_address()).as_ptr::<u64>() },
+            0xbeef
+        );
+        assert_eq!(
+            unsafe { Current::current_root_table() }.translate_addr(addr),
+            Some(frame.start_address())
+        );
+
+        let root = unsafe { Current::current_root_table() };
+        assert_eq!(
+            root.unmap(page).unwrap().start_address(),
+            frame.start_address()
+        );
+        unsafe { Current::flush(page) };
+        assert!(root.translate_addr(addr).is_none());
+
+        assert!(Current::interrupts_enabled());
+        kernel().frame_allocator().deallocate_frame(frame);
+        kernel().virt_allocator().release(addr, PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn batched_remaps_are_flushed() {
+        let addr = vmm::alloc_pages(1).unwrap();
+        let page = Page::containing_address(addr);
+        let data = addr.as_mut_ptr::<u64>();
+        // the old mapping is in the tlb now
+        unsafe { data.write_volatile(1) };
+
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        unsafe { *phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = 2 };
+
+        let root = unsafe { current_root_table() };
+        let mut batch = TlbBatch::new();
+        let old_frame = root.unmap_batched(page, &mut batch).unwrap();
+        root.map_to_batched(
+            page,
+            frame,
+            EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            &mut batch,
+        )
+        .unwrap();
+        assert!(!batch.is_empty());
+        drop(batch);
+        assert_eq!(unsafe { data.read_volatile() }, 2);
+
+        // flushing everything after the last touch leaves nothing to flush
+        let mut batch = TlbBatch::new();
+        batch.touch(page);
+        let generation = tlb_generation();
+        unsafe { flush_all() };
+        assert!(tlb_generation() > generation);
+        batch.flush();
+        assert!(batch.is_empty());
+
+        root.map_to_writeable(page, old_frame).unwrap();
+        kernel().frame_allocator().deallocate_frame(frame);
+        vmm::free_pages(addr, 1);
+    }
+
+    #[test_case]
+    fn parsing_ansi_escapes() {
+        let params = |params: &[u16]| Ansi::Sgr(params.iter().copied().collect());
+        let parsed: Vec<_> =
+            AnsiIter::new("a\x1b[31;1mb\x1b[2;3Hc\x1b[m\x1b[2J\x1b[C\x1b[5Dd\x1b[?25l\x1b[1")
+                .collect();
+
+        assert_eq!(
+            parsed,
+            [
+                Ansi::Text("a"),
+                params(&[31, 1]),
+                Ansi::Text("b"),
+                Ansi::CursorTo { row: 1, col: 2 },
+                Ansi::Text("c"),
+                params(&[]),
+                Ansi::ClearScreen,
+                Ansi::CursorForward(1),
+                Ansi::CursorBack(5),
+                Ansi::Text("d"),
+            ]
+        );
+
+        let mut rendition = Rendition::default();
+        rendition.apply(&[31, 1, 104]);
+        assert_eq!(rendition.fg, Some(COLORS[1]));
+        assert_eq!(rendition.bg, Some(COLORS[12]));
+        rendition.apply(&[38, 2, 10, 20, 30, 49]);
+        assert_eq!(rendition.fg, Some((10, 20, 30)));
+        assert_eq!(rendition.bg, None);
+        rendition.apply(&[]);
+        assert_eq!(rendition, Rendition::default());
+    }
+
+    #[test_case]
+    fn mapping_the_null_page_fails() {
+        assert!(!unsafe { current_root_table() }.is_mapped(NULL_PAGE));
+
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+
+        assert!(matches!(
+            table.map_to_writeable(NULL_PAGE, frame),
+            Err(MapToError::NullPage)
+        ));
+        assert!(!table.is_mapped(NULL_PAGE));
+
+        let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        unsafe { table.map_null_page(frame, flags) }.unwrap();
+        assert!(table.is_mapped(NULL_PAGE));
+
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[test_case]
+    fn reserved_frames_are_never_allocated() {
+        assert!(kernel()
+            .frame_allocator()
+            .is_reserved(Frame::containing_address(PhysAddr::new(0x1000))));
+
+        // reserved while it is allocated so it would be the next frame handed out once freed
+        let reserved = kernel().frame_allocator().allocate_frame().unwrap();
+        kernel()
+            .frame_allocator()
+            .reserve(reserved.start_address(), PAGE_SIZE)
+            .unwrap();
+        assert!(kernel().frame_allocator().is_reserved(reserved));
+        kernel().frame_allocator().deallocate_frame(reserved);
+
+        let mut frames = Vec::new();
+        for _ in 0..1024 {
+            let frame = kernel().frame_allocator().allocate_frame().unwrap();
+            assert_ne!(frame, reserved);
+            assert!(!kernel().frame_allocator().is_reserved(frame));
+            frames.push(frame);
+        }
+
+        for frame in frames {
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+    }
+
+    #[test_case]
+    fn vfs_resolves_mount_points() {
+        assert_eq!(normalize("/a/./b//../c/").unwrap(), "/a/c");
+        assert_eq!(normalize("/..").unwrap(), "/");
+        assert!(matches!(normalize("a/b"), Err(FSError::InvaildPath)));
+
+        let mut vfs = VFS::new();
+        vfs.mount("/", Box::new(TmpFS::new())).unwrap();
+        vfs.mount("/tmp/", Box::new(TmpFS::new())).unwrap();
+        assert!(vfs.mount("/tmp", Box::new(TmpFS::new())).is_err());
+
+        vfs.create("/tmp", "file".to_string()).unwrap();
+        vfs.createdir("/", "dir".to_string()).unwrap();
+
+        // only the fs mounted at /tmp has the file
+        let mut file = vfs.open("/dir/../tmp/./file").unwrap();
+        vfs.write(&mut file, b"mounted").unwrap();
+        vfs.close(file).unwrap();
+        assert!(vfs.open("/tmp/dir").is_err());
+        assert!(vfs.open("/dir/").is_ok());
+
+        let (root, path) = vfs.reslove_mount("/tmp/file/").unwrap();
+        assert_eq!(root.name(), "tmpfs");
+        assert_eq!(path, "/file");
+
+        let mut dir = vfs.open("/").unwrap();
+        let mut names: Vec<String> = vfs
+            .readdir(&mut dir)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["dir", "tmp"]);
+
+        vfs.umount("/tmp").unwrap();
+        assert!(vfs.open("/tmp/file").is_err());
+    }
+
+    #[test_case]
+    fn interrupts_are_counted() {
+        let breakpoints = interrupt_count(3);
+        unsafe { asm!("int3") };
+        assert_eq!(interrupt_count(3), breakpoints + 1);
+        assert!(interrupt_stats().any(|(vector, count)| vector == 3 && count > breakpoints));
+    }
+
+    #[test_case]
+    fn failed_mapping_transactions_roll_back() {
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        // crosses into another level 2 table, the 3rd page after that fails
+        let pages = Page::iter_pages(
+            Page::containing_address(VirtAddr::new(0x3FFF_E000)),
+            Page::containing_address(VirtAddr::new(0x4000_2000)),
+        );
+
+        let mut transaction = table.begin_mapping();
+        kernel().frame_allocator().fail_after(Some(7));
+        let result = pages.clone().try_for_each(|page| {
+            transaction.map_new(page, flags)?;
+            Ok(())
+        });
+        kernel().frame_allocator().fail_after(None);
+        assert!(matches!(result, Err(MapToError::FrameAllocationFailed)));
+        transaction.rollback();
+
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames + 1);
+        assert!(table.entries[..256].iter().all(|entry| !entry.is_mapped()));
+
+        let mut transaction = table.begin_mapping();
+        for page in pages.clone() {
+            transaction.map_new(page, flags).unwrap();
+        }
+        transaction.commit();
+        assert!(pages.clone().all(|page| table.is_mapped(page)));
+
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    #[test_case]
+    fn instants_measure_time() {
+        assert!(tsc::frequency() > 0);
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(1) {}
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1) && elapsed < Duration::from_secs(1));
+        assert!(Instant::now() - start >= elapsed);
+
+        // the difference is taken across the wrap of the counter
+        let before_wrap = Instant::from_ticks(u64::MAX - tsc::frequency() / 2 + 1);
+        let after_wrap = before_wrap + Duration::from_secs(1);
+        assert!(after_wrap.ticks() < before_wrap.ticks());
+        assert_eq!(
+            after_wrap.duration_since(before_wrap).as_millis(),
+            Duration::from_secs(1).as_millis()
+        );
+    }
+
+    /// runs `spike` with the heap locked, it returns the highest the heap end went once it freed
+    /// everything it allocated, then checks the heap shrank back and gave the frames back
+    fn check_the_heap_shrinks_after(spike: impl FnOnce(&mut LinkedListAllocator) -> usize) {
+        let mut allocator = global_allocator().lock();
+        let used_frames = kernel().frame_allocator().used_frames();
+        let heap_end = allocator.heap_end;
+        let kept_pages = allocator.pages_per_extend();
+
+        let peak_end = spike(&mut allocator);
+        let grown_pages = (peak_end - heap_end) / PAGE_SIZE;
+        assert!(grown_pages > kept_pages);
+        let shrunk_end = allocator.heap_end;
+        drop(allocator);
+
+        assert!(shrunk_end <= heap_end + kept_pages * PAGE_SIZE);
+        assert!(!unsafe { current_root_table() }
+            .is_mapped(Page::containing_address(VirtAddr::new(shrunk_end))));
+
+        // the page tables the spike needed are kept
+        let tables = grown_pages / 512 + 2;
+        assert!(kernel().frame_allocator().used_frames() <= used_frames + kept_pages + tables);
+    }
+
+    #[test_case]
+    fn shrinking_the_heap_after_a_spike() {
+        // more than the whole initial heap so it has to extend
+        const SPIKE: usize = 64 * 1024 * 1024;
+
+        check_the_heap_shrinks_after(|allocator| {
+            let layout = Layout::from_size_align(SPIKE, PAGE_SIZE).unwrap();
+            let spike = unsafe { allocator.alloc_mut(layout) };
+            assert!(!spike.is_null());
+            let peak_end = allocator.heap_end;
+
+            unsafe { allocator.dealloc_mut(spike, layout) };
+            peak_end
+        });
+    }
+
+    #[test_case]
+    fn shrinking_the_heap_after_a_spike_that_left_an_excess() {
+        // the last extend overshoots, what is left of it stays free after the spike
+        const SPIKE: usize = 64 * 1024 * 1024 - 3 * PAGE_SIZE - 64;
+
+        check_the_heap_shrinks_after(|allocator| {
+            let layout = Layout::from_size_align(SPIKE, 8).unwrap();
+            let spike = unsafe { allocator.alloc_mut(layout) };
+            assert!(!spike.is_null());
+            let peak_end = allocator.heap_end;
+            assert!(spike as usize + SPIKE < peak_end);
+
+            unsafe { allocator.dealloc_mut(spike, layout) };
+            peak_end
+        });
+    }
+
+    #[test_case]
+    fn shrinking_the_heap_after_a_spike_freed_out_of_order() {
+        const SPIKES: usize = 16;
+        const SPIKE: usize = 4 * 1024 * 1024;
+
+        check_the_heap_shrinks_after(|allocator| {
+            let layout = Layout::from_size_align(SPIKE, 8).unwrap();
+            let mut spikes = [core::ptr::null_mut(); SPIKES];
+            for spike in &mut spikes {
+                *spike = unsafe { allocator.alloc_mut(layout) };
+                assert!(!spike.is_null());
+            }
+            let peak_end = allocator.heap_end;
+
+            // 7 and 16 are coprime so this frees each one once, neither from the start nor from
+            // the end of the heap
+            for index in 0..SPIKES {
+                let spike = spikes[(index * 7 + 3) % SPIKES];
+                unsafe { allocator.dealloc_mut(spike, layout) };
+            }
+            peak_end
+        });
+    }
+
+    #[test_case]
+    fn the_frame_allocator_is_held_exclusively() {
+        assert!(Current::interrupts_enabled());
+
+        let frame_allocator = kernel().frame_allocator();
+        assert!(!Current::interrupts_enabled());
+        assert!(kernel().frame_allocator.is_locked());
+        drop(frame_allocator);
+
+        assert!(!kernel().frame_allocator.is_locked());
+        assert!(Current::interrupts_enabled());
+    }
+
+    #[test_case]
+    fn reading_a_line_from_a_char_device() {
+        struct FakeDevice {
+            input: RingBuffer<u8, 64>,
+            output: Locked<Vec<u8>>,
+        }
+
+        impl CharDevice for FakeDevice {
+            fn read_byte(&self) -> Option<u8> {
+                self.input.pop()
+            }
+
+            fn write_byte(&self, byte: u8) {
+                self.output.lock().push(byte)
+            }
+
+            fn readable(&self) -> bool {
+                !self.input.is_empty()
+            }
+
+            fn writable(&self) -> bool {
+                true
+            }
+        }
+
+        let device = FakeDevice {
+            input: RingBuffer::new(),
+            output: Locked::new(Vec::new()),
+        };
+        for &byte in "ab\x08cä\x7Fé\r".as_bytes() {
+            device.input.push(byte).unwrap();
+        }
+
+        assert_eq!(chardev::readline(&device), "acé");
+        assert_eq!(
+            device.output.lock().as_slice(),
+            "ab\x08 \x08cä\x08 \x08é\n".as_bytes()
+        );
+        assert!(!device.readable());
+    }
+
+    #[test_case]
+    fn the_timer_uses_tsc_deadline_when_there() {
+        assert_eq!(apic::tsc_deadline_mode(), apic::has_tsc_deadline());
+
+        // the timer keeps ticking in either mode
+        let ticks = interrupt_count(0x20);
+        let start = Instant::now();
+        while interrupt_count(0x20) == ticks {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            core::hint::spin_loop();
+        }
+    }
+
+    #[test_case]
+    fn an_idle_system_barely_ticks() {
+        const IDLE: Duration = Duration::from_millis(200);
+
+        if timer::mode() != TimerMode::Tickless {
+            println!("the timer is periodic, skipping");
+            return;
+        }
+
+        let ticks = interrupt_count(0x20);
+        let start = Instant::now();
+        timer::sleep(IDLE);
+        assert!(start.elapsed() >= IDLE);
+
+        // a periodic timer would have fired every tick
+        let periodic_ticks = (IDLE.as_nanos() / apic::TIMER_TICK.as_nanos()) as u64;
+        let idle_ticks = interrupt_count(0x20) - ticks;
+        assert!(
+            idle_ticks < periodic_ticks / 2,
+            "{} ticks while idle",
+            idle_ticks
+        );
+    }
+
+    #[test_case]
+    fn allocator_stats_add_up() {
+        let allocator = global_allocator().lock();
+        assert!(allocator_stats().is_none());
+
+        let stats = allocator.stats();
+        assert!(stats.free_nodes > 0);
+        assert!(stats.largest_free <= stats.free_bytes);
+        assert!(stats.free_bytes <= stats.heap_size);
+        assert_eq!(stats.free_bytes, allocator.free_bytes());
+        drop(allocator);
+    }
+
+    #[test_case]
+    fn page_and_frame_arithmetic() {
+        let base = Page::containing_address(VirtAddr::new(0x40_0000));
+        assert_eq!((base + 3).start_address, VirtAddr::new(0x40_3000));
+        assert_eq!(base + 3 - 3, base);
+        assert_eq!((base + 5) - base, 5);
+        assert_eq!(base - base, 0);
+        assert_eq!(Page::iter_pages(base, base + 9).len(), 10);
+
+        let frame = Frame::containing_address(PhysAddr::new(0x20_0000));
+        assert_eq!((frame + 2).start_address(), PhysAddr::new(0x20_2000));
+        assert_eq!(frame + 2 - 2, frame);
+    }
+
+    /// a vector raised with `int` by the tests, the highest dynamic one so no driver has it
+    const TEST_VECTOR: u8 = *idt::DYNAMIC_VECTORS.end();
+
+    extern "x86-interrupt" fn need_resched_handler(_frame: InterruptFrame) {
+        threading::set_need_resched();
+    }
+
+    #[test_case]
+    fn the_switch_waits_for_the_interrupt_to_return() {
+        use crate::{
+            syscalls::{STDOUT, SYSCALL_FAILED, SYS_WRITE},
+            threading::ThreadStatus,
+        };
+
+        idt::claim_vector(TEST_VECTOR).unwrap();
+        idt::set_handler(TEST_VECTOR, need_resched_handler, idt::ATTR_INT).unwrap();
+
+        let tid = threading::current_thread().unwrap();
+        unsafe { asm!("cli") };
+        // every ready thread blocks so the switch can only come back to this one, everything is
+        // unblocked before interrupts are enabled
+        let mut blocked = Vec::new();
+        let mut thread = Some(&mut *scheduler().head);
+        while let Some(current) = thread {
+            if current.status == ThreadStatus::Waiting {
+                current.status = ThreadStatus::Blocked;
+                blocked.push(current.tid);
+            }
+            thread = current.next.as_deref_mut();
+        }
+
+        let switches = scheduler().switches();
+        // set by a handler that doesn't switch, still on the same thread until one that does
+        // returns
+        unsafe { asm!("int {}", const TEST_VECTOR) };
+        assert!(threading::need_resched());
+        assert_eq!(scheduler().switches(), switches);
+        assert_eq!(threading::current_thread(), Some(tid));
+
+        // a syscall switches on the way out and doesn't send an eoi, writing to stdin fails
+        // without doing anything
+        let result: u64;
+        unsafe {
+            asm!("int 0x80", inout("rax") SYS_WRITE => result, in("rdi") STDOUT - 1);
+        }
+        assert_eq!(result, SYSCALL_FAILED);
+        assert_eq!(scheduler().switches(), switches + 1);
+        assert!(!threading::need_resched());
+        assert_eq!(threading::current_thread(), Some(tid));
+
+        for tid in blocked {
+            assert!(scheduler().unblock(tid));
+        }
+        unsafe { asm!("sti") };
+        idt::free_vector(TEST_VECTOR).unwrap();
+    }
+
+    #[test_case]
+    fn yielding_switches_threads() {
+        use crate::syscalls::SYS_YIELD;
+
+        let switches = scheduler().switches();
+        let result: u64;
+        unsafe { asm!("int 0x80", inout("rax") SYS_YIELD => result) };
+
+        assert_eq!(result, 0);
+        assert!(scheduler().switches() > switches);
+    }
+
+    #[test_case]
+    fn drivers_claim_idt_vectors() {
+        extern "x86-interrupt" fn handler(_frame: InterruptFrame) {}
+
+        assert!(idt::is_set(0x20));
+        assert!(idt::set_handler(14, handler, idt::ATTR_INT).is_err());
+
+        let vector = idt::alloc_vector().unwrap();
+        assert!(idt::DYNAMIC_VECTORS.contains(&vector));
+        assert!(!idt::is_set(vector));
+        let other = idt::alloc_vector().unwrap();
+        assert_ne!(other, vector);
+
+        idt::set_handler(vector, handler, idt::ATTR_INT).unwrap();
+        assert!(idt::is_set(vector));
+        let third = idt::alloc_vector().unwrap();
+        assert!(third != vector && third != other);
+
+        for vector in [third, other, vector] {
+            idt::free_vector(vector).unwrap();
+            assert!(!idt::is_set(vector));
+        }
+        // given back, the first free vector is handed out again
+        let again = idt::alloc_vector().unwrap();
+        assert_eq!(again, vector);
+        idt::free_vector(again).unwrap();
+        assert!(idt::free_vector(again).is_err());
+        assert!(idt::free_vector(0x20).is_err());
+    }
+
+    #[test_case]
+    fn best_fit_fragments_less_than_first_fit() {
+        const PAGES: usize = 32;
+        const ROUNDS: usize = 32;
+        const SMALL: usize = 64;
+        const LARGE: usize = 1024;
+
+        // runs a mixed workload returning the largest free node left, that is the end of the
+        // heap so the more of it the workload used the more it fragmented the rest
+        let run = |policy| {
+            let start = kernel()
+                .virt_allocator()
+                .reserve(PAGES * PAGE_SIZE, PAGE_SIZE)
+                .unwrap();
+            let start_page = Page::containing_address(start);
+            for page in Page::iter_pages(start_page, start_page + (PAGES - 1)) {
+                let frame = kernel().frame_allocator().allocate_frame().unwrap();
+                unsafe { current_root_table() }
+                    .map_to_writeable(page, frame)
+                    .unwrap();
+            }
+
+            let mut allocator = LinkedListAllocator::new();
+            unsafe { allocator.init(start.as_usize(), PAGES * PAGE_SIZE, HeapGrowth::Fixed(1)) }
+                .unwrap();
+            allocator.set_policy(policy);
+            assert_eq!(allocator.policy(), policy);
+
+            let small = Layout::from_size_align(SMALL, 8).unwrap();
+            let large = Layout::from_size_align(LARGE, 8).unwrap();
+            let alloc = |allocator: &mut LinkedListAllocator, layout| {
+                let ptr = unsafe { allocator.alloc_mut(layout) };
+                assert!(!ptr.is_null());
+                ptr
+            };
+
+            // large and small holes between allocations that stay so they can't merge
+            let mut holes = Vec::new();
+            for _ in 0..ROUNDS {
+                holes.push((alloc(&mut allocator, large), large));
+                alloc(&mut allocator, small);
+                holes.push((alloc(&mut allocator, small), small));
+                alloc(&mut allocator, large);
+            }
+
+            for (ptr, layout) in holes {
+                unsafe { allocator.dealloc_mut(ptr, layout) };
+            }
+
+            for _ in 0..ROUNDS {
+                alloc(&mut allocator, small);
+            }
+            for _ in 0..ROUNDS {
+                alloc(&mut allocator, large);
+            }
+
+            assert_eq!(allocator.heap_end, start.as_usize() + PAGES * PAGE_SIZE);
+            let largest_free = allocator.stats().largest_free;
+
+            for page in Page::iter_pages(start_page, start_page + (PAGES - 1)) {
+                let frame = unsafe { current_root_table() }.unmap(page).unwrap();
+                kernel().frame_allocator().deallocate_frame(frame);
+            }
+            kernel().virt_allocator().release(start, PAGES * PAGE_SIZE);
+
+            largest_free
+        };
+
+        let first_fit = run(FitPolicy::FirstFit);
+        let best_fit = run(FitPolicy::BestFit);
+        println!(
+            "largest free node: first fit: {} bytes, best fit: {} bytes",
+            first_fit, best_fit
+        );
+        assert!(best_fit > first_fit);
+    }
+
+    #[test_case]
+    fn the_pit_is_the_time_reference() {
+        let (start, end) = pit::reference(5, rdtsc).unwrap();
+        let expected = tsc::ns_to_ticks(5_000_000);
+        let measured = end - start;
+        assert!(
+            measured > expected * 4 / 5 && measured < expected * 6 / 5,
+            "5ms on the pit took {} tsc ticks, expected about {}",
+            measured,
+            expected
+        );
+
+        assert!(pit::reference(1000, rdtsc).is_none());
+        assert!(pit::set_periodic(0).is_err());
+        assert!(pit::set_periodic(1).is_err());
+        assert!(pit::set_oneshot(1_000_000).is_err());
+
+        assert_eq!(
+            apic::tick_source() == apic::TickSource::TscDeadline,
+            apic::tsc_deadline_mode()
+        );
+        if apic::tick_source() == apic::TickSource::Pit {
+            let count = pit::read_count();
+            let (_, after) = pit::reference(1, pit::read_count).unwrap();
+            assert_ne!(count, after);
+        }
+    }
+
+    #[test_case]
+    fn protection_keys_in_entries_and_pkru() {
+        let flags = EntryFlags::PRESENT | EntryFlags::USER_ACCESSIBLE | EntryFlags::NO_EXECUTE;
+        let keyed = flags.with_protection_key(5);
+        assert_eq!(keyed.protection_key(), 5);
+        assert!(keyed.contains(flags));
+        assert_eq!(keyed.with_protection_key(0).bits(), flags.bits());
+
+        let addr = PhysAddr::new(0x1234_5000);
+        let mut entry = Entry::new(flags, addr);
+        entry.set_protection_key(0xF);
+        assert_eq!(entry.protection_key(), 0xF);
+        assert_eq!(entry.decode().addr, addr);
+        assert!(entry.flags().contains(flags));
+
+        let Some(pkru) = pku::read_pkru() else {
+            assert!(pku::write_pkru(0).is_err());
+            return;
+        };
+
+        pku::set_key_access(3, true, false).unwrap();
+        assert_eq!(pku::read_pkru(), Some(pkru | pku::write_disable(3)));
+        pku::set_key_access(3, false, false).unwrap();
+        assert_eq!(
+            pku::read_pkru(),
+            Some(pkru | pku::access_disable(3) | pku::write_disable(3))
+        );
+        pku::write_pkru(pkru).unwrap();
+        assert_eq!(pku::read_pkru(), Some(pkru));
+    }
+
+    /// the pkru the thread started with, 0 until it is done
+    static PKRU_THREAD_START: AtomicU64 = AtomicU64::new(0);
+    /// 0 while the thread is running then 1 if its own pkru survived the switches or 2 if it
+    /// didn't
+    static PKRU_THREAD_RESULT: AtomicU8 = AtomicU8::new(0);
+
+    fn pkru_thread() {
+        let start = pku::read_pkru().unwrap();
+        pku::set_key_access(7, false, false).unwrap();
+        let expected = start | pku::access_disable(7) | pku::write_disable(7);
+
+        for _ in 0..4 {
+            threading::wait_for_interrupt();
+        }
+
+        let result = if pku::read_pkru() == Some(expected) {
+            1
+        } else {
+            2
+        };
+        PKRU_THREAD_START.store(start as u64 | 1 << 32, Ordering::SeqCst);
+        PKRU_THREAD_RESULT.store(result, Ordering::SeqCst);
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn pkru_is_per_thread() {
+        let Some(pkru) = pku::read_pkru() else {
+            return;
+        };
+
+        pku::set_key_access(5, true, false).unwrap();
+        let mine = pkru | pku::write_disable(5);
+        spawn_test_thread(pkru_thread, "pkru-test");
+
+        while PKRU_THREAD_RESULT.load(Ordering::SeqCst) == 0 {
+            assert_eq!(pku::read_pkru(), Some(mine));
+            threading::wait_for_interrupt();
+        }
+
+        // a new thread starts with every key accessible, not with the pkru of its spawner
+        assert_eq!(PKRU_THREAD_START.load(Ordering::SeqCst), 1 << 32);
+        assert_eq!(PKRU_THREAD_RESULT.load(Ordering::SeqCst), 1);
+        assert_eq!(pku::read_pkru(), Some(mine));
+        pku::write_pkru(pkru).unwrap();
+    }
+
+    static DEFERRED_RAN: AtomicBool = AtomicBool::new(false);
+    static DEFERRED_ON: AtomicU64 = AtomicU64::new(0);
+    static DEFERRED_WITH_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+    fn deferred_work() {
+        DEFERRED_ON.store(threading::current_thread().unwrap(), Ordering::SeqCst);
+        DEFERRED_WITH_INTERRUPTS.store(Current::interrupts_enabled(), Ordering::SeqCst);
+        DEFERRED_RAN.store(true, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn softirqs_run_after_the_interrupt_returns() {
+        let tid = threading::current_thread().unwrap();
+
+        // raised the way an interrupt handler does, with interrupts disabled
+        unsafe { asm!("cli") };
+        softirq::raise(deferred_work).unwrap();
+        assert!(!DEFERRED_RAN.load(Ordering::SeqCst));
+        unsafe { asm!("sti") };
+
+        let start = Instant::now();
+        while !DEFERRED_RAN.load(Ordering::SeqCst) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "the softirq never ran"
+            );
+            threading::wait_for_interrupt();
+        }
+
+        assert_ne!(DEFERRED_ON.load(Ordering::SeqCst), tid);
+        assert!(DEFERRED_WITH_INTERRUPTS.load(Ordering::SeqCst));
+        assert!(!softirq::pending());
+    }
+
+    #[test_case]
+    fn map_to_entry_returns_the_mapped_entry() {
+        let addr = kernel()
+            .virt_allocator()
+            .reserve(PAGE_SIZE, PAGE_SIZE)
+            .unwrap();
+        let page = Page::containing_address(addr);
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        let table = unsafe { current_root_table() };
+
+        let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        let entry = table.map_to_entry(page, frame, flags).unwrap();
+        assert_eq!(entry.frame(), Some(frame));
+        entry.set_flags(flags | EntryFlags::NO_EXECUTE);
+        let entry = entry as *mut Entry;
+
+        assert_eq!(
+            table.get_entry(page).map(|entry| entry as *mut Entry),
+            Some(entry)
+        );
+        assert!(unsafe { (*entry).flags() }.contains(EntryFlags::NO_EXECUTE));
+        assert!(matches!(
+            table.map_to_entry(NULL_PAGE, frame, flags),
+            Err(MapToError::NullPage)
+        ));
+
+        assert_eq!(table.unmap(page), Some(frame));
+        kernel().frame_allocator().deallocate_frame(frame);
+        kernel().virt_allocator().release(addr, PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn the_network_card_answers_arp() {
+        let Some(mac) = net::with_device(|device| device.mac_address()) else {
+            println!("no network card, skipping");
+            return;
+        };
+        assert_ne!(mac, [0; 6]);
+
+        // who has 10.0.2.2 (qemu's user network gateway) tell 10.0.2.15
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&net::BROADCAST);
+        frame.extend_from_slice(&mac);
+        frame.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0, 6, 4, 0, 1]);
+        frame.extend_from_slice(&mac);
+        frame.extend_from_slice(&[10, 0, 2, 15]);
+        frame.extend_from_slice(&[0; 6]);
+        frame.extend_from_slice(&[10, 0, 2, 2]);
+        frame.resize(net::MIN_FRAME_SIZE, 0);
+
+        assert!(
+            net::with_device(|device| device.send_frame(&[0; net::MAX_FRAME_SIZE + 1]))
+                .unwrap()
+                .is_err()
+        );
+        net::with_device(|device| device.send_frame(&frame))
+            .unwrap()
+            .unwrap();
+
+        let start = Instant::now();
+        let reply = loop {
+            assert!(start.elapsed() < Duration::from_secs(2), "no arp reply");
+            let frame = net::with_device(|device| device.receive_frame()).flatten();
+            match frame {
+                // an arp reply to us
+                Some(frame)
+                    if frame.get(12..14) == Some(&[0x08, 0x06])
+                        && frame.get(20..22) == Some(&[0, 2]) =>
+                {
+                    break frame
+                }
+                Some(_) => {}
+                None => threading::wait_for_interrupt(),
+            }
+        };
+
+        // an ethernet header and an ipv4 arp packet
+        assert!(reply.len() >= 42, "a {} bytes arp reply", reply.len());
+        assert_eq!(reply[0..6], mac);
+        assert_eq!(reply[28..32], [10, 0, 2, 2]);
+    }
+
+    #[test_case]
+    fn the_net_stack_answers_arp_and_ping() {
+        use crate::drivers::net::{ethernet::EthernetHeader, icmp, ipv4};
+
+        let mac = [0x52, 0x54, 0, 0x12, 0x34, 0x56];
+        let host = [0x52, 0x55, 10, 0, 2, 2];
+        let mut reply = [0; net::MAX_FRAME_SIZE];
+
+        let mut request = Vec::new();
+        request.extend_from_slice(&net::BROADCAST);
+        request.extend_from_slice(&host);
+        request.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0, 6, 4, 0, 1]);
+        request.extend_from_slice(&host);
+        request.extend_from_slice(&[10, 0, 2, 2]);
+        request.extend_from_slice(&[0; 6]);
+        request.extend_from_slice(&net::IP_ADDRESS);
+
+        let len = net::respond(mac, &request, &mut reply).unwrap();
+        assert_eq!(len, net::MIN_FRAME_SIZE);
+        let (ethernet, arp) = EthernetHeader::parse(&reply[..len]).unwrap();
+        assert_eq!((ethernet.destination, ethernet.source), (host, mac));
+        let arp = net::arp::ArpPacket::parse(arp).unwrap();
+        assert_eq!(arp.operation, net::arp::OPERATION_REPLY);
+        assert_eq!((arp.sender_mac, arp.sender_ip), (mac, net::IP_ADDRESS));
+        assert_eq!((arp.target_mac, arp.target_ip), (host, [10, 0, 2, 2]));
+
+        // not for us
+        request[38..42].copy_from_slice(&[10, 0, 2, 16]);
+        assert_eq!(net::respond(mac, &request, &mut reply), None);
+
+        let mut echo = [
+            icmp::TYPE_ECHO_REQUEST,
+            0,
+            0,
+            0,
+            0x12,
+            0x34,
+            0,
+            1,
+            b'p',
+            b'i',
+            b'n',
+            b'g',
+        ];
+        let checksum = ipv4::checksum(&echo);
+        echo[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut ping = [0; 14 + ipv4::HEADER_SIZE + 12];
+        EthernetHeader {
+            destination: mac,
+            source: host,
+            ethertype: net::ethernet::ETHERTYPE_IPV4,
+        }
+        .write(&mut ping);
+        ipv4::Ipv4Header::new([10, 0, 2, 2], net::IP_ADDRESS, ipv4::PROTOCOL_ICMP)
+            .write(&mut ping[14..], echo.len());
+        ping[14 + ipv4::HEADER_SIZE..].copy_from_slice(&echo);
+
+        let len = net::respond(mac, &ping, &mut reply).unwrap();
+        let (ethernet, packet) = EthernetHeader::parse(&reply[..len]).unwrap();
+        assert_eq!((ethernet.destination, ethernet.source), (host, mac));
+        let (ip, pong) = ipv4::Ipv4Header::parse(packet).unwrap();
+        assert_eq!(
+            (ip.source, ip.destination),
+            (net::IP_ADDRESS, [10, 0, 2, 2])
+        );
+        assert_eq!(pong[0], icmp::TYPE_ECHO_REPLY);
+        assert_eq!(pong[4..], echo[4..]);
+        assert_eq!(ipv4::checksum(pong), 0);
+
+        // a broken checksum gets no answer
+        ping[14 + ipv4::HEADER_SIZE + 8] ^= 1;
+        assert_eq!(net::respond(mac, &ping, &mut reply), None);
+    }
+
+    #[test_case]
+    fn init_refuses_a_heap_too_small_for_a_node() {
+        let mut buffer = NodeBuffer([0; 256]);
+        let start = buffer.0.as_mut_ptr() as usize;
+        let node_size = size_of::<Node>();
+
+        let mut allocator = LinkedListAllocator::new();
+        // aligning up to the next node eats more than the size, this used to wrap `heap_end`
+        assert!(unsafe { allocator.init(start + 1, node_size - 2, HeapGrowth::DEFAULT) }.is_err());
+        // room for the alignment but not for a node
+        assert!(unsafe { allocator.init(start + 1, node_size, HeapGrowth::DEFAULT) }.is_err());
+        assert!(!allocator.is_initialized());
+        assert_eq!(allocator.free_bytes(), 0);
+
+        unsafe { allocator.init(start + 1, node_size * 2 - 1, HeapGrowth::DEFAULT) }.unwrap();
+        assert_eq!(allocator.free_bytes(), node_size);
+        allocator.check_integrity();
+    }
+
+    /// sets the keymap to `keymap` until it is dropped, even if the test panics
+    struct KeymapGuard(&'static dyn KeyMap);
+
+    impl KeymapGuard {
+        fn set(keymap: &'static dyn KeyMap) -> Self {
+            let previous = *keymapper::keymap();
+            keymapper::set_keymap(keymap);
+            Self(previous)
+        }
+    }
+
+    impl Drop for KeymapGuard {
+        fn drop(&mut self) {
+            keymapper::set_keymap(self.0);
+        }
+    }
+
+    static GETLINE_BUFFER: Locked<([u8; 3], Option<usize>)> = Locked::new(([0; 3], None));
+
+    fn getline_thread() {
+        let mut buffer = [0; 3];
+        let len = keyboard::getline(&mut buffer);
+        *GETLINE_BUFFER.lock() = (buffer, Some(len));
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn getchar_and_getline_map_the_key_presses() {
+        let key = |code, flags| Key::new(code, flags);
+        let _keymap = KeymapGuard::set(&US_QWERTY);
+
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::KeyA, KeyFlags::empty())),
+            Some('a')
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::KeyA, KeyFlags::SHIFT)),
+            Some('A')
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::KeyA, KeyFlags::CAPS_LOCK)),
+            Some('A')
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::KeyA, KeyFlags::SHIFT | KeyFlags::CAPS_LOCK)),
+            Some('a')
+        );
+        // caps lock leaves the other keys alone
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::Key1, KeyFlags::CAPS_LOCK)),
+            Some('1')
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::Key1, KeyFlags::SHIFT | KeyFlags::CAPS_LOCK)),
+            Some('!')
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::Shift, KeyFlags::empty())),
+            None
+        );
+        assert_eq!(
+            keyboard::key_to_char(key(KeyCode::Ctrl, KeyFlags::SHIFT)),
+            None
+        );
+
+        // nobody reads, the keys typed before getline are dropped instead of being its line
+        assert!(!keyboard::reading_keys());
+        for code in [KeyCode::KeyX, KeyCode::Return] {
+            keyboard::push_key_press(key(code, KeyFlags::empty()));
+        }
+
+        spawn_test_thread(getline_thread, "getline");
+        while !keyboard::reading_keys() {
+            threading::wait_for_interrupt();
+        }
+
+        for (code, flags) in [
+            (KeyCode::Shift, KeyFlags::empty()),
+            (KeyCode::KeyH, KeyFlags::SHIFT),
+            (KeyCode::KeyI, KeyFlags::empty()),
+            (KeyCode::KeyX, KeyFlags::empty()),
+            (KeyCode::Backspace, KeyFlags::empty()),
+            (KeyCode::Key1, KeyFlags::SHIFT),
+            (KeyCode::Key2, KeyFlags::empty()),
+            (KeyCode::Return, KeyFlags::empty()),
+        ] {
+            keyboard::push_key_press(key(code, flags));
+        }
+
+        while GETLINE_BUFFER.lock().1.is_none() {
+            threading::wait_for_interrupt();
+        }
+        let (buffer, len) = *GETLINE_BUFFER.lock();
+        // the '2' doesn't fit
+        assert_eq!(core::str::from_utf8(&buffer[..len.unwrap()]), Ok("Hi!"));
+        assert!(!keyboard::reading_keys());
+    }
+
+    #[test_case]
+    fn address_ranges_overlap_only_when_they_share_an_address() {
+        use crate::memory::{PhysRange, VirtRange};
+
+        let range = |start, end| PhysRange::new(PhysAddr::new(start), PhysAddr::new(end));
+        let a = range(0x1000, 0x3000);
+
+        // identical
+        assert!(a.overlaps(&a));
+        assert_eq!(a.intersect(&a), Some(a));
+        assert!(a.contains_range(&a));
+        // adjacent on either side
+        for b in [range(0x3000, 0x4000), range(0, 0x1000)] {
+            assert!(!a.overlaps(&b) && !b.overlaps(&a));
+            assert_eq!(a.intersect(&b), None);
+        }
+        // nested
+        let b = range(0x1800, 0x2000);
+        assert!(a.overlaps(&b) && b.overlaps(&a));
+        assert_eq!(a.intersect(&b), Some(b));
+        assert!(a.contains_range(&b) && !b.contains_range(&a));
+        // partly
+        let b = range(0x2FFF, 0x5000);
+        assert_eq!(a.intersect(&b), Some(range(0x2FFF, 0x3000)));
+        assert_eq!(b.intersect(&a), a.intersect(&b));
+        // disjoint
+        assert!(!a.overlaps(&range(0x8000, 0x9000)));
+        // empty
+        let empty = range(0x2000, 0x2000);
+        assert!(empty.is_empty() && !a.overlaps(&empty) && !empty.contains(PhysAddr::new(0x2000)));
+        assert!(a.contains_range(&empty));
+
+        assert!(a.contains(PhysAddr::new(0x1000)));
+        assert!(a.contains(PhysAddr::new(0x2FFF)));
+        assert!(!a.contains(PhysAddr::new(0x3000)));
+        assert_eq!(a.len(), 0x2000);
+        assert_eq!(
+            PhysRange::from_len(PhysAddr::new(usize::MAX - 1), 16).end(),
+            PhysAddr::new(usize::MAX)
+        );
+
+        // the partial frames at the edges are covered too
+        let frames = range(0x1800, 0x3001).frames();
+        assert_eq!(frames.len(), 3);
+        let frames = frames
+            .map(|frame| frame.start_address().as_usize())
+            .collect::<Vec<_>>();
+        assert_eq!(frames, [0x1000, 0x2000, 0x3000]);
+        assert_eq!(empty.frames().len(), 0);
+        assert_eq!(range(0x2800, 0x2800).frames().len(), 0);
+        assert_eq!(
+            VirtRange::new(VirtAddr::new(0x2800), VirtAddr::new(0x2800))
+                .pages()
+                .next(),
+            None
+        );
+
+        let pages = VirtRange::from_len(VirtAddr::new(0x1000), 2 * PAGE_SIZE).pages();
+        assert_eq!(
+            pages.rev().collect::<Vec<_>>(),
+            [
+                Page::containing_address(VirtAddr::new(0x2000)),
+                Page::containing_address(VirtAddr::new(0x1000))
+            ]
+        );
+    }
+
+    #[test_case]
+    fn the_emergency_pool_only_backs_an_exhausted_heap_in_an_emergency() {
+        use crate::{memory::allocator, utils::Locked};
+
+        let mut buffer = NodeBuffer([0; 256]);
+        let heap: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+        // a heap that can't grow
+        unsafe {
+            heap.lock()
+                .init(buffer.0.as_mut_ptr() as usize, 256, HeapGrowth::Fixed(0))
+        }
+        .unwrap();
+
+        // a pool of its own, the global one has to stay for the real emergencies
+        static POOL: allocator::EmergencyPool = allocator::EmergencyPool::new();
+        let alloc = |layout| unsafe { heap.alloc_with_pool(layout, &POOL) };
+        let dealloc = |ptr, layout| unsafe { heap.dealloc_with_pool(ptr, layout, &POOL) };
+        let global_free = allocator::emergency_pool_free();
+
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let whole_heap = alloc(layout);
+        assert!(!whole_heap.is_null());
+
+        let small = Layout::from_size_align(24, 16).unwrap();
+        assert!(alloc(small).is_null());
+
+        let ptr = allocator::emergency(|| alloc(small));
+        assert!(!ptr.is_null());
+        assert!(POOL.contains(ptr));
+        assert_eq!(ptr as usize % 16, 0);
+        assert!(POOL.free_bytes() <= allocator::EMERGENCY_POOL_SIZE - small.size());
+        unsafe { ptr.write_bytes(0xAA, small.size()) };
+
+        // bounded, and freeing gives nothing back nor touches the heap
+        let huge = Layout::from_size_align(allocator::EMERGENCY_POOL_SIZE + 1, 8).unwrap();
+        assert!(allocator::emergency(|| alloc(huge)).is_null());
+        let left = POOL.free_bytes();
+        dealloc(ptr, small);
+        assert_eq!(POOL.free_bytes(), left);
+        assert_eq!(heap.lock().free_bytes(), 0);
+
+        // the heap still comes first
+        dealloc(whole_heap, layout);
+        let ptr = allocator::emergency(|| alloc(small));
+        assert_eq!(ptr, whole_heap);
+        assert_eq!(POOL.free_bytes(), left);
+        assert_eq!(allocator::emergency_pool_free(), global_free);
+    }
+
+    #[test_case]
+    fn the_faulting_stack_is_walked_only_where_it_is_mapped() {
+        use crate::arch::x86_64::{backtrace, interrupts::TrapFrame};
+
+        // the error code is the first thing on the stack
+        assert_eq!(core::mem::offset_of!(TrapFrame, error_code), 0);
+        assert_eq!(core::mem::offset_of!(TrapFrame, insturaction), 8);
+        assert_eq!(size_of::<TrapFrame>(), 6 * 8);
+
+        let (fp, rsp): (usize, usize);
+        unsafe {
+            asm!("mov {}, rbp", out(reg) fp);
+            asm!("mov {}, rsp", out(reg) rsp);
+        }
+        let frames = backtrace::capture_from(VirtAddr::new(fp), VirtAddr::new(rsp));
+        assert_ne!(frames[0], 0);
+        // the same callers up the stack as capture's, which starts a frame deeper
+        let from_here = backtrace::capture(backtrace::MAX_FRAMES);
+        assert_eq!(frames[0..4], from_here[1..5]);
+
+        // a frame pointer in an unmapped page in the bounds of the stack
+        for unmapped in [
+            NULL_PAGE.start_address + 0x100,
+            VirtAddr::new(0x7FFF_FFF0_0000),
+        ] {
+            let frames = backtrace::capture_from(unmapped, unmapped - 0x80);
+            assert!(frames.iter().all(|&frame| frame == 0));
+        }
+    }
+
+    #[test_case]
+    fn map_to_and_translate_addr_agree() {
+        const HUGE_PAGE_SIZE: usize = 512 * PAGE_SIZE;
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+
+        // in the higher half, on both sides of a level 1 table boundary
+        let window = kernel()
+            .virt_allocator()
+            .reserve(2 * HUGE_PAGE_SIZE, HUGE_PAGE_SIZE)
+            .unwrap();
+        let boundary = window + HUGE_PAGE_SIZE;
+
+        let pages = [
+            // low, nothing is mapped in the lower half of a fresh pml4
+            VirtAddr::new(0x0000_2000_0000_0000),
+            VirtAddr::new(0x0000_2000_0012_3000),
+            // level 1 index 511 then 0 of the next level 2 entry
+            VirtAddr::new(0x0000_2000_001F_F000),
+            VirtAddr::new(0x0000_2000_0020_0000),
+            // level 2 index 511 then 0 of the next level 3 entry
+            VirtAddr::new(0x0000_2000_3FFF_F000),
+            VirtAddr::new(0x0000_2000_4000_0000),
+            // level 3 index 511 then 0 of the next level 4 entry
+            VirtAddr::new(0x0000_207F_FFFF_F000),
+            VirtAddr::new(0x0000_2080_0000_0000),
+            boundary - PAGE_SIZE,
+            boundary,
+        ];
+
+        let frames = pages.map(|_| kernel().frame_allocator().allocate_frame().unwrap());
+        for (&addr, &frame) in pages.iter().zip(&frames) {
+            assert_eq!(
+                table.translate_addr(addr),
+                None,
+                "{:?} is already mapped",
+                addr
+            );
+            table
+                .map_to(Page::containing_address(addr), frame, EntryFlags::PRESENT)
+                .unwrap();
+        }
+
+        // checked once everything is mapped so a page overwriting another's entry shows up
+        for (&addr, &frame) in pages.iter().zip(&frames) {
+            for offset in [0, 0x800, PAGE_SIZE - 1] {
+                assert_eq!(
+                    table.translate_addr(addr + offset),
+                    Some(frame.start_address() + offset),
+                    "{:?} + {:#x}",
+                    addr,
+                    offset
+                );
+            }
+        }
+
+        // the pages on each side of a boundary are in different tables
+        for pair in pages[2..].chunks(2) {
+            let before = table.get_entry(Page::containing_address(pair[0])).unwrap() as *mut Entry;
+            let after = table.get_entry(Page::containing_address(pair[1])).unwrap() as *mut Entry;
+            assert_ne!(
+                before as usize & !(PAGE_SIZE - 1),
+                after as usize & !(PAGE_SIZE - 1)
+            );
+        }
+
+        for (&addr, &frame) in pages.iter().zip(&frames) {
+            assert_eq!(table.unmap(Page::containing_address(addr)), Some(frame));
+            assert_eq!(table.translate_addr(addr + 0x800), None);
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+        kernel()
+            .virt_allocator()
+            .release(window, 2 * HUGE_PAGE_SIZE);
+    }
+
+    #[cfg(feature = "recursive-paging")]
+    #[test_case]
+    fn the_recursive_slot_is_installed_at_boot() {
+        use crate::memory::recursive_paging::{self, DEFAULT_RECURSIVE_SLOT};
+
+        let value = Box::new(0x1234u64);
+        let addr = VirtAddr::from_ptr(&*value);
+        let table = unsafe { current_root_table() };
+
+        // the pml4 seen through the slot is the pml4 the cpu walks
+        let root = unsafe { recursive_paging::root_table(DEFAULT_RECURSIVE_SLOT) };
+        assert_eq!(
+            table.translate_addr(VirtAddr::from_ptr(root)),
+            table.translate_addr(VirtAddr::from_ptr(table))
+        );
+
+        // and the level 1 entry it gives is the one `addr` is mapped with
+        let entry = unsafe { recursive_paging::get_entry(DEFAULT_RECURSIVE_SLOT, addr) }.unwrap();
+        let entry_addr = VirtAddr::from_ptr(entry);
+        let offset = addr.as_usize() & (PAGE_SIZE - 1);
+        assert_eq!(
+            Some(entry.frame().unwrap().start_address() + offset),
+            table.translate_addr(addr)
+        );
+
+        let walked = VirtAddr::from_ptr(table.get_entry(Page::containing_address(addr)).unwrap());
+        assert_eq!(
+            table.translate_addr(entry_addr),
+            table.translate_addr(walked)
+        );
+        assert_eq!(*value, 0x1234);
+    }
+
+    const BUFFER_SLOTS: usize = 4;
+    const ITEMS: usize = 64;
+
+    static SLOTS: threading::sync::Semaphore = threading::sync::Semaphore::new(BUFFER_SLOTS);
+    static ITEMS_READY: threading::sync::Semaphore = threading::sync::Semaphore::new(0);
+    static BUFFER: crate::utils::Locked<alloc::collections::VecDeque<usize>> =
+        crate::utils::Locked::new(alloc::collections::VecDeque::new());
+    static PRODUCED: AtomicUsize = AtomicUsize::new(0);
+
+    fn producer_thread() {
+        for item in 1..=ITEMS {
+            SLOTS.wait();
+            BUFFER.lock().push_back(item);
+            PRODUCED.fetch_add(1, Ordering::SeqCst);
+            ITEMS_READY.signal();
+        }
+
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn semaphores_bound_a_producer_and_its_consumer() {
+        use threading::{sync::CondVar, ThreadStatus};
+
+        // signaled before anyone waits, the wait doesn't block
+        let semaphore = threading::sync::Semaphore::new(0);
+        semaphore.signal();
+        semaphore.wait();
+        assert!(!semaphore.try_wait());
+        assert!(!CondVar::new().notify_one());
+
+        let (_, tid) = spawn_test_thread(producer_thread, "producer");
+        let status = || without_interrupts(|| scheduler().thread_status(tid));
+
+        // nobody consumes, the producer fills the buffer then is off the ready queue
+        timer::sleep(Duration::from_millis(50));
+        assert_eq!(PRODUCED.load(Ordering::SeqCst), BUFFER_SLOTS);
+        assert_eq!(status(), Some(ThreadStatus::Blocked));
+
+        for expected in 1..=ITEMS {
+            ITEMS_READY.wait();
+            let item = BUFFER.lock().pop_front().unwrap();
+            assert_eq!(item, expected);
+            SLOTS.signal();
+        }
+
+        assert_eq!(PRODUCED.load(Ordering::SeqCst), ITEMS);
+        assert_eq!(SLOTS.count(), BUFFER_SLOTS);
+        assert_eq!(ITEMS_READY.count(), 0);
+    }
+
+    #[test_case]
+    fn wxaudit_finds_only_the_writable_and_executable_pages() {
+        use crate::memory::{
+            frame_allocator::Frame,
+            paging::{current_root_table, EntryFlags, Page, PAGE_SIZE},
+            wxaudit, VirtRange,
+        };
+
+        let table = unsafe { current_root_table() };
+        let window = kernel()
+            .virt_allocator()
+            .reserve(4 * PAGE_SIZE, PAGE_SIZE)
+            .unwrap();
+        let window_range = VirtRange::from_len(window, 4 * PAGE_SIZE);
+
+        let writable = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        // two adjacent writable and executable pages, a writable one which isn't executable and
+        // an executable one which isn't writable
+        let flags = [
+            writable,
+            writable,
+            writable | EntryFlags::NO_EXECUTE,
+            EntryFlags::PRESENT,
+        ];
+        let frames: [Frame; 4] =
+            flags.map(|_| kernel().frame_allocator().allocate_frame().unwrap());
+        for (i, (&flags, &frame)) in flags.iter().zip(&frames).enumerate() {
+            table
+                .map_to(
+                    Page::containing_address(window + i * PAGE_SIZE),
+                    frame,
+                    flags,
+                )
+                .unwrap();
+        }
+
+        let found: Vec<VirtRange> = wxaudit::writable_executable(table)
+            .iter()
+            .filter_map(|range| range.intersect(&window_range))
+            .collect();
+        let expected = if wxaudit::nx_enabled() { 2 } else { 3 };
+        assert_eq!(found, [VirtRange::from_len(window, expected * PAGE_SIZE)]);
+        assert_eq!(wxaudit::audit(table, window_range, false), 1);
+
+        for (i, &frame) in frames.iter().enumerate() {
+            let page = Page::containing_address(window + i * PAGE_SIZE);
+            assert_eq!(table.unmap(page), Some(frame));
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+        assert_eq!(wxaudit::audit(table, window_range, true), 0);
+        kernel().virt_allocator().release(window, 4 * PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn com1_transmits_through_its_interrupt() {
+        use crate::time::{Duration, Instant};
+
+        assert!(COM1.is_interrupt_driven());
+        assert!(!COM2.is_interrupt_driven());
+
+        // nothing drains the ring with interrupts disabled
+        unsafe { asm!("cli") };
+        COM1.write_string("queued for the transmit interrupt\n");
+        assert!(COM1.transmit_pending());
+        unsafe { asm!("sti") };
+
+        let start = Instant::now();
+        while COM1.transmit_pending() {
+            assert!(
+                start.elapsed() < Duration::from_millis(100),
+                "the transmit interrupt never came"
+            );
+            unsafe { asm!("hlt") };
+        }
+
+        // more than the ring holds with interrupts disabled, the writer drains it itself
+        unsafe { asm!("cli") };
+        for _ in 0..serial::TRANSMIT_RING_SIZE / 32 + 1 {
+            COM1.write_string("...............................\n");
+        }
+        unsafe { asm!("sti") };
+        COM1.synchronous();
+        assert!(!COM1.transmit_pending() && !COM1.is_interrupt_driven());
+        COM1.enable_transmit_interrupt();
+    }
+
+    #[test_case]
+    fn enable_features_sets_the_bits_of_what_cpuid_has() {
+        use crate::arch::x86_64::{
+            cpu::{self, CpuFeatures},
+            pku,
+        };
+
+        let enabled = cpu::enabled();
+        assert_eq!(enabled, cpu::features());
+        // x86_64 always has sse
+        assert!(enabled.contains(CpuFeatures::SSE));
+
+        let cr4 = cpu::read_cr4();
+        for (feature, bit) in [
+            (CpuFeatures::PGE, 7),
+            (CpuFeatures::SSE, 9),
+            (CpuFeatures::SSE, 10),
+            (CpuFeatures::SMEP, 20),
+            (CpuFeatures::SMAP, 21),
+            (CpuFeatures::PKU, 22),
+        ] {
+            assert_eq!(
+                enabled.contains(feature),
+                cr4 & (1 << bit) != 0,
+                "{:?}",
+                feature
+            );
+        }
+        // CR0.EM and CR0.TS are cleared for the fpu
+        assert_eq!(cpu::read_cr0() & 0b1100, 0);
+        assert_eq!(
+            enabled.contains(CpuFeatures::NX),
+            crate::memory::wxaudit::nx_enabled()
+        );
+        assert_eq!(enabled.contains(CpuFeatures::PKU), pku::enabled());
+    }
+
+    #[test_case]
+    fn user_copies_fail_on_bad_pointers_instead_of_faulting() {
+        use crate::{
+            arch::threading::CPUStatus,
+            memory::{
+                paging::{EntryFlags, Page, PAGE_SIZE},
+                user,
+            },
+            syscalls,
+        };
+
+        // a process' address space, its lower half is only for the user pages
+        let mut pml4 = TestPml4::new();
+        pml4.load(|table| {
+            let addr = VirtAddr::new(0x0000_3000_0000_0000);
+            let page = Page::containing_address(addr);
+            let frame = kernel().frame_allocator().allocate_frame().unwrap();
+            let flags = EntryFlags::PRESENT
+                | EntryFlags::WRITABLE
+                | EntryFlags::USER_ACCESSIBLE
+                | EntryFlags::NO_EXECUTE;
+            table.map_to(page, frame, flags).unwrap();
+
+            let message = *b"hello from the kernel";
+            let end = addr + PAGE_SIZE - message.len();
+            user::copy_to_user(end, &message).unwrap();
+            let mut copied = [0; 21];
+            user::copy_from_user(&mut copied, end).unwrap();
+            assert_eq!(copied, message);
+
+            // the next page isn't mapped, the copy faults half way
+            assert_eq!(user::copy_from_user(&mut copied, end + 1), Err(()));
+            assert_eq!(user::copy_to_user(end + 1, &message), Err(()));
+            assert_eq!(user::copy_from_user(&mut copied, VirtAddr::new(0)), Err(()));
+            // the kernel isn't user memory
+            let kernel_addr = VirtAddr::from_ptr(&message);
+            assert_eq!(user::copy_from_user(&mut copied, kernel_addr), Err(()));
+            assert!(user::validate_user_range(user::USER_END - 1, 2).is_err());
+            assert!(user::validate_user_range(VirtAddr::new(usize::MAX), 2).is_err());
+
+            let mut context = CPUStatus::default();
+            context.rax = syscalls::SYS_WRITE;
+            context.rdi = syscalls::STDOUT;
+            context.rsi = (end + 10).as_u64();
+            context.rdx = 100;
+            syscalls::handle(&mut context);
+            // the bytes before the unmapped page aren't a whole chunk so none are written
+            assert_eq!(context.rax, syscalls::SYSCALL_FAILED);
+            context.rax = syscalls::SYS_WRITE;
+            context.rdx = message.len() as u64 - 10;
+            syscalls::handle(&mut context);
+            assert_eq!(context.rax, message.len() as u64 - 10);
+
+            assert_eq!(table.unmap(page), Some(frame));
+            kernel().frame_allocator().deallocate_frame(frame);
+            assert_eq!(user::copy_from_user(&mut copied, end), Err(()));
+        });
+    }
+
+    #[test_case]
+    fn a_bad_pointer_passed_to_write_goes_through_the_exception_table() {
+        use crate::{
+            arch::{threading::CPUStatus, x86_64::extable},
+            syscalls,
+        };
+
+        // the user copy is the only instruction allowed to fault for now
+        let entries = extable::entries();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0];
+        assert_eq!(
+            extable::search(VirtAddr::new(entry.fault as usize)),
+            Some(VirtAddr::new(entry.fixup as usize))
+        );
+        assert_eq!(extable::search(VirtAddr::new(entry.fixup as usize)), None);
+        let faults = crate::arch::x86_64::interrupts::interrupt_count(14);
+
+        // in the lower half so only the copy can tell it isn't mapped
+        let mut context = CPUStatus::default();
+        context.rax = syscalls::SYS_WRITE;
+        context.rdi = syscalls::STDOUT;
+        context.rsi = 0x1000;
+        context.rdx = 16;
+        syscalls::handle(&mut context);
+        assert_eq!(context.rax, syscalls::SYSCALL_FAILED);
+        assert_eq!(
+            crate::arch::x86_64::interrupts::interrupt_count(14),
+            faults + 1
+        );
+    }
+
+    static KEY_READ: AtomicU8 = AtomicU8::new(0);
+
+    fn keyboard_reader_thread() {
+        use crate::drivers::{chardev::CharDevice, keyboard::KeyboardInput};
+
+        KEY_READ.store(KeyboardInput.wait_byte(), Ordering::SeqCst);
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn the_idle_thread_runs_only_while_nothing_is_ready() {
+        use crate::{
+            arch::threading::CPUStatus,
+            drivers::keyboard,
+            threading::{ThreadStatus, IDLE_TID},
+        };
+
+        // every ready thread then this one block so the switch can only go to the idle thread,
+        // this thread doesn't actually leave and everything is unblocked again before interrupts
+        // are enabled
+        let tid = threading::current_thread().unwrap();
+        let idle_switches = scheduler().idle_switches();
+        without_interrupts(|| unsafe {
+            let scheduler = scheduler();
+            let mut blocked = Vec::new();
+            let mut thread = Some(&mut *scheduler.head);
+            while let Some(current) = thread {
+                if current.status == ThreadStatus::Waiting {
+                    current.status = ThreadStatus::Blocked;
+                    blocked.push(current.tid);
+                }
+                thread = current.next.as_deref_mut();
+            }
+            scheduler.block_current();
+
+            // the context of the idle thread, switching back saves it as it was
+            let context = scheduler.switch(CPUStatus::default());
+            assert_eq!(threading::current_thread(), Some(IDLE_TID));
+            assert_eq!(scheduler.idle_switches(), idle_switches + 1);
+            // nothing to preempt it with
+            assert!(!scheduler.tick());
+
+            assert!(scheduler.unblock(tid));
+            assert!(scheduler.tick());
+            scheduler.switch(context);
+            assert_eq!(threading::current_thread(), Some(tid));
+
+            for tid in blocked {
+                assert!(scheduler.unblock(tid));
+            }
+        });
+        assert_eq!(scheduler().idle_switches(), idle_switches + 1);
+
+        // a thread reading the keyboard is off the ready queue until a key is typed, the shell
+        // doesn't read it until the terminal leaves init mode
+        KEY_READ.store(0, Ordering::SeqCst);
+        let (_, tid) = spawn_test_thread(keyboard_reader_thread, "keyboard reader");
+        let status = || without_interrupts(|| scheduler().thread_status(tid));
+
+        timer::sleep(Duration::from_millis(50));
+        assert_eq!(status(), Some(ThreadStatus::Blocked));
+        keyboard::push_char('k');
+        timer::sleep(Duration::from_millis(50));
+        assert_eq!(KEY_READ.load(Ordering::SeqCst), b'k');
+        assert_ne!(status(), Some(ThreadStatus::Blocked));
+    }
+
+    #[test_case]
+    fn entries_display_their_raw_bits_next_to_the_decoded_ones() {
+        let empty = Entry::new(EntryFlags::empty(), PhysAddr::new(0));
+        assert_eq!(empty.raw(), 0);
+        assert_eq!(empty.to_string(), "0x0000000000000000 (empty)");
+
+        let flags = EntryFlags::PRESENT
+            | EntryFlags::WRITABLE
+            | EntryFlags::ACCESSED
+            | EntryFlags::DIRTY
+            | EntryFlags::NO_EXECUTE;
+        let entry = Entry::new(flags, PhysAddr::new(0x1000));
+        assert_eq!(entry.raw(), 0x8000_0000_0000_1063);
+        assert_eq!(
+            entry.to_string(),
+            "0x8000000000001063 P RW -- -- -- A D - - NX addr=0x1000"
+        );
+
+        // an entry that isn't present but isn't empty either still shows what is in it
+        let stale = Entry::new(EntryFlags::WRITABLE, PhysAddr::new(0x2000));
+        assert_eq!(
+            stale.to_string(),
+            "0x0000000000002002 - RW -- -- -- - - - - -- addr=0x2000"
+        );
+    }
+
+    #[test_case]
+    fn the_heap_stays_in_its_region_of_the_higher_half() {
+        let stats = global_allocator().lock().stats();
+        assert_eq!(stats.heap_start, layout::HEAP.start().as_usize());
+        assert_eq!(stats.heap_limit, layout::HEAP.end().as_usize());
+
+        // the physmap of the bootloader is never where the heap goes
+        let physmap =
+            VirtRange::from_len(VirtAddr::new(crate::limine::get_phy_offset()), PAGE_SIZE);
+        assert!(!physmap.overlaps(&layout::HEAP));
+        assert_eq!(layout::heap(&[physmap]), Ok(layout::HEAP));
+        let overlapping = VirtRange::from_len(layout::HEAP.end() - PAGE_SIZE, 2 * PAGE_SIZE);
+        assert_eq!(layout::heap(&[physmap, overlapping]), Err(()));
+
+        // a heap doesn't extend past its limit
+        let start = kernel()
+            .virt_allocator()
+            .reserve(4 * PAGE_SIZE, PAGE_SIZE)
+            .unwrap();
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        unsafe { current_root_table() }
+            .map_to_writeable(Page::containing_address(start), frame)
+            .unwrap();
+
+        let mut allocator = LinkedListAllocator::new();
+        unsafe { allocator.init(start.as_usize(), PAGE_SIZE, HeapGrowth::Fixed(1)) }.unwrap();
+        allocator.set_heap_limit((start + 2 * PAGE_SIZE).as_usize());
+        assert!(allocator.extend_heap().is_ok());
+        assert!(allocator.extend_heap().is_err());
+        assert_eq!(allocator.heap_end, (start + 2 * PAGE_SIZE).as_usize());
+
+        for i in 0..2 {
+            let page = Page::containing_address(start) + i;
+            let frame = unsafe { current_root_table() }.unmap(page).unwrap();
+            kernel().frame_allocator().deallocate_frame(frame);
+        }
+        kernel().virt_allocator().release(start, 4 * PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn iter_huge_steps_by_the_huge_page_size() {
+        let start = Page::containing_address(VirtAddr::new(HUGE_PAGE_2MIB));
+        let end = Page::containing_address(VirtAddr::new(4 * HUGE_PAGE_2MIB));
+        let pages: Vec<Page> = Page::iter_huge(start, end, HugePageSize::Size2MiB)
+            .unwrap()
+            .collect();
+        assert_eq!(pages.len(), 4);
+        assert!(pages
+            .iter()
+            .enumerate()
+            .all(|(i, page)| page.start_address == VirtAddr::new((i + 1) * HUGE_PAGE_2MIB)));
+
+        let mut iter = Page::iter_huge(start, end, HugePageSize::Size2MiB).unwrap();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next_back(), Some(end));
+        assert_eq!(iter.next(), Some(start));
+        assert_eq!(iter.len(), 2);
+
+        // the last 1GiB pages of the address space
+        let last = Page::containing_address(VirtAddr::new(usize::MAX).align_down(HUGE_PAGE_1GIB));
+        let first = Page::containing_address(last.start_address - 2 * HUGE_PAGE_1GIB);
+        let mut iter = Page::iter_huge(first, last, HugePageSize::Size1GiB).unwrap();
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.next_back(), Some(last));
+        assert_eq!(iter.collect::<Vec<Page>>().len(), 2);
+
+        // misaligned bounds
+        let misaligned = Page::containing_address(VirtAddr::new(HUGE_PAGE_2MIB + PAGE_SIZE));
+        assert!(Page::iter_huge(misaligned, end, HugePageSize::Size2MiB).is_none());
+        assert!(Page::iter_huge(start, misaligned, HugePageSize::Size2MiB).is_none());
+        assert!(Page::iter_huge(start, end, HugePageSize::Size1GiB).is_none());
+    }
+
+    #[test_case]
+    fn the_tables_of_the_higher_half_are_shared() {
+        let current = unsafe { current_root_table() };
+        let current_frame = Frame::containing_address(virt_to_phys(VirtAddr::from_ptr(current)));
+        assert!(paging::is_shared_table(current_frame));
+        assert!(current.entries[256..]
+            .iter()
+            .filter_map(|entry| entry.frame())
+            .all(paging::is_shared_table));
+
+        // a process' pml4 and the tables of its lower half aren't
+        let mut pml4 = TestPml4::new();
+        assert!(!paging::is_shared_table(Frame::containing_address(pml4.0)));
+        let table = pml4.table();
+        let frame = kernel().frame_allocator().allocate_frame().unwrap();
+        table
+            .map_to_writeable(Page::containing_address(VirtAddr::new(0x4000_0000)), frame)
+            .unwrap();
+        assert!(!paging::is_shared_table(table.entries[0].frame().unwrap()));
+        assert!(!paging::is_shared_table(frame));
+
+        let used_frames = kernel().frame_allocator().used_frames();
+        drop(pml4);
+        // the frame, the 3 tables mapping it and the pml4
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames - 5);
+    }
+
+    static RAW_READ: Locked<Option<RawKeyEvent>> = Locked::new(None);
+
+    fn raw_reader_thread() {
+        let event = keyboard::wait_raw();
+        *RAW_READ.lock() = Some(event);
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn raw_mode_puts_scancodes_together_without_the_keymap() {
+        let feed = |bytes: &[u8]| {
+            without_interrupts(|| {
+                for &byte in bytes {
+                    keyboard::handle_byte(byte);
+                }
+            })
+        };
+        let event = |scancode, released| RawKeyEvent { scancode, released };
+
+        keyboard::set_raw(true);
+        while keyboard::read_raw().is_some() {}
+        assert!(keyboard::is_raw());
+
+        // a and up pressed and released then pause, its 6 bytes are a single press
+        feed(&[
+            0x1E, 0x9E, 0xE0, 0x48, 0xE0, 0xC8, 0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5,
+        ]);
+        assert_eq!(keyboard::read_raw(), Some(event(0x1E, false)));
+        assert_eq!(keyboard::read_raw(), Some(event(0x1E, true)));
+        assert_eq!(keyboard::read_raw(), Some(event(0x48E0, false)));
+        assert_eq!(keyboard::read_raw(), Some(event(0x48E0, true)));
+        assert_eq!(keyboard::read_raw(), Some(event(0xC59D_E145_1DE1, false)));
+        assert_eq!(keyboard::read_raw(), None);
+
+        // cooked mode never saw the key
+        feed(&[0x1E]);
+        assert!(!KeyCode::KeyA.is_pressed());
+        assert_eq!(keyboard::wait_raw(), event(0x1E, false));
+
+        // a scancode cut in half by leaving raw mode doesn't stick to the next one
+        feed(&[0xE0]);
+        keyboard::set_raw(false);
+        assert!(!keyboard::is_raw());
+        keyboard::set_raw(true);
+        feed(&[0x9E]);
+        assert_eq!(keyboard::read_raw(), Some(event(0x1E, true)));
+
+        // a reader is off the ready queue until an event comes in
+        let (_, tid) = spawn_test_thread(raw_reader_thread, "raw reader");
+        let status = || without_interrupts(|| scheduler().thread_status(tid));
+        timer::sleep(Duration::from_millis(50));
+        assert_eq!(status(), Some(threading::ThreadStatus::Blocked));
+        feed(&[0x1E]);
+        timer::sleep(Duration::from_millis(50));
+        assert_eq!(RAW_READ.lock().take(), Some(event(0x1E, false)));
+        keyboard::set_raw(false);
+    }
+
+    #[test_case]
+    fn freed_then_reallocated_memory_shows_the_poison_not_stale_data() {
+        use crate::memory::allocator::{ALLOC_POISON, FREE_POISON};
+        use core::mem::size_of;
+
+        let poison = cfg!(feature = "heap-poison");
+        let mut buffer = NodeBuffer([0; 256]);
+        let mut allocator = LinkedListAllocator::new();
+        unsafe { allocator.init(buffer.0.as_mut_ptr() as usize, 256, HeapGrowth::Fixed(0)) }
+            .unwrap();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let bytes = |ptr: *mut u8| unsafe { core::slice::from_raw_parts(ptr, 64) };
+
+        let ptr = unsafe { allocator.alloc_mut(layout) };
+        assert!(!ptr.is_null());
+        if poison {
+            assert!(bytes(ptr).iter().all(|&byte| byte == ALLOC_POISON));
+        }
+        unsafe { ptr.write_bytes(0x55, 64) };
+
+        // the node is written after the poison
+        unsafe { allocator.dealloc_mut(ptr, layout) };
+        let expected = if poison { FREE_POISON } else { 0x55 };
+        assert!(bytes(ptr)[size_of::<Node>()..]
+            .iter()
+            .all(|&byte| byte == expected));
+        allocator.check_integrity();
+
+        // the block freed last is the first one found again
+        let again = unsafe { allocator.alloc_mut(layout) };
+        assert_eq!(again, ptr);
+        let expected = if poison { ALLOC_POISON } else { 0x55 };
+        assert!(bytes(again)[size_of::<Node>()..]
+            .iter()
+            .all(|&byte| byte == expected));
+        if poison {
+            assert!(bytes(again).iter().all(|&byte| byte == ALLOC_POISON));
+        }
+    }
+
+    #[test_case]
+    fn mmio_registers_are_accessed_at_their_offset_and_width() {
+        use crate::memory::mmio::{RegBlock, RegOffset};
+
+        const FIRST: RegOffset<u32> = RegOffset::new(0);
+        const SECOND: RegOffset<u16> = RegOffset::new(4);
+        const WIDE: RegOffset<u64> = RegOffset::new(8);
+
+        let mut registers = [0u64; 2];
+        let registers = registers.as_mut_ptr();
+        let block = unsafe { RegBlock::new(VirtAddr::from_ptr(registers)) };
+        block.write(FIRST, 0xDEAD_BEEF);
+        block.write(SECOND, 0x1234);
+        block.write(WIDE, u64::MAX - 1);
+
+        assert_eq!(block.read(FIRST), 0xDEAD_BEEF);
+        assert_eq!(block.reg(SECOND).addr(), block.base() + 4);
+        // the u16 didn't touch the 2 bytes after it
+        assert_eq!(unsafe { registers.read_volatile() }, 0x0000_1234_DEAD_BEEF);
+        assert_eq!(unsafe { registers.add(1).read_volatile() }, u64::MAX - 1);
+        unsafe { registers.add(1).write_volatile(7) };
+        assert_eq!(block.read(WIDE), 7);
+
+        // the local apic id is in the top byte of its register, it is the one the madt gives
+        // the boot cpu
+        assert_eq!(apic::local_apic().base(), apic::get_local_apic_addr());
+        let id = apic::local_apic_id();
+        assert!(
+            apic::madt_local_apic_ids().any(|madt_id| madt_id == id),
+            "the local apic id {} isn't in the madt",
+            id
+        );
+    }
+
+    #[test_case]
+    fn the_idt_the_cpu_uses_is_ours() {
+        let idtr = idt::loaded();
+        assert_eq!(idtr, *idt::IDTDesc);
+        assert_eq!(idtr.limit() as usize, 256 * 16 - 1);
+        assert_eq!(
+            idtr.base(),
+            crate::arch::x86_64::interrupts::handlers::IDT
+                .lock()
+                .as_ptr() as usize
+        );
+
+        // loading it again changes nothing and breakpoints still reach our handler
+        idt::load();
+        assert_eq!(idt::loaded(), idtr);
+        let breakpoints = interrupt_count(3);
+        unsafe { asm!("int3") };
+        assert_eq!(interrupt_count(3), breakpoints + 1);
+    }
+
+    #[test_case]
+    fn rebalancing_the_ready_queues_doesnt_allocate() {
+        use crate::threading::priority::{ReadyQueues, HIGHEST_PRIORITY};
+
+        let mut ready: ReadyQueues<usize> = ReadyQueues::new();
+        ready.reserve(6);
+
+        // every item goes back to level item % 2, like a boost moving threads to their base
+        without_interrupts(|| {
+            let free = global_allocator().lock().free_bytes();
+            for item in 0..6 {
+                ready.push(LOWEST_PRIORITY - item % 3, item);
+            }
+            ready.rebalance(|item| item % 2);
+            assert_eq!(global_allocator().lock().free_bytes(), free);
+        });
+
+        assert_eq!(ready.highest_ready(), Some(HIGHEST_PRIORITY));
+        let mut popped = Vec::new();
+        while let Some(item) = ready.pop() {
+            popped.push(item);
+        }
+        assert_eq!(popped, [(0, 2), (0, 4), (0, 0), (1, 5), (1, 1), (1, 3)]);
+    }
+
+    const CONDVAR_ITEMS: usize = 4;
+    static CONDVAR_QUEUE: Locked<alloc::collections::VecDeque<usize>> =
+        Locked::new(alloc::collections::VecDeque::new());
+    static CONDVAR_CHANGED: threading::sync::CondVar = threading::sync::CondVar::new();
+    /// the notifies that woke the consumer
+    static CONDVAR_WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn condvar_producer_thread() {
+        for item in 1..=CONDVAR_ITEMS {
+            // long enough for the consumer to be waiting
+            timer::sleep(Duration::from_millis(10));
+            CONDVAR_QUEUE.lock().push_back(item);
+            if CONDVAR_CHANGED.notify_one() {
+                CONDVAR_WOKEN.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        exit_test_thread();
+    }
+
+    #[test_case]
+    fn condvars_wake_a_consumer_waiting_on_a_locked_queue() {
+        let consume = || {
+            let mut queue = CONDVAR_QUEUE.lock();
+            while queue.is_empty() {
+                queue = CONDVAR_CHANGED.wait(&CONDVAR_QUEUE, queue);
+            }
+            queue.pop_front().unwrap()
+        };
+
+        // notified before anyone waits, the notify is lost but the consumer sees the item and
+        // doesn't block
+        CONDVAR_QUEUE.lock().push_back(0);
+        assert!(!CONDVAR_CHANGED.notify_one());
+        assert_eq!(consume(), 0);
+
+        spawn_test_thread(condvar_producer_thread, "condvar producer");
+        for expected in 1..=CONDVAR_ITEMS {
+            assert_eq!(consume(), expected);
+        }
+
+        assert!(CONDVAR_QUEUE.lock().is_empty());
+        assert!(CONDVAR_WOKEN.load(Ordering::SeqCst) > 0);
+        assert_eq!(CONDVAR_CHANGED.notify_all(), 0);
+    }
+
+    #[test_case]
+    fn elf_segments_sharing_a_page_are_loaded_into_one() {
+        use crate::utils::elf::{
+            Elf, ElfClass, ElfHeader, ElfIEndianness, ElfInstrSet, ElfType, ProgramHeader,
+            ProgramType,
+        };
+        use core::mem::size_of;
+
+        const TEXT: usize = 0x40_0000;
+        const DATA: usize = 0x40_0800;
+        let segment = |vaddr: usize, offset: usize, mem_size: usize, flags: u32| ProgramHeader {
+            program_type: ProgramType::LOAD,
+            flags,
+            offset,
+            vaddr: VirtAddr::new(vaddr),
+            paddr: vaddr,
+            file_size: 4,
+            mem_size,
+            alignment: PAGE_SIZE,
+        };
+        let header = ElfHeader {
+            magic: *b"\x7FELF",
+            class: ElfClass::ELF64,
+            endianness: ElfIEndianness::LITTLE,
+            version: 1,
+            _osabi: 0,
+            _abiver: 0,
+            _padding: [0; 7],
+            kind: ElfType::EXE,
+            insturction_set: ElfInstrSet::AMD64,
+            version_2: 1,
+            entry_point: VirtAddr::new(TEXT),
+            program_header_offset: size_of::<ElfHeader>(),
+            section_header_table_offset: 0,
+            flags: 0,
+            size: size_of::<ElfHeader>() as u16,
+            program_header_entry_size: size_of::<ProgramHeader>() as u16,
+            program_header_entries: 2,
+            section_table_entry_size: 0,
+            section_table_entries: 0,
+            sections_names_section_offset: 0,
+        };
+        let data_offset = size_of::<ElfHeader>() + 2 * size_of::<ProgramHeader>();
+        // the text ends in the page the data starts in, the data goes on into the next page
+        let segments = [
+            segment(TEXT, data_offset, 0x100, ProgramHeader::FLAG_EXECUTABLE),
+            segment(
+                DATA,
+                data_offset + 4,
+                PAGE_SIZE,
+                ProgramHeader::FLAG_WRITABLE,
+            ),
+        ];
+
+        // one byte in so none of the headers are aligned
+        let mut file = vec![0u8; 1 + data_offset + 8];
+        unsafe {
+            let base = file.as_mut_ptr().add(1);
+            base.cast::<ElfHeader>().write_unaligned(header);
+            for (index, segment) in segments.iter().enumerate() {
+                base.add(size_of::<ElfHeader>() + index * size_of::<ProgramHeader>())
+                    .cast::<ProgramHeader>()
+                    .write_unaligned(*segment);
+            }
+        }
+        file[1 + data_offset..].copy_from_slice(b"textdata");
+
+        let elf = Elf::parse(&file[1]).unwrap();
+        assert_eq!(elf.program_headers().count(), 2);
+
+        let used_frames = kernel().frame_allocator().used_frames();
+        let mut pml4 = TestPml4::new();
+        let table = pml4.table();
+        assert_eq!(elf.load(table).unwrap(), VirtAddr::new(TEXT));
+
+        let read = |addr: usize| {
+            let phys = table.translate_addr(VirtAddr::new(addr)).unwrap();
+            unsafe { *phys_to_virt(phys).as_ptr::<[u8; 4]>() }
+        };
+        assert_eq!(&read(TEXT), b"text");
+        assert_eq!(&read(DATA), b"data");
+        assert_eq!(read(DATA + PAGE_SIZE), [0; 4]);
+
+        let mut flags = |addr: usize| {
+            table
+                .get_entry(Page::containing_address(VirtAddr::new(addr)))
+                .unwrap()
+                .flags()
+        };
+        // the shared page has the flags of both
+        let shared = flags(TEXT);
+        assert!(shared.contains(EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE));
+        assert!(!shared.contains(EntryFlags::NO_EXECUTE));
+        let data = flags(DATA + PAGE_SIZE);
+        assert!(data
+            .contains(EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE));
+
+        // two frames and the tables, nothing left over
+        drop(pml4);
+        assert_eq!(kernel().frame_allocator().used_frames(), used_frames);
+    }
+
+    const USERMODE_CODE: usize = 0x40_0000;
+    /// the user stack is the page right after the code
+    const USERMODE_STACK_END: usize = USERMODE_CODE + 2 * PAGE_SIZE;
+    const USERMODE_ARG: u64 = 0x5EED;
+
+    /// enters ring 3 running code that pushes its argument, what a failing `SYS_WRITE` returned
+    /// and its cs then spins until it is killed
+    fn usermode_thread() {
+        use crate::arch::x86_64::interrupts::{jump_to_usermode, InterruptFrame};
+
+        #[rustfmt::skip]
+        const CODE: [u8; 15] = [
+            0x57,                         // push rdi
+            0xB8, 0x02, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE (to the fd in rdi, which fails)
+            0xCD, 0x80,                   // int 0x80
+            0x50,                         // push rax
+            0x48, 0x8C, 0xC8,             // mov rax, cs
+            0x50,                         // push rax
+            0xEB, 0xFE,                   // jmp $
+        ];
+
+        let table = unsafe { current_root_table() };
+        let user = EntryFlags::PRESENT | EntryFlags::USER_ACCESSIBLE;
+        let code = kernel().frame_allocator().allocate_frame().unwrap();
+        let stack = kernel().frame_allocator().allocate_frame().unwrap();
+        unsafe {
+            let code_ptr = phys_to_virt(code.start_address()).as_mut_ptr::<u8>();
+            code_ptr.write_bytes(0, PAGE_SIZE);
+            code_ptr.copy_from_nonoverlapping(CODE.as_ptr(), CODE.len());
+            phys_to_virt(stack.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, PAGE_SIZE);
+        }
+        let code_page = Page::containing_address(VirtAddr::new(USERMODE_CODE));
+        let stack_page = Page::containing_address(VirtAddr::new(USERMODE_STACK_END - PAGE_SIZE));
+        table.map_to(code_page, code, user).unwrap();
+        table
+            .map_to(
+                stack_page,
+                stack,
+                user | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            )
+            .unwrap();
+
+        let mut frame = InterruptFrame::new();
+        frame.set_entry(
+            VirtAddr::new(USERMODE_CODE),
+            VirtAddr::new(USERMODE_STACK_END),
+        );
+        unsafe { jump_to_usermode(&frame, USERMODE_ARG) }
+    }
+
+    #[test_case]
+    fn threads_enter_ring_3_and_come_back_through_syscalls() {
+        use crate::arch::x86_64::gdt::{self, USER_CODE_SELECTOR};
+
+        let mut context = crate::arch::threading::CPUStatus::default();
+        context.set_user_entry(VirtAddr::new(USERMODE_CODE), VirtAddr::new(0x1000), 7);
+        assert_eq!(context.cs, USER_CODE_SELECTOR as u64);
+        assert_eq!(
+            (context.rip, context.rsp, context.rdi),
+            (0x40_0000, 0x1000, 7)
+        );
+
+        let (pid, _) = spawn_test_thread(usermode_thread, "usermode");
+        // what the user code pushed, read through the physmap since it is in another address
+        // space
+        let pushed = || {
+            without_interrupts(|| {
+                let root = scheduler().processes[&pid].root_page_table;
+                let table = unsafe { &*phys_to_virt(root).as_ptr::<PageTable>() };
+                let top = VirtAddr::new(USERMODE_STACK_END - 3 * 8);
+                table
+                    .translate_addr(top)
+                    .map(|phys| unsafe { *phys_to_virt(phys).as_ptr::<[u64; 3]>() })
+            })
+        };
+
+        let start = Instant::now();
+        while pushed().is_none_or(|pushed| pushed[0] == 0) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "the user code never made it past the syscall"
+            );
+            threading::wait_for_interrupt();
+        }
+
+        // pushed in reverse: cs, the syscall result then the argument
+        let [cs, result, arg] = pushed().unwrap();
+        assert_eq!(cs, USER_CODE_SELECTOR as u64);
+        assert_eq!(result, crate::syscalls::SYSCALL_FAILED);
+        assert_eq!(arg, USERMODE_ARG);
+
+        // the last switch pointed rsp0 at the stack of this thread
+        assert_eq!(
+            gdt::kernel_stack(),
+            VirtAddr::from_ptr(unsafe { (*scheduler().current_thread).stack_end })
+        );
+
+        without_interrupts(|| scheduler().pkill(pid)).unwrap();
+    }
 }
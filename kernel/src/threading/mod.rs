@@ -1,16 +1,45 @@
-use core::{alloc::Layout, arch::asm};
+pub mod priority;
+pub mod process;
+pub mod softirq;
+pub mod sync;
+pub mod timer;
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec::Vec};
 
 use crate::{
-    arch::threading::CPUStatus,
-    global_allocator, kernel,
-    memory::paging::{allocate_pml4, PageTable},
-    serial, VirtAddr,
+    arch::{fpu::FpuState, threading::CPUStatus, without_interrupts},
+    drivers::vfs::{vfs, FSError, FS},
+    kernel, log,
+    memory::{
+        paging::{
+            allocate_pml4, EntryFlags, MapToError, Page, PageTable, PAGE_SIZE, PAGE_TABLE_LEVELS,
+        },
+        phys_to_virt, vmm, wxaudit, PhysAddr,
+    },
+    scheduler, scheduler_inited,
+    time::{Duration, Instant},
+    utils::elf::{Elf, ElfError},
+    VirtAddr,
 };
 
+use priority::{time_slice, Priority, ReadyQueues, BOOST_TICKS, HIGHEST_PRIORITY, LOWEST_PRIORITY};
+use process::{Pid, Process};
+
+pub type Tid = u64;
+
+/// the tid of the idle thread, it isn't in the thread list nor in any process
+pub const IDLE_TID: Tid = Tid::MAX;
+
 pub const STACK_SIZE: usize = 4096 * 4;
-pub const STACK_LAYOUT: Layout = Layout::new::<[u8; STACK_SIZE]>();
+
+/// where the stack of the main thread of an elf process ends in its address space
+pub const USER_STACK_END: VirtAddr = VirtAddr::new(0x7FFF_FFFF_F000);
+pub const USER_STACK_SIZE: usize = 4096 * 16;
 
 /// helper function to work with `name` in Process
 fn trim_trailing_zeros(slice: &[u8]) -> &[u8] {
@@ -23,48 +52,142 @@ fn trim_trailing_zeros(slice: &[u8]) -> &[u8] {
 
 /// returns a pointer to the end of the stack
 pub fn alloc_stack() -> VirtAddr {
+    let stack_start = vmm::alloc_pages(STACK_SIZE / PAGE_SIZE).expect("failed to allocate a stack");
+    stack_start + STACK_SIZE
+}
+
+/// maps the `USER_STACK_SIZE` bytes of zeroed user pages ending at `USER_STACK_END` into
+/// `table` returning where the stack ends, nothing is left mapped if it fails
+fn map_user_stack(table: &mut PageTable) -> Result<VirtAddr, MapToError> {
+    let flags = EntryFlags::PRESENT
+        | EntryFlags::WRITABLE
+        | EntryFlags::USER_ACCESSIBLE
+        | EntryFlags::NO_EXECUTE;
+    let start = USER_STACK_END - USER_STACK_SIZE;
+
+    let mut transaction = table.begin_mapping();
+    for offset in (0..USER_STACK_SIZE).step_by(PAGE_SIZE) {
+        let frame = transaction.map_new(Page::containing_address(start + offset), flags)?;
+        unsafe {
+            phys_to_virt(frame.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, PAGE_SIZE)
+        };
+    }
+
+    transaction.commit();
+    Ok(USER_STACK_END)
+}
+
+/// set when the current thread should be switched away from, the switch happens right before
+/// returning from the timer interrupt or a syscall (see `arch::threading`) so no handler is left
+/// halfway on the stack of another thread, there is only one since only one cpu runs threads
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+/// switches away from the current thread the next time an interrupt that can switch returns
+#[inline]
+pub fn set_need_resched() {
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn need_resched() -> bool {
+    NEED_RESCHED.load(Ordering::Relaxed)
+}
+
+/// clears `need_resched` returning it, called by the return path that does the switch
+#[inline]
+pub fn take_need_resched() -> bool {
+    NEED_RESCHED.swap(false, Ordering::Relaxed)
+}
+
+/// the tid of the running thread, None before the scheduler runs
+/// read with interrupts disabled so it can't be switched away from halfway through
+pub fn current_thread() -> Option<Tid> {
+    without_interrupts(|| scheduler_inited().then(|| unsafe { (*scheduler().current_thread).tid }))
+}
+
+/// halts until the next interrupt, if the timer is what wakes us up the scheduler takes it as
+/// the current thread blocking before its time slice ended and promotes it (see `priority`)
+/// use this instead of `hlt` when waiting for something
+pub fn wait_for_interrupt() {
+    if !scheduler_inited() {
+        unsafe { asm!("hlt") };
+        return;
+    }
+
     unsafe {
-        global_allocator()
-            .lock()
-            .alloc_mut(STACK_LAYOUT)
-            .add(STACK_SIZE) as usize
+        asm!("cli");
+        let thread = scheduler().current_thread;
+        (*thread).blocked = true;
+        // sti only takes effect after hlt so the tick can't come in between
+        asm!("sti", "hlt");
+        (*thread).blocked = false;
+    }
+}
+
+/// what the cpu runs when no thread is ready, it halts until an interrupt and the tick after it
+/// switches away as soon as a thread is ready (see `Scheduler::tick`)
+pub fn idle_thread() -> ! {
+    loop {
+        unsafe { asm!("sti", "hlt") };
     }
 }
 
+#[derive(Debug)]
+pub enum SpawnElfError {
+    FS(FSError),
+    /// the file is empty
+    Empty,
+    Elf(ElfError),
+    Map(MapToError),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ProcessStatus {
+pub enum ThreadStatus {
     Waiting,
 
     Running,
     WaitingForBurying,
+    /// waiting on a `sync::WaitQueue`, out of the ready queue until `Scheduler::unblock`
+    Blocked,
 }
 
 #[derive(Debug, Clone)]
-pub struct Process {
-    pub pid: u64,
-    pub name: [u8; 64],
-    pub status: ProcessStatus,
+pub struct Thread {
+    pub tid: Tid,
+    /// the process this thread belongs to
+    pub pid: Pid,
+    pub status: ThreadStatus,
     pub context: CPUStatus,
+    /// the fpu and sse registers, saved and restored on every context switch
+    pub fpu_state: FpuState,
+    /// the level of the ready queue the thread is in
+    pub priority: Priority,
+    /// the highest level the thread can be promoted to, see `Scheduler::set_priority`
+    pub base_priority: Priority,
+    /// the ticks the thread ran for in its current time slice
+    pub ticks: u32,
+    /// wether or not the thread is halted in `wait_for_interrupt`
+    pub blocked: bool,
+    /// the `timer::epoch` the thread was last switched to in
+    pub resumed_epoch: u64,
+    /// the epoch the thread was last idle in, see `timer`
+    pub idle_epoch: u64,
+    /// when the thread started sleeping and for how long, see `timer::sleep`
+    pub sleep: Option<(Instant, Duration)>,
 
-    pub root_page_table: *mut PageTable,
     pub stack_end: *mut u8,
-    pub next: Option<Box<Process>>,
+    pub next: Option<Box<Thread>>,
 }
 
-impl Process {
-    pub fn create(function: usize, pid: u64, name: &str) -> Self {
-        let name_bytes = name.as_bytes();
-
-        let mut name = [0u8; 64];
-
-        let len = name_bytes.len().min(64);
-        name[..len].copy_from_slice(&name_bytes[..len]);
-
-        let status = ProcessStatus::Waiting;
+impl Thread {
+    /// creates a thread that starts at `function` in the address space `root_page_table`
+    pub fn create(function: usize, tid: Tid, pid: Pid, root_page_table: PhysAddr) -> Self {
+        let status = ThreadStatus::Waiting;
         let mut context = CPUStatus::default();
 
-        let stack_end = alloc_stack() as *mut u8;
-        let root_page_table = allocate_pml4().unwrap();
+        let stack_end = alloc_stack().as_mut_ptr::<u8>();
 
         #[cfg(target_arch = "x86_64")]
         {
@@ -74,156 +197,559 @@ impl Process {
 
             context.ss = 0x10;
             context.cs = 0x8;
-            context.cr3 = root_page_table as u64;
+            context.cr3 = root_page_table.as_u64();
         }
 
-        let root_page_table = (root_page_table + kernel().phy_offset) as *mut PageTable;
-
-        Process {
+        Thread {
+            tid,
             pid,
-            name,
             status,
             context,
+            fpu_state: FpuState::new(),
+            priority: HIGHEST_PRIORITY,
+            base_priority: HIGHEST_PRIORITY,
+            ticks: 0,
+            blocked: false,
+            resumed_epoch: 0,
+            idle_epoch: u64::MAX,
+            sleep: None,
 
             stack_end,
-            root_page_table,
             next: None,
         }
     }
 
     /// frees self and then returns next
-    /// frees all resources that has something to do with this process even the process stack and
-    /// page table
-    /// TODO: test this properly
-    pub fn free(&mut self) -> Option<Box<Process>> {
-        serial!("deallocating a process! ...\n");
+    /// only frees the thread stack, the address space belongs to the process
+    pub fn free(&mut self) -> Option<Box<Thread>> {
+        log!("deallocating thread {}! ...\n", self.tid);
+
+        vmm::free_pages(
+            VirtAddr::from_ptr(self.stack_end) - STACK_SIZE,
+            STACK_SIZE / PAGE_SIZE,
+        );
+        log!("deallocated the stack!\n");
+
+        self.next.take()
+    }
+
+    /// creates a copy of self named `tid` in the process `pid` that resumes at `context` (the
+    /// context self was captured in) with rax set to 0 in the address space `root_page_table`
+    /// the stack is copied into a new one and rsp, rbp and the saved rbp chain are moved to it,
+    /// other pointers into the stack still point to self's stack
+    /// self must be the current thread, the fpu state is copied from the registers
+    pub fn fork(&self, context: &CPUStatus, tid: Tid, pid: Pid, root_page_table: PhysAddr) -> Self {
+        let stack_end = alloc_stack().as_mut_ptr::<u8>();
+
+        let parent_end = self.stack_end as u64;
+        let parent_start = parent_end - STACK_SIZE as u64;
+        let child_end = stack_end as u64;
+        let child_start = child_end - STACK_SIZE as u64;
 
         unsafe {
-            global_allocator()
-                .lock()
-                .dealloc_mut(self.stack_end.sub(STACK_SIZE), STACK_LAYOUT);
+            core::ptr::copy_nonoverlapping(
+                parent_start as *const u8,
+                child_start as *mut u8,
+                STACK_SIZE,
+            );
         }
 
-        serial!("deallocated the stack!\n");
+        let relocate = |addr: u64| {
+            if (parent_start..parent_end).contains(&addr) {
+                addr - parent_start + child_start
+            } else {
+                addr
+            }
+        };
 
-        let root_page_table = unsafe { &mut (*self.root_page_table) };
-        unsafe { root_page_table.free(4) };
-        serial!("deallocated the root page table!\n");
+        // we are running on self so the registers still hold its fpu state
+        let mut fpu_state = FpuState::new();
+        fpu_state.save();
 
-        self.next.take()
+        let mut context = *context;
+        #[cfg(target_arch = "x86_64")]
+        {
+            context.rax = 0;
+            context.rsp = relocate(context.rsp);
+            context.rbp = relocate(context.rbp);
+            context.cr3 = root_page_table.as_u64();
+
+            // every frame starts with the caller's rbp (we force frame pointers)
+            let mut rbp = context.rbp;
+            while rbp >= child_start && rbp + 8 <= child_end {
+                let saved_rbp = unsafe { &mut *(rbp as *mut u64) };
+                *saved_rbp = relocate(*saved_rbp);
+
+                if *saved_rbp <= rbp {
+                    break;
+                }
+                rbp = *saved_rbp;
+            }
+        }
+
+        Thread {
+            tid,
+            pid,
+            status: ThreadStatus::Waiting,
+            context,
+            fpu_state,
+            priority: self.base_priority,
+            base_priority: self.base_priority,
+            ticks: 0,
+            blocked: false,
+            resumed_epoch: 0,
+            idle_epoch: u64::MAX,
+            sleep: None,
+
+            stack_end,
+            next: None,
+        }
     }
 }
+
 #[derive(Debug)]
 pub struct Scheduler {
-    pub head: Box<Process>,
+    pub head: Box<Thread>,
     /// raw pointers for peformance, we are ring0 we need the lowest stuff
-    pub current_process: *mut Process,
-    next_pid: u64,
+    pub current_thread: *mut Thread,
+    pub processes: BTreeMap<Pid, Process>,
+    /// the threads that are waiting to run, the current thread isn't in it
+    ready: ReadyQueues<*mut Thread>,
+    /// runs `idle_thread` when `ready` is empty, the only one since only this cpu schedules
+    idle: Box<Thread>,
+    /// the switches to `idle`
+    idle_switches: u64,
+    /// the ticks since the scheduler started
+    ticks: u64,
+    /// the calls to `Self::switch` since the scheduler started
+    switches: u64,
+    next_pid: Pid,
+    next_tid: Tid,
 }
 
 impl Scheduler {
+    /// creates the scheduler with a process named `name` (pid 0) running `function`
     #[inline]
     pub fn init(function: usize, name: &str) -> Self {
-        let mut process = Box::new(Process::create(function, 0, name));
+        let root_page_table = allocate_pml4().unwrap();
+
+        let mut processes = BTreeMap::new();
+        let mut process = Process::create(0, root_page_table, name);
+        process.add_thread(0);
+        processes.insert(0, process);
+
+        let mut thread = Box::new(Thread::create(function, 0, 0, root_page_table));
+        let idle = Box::new(Thread::create(
+            idle_thread as usize,
+            IDLE_TID,
+            0,
+            root_page_table,
+        ));
+        // the first switch queues the thread, see `Self::add_thread_to_queue`
+        let mut ready = ReadyQueues::new();
+        ready.reserve(1);
         Self {
-            current_process: &mut *process,
-            head: process,
+            current_thread: &mut *thread,
+            head: thread,
+            processes,
+            ready,
+            idle,
+            idle_switches: 0,
+            ticks: 0,
+            switches: 0,
             next_pid: 1,
+            next_tid: 1,
+        }
+    }
+
+    /// accounts a tick to the current thread returning wether or not it should be switched away
+    /// from, it keeps running until its time slice ends unless it blocked, exited or a higher
+    /// priority thread is ready (see `priority`)
+    /// called by the timer which sets `need_resched` with the result, the switch itself happens
+    /// when the interrupt returns
+    pub unsafe fn tick(&mut self) -> bool {
+        let current = self.current_thread;
+        if (*current).blocked {
+            (*current).idle_epoch = (*current).resumed_epoch;
+        }
+
+        self.ticks += 1;
+        if self.ticks % BOOST_TICKS == 0 {
+            self.boost();
+        }
+
+        // any ready thread preempts the idle thread, an irq waking one calls `timer::wake` so
+        // the tick comes right after it even when tickless
+        if current == self.idle_ptr() {
+            return self.ready.highest_ready().is_some();
+        }
+
+        if (*current).status == ThreadStatus::WaitingForBurying {
+            return true;
+        }
+
+        if (*current).blocked {
+            (*current).priority = (*current)
+                .priority
+                .saturating_sub(1)
+                .max((*current).base_priority);
+            (*current).ticks = 0;
+            return true;
+        }
+
+        (*current).ticks += 1;
+        if (*current).ticks >= time_slice((*current).priority) {
+            (*current).priority = ((*current).priority + 1).min(LOWEST_PRIORITY);
+            (*current).ticks = 0;
+            return true;
         }
+
+        self.ready
+            .highest_ready()
+            .is_some_and(|level| level < (*current).priority)
     }
 
-    /// context switches into next process, takes current context outputs new context
+    /// context switches into next thread, takes current context outputs new context
+    /// the current thread goes back to the ready queue of its level unless it exited or blocked,
+    /// the idle thread runs if no thread is ready
     pub unsafe fn switch(&mut self, context: CPUStatus) -> CPUStatus {
         unsafe { asm!("cli") }
 
-        (*self.current_process).context = context;
-
-        if (*self.current_process).status != ProcessStatus::WaitingForBurying {
-            (*self.current_process).status = ProcessStatus::Waiting;
+        let current = self.current_thread;
+        let idle = self.idle_ptr();
+        (*current).context = context;
+        self.switches += 1;
+
+        if !matches!(
+            (*current).status,
+            ThreadStatus::WaitingForBurying | ThreadStatus::Blocked
+        ) {
+            (*current).status = ThreadStatus::Waiting;
+            if current != idle {
+                self.ready.push((*current).priority, current);
+            }
         }
 
+        // we are still on the stack of current
+        self.bury(current);
+
         loop {
-            if (*self.current_process)
-                .next
-                .as_ref()
-                .is_some_and(|x| x.status == ProcessStatus::WaitingForBurying)
-            {
-                (*self.current_process).next =
-                    (*self.current_process).next.as_mut().unwrap().free();
+            let Some((_, next)) = self.ready.pop() else {
+                (*idle).status = ThreadStatus::Running;
+                self.current_thread = idle;
+                self.idle_switches += 1;
+                break;
+            };
+
+            // exited threads are buried on the next switch
+            if (*next).status == ThreadStatus::Waiting {
+                (*next).status = ThreadStatus::Running;
+                (*next).resumed_epoch = timer::epoch();
+                self.current_thread = next;
+                break;
+            }
+        }
+
+        return (*self.current_thread).context;
+    }
+
+    /// takes the current thread out of the ready queue from its next switch on, see
+    /// `sync::WaitQueue`
+    #[inline]
+    pub unsafe fn block_current(&mut self) {
+        (*self.current_thread).status = ThreadStatus::Blocked;
+    }
+
+    /// puts the thread with tid `tid` back in the ready queue if it is blocked, returns wether or
+    /// not it was
+    /// doesn't allocate (the ready queue has room for every thread, see
+    /// `Self::add_thread_to_queue`) so interrupt handlers can call it with interrupts disabled
+    pub fn unblock(&mut self, tid: Tid) -> bool {
+        let current = self.current_thread;
+
+        let mut thread = Some(&mut *self.head);
+        while let Some(next) = thread {
+            if next.tid != tid {
+                thread = next.next.as_deref_mut();
+                continue;
+            }
+
+            if next.status != ThreadStatus::Blocked {
+                return false;
             }
 
-            if (*self.current_process).next.is_some() {
-                self.current_process = &mut **(*self.current_process).next.as_mut().unwrap();
+            let next: *mut Thread = next;
+            // blocked but not switched away from yet, it just keeps running
+            if next == current {
+                unsafe { (*next).status = ThreadStatus::Running };
             } else {
-                self.current_process = &mut *self.head;
+                unsafe { (*next).status = ThreadStatus::Waiting };
+                self.ready.push(unsafe { (*next).priority }, next);
             }
 
-            if (*self.current_process).status == ProcessStatus::Waiting {
-                (*self.current_process).status = ProcessStatus::Running;
-                break;
+            timer::wake();
+            return true;
+        }
+
+        false
+    }
+
+    /// the calls to `Self::switch` since the scheduler started
+    #[inline]
+    pub fn switches(&self) -> u64 {
+        self.switches
+    }
+
+    /// the switches to the idle thread since the scheduler started
+    #[inline]
+    pub fn idle_switches(&self) -> u64 {
+        self.idle_switches
+    }
+
+    /// the status of the thread with tid `tid`, None if there is no such thread
+    pub fn thread_status(&self, tid: Tid) -> Option<ThreadStatus> {
+        let mut current = Some(&*self.head);
+        while let Some(thread) = current {
+            if thread.tid == tid {
+                return Some(thread.status);
             }
+            current = thread.next.as_deref();
         }
 
-        return (*self.current_process).context;
+        None
     }
 
-    /// appends a process to the end of the scheduler head
-    fn add_process(&mut self, process: Process) {
-        let mut current = &mut *self.head;
-        while let Some(ref mut process) = current.next {
-            current = &mut **process;
+    #[inline]
+    fn idle_ptr(&mut self) -> *mut Thread {
+        &mut *self.idle
+    }
+
+    /// wether or not every thread is idle (see `timer`) and no sleep is over
+    pub fn is_idle(&self) -> bool {
+        let epoch = timer::epoch();
+
+        let mut current = Some(&*self.head);
+        while let Some(thread) = current {
+            let over = thread
+                .sleep
+                .is_some_and(|(start, duration)| start.elapsed() >= duration);
+
+            if !matches!(
+                thread.status,
+                ThreadStatus::WaitingForBurying | ThreadStatus::Blocked
+            ) && (thread.idle_epoch != epoch || over)
+            {
+                return false;
+            }
+
+            current = thread.next.as_deref();
         }
 
-        current.next = Some(Box::new(process));
+        true
     }
 
-    /// sets a process with pid `pid` status to WaitingForBurying returns Err(()) if there is no
-    /// such a process
-    pub fn pkill(&mut self, pid: u64) -> Result<(), ()> {
-        let mut current = &mut *self.head;
-        let mut found = false;
-        while let Some(ref mut process) = current.next {
-            if current.pid == pid {
-                found = true;
-                break;
+    /// the tsc value the earliest sleep that isn't over yet ends at
+    pub fn next_sleep_deadline(&self) -> Option<u64> {
+        let mut deadline: Option<u64> = None;
+
+        let mut current = Some(&*self.head);
+        while let Some(thread) = current {
+            let sleep = thread
+                .sleep
+                .filter(|(start, duration)| start.elapsed() < *duration);
+
+            if let Some((start, duration)) = sleep {
+                let end = (start + duration).ticks();
+                deadline = Some(deadline.map_or(end, |deadline| deadline.min(end)));
             }
 
-            current = &mut **process;
-            if current.pid == pid {
-                found = true;
-                break;
+            current = thread.next.as_deref();
+        }
+
+        deadline
+    }
+
+    /// frees every thread waiting for burying except `running` whose stack we are on
+    unsafe fn bury(&mut self, running: *mut Thread) {
+        let mut current: *mut Thread = &mut *self.head;
+
+        while let Some(next) = (*current).next.as_deref_mut() {
+            let next: *mut Thread = next;
+
+            if (*next).status == ThreadStatus::WaitingForBurying && next != running {
+                let (tid, pid) = ((*next).tid, (*next).pid);
+
+                self.ready.remove(&next);
+                (*current).next = (*next).free();
+                self.thread_buried(pid, tid);
+            } else {
+                current = next;
             }
         }
+    }
 
-        if !found {
-            Err(())
-        } else {
-            current.status = ProcessStatus::WaitingForBurying;
-            Ok(())
+    /// moves every thread back to its base level so threads stuck in the lower levels still run
+    fn boost(&mut self) {
+        let mut current = Some(&mut *self.head);
+        while let Some(thread) = current {
+            thread.priority = thread.base_priority;
+            thread.ticks = 0;
+
+            current = thread.next.as_deref_mut();
         }
+
+        // runs in the timer interrupt, nothing allocates
+        self.ready
+            .rebalance(|&thread| unsafe { (*thread).priority });
     }
 
-    /// sets all process(s) with name `name` status to WaitingForBurying returns Err(()) if there is no
-    /// such a process
-    /// current implentation just collects all the pids and executes `Self::pkill`
-    /// TODO: work on better kill implentations for now this works
-    pub fn pkillall(&mut self, name: &[u8]) -> Result<(), ()> {
-        let mut current = &mut *self.head;
-        let mut plist = Vec::new();
+    /// sets the base level of the thread with tid `tid` to `level` moving it to `level`
+    /// returns Err(()) if there is no such a thread or `level` isn't a valid level
+    pub fn set_priority(&mut self, tid: Tid, level: Priority) -> Result<(), ()> {
+        if level > LOWEST_PRIORITY {
+            return Err(());
+        }
 
-        while let Some(ref mut process) = current.next {
-            if trim_trailing_zeros(&current.name) == name {
-                plist.push(current.pid);
-                break;
+        let mut current = Some(&mut *self.head);
+        while let Some(thread) = current {
+            if thread.tid == tid {
+                thread.priority = level;
+                thread.base_priority = level;
+                thread.ticks = 0;
+
+                let thread: *mut Thread = thread;
+                self.ready.remove(&thread);
+                if thread != self.current_thread
+                    && unsafe { (*thread).status } == ThreadStatus::Waiting
+                {
+                    self.ready.push(level, thread);
+                }
+                return Ok(());
             }
 
-            current = &mut **process;
-            if trim_trailing_zeros(&current.name) == name {
-                plist.push(current.pid);
-                break;
+            current = thread.next.as_deref_mut();
+        }
+
+        Err(())
+    }
+
+    /// removes a freed thread from its process, freeing the process if it was its last thread
+    fn thread_buried(&mut self, pid: Pid, tid: Tid) {
+        let Some(process) = self.processes.get_mut(&pid) else {
+            return;
+        };
+
+        if process.remove_thread(tid) {
+            process.free();
+            self.processes.remove(&pid);
+        }
+    }
+
+    /// appends a thread to the end of the scheduler head and to the ready queue of its level
+    /// every level gets room for every thread here so the interrupt handlers pushing to the ready
+    /// queue (`Self::switch`, `Self::unblock`, `Self::boost`) never allocate
+    fn add_thread_to_queue(&mut self, thread: Thread) {
+        let mut threads = 1;
+        let mut current = &mut *self.head;
+        while let Some(ref mut thread) = current.next {
+            current = &mut **thread;
+            threads += 1;
+        }
+
+        self.ready.reserve(threads + 1);
+        let thread = &mut **current.next.insert(Box::new(thread));
+        self.ready.push(thread.priority, thread);
+    }
+
+    /// creates a process that owns the pml4 at `root_page_table` returning its pid, the process
+    /// doesn't run until a thread is added to it using `Self::add_thread`
+    pub fn create_process(&mut self, root_page_table: PhysAddr, name: &str) -> Pid {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+
+        self.processes
+            .insert(pid, Process::create(pid, root_page_table, name));
+        pid
+    }
+
+    /// creates a thread running `function` in the process with pid `pid` without queueing it
+    /// returns Err(()) if there is no such a process
+    fn create_thread(&mut self, pid: Pid, function: usize) -> Result<Thread, ()> {
+        let process = self.processes.get_mut(&pid).ok_or(())?;
+
+        let tid = self.next_tid;
+        self.next_tid += 1;
+
+        process.add_thread(tid);
+        Ok(Thread::create(function, tid, pid, process.root_page_table))
+    }
+
+    /// adds a thread running `function` to the process with pid `pid` returning its tid
+    /// returns Err(()) if there is no such a process
+    pub fn add_thread(&mut self, pid: Pid, function: usize) -> Result<Tid, ()> {
+        let thread = self.create_thread(pid, function)?;
+        let tid = thread.tid;
+        self.add_thread_to_queue(thread);
+
+        Ok(tid)
+    }
+
+    /// adds a thread to the process with pid `pid` that starts in ring 3 at `entry` on the user
+    /// stack ending at `stack` with `arg` as its first argument, returns its tid
+    /// its kernel stack is only used by the interrupts coming from ring 3
+    /// returns Err(()) if there is no such a process
+    pub fn add_user_thread(
+        &mut self,
+        pid: Pid,
+        entry: VirtAddr,
+        stack: VirtAddr,
+        arg: u64,
+    ) -> Result<Tid, ()> {
+        let mut thread = self.create_thread(pid, entry.as_usize())?;
+        #[cfg(target_arch = "x86_64")]
+        thread.context.set_user_entry(entry, stack, arg);
+
+        let tid = thread.tid;
+        self.add_thread_to_queue(thread);
+        Ok(tid)
+    }
+
+    /// sets the exit code of the process with pid `pid` and marks all of its threads as
+    /// WaitingForBurying, the process gets freed once its last thread is buried
+    /// returns Err(()) if there is no such a process
+    pub fn exit(&mut self, pid: Pid, code: usize) -> Result<(), ()> {
+        let process = self.processes.get_mut(&pid).ok_or(())?;
+        process.exit_code = Some(code);
+
+        let mut current = Some(&mut *self.head);
+        while let Some(thread) = current {
+            if thread.pid == pid {
+                thread.status = ThreadStatus::WaitingForBurying;
             }
+
+            current = thread.next.as_deref_mut();
         }
 
+        // someone may be waiting for it
+        timer::wake();
+        Ok(())
+    }
+
+    /// kills the process with pid `pid` see `Self::exit`
+    /// returns Err(()) if there is no such a process
+    pub fn pkill(&mut self, pid: Pid) -> Result<(), ()> {
+        self.exit(pid, 1)
+    }
+
+    /// kills all process(s) with name `name` returns Err(()) if there is no such a process
+    pub fn pkillall(&mut self, name: &[u8]) -> Result<(), ()> {
+        let plist: Vec<Pid> = self
+            .processes
+            .values()
+            .filter(|process| trim_trailing_zeros(&process.name) == name)
+            .map(|process| process.pid)
+            .collect();
+
         if plist.is_empty() {
             Err(())
         } else {
@@ -235,10 +761,92 @@ impl Scheduler {
         }
     }
 
-    /// wrapper around `Process::create` that also adds the result to self using
-    /// `Self::add_process`
-    pub fn create_process(&mut self, function: usize, name: &str) {
-        self.add_process(Process::create(function, self.next_pid, name));
-        self.next_pid += 1;
+    /// creates a process with its own address space and a single thread running `function`
+    pub fn spawn(&mut self, function: usize, name: &str) -> Pid {
+        let root_page_table = allocate_pml4().unwrap();
+
+        let pid = self.create_process(root_page_table, name);
+        self.add_thread(pid, function).unwrap();
+        pid
+    }
+
+    /// creates a process that runs `elf`, `elf` gets loaded into the process' own page table
+    /// and the process starts in ring 3 at `elf`'s entry point on a stack ending at
+    /// `USER_STACK_END`
+    pub fn create_elf_process(&mut self, elf: &Elf, name: &str) -> Result<Pid, MapToError> {
+        let root_page_table = allocate_pml4()?;
+        let table = unsafe { &mut *phys_to_virt(root_page_table).as_mut_ptr::<PageTable>() };
+
+        let loaded = elf
+            .load(table)
+            .and_then(|entry_point| Ok((entry_point, map_user_stack(table)?)));
+        let (entry_point, stack) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                unsafe { table.free(PAGE_TABLE_LEVELS) };
+                return Err(err);
+            }
+        };
+
+        if kernel().cmdline.wxaudit() {
+            wxaudit::audit(
+                table,
+                wxaudit::LOWER_HALF,
+                kernel().cmdline.wxaudit_strict(),
+            );
+        }
+
+        let pid = self.create_process(root_page_table, name);
+        self.add_user_thread(pid, entry_point, stack, 0).unwrap();
+        Ok(pid)
+    }
+
+    /// reads the elf at `path` and creates a process named `name` running it see
+    /// `Self::create_elf_process`
+    pub fn spawn_elf(&mut self, path: &str, name: &str) -> Result<Pid, SpawnElfError> {
+        let mut file = vfs().open(path).map_err(SpawnElfError::FS)?;
+
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.resize(file.size(), 0);
+        let read = vfs().read(&mut file, &mut buffer);
+        vfs().close(file).map_err(SpawnElfError::FS)?;
+        read.map_err(SpawnElfError::FS)?;
+
+        if buffer.is_empty() {
+            return Err(SpawnElfError::Empty);
+        }
+
+        let elf = Elf::parse(&buffer[0]).map_err(SpawnElfError::Elf)?;
+        self.create_elf_process(&elf, name)
+            .map_err(SpawnElfError::Map)
+    }
+
+    /// duplicates the process of the current thread returning the child pid, `context` is the
+    /// context the current thread was captured in (for example by a syscall)
+    /// the child gets a deep copy of the parent address space (see `PageTable::clone_deep`), a
+    /// copy of its open files and a single thread that resumes at `context` with rax set to 0
+    pub fn fork(&mut self, context: &CPUStatus) -> Result<Pid, MapToError> {
+        let parent = unsafe { &*self.current_thread };
+        let process = &self.processes[&parent.pid];
+
+        let parent_table = unsafe { &*phys_to_virt(process.root_page_table).as_ptr::<PageTable>() };
+        let root_page_table = unsafe { parent_table.clone_deep(PAGE_TABLE_LEVELS)? };
+
+        let name = process.name;
+        let files = process.files.clone();
+
+        let pid = self.create_process(root_page_table, "");
+        let tid = self.next_tid;
+        self.next_tid += 1;
+
+        let child = self.processes.get_mut(&pid).unwrap();
+        child.name = name;
+        child.files = files;
+        child.add_thread(tid);
+
+        let thread = parent.fork(context, tid, pid, root_page_table);
+        self.add_thread_to_queue(thread);
+
+        Ok(pid)
     }
 }
@@ -0,0 +1,98 @@
+// the scheduler is a basic multi-level feedback queue, each priority level has its own ready
+// queue and the scheduler runs the first thread of the highest non-empty level, threads of the
+// same level are round-robined
+// - a thread that uses its whole time slice is demoted by one level, lower levels get longer
+//   slices
+// - a thread that blocks before its slice ends (see `threading::wait_for_interrupt`) is promoted
+//   by one level
+// - every `BOOST_TICKS` ticks every thread goes back to its base level so cpu bound threads
+//   stuck in the lowest level still run
+// a thread never goes above its base level, which is the highest by default and which
+// `Scheduler::set_priority` changes
+
+use alloc::collections::VecDeque;
+
+/// a priority level, 0 is the highest
+pub type Priority = usize;
+
+pub const PRIORITY_LEVELS: usize = 4;
+pub const HIGHEST_PRIORITY: Priority = 0;
+pub const LOWEST_PRIORITY: Priority = PRIORITY_LEVELS - 1;
+/// the ticks between two priority boosts
+pub const BOOST_TICKS: u64 = 64;
+
+/// the ticks a thread at `level` runs for before being preempted
+#[inline]
+pub const fn time_slice(level: Priority) -> u32 {
+    1 << level
+}
+
+#[derive(Debug)]
+pub struct ReadyQueues<T> {
+    levels: [VecDeque<T>; PRIORITY_LEVELS],
+}
+
+impl<T: PartialEq> ReadyQueues<T> {
+    pub const fn new() -> Self {
+        Self {
+            levels: [const { VecDeque::new() }; PRIORITY_LEVELS],
+        }
+    }
+
+    /// makes room for `count` items in every level so pushing up to `count` items in total
+    /// doesn't allocate, the queues are pushed to by interrupt handlers which can't allocate (the
+    /// heap lock doesn't disable interrupts, an interrupt taking it from under a thread holding it
+    /// never gets it)
+    pub fn reserve(&mut self, count: usize) {
+        for queue in &mut self.levels {
+            queue.reserve(count.saturating_sub(queue.len()));
+        }
+    }
+
+    /// appends `item` to the queue of `level`, there must be room for it (see `Self::reserve`)
+    /// since it may be called from an interrupt handler
+    #[inline]
+    pub fn push(&mut self, level: Priority, item: T) {
+        let queue = &mut self.levels[level.min(LOWEST_PRIORITY)];
+        debug_assert!(
+            queue.len() < queue.capacity(),
+            "pushing to a full ready queue would allocate"
+        );
+        queue.push_back(item);
+    }
+
+    /// takes the first item of the highest non-empty level
+    pub fn pop(&mut self) -> Option<(Priority, T)> {
+        self.levels
+            .iter_mut()
+            .enumerate()
+            .find_map(|(level, queue)| Some((level, queue.pop_front()?)))
+    }
+
+    /// the highest level with a ready item
+    pub fn highest_ready(&self) -> Option<Priority> {
+        self.levels.iter().position(|queue| !queue.is_empty())
+    }
+
+    /// removes `item` from whichever level it is queued in
+    pub fn remove(&mut self, item: &T) {
+        for queue in &mut self.levels {
+            queue.retain(|queued| queued != item);
+        }
+    }
+
+    /// moves every queued item to the queue of `level_of(item)` keeping their order in each
+    /// level, in place so it doesn't allocate once every level has room for every item (see
+    /// `Self::reserve`)
+    pub fn rebalance(&mut self, level_of: impl Fn(&T) -> Priority) {
+        // the items moved to a level that wasn't visited yet go after the ones it had
+        let lens = self.levels.each_ref().map(|queue| queue.len());
+
+        for (level, len) in lens.into_iter().enumerate() {
+            for _ in 0..len {
+                let item = self.levels[level].pop_front().unwrap();
+                self.push(level_of(&item), item);
+            }
+        }
+    }
+}
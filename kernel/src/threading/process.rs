@@ -0,0 +1,77 @@
+use alloc::vec::Vec;
+
+use crate::{
+    drivers::vfs::{vfs, FileDescriptor, FS},
+    memory::{
+        paging::{PageTable, PAGE_TABLE_LEVELS},
+        phys_to_virt, PhysAddr,
+    },
+    serial,
+};
+
+use super::Tid;
+
+pub type Pid = u64;
+
+/// a process owns an address space, open files and the threads running in it
+/// threads are kept in the scheduler, the process only keeps their tids
+#[derive(Debug)]
+pub struct Process {
+    pub pid: Pid,
+    pub name: [u8; 64],
+    /// physical address of the process' pml4, loaded into cr3 by each of its threads
+    pub root_page_table: PhysAddr,
+    pub threads: Vec<Tid>,
+    pub files: Vec<FileDescriptor>,
+    /// set by `Scheduler::exit`, the process is freed once its last thread is buried
+    pub exit_code: Option<usize>,
+}
+
+impl Process {
+    /// creates a process owning the pml4 at `root_page_table` (see `allocate_pml4`) with no
+    /// threads
+    pub fn create(pid: Pid, root_page_table: PhysAddr, name: &str) -> Self {
+        let name_bytes = name.as_bytes();
+
+        let mut name = [0u8; 64];
+
+        let len = name_bytes.len().min(64);
+        name[..len].copy_from_slice(&name_bytes[..len]);
+
+        Self {
+            pid,
+            name,
+            root_page_table,
+            threads: Vec::new(),
+            files: Vec::new(),
+            exit_code: None,
+        }
+    }
+
+    #[inline]
+    pub fn add_thread(&mut self, tid: Tid) {
+        self.threads.push(tid);
+    }
+
+    /// removes `tid` from the process threads returns true if it was the last thread
+    pub fn remove_thread(&mut self, tid: Tid) -> bool {
+        self.threads.retain(|thread| *thread != tid);
+        self.threads.is_empty()
+    }
+
+    /// frees all the resources owned by the process, closing its files and freeing its address
+    /// space
+    /// the process must have no threads left and its address space must not be the current one
+    pub fn free(&mut self) {
+        serial!("deallocating process {}...\n", self.pid);
+
+        for file in self.files.drain(..) {
+            _ = vfs().close(file);
+        }
+
+        let root_page_table =
+            unsafe { &mut *phys_to_virt(self.root_page_table).as_mut_ptr::<PageTable>() };
+        unsafe { root_page_table.free(PAGE_TABLE_LEVELS) };
+        serial!("deallocated the root page table!\n");
+    }
+}
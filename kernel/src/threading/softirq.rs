@@ -0,0 +1,51 @@
+// the bottom halves of the interrupt handlers, a handler only does what can't wait (reading the
+// device, acking it) and `raise`s the rest which `softirq_thread` runs later with interrupts
+// enabled, so the time spent with interrupts disabled stays short and the deferred work can take
+// locks and wake threads like any thread can
+// the work is a plain fn, it should handle everything its handler queued since it last ran
+// (draining a ring and not a single byte from it) so raising it again while it is still queued
+// doesn't lose anything and a full queue only drops work that is already covered
+
+use crate::utils::ring_buffer::{Full, RingBuffer};
+
+use super::{timer, wait_for_interrupt};
+
+pub type Work = fn();
+
+const MAX_PENDING: usize = 64;
+
+static PENDING: RingBuffer<Work, MAX_PENDING> = RingBuffer::new();
+
+/// queues `work` for `softirq_thread`, safe to call from an interrupt handler
+/// returns Err(Full) if too much work is pending, `work` is dropped then
+#[inline]
+pub fn raise(work: Work) -> Result<(), Full> {
+    let result = PENDING.push(work);
+    // the softirq thread may be idle
+    timer::wake();
+    result
+}
+
+/// wether or not some work is waiting for `softirq_thread`
+#[inline]
+pub fn pending() -> bool {
+    !PENDING.is_empty()
+}
+
+/// runs the work pending until there is none left, returns how much ran
+pub fn run_pending() -> usize {
+    let mut ran = 0;
+    while let Some(work) = PENDING.pop() {
+        work();
+        ran += 1;
+    }
+    ran
+}
+
+/// runs the work `raise`d by the interrupt handlers, there must be only one softirq thread
+pub fn softirq_thread() -> ! {
+    loop {
+        run_pending();
+        wait_for_interrupt();
+    }
+}
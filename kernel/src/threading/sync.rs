@@ -0,0 +1,189 @@
+// blocking between threads, a thread waiting on a `WaitQueue` is out of the ready queue (see
+// `ThreadStatus::Blocked`) so it takes no time slices until another thread or an interrupt
+// handler wakes it, `Semaphore` and `CondVar` are built on it
+// a thread joins the queue and blocks with interrupts disabled, whoever changes what it waits for
+// then wakes it, and a wake that comes before it got switched away from just keeps it running, so
+// on a single cpu a wake can't get lost between checking and blocking
+// before the scheduler runs there is nothing to switch to, waiting halts until an interrupt
+// instead
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::vec_deque::VecDeque;
+use spin::MutexGuard;
+
+use crate::{arch::without_interrupts, scheduler, scheduler_inited, utils::Locked};
+
+use super::{wait_for_interrupt, ThreadStatus, Tid};
+
+/// the threads waiting for something, woken in the order they started waiting
+#[derive(Debug)]
+pub struct WaitQueue {
+    /// only locked with interrupts disabled, interrupt handlers can wake threads
+    waiters: Locked<VecDeque<Tid>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Locked::new(VecDeque::new()),
+        }
+    }
+
+    /// adds the current thread to the queue and blocks it, it keeps running until its next
+    /// switch, must be called with interrupts disabled and followed by `Self::sleep`
+    fn enqueue_current(&self) {
+        if !scheduler_inited() {
+            return;
+        }
+
+        let scheduler = scheduler();
+        let tid = unsafe { (*scheduler.current_thread).tid };
+        self.waiters.lock().push_back(tid);
+        unsafe { scheduler.block_current() };
+    }
+
+    /// waits until the current thread is woken
+    fn sleep() {
+        if !scheduler_inited() {
+            wait_for_interrupt();
+            return;
+        }
+
+        let thread = scheduler().current_thread;
+        while without_interrupts(|| unsafe { (*thread).status }) == ThreadStatus::Blocked {
+            wait_for_interrupt();
+        }
+    }
+
+    /// blocks until `condition` returns true, it is checked with interrupts disabled right
+    /// before blocking and again every time the thread is woken
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let done = without_interrupts(|| {
+                let done = condition();
+                if !done {
+                    self.enqueue_current();
+                }
+                done
+            });
+
+            if done {
+                return;
+            }
+            Self::sleep();
+        }
+    }
+
+    /// wakes the thread that waited the longest, returns wether or not there was one
+    /// the threads that exited while waiting are skipped, doesn't allocate so it is safe to call
+    /// from an interrupt handler (see `Scheduler::unblock`)
+    pub fn wake_one(&self) -> bool {
+        without_interrupts(|| loop {
+            let tid = self.waiters.lock().pop_front();
+            let Some(tid) = tid else {
+                return false;
+            };
+
+            if scheduler().unblock(tid) {
+                return true;
+            }
+        })
+    }
+
+    /// wakes every waiting thread, returns how many there were
+    pub fn wake_all(&self) -> usize {
+        let mut woken = 0;
+        while self.wake_one() {
+            woken += 1;
+        }
+        woken
+    }
+}
+
+/// a counting semaphore
+#[derive(Debug)]
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// takes one from the count if it isn't 0, doesn't block
+    pub fn try_wait(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// blocks until the count isn't 0 then takes one from it
+    pub fn wait(&self) {
+        self.waiters.wait_until(|| self.try_wait());
+    }
+
+    /// adds one to the count waking a single waiter, safe to call from an interrupt handler
+    pub fn signal(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// waits for a condition on the data behind a `Locked` to change, the thread changing it holds the
+/// lock and notifies
+/// a waiter can't tell a notify from a wake before the scheduler runs, it should check its
+/// condition again in a loop
+#[derive(Debug)]
+pub struct CondVar {
+    waiters: WaitQueue,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// releases `guard` (taken from `locked`) and blocks until notified, then takes the lock
+    /// again, the thread is queued before the lock is released so a notify right after can't be
+    /// missed
+    pub fn wait<'a, T>(
+        &self,
+        locked: &'a Locked<T>,
+        guard: MutexGuard<'a, T>,
+    ) -> MutexGuard<'a, T> {
+        without_interrupts(|| {
+            self.waiters.enqueue_current();
+            drop(guard);
+        });
+
+        WaitQueue::sleep();
+        locked.lock()
+    }
+
+    /// wakes the thread that waited the longest, returns wether or not there was one
+    #[inline]
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    /// wakes every waiting thread, returns how many there were
+    #[inline]
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
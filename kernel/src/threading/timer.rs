@@ -0,0 +1,106 @@
+// the timer driving the scheduler, in `TimerMode::Periodic` it ticks every so often whatever the
+// threads do, in `TimerMode::Tickless` (only with the tsc deadline timer, see
+// `Arch::TIMER_TICK`) it is armed once for the next event: the end of the tick or the earliest
+// `sleep` deadline, and not at all once every thread is idle so the cpu stays halted until an irq
+// a thread is idle once it was switched away from while blocked in `wait_for_interrupt` after it
+// was switched to in the current epoch, `wake` starts a new epoch so every thread gets to look
+// again, device irqs call it and so must anything that ends the wait of another thread (the
+// logger ring, `softirq::raise`, the keyboard bottom half and `Scheduler::exit` do) or that
+// thread may only notice on the next irq
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{
+    arch::{Arch, Current},
+    scheduler, scheduler_inited,
+    time::{Duration, Instant},
+};
+
+use super::wait_for_interrupt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    Periodic,
+    Tickless,
+}
+
+static TICKLESS: AtomicBool = AtomicBool::new(false);
+/// set when the timer was left disarmed because every thread is idle
+static STOPPED: AtomicBool = AtomicBool::new(false);
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+pub fn mode() -> TimerMode {
+    if TICKLESS.load(Ordering::Relaxed) {
+        TimerMode::Tickless
+    } else {
+        TimerMode::Periodic
+    }
+}
+
+/// returns Err(()) and stays periodic if `mode` is tickless but the timer isn't in tsc deadline
+/// mode
+pub fn set_mode(mode: TimerMode) -> Result<(), ()> {
+    if mode == TimerMode::Tickless && !Current::timer_deadline_mode() {
+        return Err(());
+    }
+
+    TICKLESS.store(mode == TimerMode::Tickless, Ordering::Relaxed);
+    // the timer may be stopped
+    wake();
+    Ok(())
+}
+
+/// the current epoch, see the top of this file
+#[inline]
+pub fn epoch() -> u64 {
+    EPOCH.load(Ordering::Relaxed)
+}
+
+/// starts a new epoch restarting the timer if it was stopped, safe to call from an interrupt
+/// handler
+#[inline]
+pub fn wake() {
+    EPOCH.fetch_add(1, Ordering::Relaxed);
+    if STOPPED.swap(false, Ordering::Relaxed) {
+        Current::arm_timer_deadline(Current::counter());
+    }
+}
+
+/// arms the timer for the next event, called by every tick after the scheduler switched
+pub fn arm() {
+    if mode() == TimerMode::Periodic || !scheduler_inited() {
+        Current::rearm_timer();
+        return;
+    }
+
+    let scheduler = scheduler();
+    let deadline = scheduler.next_sleep_deadline();
+
+    if scheduler.is_idle() {
+        STOPPED.store(true, Ordering::Relaxed);
+        // 0 disarms
+        Current::arm_timer_deadline(deadline.unwrap_or(0));
+    } else {
+        STOPPED.store(false, Ordering::Relaxed);
+        let tick = Current::counter()
+            + Current::ns_to_counter_ticks(Current::TIMER_TICK.as_nanos() as u64);
+        Current::arm_timer_deadline(deadline.map_or(tick, |deadline| deadline.min(tick)));
+    }
+}
+
+/// waits for at least `duration`, the thread is idle meanwhile
+pub fn sleep(duration: Duration) {
+    let start = Instant::now();
+    if scheduler_inited() {
+        unsafe { (*scheduler().current_thread).sleep = Some((start, duration)) };
+    }
+
+    while start.elapsed() < duration {
+        wait_for_interrupt();
+    }
+
+    if scheduler_inited() {
+        unsafe { (*scheduler().current_thread).sleep = None };
+    }
+}
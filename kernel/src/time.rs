@@ -0,0 +1,69 @@
+// time measured with the monotonic clock, the tsc on x86_64 (see `Arch::counter`), for
+// timeouts and benchmarks:
+// `let start = Instant::now(); ...; if start.elapsed() > Duration::from_millis(50) { .. }`
+// the counter is `COUNTER_BITS` wide and differences are taken modulo that so an `Instant` is
+// still fine after the counter wraps, as long as what is measured is shorter than a wrap
+
+use core::ops::{Add, Sub};
+
+pub use core::time::Duration;
+
+use crate::arch::{Arch, Current};
+
+/// the width of the monotonic counter
+const COUNTER_BITS: u32 = 64;
+const COUNTER_MASK: u64 = u64::MAX >> (64 - COUNTER_BITS);
+
+/// a point in time of the monotonic clock, only meaningful compared to another `Instant`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instant(u64);
+
+impl Instant {
+    #[inline]
+    pub fn now() -> Self {
+        Self(Current::counter() & COUNTER_MASK)
+    }
+
+    /// the instant the counter was at `ticks`
+    #[inline]
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks & COUNTER_MASK)
+    }
+
+    #[inline]
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// the time from `earlier` to self, `earlier` must be before self
+    #[inline]
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        let ticks = self.0.wrapping_sub(earlier.0) & COUNTER_MASK;
+        Duration::from_nanos(Current::counter_ticks_to_ns(ticks))
+    }
+
+    /// the time since self
+    #[inline]
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    #[inline]
+    fn add(self, rhs: Duration) -> Self::Output {
+        let ticks = Current::ns_to_counter_ticks(rhs.as_nanos() as u64);
+        Self::from_ticks(self.0.wrapping_add(ticks))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    #[inline]
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
@@ -1,8 +1,14 @@
 use core::ffi::{c_char, CStr};
 
-use alloc::slice;
+use alloc::{collections::BTreeMap, slice};
 
-use crate::{serial, VirtAddr};
+use crate::{
+    memory::{
+        paging::{EntryFlags, MapToError, Page, PageTable, PAGE_SIZE},
+        phys_to_virt,
+    },
+    serial, VirtAddr,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ElfType(u16);
@@ -128,15 +134,83 @@ pub struct SectionHeader {
     pub entry_size: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramType(u32);
+impl ProgramType {
+    pub const LOAD: Self = Self(1);
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub program_type: ProgramType,
+    pub flags: u32,
+    pub offset: usize,
+
+    pub vaddr: VirtAddr,
+    pub paddr: usize,
+
+    pub file_size: usize,
+    pub mem_size: usize,
+
+    pub alignment: usize,
+}
+
+impl ProgramHeader {
+    pub const FLAG_EXECUTABLE: u32 = 1 << 0;
+    pub const FLAG_WRITABLE: u32 = 1 << 1;
+
+    /// the flags the pages of the segment are mapped with, user pages that are only executable
+    /// if the segment is
+    pub fn entry_flags(&self) -> EntryFlags {
+        let mut flags = EntryFlags::PRESENT | EntryFlags::USER_ACCESSIBLE;
+        if self.flags & Self::FLAG_WRITABLE != 0 {
+            flags |= EntryFlags::WRITABLE;
+        }
+        if self.flags & Self::FLAG_EXECUTABLE == 0 {
+            flags |= EntryFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// an elf file, the file is a byte buffer with no alignment so the headers are copied out of it
+/// with `read_unaligned` instead of being referenced
 #[derive(Debug)]
 pub struct Elf<'a> {
-    pub header: &'a ElfHeader,
-    pub sections: &'a [SectionHeader],
+    bytes: &'a u8,
+    pub header: ElfHeader,
 }
 impl<'a> Elf<'a> {
     #[inline]
-    pub fn section_names_table(&self) -> &SectionHeader {
-        &self.sections[self.header.sections_names_section_offset as usize]
+    fn base(&self) -> *const u8 {
+        self.bytes as *const u8
+    }
+
+    /// the `T` at `offset` in the file
+    #[inline]
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.base().add(offset).cast::<T>().read_unaligned() }
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = SectionHeader> + '_ {
+        (0..self.header.section_table_entries as usize).map(|index| {
+            self.read(self.header.section_header_table_offset + index * size_of::<SectionHeader>())
+        })
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.header.program_header_entries as usize).map(|index| {
+            self.read(self.header.program_header_offset + index * size_of::<ProgramHeader>())
+        })
+    }
+
+    #[inline]
+    pub fn section_names_table(&self) -> SectionHeader {
+        self.read(
+            self.header.section_header_table_offset
+                + self.header.sections_names_section_offset as usize * size_of::<SectionHeader>(),
+        )
     }
 
     pub fn section_names_table_index(&self, name_index: u32) -> &str {
@@ -145,24 +219,17 @@ impl<'a> Elf<'a> {
         }
 
         let name_table = self.section_names_table();
-        let name_ptr = unsafe {
-            (self.header as *const ElfHeader as *const u8)
-                .add(name_table.offset)
-                .add(name_index as usize) as *const c_char
-        };
+        let name_ptr =
+            unsafe { self.base().add(name_table.offset).add(name_index as usize) as *const c_char };
 
         let str = unsafe { CStr::from_ptr(name_ptr) };
         str.to_str().unwrap()
     }
 
     #[inline]
-    pub fn string_table(&self) -> Option<&SectionHeader> {
-        for section in self.sections {
-            if self.section_names_table_index(section.name_index) == ".strtab" {
-                return Some(section);
-            }
-        }
-        None
+    pub fn string_table(&self) -> Option<SectionHeader> {
+        self.sections()
+            .find(|section| self.section_names_table_index(section.name_index) == ".strtab")
     }
 
     pub fn string_table_index(&self, name_index: u32) -> &str {
@@ -171,69 +238,99 @@ impl<'a> Elf<'a> {
         }
 
         let str_table = self.string_table().unwrap();
-        let str_ptr = unsafe {
-            (self.header as *const ElfHeader as *const u8)
-                .add(str_table.offset)
-                .add(name_index as usize) as *const c_char
-        };
+        let str_ptr =
+            unsafe { self.base().add(str_table.offset).add(name_index as usize) as *const c_char };
 
         let str = unsafe { CStr::from_ptr(str_ptr) };
         str.to_str().unwrap()
     }
 
     #[inline]
-    pub fn symtable(&self) -> Option<&[Sym]> {
-        for section in self.sections {
-            if section.section_type == 2 {
-                let sym_ptr = unsafe {
-                    (self.header as *const ElfHeader as *const u8).add(section.offset) as *const Sym
-                };
-
-                let sym_len = section.size / section.entry_size;
-
-                let sym_table = unsafe { slice::from_raw_parts(sym_ptr, sym_len) };
-                return Some(sym_table);
-            }
-        }
+    pub fn symtable(&self) -> Option<impl Iterator<Item = Sym> + '_> {
+        let section = self.sections().find(|section| section.section_type == 2)?;
+        let sym_len = section.size / section.entry_size;
 
-        return None;
+        Some((0..sym_len).map(move |index| self.read(section.offset + index * section.entry_size)))
     }
 
     pub fn sym_from_value_range(&self, value: VirtAddr) -> Option<Sym> {
-        for sym in self.symtable()? {
-            if sym.value <= value && (sym.value + sym.size as usize) >= value {
-                return Some(*sym);
-            }
-        }
-
-        return None;
+        self.symtable()?
+            .find(|sym| sym.value <= value && (sym.value + sym.size as usize) >= value)
     }
 
     /// creates an elf from a u8 ptr that lives as long as `bytes`
-    pub fn parse(bytes: &u8) -> Result<Self, ElfError> {
-        let bytes = bytes as *const u8;
-        let header_ptr = bytes as *const ElfHeader;
-
-        let header = unsafe {
-            if (*header_ptr).verify() {
-                &*header_ptr
-            } else {
-                return Err(ElfError::NotAnElf);
-            }
-        };
+    pub fn parse(bytes: &'a u8) -> Result<Self, ElfError> {
+        let header = unsafe { (bytes as *const u8).cast::<ElfHeader>().read_unaligned() };
+        if !header.verify() {
+            return Err(ElfError::NotAnElf);
+        }
 
         header.supported()?;
+        Ok(Self { bytes, header })
+    }
 
-        let header_table_ptr = unsafe { bytes.offset(header.section_header_table_offset as isize) }
-            as *const SectionHeader;
-        let header_table = unsafe {
-            slice::from_raw_parts(header_table_ptr, header.section_table_entries as usize)
+    /// maps and copies every loadable segment into `table` (which doesn't have to be the current
+    /// page table) then returns the entry point
+    /// segments are copied through the physical map so this works before switching to `table`
+    /// a page shared by segments is mapped once with the flags of all of them (writable or
+    /// executable if one of them is) and gets the data of each
+    /// if mapping a page fails nothing is left mapped
+    pub fn load(&self, table: &mut PageTable) -> Result<VirtAddr, MapToError> {
+        let segments = || {
+            self.program_headers()
+                .filter(|program_header| program_header.program_type == ProgramType::LOAD)
         };
 
-        Ok(Self {
-            header,
-            sections: header_table,
-        })
+        let mut pages: BTreeMap<VirtAddr, EntryFlags> = BTreeMap::new();
+        for segment in segments() {
+            let start = segment.vaddr.align_down(PAGE_SIZE);
+            let end = (segment.vaddr + segment.mem_size).align_up(PAGE_SIZE);
+            let flags = segment.entry_flags();
+
+            for page_start in (start.as_usize()..end.as_usize()).step_by(PAGE_SIZE) {
+                pages
+                    .entry(VirtAddr::new(page_start))
+                    .and_modify(|merged| {
+                        *merged |= flags & EntryFlags::WRITABLE;
+                        if !flags.contains(EntryFlags::NO_EXECUTE) {
+                            merged.remove(EntryFlags::NO_EXECUTE);
+                        }
+                    })
+                    .or_insert(flags);
+            }
+        }
+
+        let mut transaction = table.begin_mapping();
+        for (&page_start, &flags) in &pages {
+            let frame = transaction.map_new(Page::containing_address(page_start), flags)?;
+
+            let frame_ptr = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+            let frame_bytes = unsafe { slice::from_raw_parts_mut(frame_ptr, PAGE_SIZE) };
+            frame_bytes.fill(0);
+
+            // the parts of the segments' file data that live in this page
+            for segment in segments() {
+                let file_start = segment.vaddr.as_usize();
+                let file_end = file_start + segment.file_size;
+                let copy_start = file_start.max(page_start.as_usize());
+                let copy_end = file_end.min(page_start.as_usize() + PAGE_SIZE);
+
+                if copy_start < copy_end {
+                    let src = unsafe {
+                        slice::from_raw_parts(
+                            self.base().add(segment.offset + (copy_start - file_start)),
+                            copy_end - copy_start,
+                        )
+                    };
+
+                    let page_offset = copy_start - page_start.as_usize();
+                    frame_bytes[page_offset..page_offset + src.len()].copy_from_slice(src);
+                }
+            }
+        }
+
+        transaction.commit();
+        Ok(self.header.entry_point)
     }
 
     pub fn debug(&self) {
@@ -248,7 +345,7 @@ impl<'a> Elf<'a> {
             )
         }
 
-        for section in self.sections {
+        for section in self.sections() {
             serial!(
                 "section {}: '{}'\n",
                 section.name_index,
@@ -1,15 +1,132 @@
 pub mod elf;
+pub mod ring_buffer;
 // TODO: impl our own Optional type
-use spin::Mutex;
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use spin::{Mutex, MutexGuard};
+
+use crate::arch::{Arch, Current};
 
 pub struct Locked<T> {
     pub inner: Mutex<T>,
+    /// where self was last locked from through `Self::lock`, only recorded in debug builds
+    last_locker: AtomicPtr<Location<'static>>,
 }
 
 impl<T> Locked<T> {
     pub const fn new(inner: T) -> Self {
         Self {
             inner: Mutex::new(inner),
+            last_locker: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// locks self, in debug builds the caller is remembered see `Self::last_locker`
+    #[track_caller]
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let guard = self.inner.lock();
+
+        if cfg!(debug_assertions) {
+            let caller: *const Location<'static> = Location::caller();
+            self.last_locker.store(caller.cast_mut(), Ordering::Relaxed);
+        }
+        guard
+    }
+
+    /// locks self for something that must never be locked twice at once, interrupts are
+    /// disabled until the guard is dropped so with one cpu the lock can only be held already if
+    /// the caller (or something it called) holds it, that panics instead of deadlocking
+    #[track_caller]
+    pub fn lock_exclusive(&self) -> ExclusiveGuard<'_, T> {
+        let interrupts = Current::interrupts_enabled();
+        Current::disable_interrupts();
+
+        let Some(guard) = self.inner.try_lock() else {
+            match self.last_locker() {
+                Some(location) => panic!(
+                    "{} locked while already held, it was taken at {}",
+                    core::any::type_name::<T>(),
+                    location
+                ),
+                None => panic!("{} locked while already held", core::any::type_name::<T>()),
+            }
+        };
+
+        if cfg!(debug_assertions) {
+            let caller: *const Location<'static> = Location::caller();
+            self.last_locker.store(caller.cast_mut(), Ordering::Relaxed);
+        }
+
+        ExclusiveGuard {
+            guard: ManuallyDrop::new(guard),
+            interrupts,
+        }
+    }
+
+    /// where self was last locked from through `Self::lock`, None if it wasn't or in release
+    /// builds
+    pub fn last_locker(&self) -> Option<&'static Location<'static>> {
+        unsafe { self.last_locker.load(Ordering::Relaxed).as_ref() }
+    }
+
+    #[inline]
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// unlocks self even if someone holds it returning wether or not it was locked
+    /// only for the panic handler, whoever held the lock was interrupted by the panic and never
+    /// runs again
+    pub unsafe fn force_unlock(&self) -> bool {
+        let locked = self.is_locked();
+        if locked {
+            self.inner.force_unlock();
+        }
+        locked
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Locked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// see `Locked::lock_exclusive`
+pub struct ExclusiveGuard<'a, T> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    /// wether or not interrupts were enabled before locking
+    interrupts: bool,
+}
+
+impl<T> Deref for ExclusiveGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for ExclusiveGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for ExclusiveGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlocks before interrupts can come back
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts {
+            Current::enable_interrupts();
         }
     }
 }
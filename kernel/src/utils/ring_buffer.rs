@@ -0,0 +1,151 @@
+// a bounded lock-free multi producer multi consumer queue, it is safe to push or pop from an
+// interrupt handler even if the interrupted code was pushing or popping itself
+// each slot has a sequence number telling whose turn it is, a producer reserves a position by
+// moving `head` then publishes its value by bumping the slot sequence, only then a consumer
+// can reserve it by moving `tail` and take it
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// returned by `RingBuffer::push` if the buffer is full, the event is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Full;
+
+struct Slot<T> {
+    /// the position this slot is expected at minus the slot index, starts at 0 so the buffer
+    /// can be created in a const context
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct RingBuffer<T, const N: usize> {
+    slots: [Slot<T>; N],
+    /// the next position a producer writes to
+    head: AtomicUsize,
+    /// the next position the consumer reads from
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    const EMPTY_SLOT: Slot<T> = Slot {
+        sequence: AtomicUsize::new(0),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+    };
+
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY_SLOT; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// returns the slot at `pos` and its sequence
+    #[inline]
+    fn slot(&self, pos: usize) -> (&Slot<T>, usize) {
+        let index = pos % N;
+        let slot = &self.slots[index];
+
+        (
+            slot,
+            slot.sequence.load(Ordering::Acquire).wrapping_add(index),
+        )
+    }
+
+    #[inline]
+    fn set_sequence(&self, pos: usize, sequence: usize) {
+        let index = pos % N;
+        self.slots[index]
+            .sequence
+            .store(sequence.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// pushes `event` to the end of the buffer, never blocks so it is safe to call from an
+    /// interrupt handler, returns Err(Full) and drops `event` if the buffer is full
+    pub fn push(&self, event: T) -> Result<(), Full> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let (slot, sequence) = self.slot(pos);
+            let diff = sequence.wrapping_sub(pos) as isize;
+
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(event) };
+                        self.set_sequence(pos, pos.wrapping_add(1));
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // the consumer didn't take the event a lap ago yet
+                return Err(Full);
+            } else {
+                // another producer took this position
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// pushes `event` dropping the oldest event to make room for it if the buffer is full,
+    /// returns wether or not an event was dropped
+    /// `event` itself is dropped if the oldest event is still being popped
+    pub fn push_overwriting(&self, event: T) -> bool {
+        if self.push(event).is_ok() {
+            return false;
+        }
+
+        _ = self.pop();
+        _ = self.push(event);
+        true
+    }
+
+    /// pops the oldest event, returns None if the buffer is empty or the oldest event is still
+    /// being pushed
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let (slot, sequence) = self.slot(pos);
+            let diff = sequence.wrapping_sub(pos.wrapping_add(1)) as isize;
+
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let event = unsafe { (*slot.value.get()).assume_init() };
+                        self.set_sequence(pos, pos.wrapping_add(N));
+                        return Some(event);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // nothing was pushed here yet
+                return None;
+            } else {
+                // another consumer took this position
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::Relaxed)
+    }
+}
@@ -1,4 +1,6 @@
-// it is simple, it just takes a module and takes all of its functions!
+// it is simple, it just takes a module and takes all of its `#[test_case]` functions!
+// the other functions are left alone so they can be used as helpers
+// `test_main` passes the collected functions to `crate::test::test_runner`
 
 use core::panic;
 
@@ -12,26 +14,30 @@ pub fn test_module(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let mut content = module.content.take().unwrap();
 
-    let func_names: Vec<_> = content
-        .1
-        .iter()
-        .filter_map(|x| {
-            if let Item::Fn(func) = x {
-                Some(func.sig.ident.clone())
-            } else {
-                None
+    let mut tests = Vec::new();
+    for item in content.1.iter_mut() {
+        if let Item::Fn(func) = item {
+            let attrs_len = func.attrs.len();
+            // we remove #[test_case] ourselves, it only works in `cargo test` builds
+            func.attrs.retain(|attr| !attr.path.is_ident("test_case"));
+
+            if func.attrs.len() != attrs_len {
+                // a `#[cfg]`ed out test is left out of the list too
+                let cfgs: Vec<_> = func
+                    .attrs
+                    .iter()
+                    .filter(|attr| attr.path.is_ident("cfg"))
+                    .cloned()
+                    .collect();
+                let name = func.sig.ident.clone();
+                tests.push(quote! { #(#cfgs)* &#name });
             }
-        })
-        .collect();
-    let len = func_names.len();
+        }
+    }
+
     let test_main: Item = parse_quote! {
         pub fn test_main() {
-            cross_println!("running {} tests...", #len);
-            #(
-                cross_println!("running {} test...", stringify!(#func_names));
-                #func_names();
-                cross_println!("[ok]");
-            )*
+            crate::test::test_runner(&[#(#tests),*]);
         }
     };
 
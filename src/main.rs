@@ -1,7 +1,14 @@
 use ovmf_prebuilt;
+use std::io::{BufRead, BufReader};
+use std::process::Stdio;
 // code for running qemu and testing, kernel src avalible at kernel
 
-fn main() {
+/// the exit code qemu exits with when the kernel exits with success, `(0x10 << 1) | 1`
+const QEMU_SUCCESS: i32 = 33;
+
+/// creates the qemu command used to run the kernel, the isa-debug-exit device lets the kernel
+/// exit qemu (see kernel/src/arch/x86_64/qemu.rs)
+fn qemu_command(display: &str) -> std::process::Command {
     let iso_path = env!("ISO_PATH");
 
     let uefi = true;
@@ -12,15 +19,103 @@ fn main() {
         cmd.arg("-drive")
             .arg(format!("format=raw,file={iso_path}"))
             .arg("-display")
-            .arg("sdl")
+            .arg(display)
             .arg("-serial")
             .arg("stdio")
+            // COM2, where the kernel `log!`s to
+            .arg("-serial")
+            .arg("file:kernel.log")
+            .arg("-device")
+            .arg("isa-debug-exit,iobase=0xf4,iosize=0x04")
+            // a virtio-net card on qemu's user network, without its option rom so the firmware
+            // doesn't try to boot from the network
+            .arg("-netdev")
+            .arg("user,id=net0")
+            .arg("-device")
+            .arg("virtio-net-pci,netdev=net0,romfile=")
             .arg("-enable-kvm")
             .arg("-m")
             .arg("512M")
             .arg("-smp")
             .arg("2");
     }
-    let mut child = cmd.spawn().unwrap();
+    cmd
+}
+
+/// boots the kernel headless and prints the results of its microbenchmarks, one
+/// `<name> iterations=<n> cycles=<total> per_iteration=<total / n>` line each (see
+/// kernel/src/bench.rs), so two runs can be diffed
+fn bench() {
+    if !cfg!(feature = "bench") {
+        eprintln!("NAVI_BENCH=1 needs the kernel built with `--features bench`");
+        std::process::exit(1);
+    }
+
+    let mut child = qemu_command("none").stdout(Stdio::piped()).spawn().unwrap();
+
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        let line = line.unwrap();
+        match line.trim_end().strip_prefix("bench: ") {
+            Some("done") => break,
+            Some(result) => println!("{result}"),
+            None => {}
+        }
+    }
+
+    let status = child.wait().unwrap();
+    if status.code() != Some(QEMU_SUCCESS) {
+        eprintln!("qemu exited before the benchmarks finished: {status}");
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    if std::env::var("NAVI_BENCH").is_ok_and(|value| value == "1") {
+        return bench();
+    }
+
+    let mut child = qemu_command("sdl").spawn().unwrap();
     child.wait().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{BufRead, BufReader, Stdio};
+
+    /// the exit code qemu exits with when a kernel test fails, `(0x11 << 1) | 1`
+    const QEMU_FAILED: i32 = 35;
+
+    /// boots the kernel (which is always built with the `test` feature) and waits for the
+    /// in-kernel tests to finish, a failing test makes the kernel exit qemu
+    #[test]
+    fn kernel_tests() {
+        let mut child = super::qemu_command("none")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut passed = false;
+
+        for line in stdout.lines() {
+            let line = line.unwrap();
+            println!("{line}");
+
+            if line.starts_with("all ") && line.ends_with(" tests passed") {
+                passed = true;
+                break;
+            }
+        }
+
+        if passed {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return;
+        }
+
+        let status = child.wait().unwrap();
+        assert_ne!(status.code(), Some(QEMU_FAILED), "a kernel test failed");
+        panic!("qemu exited before the kernel tests finished: {status}");
+    }
+}
@@ -0,0 +1,11 @@
+#!/bin/sh
+# runs the in-kernel tests with the default kernel and once more for each feature that changes
+# how memory is handled, the tests of a feature only check it in a build that has it
+set -e
+
+cargo test
+
+for feature in heap-integrity heap-poison recursive-paging; do
+    echo "testing with --features $feature"
+    cargo test --features "$feature"
+done